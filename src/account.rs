@@ -0,0 +1,2095 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::io::{Error, ErrorKind, Result};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "json")]
+use serde_json::json;
+
+use crate::report::{SetupReport, StepStatus};
+use crate::client_tls::ClientTls;
+use crate::connectivity::{self, Connectivity};
+use crate::discover;
+use crate::errors::{account_not_found, MultigitError};
+#[cfg(feature = "json")]
+use crate::events;
+use crate::fsutil;
+use crate::git_settings;
+use crate::gitconfig;
+use crate::host_conflict::HostBlockChoice;
+use crate::github;
+use crate::keys::{self, Keychain};
+use crate::known_hosts::{self, KNOWN_HOSTS_STEP};
+use crate::managed;
+use crate::multiplex;
+use crate::platform;
+use crate::provider;
+use crate::proxy;
+use crate::sendemail::SendEmail;
+use crate::ssh_config;
+use crate::state::State;
+use crate::store::{self, Settings};
+use crate::tamper::{Resolution, UserSection};
+use crate::templates::{self, TemplateKind};
+use crate::test_push;
+use crate::wsl;
+
+/// The provider Host aliases point at unless the account says otherwise.
+pub const PROVIDER_HOST: &str = "github.com";
+
+/// The Host alias of an account `setup` isn't given one for:
+/// `<hostname>-<name>`.
+pub fn default_host(hostname: &str, name: &str) -> String {
+    format!("{}-{}", hostname, name)
+}
+
+/// Rejects a Host alias equal to the provider's own hostname: the Host
+/// block would then redefine the real host and the insteadOf rewrite would
+/// map it onto itself.
+fn validate_alias(alias: &str, account_name: &str, hostname: &str) -> Result<()> {
+    if alias.trim_end_matches('.').eq_ignore_ascii_case(hostname) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "--host {} is the provider's real hostname; use an alias such as {}-{}",
+                alias, hostname, account_name
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a `--hostname` ssh couldn't connect to.
+fn validate_hostname(hostname: &str) -> Result<()> {
+    let valid = !hostname.is_empty()
+        && !hostname.starts_with(['-', '.'])
+        && hostname.chars().all(|c| c.is_ascii_alphanumeric() || "-.".contains(c));
+    if !valid {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("--hostname {}: expected a hostname such as gitlab.example.com", hostname)));
+    }
+    Ok(())
+}
+
+/// The hooks directory, relative paths taken from the home dir like the
+/// codebase dir. git silently runs no hooks when the directory is missing,
+/// so it has to exist.
+fn validate_hooks_path(home_dir: &Path, path: &Path) -> Result<PathBuf> {
+    let path = home_dir.join(path);
+    if !path.is_dir() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("--hooks-path {} is not a directory", path.display()),
+        ));
+    }
+    if executable_hooks(&path).is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("--hooks-path {} has no executable hooks; git would run nothing from it", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+/// The hooks git would run from `dir`: executable files, leaving out the
+/// `.sample` ones git ships.
+pub fn executable_hooks(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut hooks: Vec<String> = entries
+        .flatten()
+        .filter(|entry| {
+            let Ok(metadata) = entry.path().metadata() else {
+                return false;
+            };
+            #[cfg(unix)]
+            let executable = {
+                use std::os::unix::fs::PermissionsExt;
+                metadata.permissions().mode() & 0o111 != 0
+            };
+            #[cfg(not(unix))]
+            let executable = true;
+            metadata.is_file() && executable
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.ends_with(".sample"))
+        .collect();
+    hooks.sort();
+    hooks
+}
+
+/// For a codebase dir with `*` or `?` in it, such as `clients/*/`, the
+/// directory before the first wildcard, which holds the include file, and
+/// the pattern itself, both taken from the home dir when relative. `None`
+/// for a plain directory. The pattern goes into the includeIf verbatim, so
+/// it has to end in `/` or `/**` to cover the repositories below it, and
+/// may not hold anything git or multigit would read differently.
+pub fn split_dir_glob(home_dir: &Path, dir: &str) -> Result<Option<(PathBuf, String)>> {
+    if !dir.contains(['*', '?']) {
+        return Ok(None);
+    }
+    let invalid = |reason: &str| Error::new(ErrorKind::InvalidInput, format!("--codebase-dir {}: {}", dir, reason));
+    if dir.contains(|c: char| c.is_whitespace() || c.is_control() || "\\\"[]".contains(c)) {
+        return Err(invalid("only * and ? may be used as wildcards, without spaces, quotes or backslashes"));
+    }
+    if !dir.ends_with('/') && !dir.ends_with("/**") {
+        return Err(invalid("a pattern has to end in / or /** to cover the repositories below it"));
+    }
+    let pattern = if dir.starts_with('/') { dir.to_string() } else { format!("{}/{}", home_dir.display(), dir) };
+    let components: Vec<&str> = pattern.split('/').filter(|component| !component.is_empty()).collect();
+    if components.iter().any(|component| *component == "." || *component == "..") {
+        return Err(invalid("a pattern can't contain . or .."));
+    }
+    let base: PathBuf = std::iter::once("/")
+        .chain(components.iter().copied().take_while(|component| !component.contains(['*', '?'])))
+        .collect();
+    if !base.starts_with(home_dir) || base == home_dir {
+        return Err(invalid("the part before the first wildcard has to be a directory inside the home dir"));
+    }
+    Ok(Some((base, pattern)))
+}
+
+/// The gitdir pattern for a directory. git only matches the repositories
+/// underneath a pattern that ends in `/`; without it the pattern has to
+/// equal a repository's `.git` directory.
+pub fn gitdir_pattern(dir: &str) -> String {
+    if dir.ends_with('/') {
+        dir.to_string()
+    } else {
+        format!("{}/", dir)
+    }
+}
+
+/// Name of the setup step that checks the key against the provider.
+pub const CONNECTIVITY_STEP: &str = "connectivity";
+
+/// Name of the setup step that asks the provider whose token is in use.
+pub const LOGIN_STEP: &str = "provider login";
+
+/// Name of the setup step that loads the key into ssh-agent.
+pub const AGENT_STEP: &str = "ssh-agent";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GitAccount {
+    pub name: String,
+    pub email: String,
+    pub ssh_key: String,
+    pub codebase_dir_path: PathBuf,
+    /// The ssh Host alias used in the insteadOf rewrite, e.g. `github.com-work`.
+    #[serde(default)]
+    pub host: String,
+    /// Additional keys offered after `ssh_key`, in order of preference.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secondary_keys: Vec<SecondaryKey>,
+    /// Never load this account's keys into ssh-agent.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_agent: bool,
+    /// Set by `multigit disable`: the account's blocks are commented out of
+    /// the live config but everything is kept for `multigit enable`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub disabled: bool,
+    /// Login pre-filled for HTTPS remotes on the provider, so git doesn't
+    /// default to the local user name before the credential helper runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_username: Option<String>,
+    /// Restricts the Host block's `PubkeyAcceptedAlgorithms`, for hosts that
+    /// otherwise negotiate an algorithm the key doesn't use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey_algorithms: Option<String>,
+    /// Write the Host block from the minimal template.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub minimal_ssh_block: bool,
+    /// Extra settings for the include file from `setup --set`, by key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub git_settings: BTreeMap<String, String>,
+    /// `None` for accounts set up before `--keychain`, which always got the
+    /// keychain directives.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keychain: Option<Keychain>,
+    /// Also write an includeIf with the Windows form of the codebase dir, for
+    /// git for Windows under WSL.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub also_windows: bool,
+    /// `core.hooksPath` for repositories under the codebase dir.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks_path: Option<PathBuf>,
+    /// `init.templateDir` for repositories created under the codebase dir.
+    /// git looks it up before the repository exists, so the include file
+    /// can't supply it to `git init` itself; `clone-all` passes it on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_dir: Option<PathBuf>,
+    /// Git LFS's `filter "lfs"` in the include file, for LFS repositories
+    /// under the codebase dir without a global `git lfs install`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
+    /// Subdirectories of the codebase dir that use another account.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusions: Vec<Exclusion>,
+    /// ssh certificate signed for `ssh_key`, offered with it through the
+    /// Host block's `CertificateFile`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_file: Option<PathBuf>,
+    /// `ControlPersist` of the account's shared ssh connection; `None` when
+    /// connections aren't shared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplexing: Option<String>,
+    /// Leave `https://` and `git://` remotes on the provider alone; only
+    /// `git@` ones go through the alias.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_rewrite_https: bool,
+    /// The account's login on the provider, as given to setup or as the
+    /// provider reported it for the account's token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<String>,
+    /// The glob the account covers when setup was given one, such as
+    /// `/home/me/clients/*/`; `codebase_dir_path` is then the directory
+    /// before its first wildcard.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Scope::is_dir")]
+    pub scope: Scope,
+    /// Who commits are recorded as committed by, when that isn't the
+    /// author: git's `committer.name` and `committer.email`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_email: Option<String>,
+    /// Addresses the user committed under before, for `mailmap generate`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_emails: Vec<String>,
+    /// `git send-email` settings for the include file.
+    #[serde(default, skip_serializing_if = "SendEmail::is_empty")]
+    pub sendemail: SendEmail,
+    /// The Host block's `UserKnownHostsFile`, in place of
+    /// `~/.ssh/known_hosts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_hosts_file: Option<PathBuf>,
+    /// Setup created `known_hosts_file`, so it goes with the account.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub known_hosts_created: bool,
+    /// `StrictHostKeyChecking yes`: only hosts already in the known hosts
+    /// file are accepted.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_host_key_checking: bool,
+    /// PKCS#11 library the Host block offers keys from (`PKCS11Provider`).
+    /// Such accounts have no key file: `ssh_key` is empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkcs11_provider: Option<PathBuf>,
+    /// `http.proxy` for https remotes under the codebase dir.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    /// The Host block's `ProxyJump`, for ssh remotes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+    /// TLS client certificate for https remotes.
+    #[serde(default, skip_serializing_if = "ClientTls::is_empty")]
+    pub client_tls: ClientTls,
+    /// Repository `test-push` checks write access with, as `owner/repo`
+    /// or a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_repo: Option<String>,
+    /// Where the Host alias connects, when it's not `PROVIDER_HOST`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// When the account was added, in seconds since the epoch. Missing for
+    /// accounts added before this was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+}
+
+/// A subdirectory handed to another account by an includeIf placed after
+/// the owning account's own, which git then applies last.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Exclusion {
+    pub dir: PathBuf,
+    pub account: String,
+}
+
+/// Which repositories an account's includeIf applies to (`setup --scope`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Repositories under the codebase dir, with a `gitdir/i:` condition.
+    #[default]
+    Dir,
+    /// Repositories with a remote through the account's Host alias, wherever
+    /// they are, with `hasconfig:remote.*.url:` conditions. Needs git 2.36.
+    RemoteUrl,
+}
+
+impl Scope {
+    pub fn is_dir(&self) -> bool {
+        *self == Scope::Dir
+    }
+}
+
+/// The type of the key setup generates unless `--key-type` says otherwise.
+pub const DEFAULT_KEY_TYPE: &str = "ed25519";
+
+/// The oldest git that understands `hasconfig:remote.*.url:`.
+pub const HASCONFIG_GIT: (u32, u32) = (2, 36);
+
+/// The oldest git that reads `committer.name` and `committer.email`.
+pub const COMMITTER_CONFIG_GIT: (u32, u32) = (2, 22);
+
+/// What `git lfs install` puts in the global gitconfig.
+const LFS_FILTER: &str = "[filter \"lfs\"]\n    clean = git-lfs clean -- %f\n    smudge = git-lfs smudge -- %f\n    process = git-lfs filter-process\n    required = true\n";
+
+/// Optional per-account settings given to setup.
+#[derive(Default)]
+pub struct SetupOptions {
+    pub https_username: Option<String>,
+    pub pubkey_algorithms: Option<String>,
+    pub minimal: bool,
+    /// Use this private key instead of generating one.
+    pub existing_key: Option<PathBuf>,
+    /// Type of the generated key, or `keys::AUTO_KEY_TYPE`; ed25519 when
+    /// unset.
+    pub key_type: Option<String>,
+    /// What to do with a Host block for the alias that multigit didn't
+    /// write and that differs from its own; asked when unset.
+    pub host_block: Option<HostBlockChoice>,
+    pub git_settings: BTreeMap<String, String>,
+    /// Defaults to `keys::default_keychain()`.
+    pub keychain: Option<Keychain>,
+    pub also_windows: bool,
+    /// Shared hooks directory; must exist.
+    pub hooks_path: Option<PathBuf>,
+    /// Template directory for `git init`; must exist.
+    pub template_dir: Option<PathBuf>,
+    pub lfs: bool,
+    pub certificate_file: Option<PathBuf>,
+    /// `ControlPersist` duration for a shared connection.
+    pub multiplexing: Option<String>,
+    pub no_rewrite_https: bool,
+    /// Login the account's token and key are expected to belong to.
+    pub login: Option<String>,
+    pub scope: Scope,
+    pub committer_name: Option<String>,
+    pub committer_email: Option<String>,
+    pub previous_emails: Vec<String>,
+    pub sendemail: SendEmail,
+    /// Add the provider's verified host keys to known_hosts.
+    pub check_remote_host_key: bool,
+    pub known_hosts_file: Option<PathBuf>,
+    pub strict_host_key_checking: bool,
+    /// Use the keys of this PKCS#11 library instead of a key file.
+    pub pkcs11_provider: Option<PathBuf>,
+    pub http_proxy: Option<String>,
+    pub proxy_jump: Option<String>,
+    pub client_tls: ClientTls,
+    pub canary_repo: Option<String>,
+    /// `None` for `PROVIDER_HOST`.
+    pub hostname: Option<String>,
+}
+
+impl SetupOptions {
+    /// `setup --copy-from`: fills in from `source` what wasn't given: the
+    /// key type, the ssh and git options and the `--set` settings, those
+    /// given winning. What belongs to the identity stays the new account's
+    /// own: the key and its certificate, the login, the committer, the
+    /// send-email user and sender, and the TLS client certificate. The
+    /// source's switches can't be turned off, only added to. The hostname
+    /// is left to the caller, since the Host alias follows from it.
+    pub fn inherit(&mut self, source: &GitAccount) {
+        if self.key_type.is_none() && self.existing_key.is_none() {
+            self.key_type = keys::key_strength(&source.ssh_key).map(|(_, key_type)| key_type);
+        }
+        self.pubkey_algorithms = self.pubkey_algorithms.take().or_else(|| source.pubkey_algorithms.clone());
+        self.minimal |= source.minimal_ssh_block;
+        for (key, value) in &source.git_settings {
+            self.git_settings.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+        self.keychain = self.keychain.or(source.keychain);
+        self.hooks_path = self.hooks_path.take().or_else(|| source.hooks_path.clone());
+        self.template_dir = self.template_dir.take().or_else(|| source.template_dir.clone());
+        self.lfs |= source.lfs;
+        self.multiplexing = self.multiplexing.take().or_else(|| source.multiplexing.clone());
+        self.no_rewrite_https |= source.no_rewrite_https;
+        if self.scope.is_dir() {
+            self.scope = source.scope;
+        }
+        if self.sendemail.smtp_server.is_none() {
+            self.sendemail.smtp_server = source.sendemail.smtp_server.clone();
+            self.sendemail.smtp_encryption = self.sendemail.smtp_encryption.or(source.sendemail.smtp_encryption);
+            self.sendemail.smtp_server_port = self.sendemail.smtp_server_port.or(source.sendemail.smtp_server_port);
+            self.sendemail.credential_helper = self.sendemail.credential_helper.take().or_else(|| source.sendemail.credential_helper.clone());
+        }
+        self.known_hosts_file = self.known_hosts_file.take().or_else(|| source.known_hosts_file.clone());
+        self.strict_host_key_checking |= source.strict_host_key_checking;
+        self.http_proxy = self.http_proxy.take().or_else(|| source.http_proxy.clone());
+        self.proxy_jump = self.proxy_jump.take().or_else(|| source.proxy_jump.clone());
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SecondaryKey {
+    pub label: String,
+    pub path: String,
+    pub key_type: String,
+}
+
+/// Puts a `GitAccount` together from setup's pieces, checking in `build`
+/// that they make a usable account.
+pub struct GitAccountBuilder {
+    account: GitAccount,
+}
+
+impl GitAccountBuilder {
+    pub fn new(name: &str, email: &str, ssh_key: &str, codebase_dir_path: PathBuf, host: &str) -> Self {
+        GitAccountBuilder { account: GitAccount::new(name, email, ssh_key, codebase_dir_path, host) }
+    }
+
+    pub fn no_agent(mut self, no_agent: bool) -> Self {
+        self.account.no_agent = no_agent;
+        self
+    }
+
+    /// Takes setup's options, with the keychain mode and hooks path as
+    /// `validate_setup` resolved them.
+    pub fn options(mut self, options: SetupOptions, keychain: Keychain, hooks_path: Option<PathBuf>) -> Self {
+        let account = &mut self.account;
+        account.https_username = options.https_username;
+        account.pubkey_algorithms = options.pubkey_algorithms;
+        account.minimal_ssh_block = options.minimal;
+        account.git_settings = options.git_settings;
+        account.keychain = Some(keychain);
+        account.also_windows = options.also_windows;
+        account.hooks_path = hooks_path;
+        account.template_dir = options.template_dir;
+        account.lfs = options.lfs;
+        account.certificate_file = options.certificate_file;
+        account.multiplexing = options.multiplexing;
+        account.no_rewrite_https = options.no_rewrite_https;
+        account.login = options.login;
+        account.scope = options.scope;
+        account.committer_name = options.committer_name;
+        account.committer_email = options.committer_email;
+        account.previous_emails = options.previous_emails;
+        account.sendemail = options.sendemail;
+        account.known_hosts_file = options.known_hosts_file;
+        account.strict_host_key_checking = options.strict_host_key_checking;
+        account.pkcs11_provider = options.pkcs11_provider;
+        account.http_proxy = options.http_proxy;
+        account.proxy_jump = options.proxy_jump;
+        account.client_tls = options.client_tls;
+        account.canary_repo = options.canary_repo;
+        account.hostname = options.hostname;
+        self
+    }
+
+    /// The glob of a codebase dir that `split_dir_glob` split.
+    pub fn dir_pattern(mut self, dir_pattern: Option<String>) -> Self {
+        self.account.dir_pattern = dir_pattern;
+        self
+    }
+
+    pub fn build(self) -> std::result::Result<GitAccount, MultigitError> {
+        let account = self.account;
+        let invalid = |message: String| MultigitError::InvalidAccount { account: account.name.clone(), message };
+        let missing = |what: &str| invalid(format!("Account '{}' has no {}", account.name, what));
+        if account.name.trim().is_empty() {
+            return Err(invalid("An account needs a name".to_string()));
+        }
+        if account.email.trim().is_empty() {
+            return Err(missing("email"));
+        }
+        if account.ssh_key.is_empty() && account.pkcs11_provider.is_none() {
+            return Err(missing("ssh key"));
+        }
+        validate_alias(&account.host, &account.name, account.hostname()).map_err(|e| invalid(e.to_string()))?;
+        if account.dir_pattern.is_some() && !account.scope.is_dir() {
+            return Err(invalid(format!("Account '{}' is scoped by remote URL but has a glob codebase dir", account.name)));
+        }
+        account.sendemail.validate(&account.name).map_err(|e| invalid(e.to_string()))?;
+        Ok(account)
+    }
+}
+
+impl GitAccount {
+    pub fn new(name: &str, email: &str, ssh_key: &str, codebase_dir_path: PathBuf, host: &str) -> GitAccount {
+        GitAccount {
+            name: name.to_string(),
+            email: email.to_string(),
+            ssh_key: ssh_key.to_string(),
+            codebase_dir_path,
+            host: host.to_string(),
+            secondary_keys: Vec::new(),
+            no_agent: false,
+            disabled: false,
+            https_username: None,
+            pubkey_algorithms: None,
+            minimal_ssh_block: false,
+            git_settings: BTreeMap::new(),
+            keychain: None,
+            also_windows: false,
+            hooks_path: None,
+            template_dir: None,
+            lfs: false,
+            exclusions: Vec::new(),
+            certificate_file: None,
+            multiplexing: None,
+            no_rewrite_https: false,
+            login: None,
+            dir_pattern: None,
+            scope: Scope::Dir,
+            committer_name: None,
+            committer_email: None,
+            previous_emails: Vec::new(),
+            sendemail: SendEmail::default(),
+            known_hosts_file: None,
+            known_hosts_created: false,
+            strict_host_key_checking: false,
+            pkcs11_provider: None,
+            http_proxy: None,
+            proxy_jump: None,
+            client_tls: ClientTls::default(),
+            canary_repo: None,
+            hostname: None,
+            created_at: None,
+        }
+    }
+
+    /// When the account was added and whether that was recorded; for older
+    /// accounts, when the key file was last modified instead.
+    pub fn created(&self) -> Option<(u64, bool)> {
+        if let Some(at) = self.created_at {
+            return Some((at, true));
+        }
+        let modified = fs::metadata(&self.ssh_key).and_then(|metadata| metadata.modified()).ok()?;
+        Some((modified.duration_since(UNIX_EPOCH).ok()?.as_secs(), false))
+    }
+
+    /// Where the account's Host alias connects.
+    pub fn hostname(&self) -> &str {
+        self.hostname.as_deref().unwrap_or(PROVIDER_HOST)
+    }
+
+    pub fn keychain(&self) -> Keychain {
+        self.keychain.unwrap_or(Keychain::Store)
+    }
+
+    /// The Windows path of the include file, for `also_windows` accounts
+    /// whose codebase dir is on a Windows drive.
+    pub fn windows_include_path(&self) -> Option<String> {
+        let dir = wsl::windows_path(&self.codebase_dir_path).filter(|_| self.also_windows)?;
+        Some(format!("{}/.gitconfig", dir))
+    }
+
+    /// The gitdir pattern of the account's includeIf.
+    pub fn gitdir_pattern(&self) -> String {
+        match &self.dir_pattern {
+            Some(pattern) => pattern.clone(),
+            None => gitdir_pattern(&self.codebase_dir_path.to_string_lossy()),
+        }
+    }
+
+    /// The remote URLs a `--scope remote-url` account would have: through
+    /// the Host alias, or for an HTTPS account with its username. git
+    /// matches them as paths, so `**` only spans directories after a `/`.
+    pub fn remote_url_patterns(&self) -> Vec<String> {
+        match &self.https_username {
+            Some(user) => vec![format!("https://{}@{}/**", user, self.hostname())],
+            None => vec![format!("git@{}:*/**", self.host), format!("ssh://git@{}/**", self.host)],
+        }
+    }
+
+    /// The conditions of the account's own includeIfs, without the Windows
+    /// one and the exclusions.
+    pub fn include_conditions(&self) -> Vec<String> {
+        match self.scope {
+            Scope::Dir => vec![format!("gitdir/i:{}", self.gitdir_pattern())],
+            Scope::RemoteUrl => self.remote_url_patterns().iter().map(|pattern| format!("hasconfig:remote.*.url:{}", pattern)).collect(),
+        }
+    }
+
+    /// The primary key file, or the PKCS#11 library the keys come from.
+    pub fn key_display(&self) -> String {
+        match &self.pkcs11_provider {
+            Some(provider) => format!("PKCS#11 {}", provider.display()),
+            None => self.ssh_key.clone(),
+        }
+    }
+
+    /// The codebase dir, or the glob for accounts that have one.
+    pub fn dir_display(&self) -> String {
+        match &self.dir_pattern {
+            Some(pattern) => pattern.clone(),
+            None => self.codebase_dir_path.display().to_string(),
+        }
+    }
+
+    /// Whether `path` is in the account's codebase dir, or in a directory
+    /// its glob matches. For a `--scope remote-url` account, whether `path`
+    /// is a repository with a remote the account's includeIf matches.
+    pub fn covers(&self, path: &Path) -> bool {
+        if self.scope == Scope::RemoteUrl {
+            let patterns = self.remote_url_patterns();
+            return discover::remote_urls(path)
+                .iter()
+                .any(|remote| remote.key == "url" && patterns.iter().any(|pattern| gitconfig::remote_url_matches(pattern, &remote.url)));
+        }
+        match &self.dir_pattern {
+            Some(pattern) => gitconfig::gitdir_matches(pattern, true, &path.join(".git"), Path::new("/")),
+            None => path.starts_with(&self.codebase_dir_path),
+        }
+    }
+
+    /// How deep the account's directory is, for ordering nested ones. A glob
+    /// counts its wildcard components, and a plain directory beats a glob
+    /// of the same depth. A remote URL beats any directory, so those
+    /// includeIfs go last.
+    pub fn specificity(&self) -> (usize, bool) {
+        if self.scope == Scope::RemoteUrl {
+            return (usize::MAX, true);
+        }
+        match &self.dir_pattern {
+            Some(pattern) => (Path::new(pattern.trim_end_matches("/**")).components().count(), false),
+            None => (self.codebase_dir_path.components().count(), true),
+        }
+    }
+
+    /// A directory the account covers: the codebase dir, or for a glob the
+    /// path it matches with `multigit-probe` in place of each wildcard.
+    pub fn probe_dir(&self) -> PathBuf {
+        let Some(pattern) = &self.dir_pattern else {
+            return self.codebase_dir_path.clone();
+        };
+        std::iter::once("/".to_string())
+            .chain(
+                pattern
+                    .split('/')
+                    .filter(|component| !component.is_empty() && *component != "**")
+                    .map(|component| component.replace("**", "*").replace('*', "multigit-probe").replace('?', "x")),
+            )
+            .collect()
+    }
+
+    /// Every `path` value of the account's includeIfs.
+    pub fn include_paths(&self) -> Vec<String> {
+        let mut paths = vec![self.codebase_dir_path.join(".gitconfig").to_string_lossy().into_owned()];
+        paths.extend(self.windows_include_path());
+        paths
+    }
+
+    /// Every key the Host block should list, primary first.
+    pub fn identity_files(&self) -> Vec<&str> {
+        // PKCS#11 accounts have no primary key file.
+        let mut files: Vec<&str> = [self.ssh_key.as_str()].into_iter().filter(|key| !key.is_empty()).collect();
+        files.extend(self.secondary_keys.iter().map(|key| key.path.as_str()));
+        files
+    }
+
+    /// insteadOf rules sending copy-pasted `https://` and `git://` remotes on
+    /// the provider through the alias, so pushes use the account's key, as
+    /// the `url` section header and its lines. HTTPS accounts keep their
+    /// https remotes, and only the long-gone `git://` protocol is moved
+    /// onto HTTPS.
+    fn url_rewrites(&self) -> Option<(String, Vec<String>)> {
+        if self.no_rewrite_https || !provider::https_matches_ssh(self.hostname()) {
+            return None;
+        }
+        let host = self.hostname();
+        if self.https_username.is_some() {
+            return Some((format!("url \"https://{}/\"", host), vec![format!("    insteadOf = git://{}/", host)]));
+        }
+        Some((
+            format!("url \"git@{}:\"", self.host),
+            vec![format!("    insteadOf = https://{}/", host), format!("    insteadOf = git://{}/", host)],
+        ))
+    }
+
+    /// Values for the placeholders in the ssh Host and gitconfig templates.
+    pub fn template_vars<'a>(&'a self, codebase_dir: &'a str) -> Vec<(&'static str, &'a str)> {
+        vec![
+            ("name", &self.name),
+            ("email", &self.email),
+            ("alias", &self.host),
+            ("hostname", self.hostname()),
+            ("identity_file", &self.ssh_key),
+            ("codebase_dir", codebase_dir),
+        ]
+    }
+}
+
+#[derive(Clone)]
+pub struct GitAccountManager {
+    pub accounts: HashMap<String, GitAccount>,
+    pub home_dir: PathBuf,
+    pub settings: Settings,
+    /// Skip ssh-agent for every account during this run (`--no-agent`).
+    pub no_agent: bool,
+    /// Defer every step that needs the network (`--offline`).
+    pub offline: bool,
+    /// Passphrase for keys generated during this run; empty when unset.
+    pub key_passphrase: Option<String>,
+    pub allow_duplicate_email: bool,
+    /// Leave what a failed setup wrote in place instead of removing it
+    /// (`--no-rollback`).
+    pub no_rollback: bool,
+    /// How to resolve managed blocks edited by hand without asking
+    /// (`--force-ours`, `--force-theirs`).
+    pub tamper: Option<Resolution>,
+    /// What rewriting an include file does with a hand-edited `[user]`
+    /// (`--user-section`).
+    pub user_section: UserSection,
+    /// Provider API token given with `--token`, ahead of every other source.
+    pub token: Option<String>,
+    /// Fail and roll back setups that warn (`--strict`).
+    pub strict: bool,
+}
+
+pub fn run_ssh_keygen(key_type: &str, comment: &str, key_file: &Path, passphrase: &str) -> Result<()> {
+    let output = Command::new("ssh-keygen")
+        .args(["-t", key_type, "-C", comment])
+        .arg("-f")
+        .arg(key_file)
+        .args(["-N", passphrase])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::other(
+            format!("Failed to generate SSH key: {}, {}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
+        ));
+    }
+    Ok(())
+}
+
+/// Points `link` (the codebase dir's `.gitconfig`) at `exported`, the copy kept
+/// in the user's export directory. A plain file already at `link` becomes the
+/// exported file if there isn't one yet, otherwise it is set aside as a backup.
+fn link_exported_gitconfig(link: &Path, exported: &Path) -> Result<()> {
+    if let Some(parent) = exported.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::symlink_metadata(link) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            if fs::read_link(link)? == exported {
+                return Ok(());
+            }
+            println!("Replacing symlink {} -> {}", link.display(), fs::read_link(link)?.display());
+            fs::remove_file(link)?;
+        }
+        Ok(_) if !exported.exists() => {
+            println!("Moving {} to {}", link.display(), exported.display());
+            fs::rename(link, exported)?;
+        }
+        Ok(_) => {
+            let backup = link.with_extension("multigit-bak");
+            println!("{} already exists; keeping the existing file as {}", exported.display(), backup.display());
+            fs::rename(link, &backup)?;
+        }
+        Err(_) => {}
+    }
+
+    fsutil::symlink(exported, link)
+}
+
+/// A step's outcome, with `hint` added when it failed on a file that can't
+/// be written.
+fn step_status(result: Result<()>, hint: &str) -> StepStatus {
+    match result {
+        Ok(()) => StepStatus::Ok,
+        Err(e) if fsutil::is_blocked(&e) => StepStatus::Failed(format!("{}; {}", e, hint)),
+        Err(e) => StepStatus::Failed(e.to_string()),
+    }
+}
+
+impl GitAccountManager {
+    pub fn new(home_dir: PathBuf) -> Self {
+        GitAccountManager {
+            accounts: HashMap::new(),
+            home_dir,
+            settings: Settings::default(),
+            no_agent: false,
+            offline: false,
+            key_passphrase: None,
+            allow_duplicate_email: false,
+            no_rollback: false,
+            tamper: None,
+            user_section: UserSection::Overwrite,
+            token: None,
+            strict: false,
+        }
+    }
+
+    /// Creates a manager populated with the accounts persisted under `home_dir`.
+    pub fn load(home_dir: PathBuf) -> Result<Self> {
+        let mut manager = GitAccountManager::new(home_dir);
+        let (settings, accounts) = store::load_accounts(&manager.home_dir)?;
+        manager.settings = settings;
+        for account in accounts {
+            manager.accounts.insert(account.name.clone(), account);
+        }
+        Ok(manager)
+    }
+
+    /// Accounts sorted by name, leaving out disabled ones unless asked.
+    pub fn sorted_accounts(&self, include_disabled: bool) -> Vec<&GitAccount> {
+        let mut accounts: Vec<&GitAccount> = self.accounts.values()
+            .filter(|account| include_disabled || !account.disabled)
+            .collect();
+        accounts.sort_by(|a, b| a.name.cmp(&b.name));
+        accounts
+    }
+
+    /// Every hostname an account connects to, and the known providers',
+    /// lowercased: the hosts whose `<hostname>-` names are Host aliases.
+    pub fn provider_hostnames(&self) -> Vec<String> {
+        let mut hostnames: Vec<String> = provider::Provider::ALL.iter().map(|provider| provider.hostname().to_string()).collect();
+        hostnames.extend(self.accounts.values().map(|account| account.hostname().to_ascii_lowercase()));
+        hostnames.sort();
+        hostnames.dedup();
+        hostnames
+    }
+
+    /// The account whose codebase dir contains `path`, preferring the most
+    /// specific one when directories nest. Exclusions count as directories
+    /// of the account they hand over to.
+    pub fn account_for_path(&self, path: &Path) -> Option<&GitAccount> {
+        let enabled = |name: &str| self.accounts.get(name).filter(|account| !account.disabled);
+        self.accounts.values()
+            .filter(|account| !account.disabled)
+            .flat_map(|account| {
+                let exclusions = account
+                    .exclusions
+                    .iter()
+                    .filter(|exclusion| path.starts_with(&exclusion.dir))
+                    .filter_map(|exclusion| Some(((exclusion.dir.components().count(), true), enabled(&exclusion.account)?)));
+                account.covers(path).then(|| (account.specificity(), account)).into_iter().chain(exclusions)
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, account)| account)
+    }
+
+    /// ssh talks to a PKCS#11 token itself, so those accounts skip the agent
+    /// too.
+    pub fn skips_agent(&self, account: &GitAccount) -> bool {
+        self.no_agent || account.no_agent || account.pkcs11_provider.is_some()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut accounts: Vec<GitAccount> = self.accounts.values().cloned().collect();
+        accounts.sort_by(|a, b| a.name.cmp(&b.name));
+        store::save_accounts(&self.home_dir, &self.settings, accounts)
+    }
+
+    /// Registers a built account without writing anything. Returns a
+    /// warning when another account has the same email.
+    pub fn add_account(&mut self, mut account: GitAccount) -> Option<String> {
+        if account.created_at.is_none() {
+            account.created_at = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs());
+        }
+        let duplicate = self.accounts.values()
+            .find(|other| other.name != account.name && other.email.eq_ignore_ascii_case(&account.email))
+            .filter(|_| !self.allow_duplicate_email)
+            .map(|other| format!(
+                "accounts '{}' and '{}' both use {}; pass --allow-duplicate-email if this is intended",
+                other.name, account.name, account.email
+            ));
+
+        self.accounts.insert(account.name.clone(), account);
+        duplicate
+    }
+
+    /// The key setup generates for the account, named by the
+    /// `--key-filename` template.
+    pub fn default_key_path(&self, account_name: &str, host: &str, key_type: &str) -> Result<PathBuf> {
+        let template = self.settings.key_filename.as_deref().unwrap_or(keys::DEFAULT_KEY_FILENAME);
+        Ok(self.home_dir.join(".ssh").join(keys::key_filename(template, account_name, host, key_type)?))
+    }
+
+    fn generate_ssh_key(&mut self, account_name: &str, email: &str, host: &str, key_type: &str) -> Result<String> {
+        let ssh_dir = self.home_dir.join(".ssh");
+        fsutil::create_dir_all(&ssh_dir)?;
+
+        let key_file = self.default_key_path(account_name, host, key_type)?;
+        let key_file_str = fsutil::utf8(&key_file)?;
+
+        let comment = keys::key_comment(self.settings.comment_prefix.as_deref(), email);
+        run_ssh_keygen(key_type, &comment, &key_file, self.key_passphrase.as_deref().unwrap_or(""))?;
+        println!("Key file: {}", key_file_str);
+        Ok(key_file_str.to_string())
+    }
+
+    pub fn add_ssh_agent(&self, ssh_key_file: &str, keychain: Keychain) -> Result<()> {
+        let output = Command::new("ssh-add")
+        .args(keys::ssh_add_args(keychain))
+        .args([
+            ssh_key_file
+        ])
+        .output()
+        .map_err(|e| Error::new(e.kind(), format!("Failed to run ssh-add: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(Error::other(
+                format!("Failed to add SSH key to agent: {}, {}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// What goes between the markers of the account's include file.
+    pub fn include_file_body(&self, account: &GitAccount) -> Result<String> {
+        let codebase_dir = account.codebase_dir_path.to_string_lossy();
+        let template = templates::load(&self.home_dir, TemplateKind::GitconfigInclude)?;
+        let mut config_content = templates::render(&template, &account.template_vars(&codebase_dir))?;
+        // In the template's own section for the alias, when it has one.
+        if let Some((header, entries)) = account.url_rewrites() {
+            config_content = gitconfig::add_to_section(&config_content, &header, &entries);
+        }
+        if account.committer_name.is_some() || account.committer_email.is_some() {
+            config_content.push_str("[committer]\n");
+            for (key, value) in [("name", &account.committer_name), ("email", &account.committer_email)] {
+                if let Some(value) = value {
+                    config_content.push_str(&format!("    {} = {}\n", key, value));
+                }
+            }
+        }
+        if let Some(username) = &account.https_username {
+            config_content.push_str(&format!(
+                "[credential \"https://{}\"]\n    username = {}\n",
+                account.hostname(), username
+            ));
+        }
+        if let Some(hooks_path) = &account.hooks_path {
+            config_content.push_str(&format!("[core]\n    hooksPath = {}\n", hooks_path.display()));
+        }
+        if let Some(template_dir) = &account.template_dir {
+            config_content.push_str(&format!("[init]\n    templateDir = {}\n", template_dir.display()));
+        }
+        if account.lfs {
+            config_content.push_str(LFS_FILTER);
+        }
+        if let Some(url) = &account.http_proxy {
+            config_content.push_str(&format!("[http]\n    proxy = {}\n", url));
+        }
+        config_content.push_str(&account.client_tls.render(account.hostname()));
+        config_content.push_str(&account.sendemail.render());
+        config_content.push_str(&git_settings::render(&account.git_settings));
+        Ok(config_content)
+    }
+
+    /// Where the include file's content lives: the codebase dir, or the
+    /// export dir the codebase dir's `.gitconfig` links to.
+    pub fn include_file_path(&self, account: &GitAccount) -> PathBuf {
+        match &self.settings.gitconfig_export_dir {
+            Some(export_dir) => export_dir.join(format!("{}.gitconfig", account.name)),
+            None => account.codebase_dir_path.join(".gitconfig"),
+        }
+    }
+
+    pub fn setup_local_gitconfig(&self, account: &GitAccount) -> Result<()> {
+        let config_content = self.include_file_body(account)?;
+        let mut gitconfig_path = account.codebase_dir_path.join(".gitconfig");
+
+        if let Some(parent) = gitconfig_path.parent() {
+            fsutil::create_dir_all(parent)?;
+        }
+
+        if self.settings.gitconfig_export_dir.is_some() {
+            let exported = self.include_file_path(account);
+            link_exported_gitconfig(&gitconfig_path, &exported)?;
+            gitconfig_path = exported;
+        }
+
+        let (config_content, user_only) = self.user_section_edits(&account.name, &gitconfig_path, config_content)?;
+        if !user_only && !self.resolve_tamper(&account.name, &gitconfig_path, &config_content)? {
+            return Ok(());
+        }
+        fsutil::modify(&gitconfig_path, |existing| {
+            let merged = managed::merge(existing, &account.name, &config_content);
+            Ok((merged != existing).then_some(merged))
+        })
+    }
+
+    /// The global gitconfig with the account's includeIf region in place,
+    /// or `None` when `existing` already has it.
+    pub fn global_gitconfig_update(&self, account_name: &str, codebase_path_str: &str, global_gitconfig_path_str: &str, existing: &str) -> Option<String> {
+        // Entries other tools or the user added stay where they are; ours go
+        // in their own marked region.
+        let include_if = self.include_if_body(account_name, codebase_path_str, global_gitconfig_path_str);
+        let merged = managed::merge(existing, account_name, &include_if);
+        (merged != existing).then_some(merged)
+    }
+
+    /// The account's includeIf entries, its exclusions' after its own.
+    /// With `--home-relative-paths` the paths inside the home dir are
+    /// written with `~/`, which git expands in both places.
+    pub fn include_if_body(&self, account_name: &str, codebase_path_str: &str, global_gitconfig_path_str: &str) -> String {
+        let home = |path: &str| if self.settings.home_relative_paths { ssh_config::contract_tilde(path, &self.home_dir) } else { path.to_string() };
+        let conditions = match self.accounts.get(account_name) {
+            Some(account) => account.include_conditions(),
+            None => vec![format!("gitdir/i:{}", gitdir_pattern(codebase_path_str))],
+        };
+        let mut include_if = String::new();
+        for condition in conditions {
+            let condition = match condition.strip_prefix("gitdir/i:") {
+                Some(pattern) => format!("gitdir/i:{}", home(pattern)),
+                None => condition,
+            };
+            include_if.push_str(&format!("[includeIf \"{}\"]\n    path = {}\n", condition, home(global_gitconfig_path_str)));
+        }
+        if let Some(account) = self.accounts.get(account_name) {
+            if let (Some(dir), Some(path)) = (wsl::windows_path(&account.codebase_dir_path), account.windows_include_path()) {
+                include_if.push_str(&format!("[includeIf \"gitdir/i:{}\"]\n    path = {}\n", gitdir_pattern(&dir), path));
+            }
+            // After the account's own entries, so git applies them last.
+            for exclusion in &account.exclusions {
+                if let Some(other) = self.accounts.get(&exclusion.account) {
+                    include_if.push_str(&format!(
+                        "[includeIf \"gitdir/i:{}\"]\n    path = {}\n",
+                        home(&gitdir_pattern(&exclusion.dir.to_string_lossy())),
+                        home(&other.codebase_dir_path.join(".gitconfig").to_string_lossy())
+                    ));
+                }
+            }
+        }
+        include_if
+    }
+
+    /// Prints the includeIf entries for `account_name` for the user to add
+    /// to the global gitconfig, in `--no-global-edit` mode.
+    fn print_include_if_snippet(&self, account_name: &str, codebase_path_str: &str, global_gitconfig_path_str: &str) {
+        println!("Add this to {} yourself (--no-global-edit):", self.global_gitconfig_path().display());
+        print!("{}", self.include_if_body(account_name, codebase_path_str, global_gitconfig_path_str));
+    }
+
+    pub fn setup_global_gitconfig(&self, account_name: &str, codebase_path_str: &str, global_gitconfig_path_str: &str) -> Result<()> {
+        if self.settings.no_global_edit {
+            self.print_include_if_snippet(account_name, codebase_path_str, global_gitconfig_path_str);
+            return Ok(());
+        }
+        let global_gitconfig_path = self.home_dir.join(".gitconfig");
+
+        let include_if = self.include_if_body(account_name, codebase_path_str, global_gitconfig_path_str);
+        if !self.resolve_tamper(account_name, &global_gitconfig_path, &include_if)? {
+            return Ok(());
+        }
+        fsutil::modify(&global_gitconfig_path, |existing| {
+            Ok(self.global_gitconfig_update(account_name, codebase_path_str, global_gitconfig_path_str, existing))
+        })?;
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &global_gitconfig_path);
+        state.save(&self.home_dir)
+    }
+
+    /// Writes every enabled account's includeIf region again, after a
+    /// setting that changes how it's written.
+    pub fn rewrite_all_include_ifs(&self) -> Result<()> {
+        for account in self.sorted_accounts(false) {
+            self.rewrite_include_ifs(&account.name)?;
+        }
+        self.fix_include_if_order()
+    }
+
+    /// Writes the account's include file and the includeIf pointing at it,
+    /// recording each as a step. A failed include file still gets its
+    /// includeIf: git skips includes whose file is missing.
+    fn associate_account_with_dir(&mut self, account_name: &str, report: &mut SetupReport) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+
+        let codebase_path = &account.codebase_dir_path;
+
+        let gitconfig_path = codebase_path.join(".gitconfig");
+
+        let hint = match &self.settings.gitconfig_export_dir {
+            None => "rerun with --export-gitconfig-dir <dir> to keep the include file in a directory you can write".to_string(),
+            Some(dir) => format!("check the permissions on {} and {}", dir.display(), codebase_path.display()),
+        };
+        report.record("include file", step_status(self.setup_local_gitconfig(account), &hint));
+
+        let (cb_path, gc_path) = match (fsutil::utf8(codebase_path), fsutil::utf8(&gitconfig_path)) {
+            (Ok(cb_path), Ok(gc_path)) => (cb_path, gc_path),
+            (Err(e), _) | (_, Err(e)) => {
+                report.record("includeIf", StepStatus::Failed(e.to_string()));
+                return Ok(());
+            }
+        };
+        if self.settings.no_global_edit {
+            self.print_include_if_snippet(account_name, cb_path, gc_path);
+            report.record("includeIf", StepStatus::Skipped("--no-global-edit".to_string()));
+            return Ok(());
+        }
+        let hint = format!(
+            "add `[includeIf \"{}\"]` with `path = {}` to {} yourself",
+            account.include_conditions().join("\"]` and `[includeIf \""),
+            gc_path,
+            self.global_gitconfig_path().display()
+        );
+        report.record("includeIf", step_status(self.setup_global_gitconfig(account_name, cb_path, gc_path), &hint));
+
+        Ok(())
+    }
+
+    /// The file multigit's Host blocks live in.
+    pub fn ssh_config_path(&self) -> PathBuf {
+        if self.settings.managed_ssh_config {
+            store::managed_ssh_config_path(&self.home_dir)
+        } else {
+            self.home_dir.join(".ssh/config")
+        }
+    }
+
+    /// `~/.ssh/config` with the `Include` of the managed ssh config at the
+    /// top, where ssh honours it, or `None` when it's already there.
+    pub fn ssh_include_update(&self, existing: &str) -> Option<String> {
+        let managed = store::managed_ssh_config_path(&self.home_dir);
+        let include_value = match managed.strip_prefix(&self.home_dir) {
+            Ok(relative) => format!("~/{}", relative.display()),
+            Err(_) => managed.display().to_string(),
+        };
+
+        let already_included = existing.lines().any(|line| match ssh_config::directive(line) {
+            Some((keyword, value)) => keyword == "include" && value
+                .split_whitespace()
+                .any(|v| ssh_config::expand_tilde(v, &self.home_dir) == managed.to_string_lossy()),
+            None => false,
+        });
+        (!already_included).then(|| format!("Include {}\n\n{}", include_value, existing))
+    }
+
+    /// Makes sure `~/.ssh/config` includes the managed ssh config.
+    pub fn ensure_ssh_include(&self) -> Result<()> {
+        fsutil::modify(&self.home_dir.join(".ssh/config"), |existing| Ok(self.ssh_include_update(existing)))
+    }
+
+    fn setup_ssh_config(&self, name: &str, host: &str) -> Result<()> {
+        let ssh_config_path = self.ssh_config_path();
+        if self.settings.managed_ssh_config {
+            self.ensure_ssh_include()?;
+            if let Some(parent) = ssh_config_path.parent() {
+                fsutil::create_dir_all(parent)?;
+            }
+        }
+
+        fsutil::modify(&ssh_config_path, |existing_config| self.ssh_config_update(name, host, existing_config))
+    }
+
+    /// The ssh config with the account's Host block appended, or `None` when
+    /// a block for `host` is already there.
+    pub fn ssh_config_update(&self, name: &str, host: &str, existing_config: &str) -> Result<Option<String>> {
+        let account = self.accounts.get(name).ok_or_else(|| account_not_found(name))?;
+        let block = self.host_block(account)?;
+        let config_content = format!("\n{}", managed::wrap(name, &block));
+
+        // Only a block for exactly this alias counts; `Host github.com-workshop`
+        // doesn't cover `github.com-work`.
+        let lines: Vec<&str> = existing_config.lines().collect();
+        if ssh_config::find_host(&ssh_config::host_blocks(&lines), host).is_some() {
+            return Ok(None);
+        }
+        Ok(Some(format!("{}{}", existing_config, config_content)))
+    }
+
+    /// The account's Host block, without the markers.
+    pub fn host_block(&self, account: &GitAccount) -> Result<String> {
+        let codebase_dir = account.codebase_dir_path.to_string_lossy();
+        let kind = if account.minimal_ssh_block { TemplateKind::SshHostMinimal } else { TemplateKind::SshHost };
+        let template = templates::load(&self.home_dir, kind)?;
+        let mut block = templates::render(&template, &account.template_vars(&codebase_dir))?;
+        if account.keychain() == Keychain::Store && !account.minimal_ssh_block {
+            block.push_str("    AddKeysToAgent yes\n    UseKeychain yes\n");
+        }
+        if let Some(algorithms) = &account.pubkey_algorithms {
+            block.push_str(&format!("    PubkeyAcceptedAlgorithms {}\n", algorithms));
+        }
+        if let Some(certificate) = &account.certificate_file {
+            block.push_str(&format!("    CertificateFile {}\n", certificate.display()));
+        }
+        if let Some(persist) = &account.multiplexing {
+            block.push_str(&multiplex::host_block_lines(persist));
+        }
+        if let Some(jump) = &account.proxy_jump {
+            block.push_str(&format!("    ProxyJump {}\n", jump));
+        }
+        if let Some(path) = &account.known_hosts_file {
+            block.push_str(&format!("    UserKnownHostsFile {}\n", path.display()));
+        }
+        if let Some(provider) = &account.pkcs11_provider {
+            // The template's IdentityFile would name a key that isn't there.
+            block = block
+                .lines()
+                .filter(|line| !line.split_whitespace().next().is_some_and(|keyword| keyword.eq_ignore_ascii_case("IdentityFile")))
+                .map(|line| format!("{}\n", line))
+                .collect();
+            block.push_str(&format!("    PKCS11Provider {}\n", provider.display()));
+        }
+        if account.strict_host_key_checking {
+            block.push_str("    StrictHostKeyChecking yes\n");
+        }
+        Ok(block)
+    }
+
+    /// Checks setup's arguments before anything is written, returning the
+    /// keychain mode and the resolved hooks path.
+    pub fn validate_setup(&self, name: &str, codebase_dir: &str, host: &str, options: &SetupOptions) -> Result<(Keychain, Option<PathBuf>)> {
+        templates::validate(&self.home_dir)?;
+        let hostname = options.hostname.as_deref().unwrap_or(PROVIDER_HOST);
+        validate_hostname(hostname)?;
+        validate_alias(host, name, hostname)?;
+        if options.check_remote_host_key && hostname != PROVIDER_HOST {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("--check-remote-host-key only knows the published host keys of {}, not {}", PROVIDER_HOST, hostname),
+            ));
+        }
+        if let Some(algorithms) = &options.pubkey_algorithms {
+            keys::validate_pubkey_algorithms(algorithms)?;
+        }
+        if let Some(persist) = &options.multiplexing {
+            multiplex::validate(persist)?;
+        }
+        if let Some(url) = &options.http_proxy {
+            proxy::validate_url(url)?;
+        }
+        if let Some(jump) = &options.proxy_jump {
+            proxy::validate_jump(jump)?;
+        }
+        options.sendemail.validate(name)?;
+        options.client_tls.validate(name)?;
+        git_settings::validate(&options.git_settings)?;
+        if let Some(repo) = &options.canary_repo {
+            test_push::validate_repo(repo)?;
+        }
+        if let Some(path) = &options.known_hosts_file {
+            fsutil::utf8(path)?;
+        }
+        if let Some(provider) = &options.pkcs11_provider {
+            keys::check_pkcs11_provider(provider)?;
+        }
+        if let Some(certificate) = &options.certificate_file {
+            fsutil::utf8(certificate)?;
+        }
+        // git init quietly copies nothing from a directory that isn't there.
+        if let Some(dir) = &options.template_dir {
+            if !dir.is_dir() {
+                return Err(Error::new(ErrorKind::NotFound, format!("--template-dir {} is not a directory", dir.display())));
+            }
+            fsutil::utf8(dir)?;
+        }
+        match &options.existing_key {
+            Some(path) => {
+                keys::check_existing_key(path)?;
+                fsutil::utf8(path)?;
+                // A generated key is only signed after setup; an existing
+                // one's certificate has to be there already.
+                if let Some(certificate) = &options.certificate_file {
+                    keys::check_certificate(certificate, path)?;
+                }
+            }
+            None => {
+                let key_type = options.key_type.as_deref().unwrap_or(DEFAULT_KEY_TYPE);
+                keys::validate_key_type(key_type)?;
+                fsutil::utf8(&self.default_key_path(name, host, key_type)?)?;
+            }
+        }
+        // The codebase dir goes into the includeIf and the key path into
+        // the Host block.
+        fsutil::utf8(&self.home_dir.join(codebase_dir))?;
+        let keychain = options.keychain.unwrap_or_else(keys::default_keychain);
+        keys::validate_keychain(keychain)?;
+        if split_dir_glob(&self.home_dir, codebase_dir)?.is_some() && options.also_windows {
+            return Err(Error::new(ErrorKind::InvalidInput, "--also-windows doesn't work with a glob codebase dir"));
+        }
+        if options.scope == Scope::RemoteUrl && (options.also_windows || split_dir_glob(&self.home_dir, codebase_dir)?.is_some()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--scope remote-url matches remote URLs, not directories; it doesn't work with --also-windows or a glob codebase dir",
+            ));
+        }
+        if options.also_windows && wsl::windows_path(&self.home_dir.join(codebase_dir)).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--also-windows needs a codebase dir on a Windows drive, under /mnt/<drive>/",
+            ));
+        }
+        let hooks_path = options.hooks_path.as_deref().map(|path| validate_hooks_path(&self.home_dir, path)).transpose()?;
+        Ok((keychain, hooks_path))
+    }
+
+    pub fn setup_account(&mut self, name: &str, email: &str, codebase_dir: &str, host: &str, options: SetupOptions) -> Result<SetupReport> {
+        let mut options = options;
+        // Picked first: the type is part of the key's file name.
+        if options.key_type.as_deref() == Some(keys::AUTO_KEY_TYPE) && options.existing_key.is_none() {
+            let (key_type, reason) = keys::negotiate_key_type(self.offline, options.hostname.as_deref().unwrap_or(PROVIDER_HOST));
+            println!("Key type: {} ({})", key_type, reason);
+            options.key_type = Some(key_type.to_string());
+        }
+        if let (Some(key), None) = (&options.existing_key, &options.certificate_file) {
+            if let Some(certificate) = keys::colocated_certificate(key) {
+                println!("Certificate: {} (found next to the key)", certificate.display());
+                options.certificate_file = Some(certificate);
+            }
+        }
+        let (keychain, hooks_path) = self.validate_setup(name, codebase_dir, host, &options)?;
+        let glob = split_dir_glob(&self.home_dir, codebase_dir)?;
+        let base = glob.as_ref().map(|(base, _)| base.to_string_lossy().into_owned());
+        let resolved = self.resolve_host_conflict(name, &self.home_dir.join(base.as_deref().unwrap_or(codebase_dir)), host, &mut options)?;
+        if resolved != host {
+            self.validate_setup(name, codebase_dir, &resolved, &options)?;
+        }
+        let host = resolved.as_str();
+        let codebase_dir = base.as_deref().unwrap_or(codebase_dir);
+        let mut report = SetupReport::new(name);
+        let hostname = options.hostname.as_deref().unwrap_or(PROVIDER_HOST);
+        for email in std::iter::once(email).chain(options.committer_email.as_deref()) {
+            if let Some(warning) = self.check_email_domain(email, hostname)? {
+                report.warn(warning);
+            }
+        }
+
+        let ssh_key = match (&options.existing_key, &options.pkcs11_provider) {
+            (_, Some(provider)) => {
+                report.record("generate key", StepStatus::Skipped(format!("keys come from {}", provider.display())));
+                String::new()
+            }
+            (Some(path), None) => {
+                report.record("generate key", StepStatus::Skipped(format!("using existing key {}", path.display())));
+                path.to_string_lossy().into_owned()
+            }
+            (None, None) => {
+                let key_type = options.key_type.clone().unwrap_or_else(|| DEFAULT_KEY_TYPE.to_string());
+                #[cfg(feature = "json")]
+                events::emit("keygen_start", Some(name), json!({ "key_type": key_type }));
+                match self.generate_ssh_key(name, email, host, &key_type) {
+                    Ok(ssh_key) => {
+                        #[cfg(feature = "json")]
+                        events::emit("keygen_done", Some(name), json!({ "path": ssh_key }));
+                        report.record("generate key", StepStatus::Ok);
+                        ssh_key
+                    }
+                    // Nothing is written yet, so there's nothing to undo.
+                    Err(e) => {
+                        report.record("generate key", StepStatus::Failed(e.to_string()));
+                        return Ok(report);
+                    }
+                }
+            }
+        };
+
+        if self.no_agent {
+            report.record(AGENT_STEP, StepStatus::Skipped("--no-agent".to_string()));
+        } else if options.pkcs11_provider.is_some() {
+            report.record(AGENT_STEP, StepStatus::Skipped("ssh reads the PKCS#11 token itself".to_string()));
+        } else {
+            report.record(AGENT_STEP, step_status(self.add_ssh_agent(&ssh_key, keychain), "rerun with --no-agent to leave the key out of the agent"));
+        }
+
+        if let Some(hooks_path) = &hooks_path {
+            report.warn(format!(
+                "core.hooksPath {} replaces the .git/hooks of every repository under {}; hooks installed there stop running",
+                hooks_path.display(),
+                self.home_dir.join(codebase_dir).display()
+            ));
+        }
+        if let Some(template_dir) = &options.template_dir {
+            report.warn(format!(
+                "git reads init.templateDir before a new repository exists, when the include file doesn't apply yet; \
+                 clone-all passes it on, otherwise run git init or git clone with --template {}",
+                template_dir.display()
+            ));
+        }
+        if options.lfs && !platform::git_lfs_installed() {
+            report.warn("git-lfs isn't installed; LFS files stay pointers until it is".to_string());
+        }
+        if let Some(certificate) = options.certificate_file.as_ref().filter(|path| !path.exists()) {
+            report.warn(format!("certificate {} doesn't exist yet; ssh skips it until it does", certificate.display()));
+        }
+        let also_windows = options.also_windows;
+        let check_remote_host_key = options.check_remote_host_key;
+        let account = GitAccountBuilder::new(name, email, &ssh_key, self.home_dir.join(codebase_dir), host)
+            .no_agent(self.no_agent)
+            .options(options, keychain, hooks_path)
+            .dir_pattern(glob.map(|(_, pattern)| pattern))
+            .build()?;
+        if let Some(warning) = self.add_account(account) {
+            report.warn(warning);
+        }
+        let account = &self.accounts[name];
+        if let Some(warning) = keys::rsa_sha1_warning(&account.ssh_key, account.hostname(), account.pubkey_algorithms.as_deref()) {
+            report.warn(warning);
+        }
+        if self.accounts[name].scope == Scope::RemoteUrl {
+            match platform::git_version() {
+                Some(version) if version >= HASCONFIG_GIT => {}
+                version => report.warn(format!(
+                    "{} doesn't understand hasconfig:remote.*.url includeIfs, which need git {}.{} or later; {}'s identity only applies once git is upgraded",
+                    version.map(|(major, minor)| format!("git {}.{}", major, minor)).unwrap_or_else(|| "this git".to_string()),
+                    HASCONFIG_GIT.0,
+                    HASCONFIG_GIT.1,
+                    name
+                )),
+            }
+        }
+        let account = &self.accounts[name];
+        if account.committer_name.is_some() || account.committer_email.is_some() {
+            match platform::git_version() {
+                Some(version) if version >= COMMITTER_CONFIG_GIT => {}
+                version => report.warn(format!(
+                    "{} ignores committer.name and committer.email, which need git {}.{} or later; {}'s commits get the author as committer until git is upgraded",
+                    version.map(|(major, minor)| format!("git {}.{}", major, minor)).unwrap_or_else(|| "this git".to_string()),
+                    COMMITTER_CONFIG_GIT.0,
+                    COMMITTER_CONFIG_GIT.1,
+                    name
+                )),
+            }
+        }
+        if also_windows {
+            report.warn(format!(
+                "git for Windows reads its own global gitconfig; include {} from it. It also can't see the Linux-side ssh agent or keys, so it needs {} set up on the Windows side",
+                self.global_gitconfig_path().display(),
+                host
+            ));
+        }
+        for warning in self.nesting_warnings(name) {
+            report.warn(warning);
+        }
+        // Duplicate emails, overlapping dirs and the like are known by now;
+        // only the key and the agent have been touched.
+        if self.strict && !report.strict_problems().is_empty() {
+            let done: Vec<&str> = report.steps.iter().filter(|(_, status)| matches!(status, StepStatus::Ok)).map(|(step, _)| *step).collect();
+            let rollback = self.roll_back_setup(name, &done, report.succeeded("generate key"));
+            report.record("rollback", step_status(rollback, "remove what's left by hand"));
+            return Ok(report);
+        }
+        self.associate_account_with_dir(name, &mut report)?;
+        if let Some(order) = self.include_if_order()?.filter(|_| report.succeeded("includeIf")) {
+            self.fix_include_if_order()?;
+            // Placing them is what --include-placement asked for.
+            if order.current != order.expected {
+                report.warn("moved the includeIf entries so the most specific directory comes last".to_string());
+            }
+        }
+        let hint = if self.settings.managed_ssh_config {
+            format!("check the permissions on {} and ~/.ssh/config", self.ssh_config_path().display())
+        } else {
+            format!(
+                "rerun with --managed-ssh-config to keep Host blocks in {} (~/.ssh/config then only needs an Include line)",
+                store::managed_ssh_config_path(&self.home_dir).display()
+            )
+        };
+        if self.accounts[name].multiplexing.is_some() {
+            if let Err(e) = multiplex::ensure_socket_dir(&self.home_dir) {
+                report.warn(format!("connections won't be shared until {} exists: {}", multiplex::socket_dir(&self.home_dir).display(), e));
+            }
+        }
+        if let Some(path) = self.accounts[name].known_hosts_file.clone() {
+            match known_hosts::ensure_file(&path) {
+                Ok(created) => self.accounts.get_mut(name).expect("just added").known_hosts_created = created,
+                Err(e) => report.warn(format!("ssh can't record host keys for {} until {} exists: {}", name, path.display(), e)),
+            }
+            if self.accounts[name].strict_host_key_checking && !check_remote_host_key && !known_hosts::has_provider_keys(&path) {
+                report.warn(format!(
+                    "{} has no host keys for {}, so StrictHostKeyChecking refuses to connect; add them there or rerun with --check-remote-host-key",
+                    path.display(),
+                    self.accounts[name].hostname()
+                ));
+            }
+        }
+        report.record("ssh config", step_status(self.setup_ssh_config(name, host), &hint));
+        #[cfg(feature = "json")]
+        if report.succeeded("ssh config") {
+            events::emit("ssh_config_written", Some(name), json!({ "path": self.ssh_config_path(), "host": host }));
+        }
+        let login = self.check_login(name, &mut report);
+        report.record(LOGIN_STEP, login);
+
+        self.save()?;
+
+        let mut state = State::load(&self.home_dir)?;
+        let include_path = self.home_dir.join(codebase_dir).join(".gitconfig");
+        for (step, path) in [("ssh config", self.ssh_config_path()), ("includeIf", self.global_gitconfig_path()), ("include file", include_path)] {
+            if report.succeeded(step) {
+                state.record_write(name, &path);
+            }
+        }
+        if let Some(fingerprint) = keys::fingerprint_hash(&ssh_key) {
+            state.record_key(name, &ssh_key, &fingerprint);
+        }
+        if self.offline {
+            if check_remote_host_key {
+                state.defer(name, KNOWN_HOSTS_STEP);
+                report.record(KNOWN_HOSTS_STEP, StepStatus::Deferred("offline".to_string()));
+            }
+            state.defer(name, CONNECTIVITY_STEP);
+            report.record(CONNECTIVITY_STEP, StepStatus::Deferred("offline".to_string()));
+        } else {
+            state.deferred.retain(|step| step.account != name);
+            // Before the connectivity check, which would accept whatever
+            // key the host presents.
+            if check_remote_host_key {
+                report.record(KNOWN_HOSTS_STEP, known_hosts::host_keys_step(&known_hosts::account_file(&self.home_dir, &self.accounts[name])));
+            }
+            report.record(CONNECTIVITY_STEP, self.check_connectivity(name));
+        }
+        state.save(&self.home_dir)?;
+
+        let warned = self.strict && !report.strict_problems().is_empty();
+        if !report.config_written() || warned {
+            if self.no_rollback {
+                report.warn(format!(
+                    "--no-rollback: {}'s partial setup is left in place; fix the failed step and run multigit doctor --repair, or undo it with multigit remove {}",
+                    name, name
+                ));
+            } else {
+                // A key that was there before setup stays.
+                let generated = report.succeeded("generate key");
+                let done: Vec<&str> = report.steps.iter().filter(|(_, status)| matches!(status, StepStatus::Ok)).map(|(step, _)| *step).collect();
+                let rollback = self.roll_back_setup(name, &done, generated);
+                report.record("rollback", step_status(rollback, "remove what's left by hand"));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Asks the provider whose token the account has, from `api_token`. A login other than
+    /// the expected one fails the step; without one, the login found is
+    /// recorded and shown so the user can tell it's the right one. Not
+    /// being able to ask is only a warning.
+    fn check_login(&mut self, account_name: &str, report: &mut SetupReport) -> StepStatus {
+        if self.offline {
+            return StepStatus::Skipped("offline".to_string());
+        }
+        let hostname = self.accounts[account_name].hostname();
+        if hostname != PROVIDER_HOST {
+            return StepStatus::Skipped(format!("multigit can only ask {} about logins, not {}", PROVIDER_HOST, hostname));
+        }
+        let Some((token, _)) = self.api_token(Some(account_name)) else {
+            return StepStatus::Skipped(format!(
+                "no token; run multigit token set {}, set {} or log in with gh",
+                account_name,
+                github::account_token_var(account_name)
+            ));
+        };
+        let login = match github::current_login(&token) {
+            Ok(login) => login,
+            Err(e) => {
+                report.warn(format!("couldn't check which {} user the token belongs to: {}", PROVIDER_HOST, e));
+                return StepStatus::Skipped("provider API unavailable".to_string());
+            }
+        };
+        let account = self.accounts.get_mut(account_name).expect("setup added the account");
+        match &account.login {
+            Some(expected) if !expected.eq_ignore_ascii_case(&login) => StepStatus::Failed(format!(
+                "the token belongs to {}, not {}; set {} to {}'s token",
+                login,
+                expected,
+                github::account_token_var(account_name),
+                expected
+            )),
+            Some(_) => StepStatus::Ok,
+            None => {
+                report.warn(format!("the token belongs to {} user {}; if that's the wrong user, fix the token and pass --login", PROVIDER_HOST, login));
+                account.login = Some(login);
+                StepStatus::Ok
+            }
+        }
+    }
+
+    /// Runs `ssh -T` against the account's Host alias. The greeting names
+    /// the user the key belongs to, which has to be the account's login
+    /// when one is known.
+    pub fn check_connectivity(&self, account_name: &str) -> StepStatus {
+        let Some(account) = self.accounts.get(account_name) else {
+            return StepStatus::Failed(format!("Account '{}' not found", account_name));
+        };
+        // ssh reads the Host block from disk.
+        if let Err(e) = fsutil::flush() {
+            return StepStatus::Failed(e.to_string());
+        }
+
+        match connectivity::test_connection(&account.host) {
+            Connectivity::Authenticated(greeting) => {
+                println!("{}: {}", account.name, greeting);
+                match (connectivity::greeting_login(&greeting), &account.login) {
+                    (Some(actual), Some(expected)) if !actual.eq_ignore_ascii_case(expected) => StepStatus::Failed(format!(
+                        "the key authenticates as {}, not {}; {} is registered with the wrong user",
+                        actual, expected, account.key_display()
+                    )),
+                    _ => StepStatus::Ok,
+                }
+            }
+            Connectivity::Denied => match &account.pkcs11_provider {
+                Some(provider) => StepStatus::Failed(format!(
+                    "permission denied; add the token's public key (ssh-keygen -D {}) to your {} account",
+                    provider.display(),
+                    account.hostname()
+                )),
+                None => StepStatus::Failed(format!("permission denied; add {}.pub to your {} account", account.ssh_key, account.hostname())),
+            },
+            Connectivity::TimedOut => StepStatus::Failed("ssh timed out".to_string()),
+            Connectivity::Error(e) => StepStatus::Failed(e),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::DeferredStep;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn ssh_config_update_matches_the_alias_exactly() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.add_account(testutil::account(&home, "work"));
+        let existing = "Host github.com-workshop\n    HostName github.com\n";
+
+        let updated = manager.ssh_config_update("work", "github.com-work", existing).unwrap().expect("block appended");
+        assert!(updated.contains("Host github.com-work\n"));
+        assert!(updated.starts_with(existing));
+
+        assert!(manager.ssh_config_update("work", "github.com-work", &updated).unwrap().is_none());
+    }
+
+    #[test]
+    fn managed_ssh_config_leaves_only_an_include_in_the_main_config() {
+        let home = TempHome::new();
+        home.write(".ssh/config", "Host personal\n    HostName example.com\n");
+        let mut manager = home.manager();
+        manager.settings.managed_ssh_config = true;
+        testutil::set_up(&mut manager, &["work"]);
+
+        let main = home.read(".ssh/config");
+        assert_eq!(main, "Include ~/.config/multigit/ssh_config\n\nHost personal\n    HostName example.com\n");
+        assert!(fs::read_to_string(store::managed_ssh_config_path(&home.path)).unwrap().contains("Host github.com-work\n"));
+
+        manager.ensure_ssh_include().unwrap();
+        assert_eq!(home.read(".ssh/config"), main);
+    }
+
+    #[test]
+    fn offline_setup_defers_the_network_steps() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let report = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+
+        assert!(report.config_written());
+        assert!(report.succeeded("ssh config") && report.succeeded("includeIf"));
+        assert!(report.steps.iter().any(|(step, status)| *step == CONNECTIVITY_STEP && matches!(status, StepStatus::Deferred(_))));
+        let deferred = State::load(&home.path).unwrap().deferred;
+        assert_eq!(deferred, [DeferredStep { account: "work".to_string(), step: CONNECTIVITY_STEP.to_string() }]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exported_gitconfig_is_symlinked_from_the_codebase_dir() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.settings.gitconfig_export_dir = Some(home.join("dotfiles/git"));
+        testutil::set_up(&mut manager, &["work"]);
+
+        let link = home.join("code/work/.gitconfig");
+        let exported = home.join("dotfiles/git/work.gitconfig");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::canonicalize(&link).unwrap(), exported);
+        assert!(fs::read_to_string(&exported).unwrap().contains("email = work@example.com"));
+    }
+
+    #[test]
+    fn duplicate_email_warns_unless_allowed() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let same_email = |name: &str| GitAccount { email: "me@example.com".to_string(), ..testutil::account(&home, name) };
+        assert!(manager.add_account(same_email("work")).is_none());
+
+        let warning = manager.add_account(same_email("side")).expect("duplicate email warns");
+        assert!(warning.contains("'work' and 'side' both use me@example.com"));
+        assert!(manager.add_account(GitAccount { email: "ME@example.com".to_string(), ..testutil::account(&home, "other") }).is_some());
+
+        manager.allow_duplicate_email = true;
+        assert!(manager.add_account(same_email("third")).is_none());
+    }
+
+    #[test]
+    fn host_block_restricts_pubkey_algorithms_when_asked() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let options = SetupOptions { pubkey_algorithms: Some("ssh-ed25519,rsa-sha2-512".to_string()), ..SetupOptions::default() };
+        manager.setup_account("work", "me@example.com", "code/work", "github.com-work", options).unwrap();
+        assert!(home.read(".ssh/config").contains("    PubkeyAcceptedAlgorithms ssh-ed25519,rsa-sha2-512\n"));
+
+        testutil::set_up(&mut manager, &["home"]);
+        assert!(!manager.host_block(&manager.accounts["home"]).unwrap().contains("PubkeyAcceptedAlgorithms"));
+
+        let options = SetupOptions { pubkey_algorithms: Some("ssh-dss".to_string()), ..SetupOptions::default() };
+        let Err(error) = manager.setup_account("side", "me@side.example", "code/side", "github.com-side", options) else {
+            panic!("ssh-dss is accepted");
+        };
+        assert!(error.to_string().contains("Unknown pubkey algorithm 'ssh-dss'"));
+    }
+
+    #[test]
+    fn minimal_host_block_keeps_only_what_ssh_needs() {
+        let home = TempHome::new();
+        let manager = home.manager();
+        let full = manager.host_block(&testutil::account(&home, "work")).unwrap();
+        assert!(full.contains("    AddKeysToAgent yes\n    UseKeychain yes\n"));
+
+        let minimal = manager.host_block(&GitAccount { minimal_ssh_block: true, ..testutil::account(&home, "work") }).unwrap();
+        let key = home.join(".ssh/id_ed25519_work");
+        assert_eq!(
+            minimal,
+            format!("Host github.com-work\n    HostName github.com\n    User git\n    IdentityFile {}\n    IdentitiesOnly yes\n", key.display())
+        );
+    }
+
+    #[test]
+    fn existing_key_is_registered_without_keygen() {
+        let home = TempHome::new();
+        let key = home.join("keys/work");
+        fs::create_dir_all(home.join("keys")).unwrap();
+        run_ssh_keygen("ed25519", "me@example.com", &key, "").unwrap();
+        let private = fs::read(&key).unwrap();
+
+        let mut manager = home.manager();
+        let options = SetupOptions { existing_key: Some(key.clone()), ..SetupOptions::default() };
+        let report = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", options).unwrap();
+
+        assert!(report.steps.iter().any(|(step, status)| *step == "generate key" && matches!(status, StepStatus::Skipped(_))));
+        assert!(!home.join(".ssh/id_ed25519_work").exists());
+        assert_eq!(fs::read(&key).unwrap(), private);
+        assert_eq!(manager.accounts["work"].ssh_key, key.to_string_lossy());
+        assert!(home.read(".ssh/config").contains(&format!("    IdentityFile {}\n", key.display())));
+    }
+
+    #[test]
+    fn signed_key_gets_a_certificate_file_line() {
+        let home = TempHome::new();
+        fs::create_dir_all(home.join("keys")).unwrap();
+        let (key, ca, other) = (home.join("keys/work"), home.join("keys/ca"), home.join("keys/other"));
+        for path in [&key, &ca, &other] {
+            run_ssh_keygen("ed25519", "me@example.com", path, "").unwrap();
+        }
+        let signed = Command::new("ssh-keygen").arg("-q").arg("-s").arg(&ca).args(["-I", "work", "-n", "git"]).arg(key.with_extension("pub")).status().unwrap();
+        assert!(signed.success());
+        let certificate = home.join("keys/work-cert.pub");
+
+        let mut manager = home.manager();
+        let options = SetupOptions { existing_key: Some(key.clone()), ..SetupOptions::default() };
+        manager.setup_account("work", "me@example.com", "code/work", "github.com-work", options).unwrap();
+        let config = home.read(".ssh/config");
+        assert!(config.contains(&format!("    IdentityFile {}\n    CertificateFile {}\n", key.display(), certificate.display())), "{}", config);
+
+        let mismatched = SetupOptions { existing_key: Some(other), certificate_file: Some(certificate), ..SetupOptions::default() };
+        let Err(error) = manager.setup_account("home", "home@example.com", "code/home", "github.com-home", mismatched) else {
+            panic!("setup took a certificate issued for another key");
+        };
+        assert!(error.to_string().contains("issued for another key"), "{}", error);
+    }
+
+    #[test]
+    fn alias_equal_to_the_provider_hostname_is_rejected() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        for alias in ["github.com", "GitHub.com."] {
+            let Err(error) = manager.setup_account("work", "me@example.com", "code/work", alias, SetupOptions::default()) else {
+                panic!("--host {} is accepted", alias);
+            };
+            assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            assert!(error.to_string().contains("is the provider's real hostname; use an alias such as github.com-work"));
+        }
+        assert!(manager.accounts.is_empty());
+        assert!(!home.join(".ssh").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hooks_path_goes_in_the_include_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let home = TempHome::new();
+        let hook = home.write("hooks/pre-commit", "#!/bin/sh\n");
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut manager = home.manager();
+
+        let missing = SetupOptions { hooks_path: Some(PathBuf::from("no-hooks")), ..SetupOptions::default() };
+        let Err(error) = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", missing) else {
+            panic!("a missing hooks dir is accepted");
+        };
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let options = SetupOptions { hooks_path: Some(PathBuf::from("hooks")), ..SetupOptions::default() };
+        manager.setup_account("work", "me@example.com", "code/work", "github.com-work", options).unwrap();
+        assert!(home.read("code/work/.gitconfig").contains(&format!("[core]\n    hooksPath = {}\n", home.join("hooks").display())));
+    }
+
+    #[test]
+    fn url_rewrites_share_the_templates_url_section() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let include = home.read("code/work/.gitconfig");
+        assert_eq!(include.matches("[url ").count(), 1, "{}", include);
+        assert!(include.contains(
+            "[url \"git@github.com-work:\"]\n    insteadOf = git@github.com:\n    insteadOf = https://github.com/\n    insteadOf = git://github.com/\n[user]\n"
+        ), "{}", include);
+
+        let options = SetupOptions { https_username: Some("me".to_string()), ..SetupOptions::default() };
+        manager.setup_account("web", "web@example.com", "code/web", "github.com-web", options).unwrap();
+        let include = home.read("code/web/.gitconfig");
+        assert!(include.contains("[url \"git@github.com-web:\"]\n    insteadOf = git@github.com:\n[user]\n"), "{}", include);
+        assert!(include.contains("[url \"https://github.com/\"]\n    insteadOf = git://github.com/\n"), "{}", include);
+
+        // A template without the section gets one of its own.
+        home.write(".config/multigit/templates/gitconfig_include.tmpl", "[user]\n    email = {email}\n");
+        manager.setup_account("own", "own@example.com", "code/own", "github.com-own", SetupOptions::default()).unwrap();
+        let include = home.read("code/own/.gitconfig");
+        assert!(include.contains("[user]\n    email = own@example.com\n[url \"git@github.com-own:\"]\n    insteadOf = https://github.com/\n"), "{}", include);
+    }
+
+    #[test]
+    fn template_dir_goes_in_the_include_file() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+
+        let missing = SetupOptions { template_dir: Some(home.join("no-template")), ..SetupOptions::default() };
+        let Err(error) = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", missing) else {
+            panic!("a missing template dir is accepted");
+        };
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        fs::create_dir_all(home.join("template/hooks")).unwrap();
+        let options = SetupOptions { template_dir: Some(home.join("template")), ..SetupOptions::default() };
+        manager.setup_account("work", "me@example.com", "code/work", "github.com-work", options).unwrap();
+        assert!(home.read("code/work/.gitconfig").contains(&format!("[init]\n    templateDir = {}\n", home.join("template").display())));
+    }
+
+    #[test]
+    fn failed_include_if_is_named_in_the_report_and_rolled_back() {
+        let home = TempHome::new();
+        // A directory where its lock file goes keeps the global gitconfig
+        // from being written.
+        fs::create_dir_all(home.join(".gitconfig.multigit-lock")).unwrap();
+        let mut manager = home.manager();
+
+        let report = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+        assert_eq!(report.first_failure().map(|(step, _)| step), Some("includeIf"));
+        let summary = report.summary();
+        assert!(summary.starts_with("generate key ok, "), "{}", summary);
+        assert!(summary.contains(", includeIf FAILED: "), "{}", summary);
+        assert!(report.succeeded("rollback"));
+        assert!(!home.join(".ssh/id_ed25519_work").exists());
+
+        manager.no_rollback = true;
+        let report = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+        assert_eq!(report.first_failure().map(|(step, _)| step), Some("includeIf"));
+        assert!(!report.steps.iter().any(|(step, _)| *step == "rollback"));
+        assert!(home.join(".ssh/id_ed25519_work").exists());
+    }
+
+    #[test]
+    fn copied_account_inherits_the_key_type_and_ssh_options() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let options = SetupOptions {
+            key_type: Some("ecdsa".to_string()),
+            pubkey_algorithms: Some("ecdsa-sha2-nistp256".to_string()),
+            proxy_jump: Some("bastion.example.com".to_string()),
+            git_settings: [("fetch.prune".to_string(), "true".to_string())].into(),
+            ..SetupOptions::default()
+        };
+        manager.setup_account("work", "work@example.com", "code/work", "github.com-work", options).unwrap();
+
+        let mut options = SetupOptions::default();
+        options.inherit(&manager.accounts["work"]);
+        manager.setup_account("side", "side@example.com", "code/side", "github.com-side", options).unwrap();
+
+        let (work, side) = (&manager.accounts["work"], &manager.accounts["side"]);
+        assert_ne!(side.ssh_key, work.ssh_key);
+        assert_eq!(keys::key_strength(&side.ssh_key).map(|(_, key_type)| key_type).as_deref(), Some("ecdsa"));
+        assert_ne!(keys::fingerprint(&side.ssh_key), keys::fingerprint(&work.ssh_key));
+        assert_eq!(side.email, "side@example.com");
+        assert_eq!(side.pubkey_algorithms.as_deref(), Some("ecdsa-sha2-nistp256"));
+        assert_eq!(side.proxy_jump.as_deref(), Some("bastion.example.com"));
+        let config = home.read(".ssh/config");
+        let block = &config[config.find("Host github.com-side").unwrap()..];
+        assert!(block.contains("    PubkeyAcceptedAlgorithms ecdsa-sha2-nistp256\n") && block.contains("    ProxyJump bastion.example.com\n"), "{}", block);
+        let include = home.read("code/side/.gitconfig");
+        assert!(include.contains("email = side@example.com") && include.contains("[fetch]\n    prune = true\n"), "{}", include);
+    }
+
+    #[test]
+    fn remote_url_scope_writes_a_hasconfig_include_if() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let options = SetupOptions { scope: Scope::RemoteUrl, ..SetupOptions::default() };
+        manager.setup_account("work", "work@example.com", "code/work", "github.com-work", options).unwrap();
+
+        let gitconfig = home.read(".gitconfig");
+        assert!(gitconfig.contains("[includeIf \"hasconfig:remote.*.url:git@github.com-work:*/**\"]"), "{}", gitconfig);
+        assert!(gitconfig.contains("[includeIf \"hasconfig:remote.*.url:ssh://git@github.com-work/**\"]"), "{}", gitconfig);
+        assert!(!gitconfig.contains("gitdir"), "{}", gitconfig);
+    }
+
+    #[test]
+    fn no_global_edit_leaves_the_global_gitconfig_alone() {
+        let home = TempHome::new();
+        home.write(".gitconfig", "[user]\n    name = Me\n");
+        let mut manager = home.manager();
+        manager.settings.no_global_edit = true;
+        let report = manager.setup_account("work", "work@example.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+
+        assert_eq!(home.read(".gitconfig"), "[user]\n    name = Me\n");
+        assert!(report.steps.iter().any(|(step, status)| *step == "includeIf" && matches!(status, StepStatus::Skipped(_))));
+        assert!(home.read("code/work/.gitconfig").contains("email = work@example.com"));
+        let dir = home.join("code/work");
+        let snippet = manager.include_if_body("work", &dir.to_string_lossy(), &dir.join(".gitconfig").to_string_lossy());
+        assert!(snippet.contains(&format!("[includeIf \"gitdir/i:{}/\"]", dir.display())), "{}", snippet);
+        assert!(snippet.contains(&format!("path = {}/.gitconfig", dir.display())), "{}", snippet);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_home_fails_setup_without_panicking() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let home = TempHome::new();
+        let odd = home.join(OsStr::from_bytes(b"h\xffme"));
+        fs::create_dir_all(&odd).unwrap();
+        let mut manager = GitAccountManager::new(odd.clone());
+        manager.offline = true;
+        manager.no_agent = true;
+
+        let Err(error) = manager.setup_account("work", "work@example.com", "code/work", "github.com-work", SetupOptions::default()) else {
+            panic!("setup wrote a non-UTF-8 path into the config");
+        };
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains(&*odd.to_string_lossy()), "{}", error);
+        assert!(!odd.join(".ssh/config").exists());
+    }
+
+    #[test]
+    fn pkcs11_account_offers_the_provider_and_no_identity_file() {
+        let home = TempHome::new();
+        let provider = home.write("lib/opensc-pkcs11.so", "");
+        let mut manager = home.manager();
+        let options = SetupOptions { pkcs11_provider: Some(provider.clone()), ..SetupOptions::default() };
+        manager.setup_account("work", "work@example.com", "code/work", "github.com-work", options).unwrap();
+
+        let config = home.read(".ssh/config");
+        assert!(config.contains(&format!("    PKCS11Provider {}\n", provider.display())), "{}", config);
+        assert!(!config.contains("IdentityFile"), "{}", config);
+        assert!(!home.join(".ssh/id_ed25519_work").exists());
+
+        let missing = SetupOptions { pkcs11_provider: Some(home.join("lib/missing.so")), ..SetupOptions::default() };
+        let Err(error) = manager.setup_account("home", "home@example.com", "code/home", "github.com-home", missing) else {
+            panic!("setup accepted a missing PKCS#11 library");
+        };
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn created_at_is_stamped_and_survives_a_reload() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        testutil::set_up(&mut manager, &["work"]);
+        let created_at = manager.accounts["work"].created_at.unwrap();
+        assert!(created_at >= before && created_at <= before + 60);
+
+        let reloaded = GitAccountManager::load(home.path.clone()).unwrap();
+        assert_eq!(reloaded.accounts["work"].created_at, Some(created_at));
+        assert_eq!(reloaded.accounts["work"].created(), Some((created_at, true)));
+
+        let older = GitAccount { created_at: None, ..reloaded.accounts["work"].clone() };
+        let (modified, recorded) = older.created().unwrap();
+        assert!(!recorded && modified >= before);
+        assert_eq!(GitAccount { ssh_key: home.join("gone").display().to_string(), ..older }.created(), None);
+    }
+
+    #[test]
+    fn strict_stops_setup_with_an_overlapping_dir_before_writing() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let gitconfig = home.read(".gitconfig");
+
+        manager.strict = true;
+        let report = manager.setup_account("side", "side@example.com", "code/work/side", "github.com-side", SetupOptions::default()).unwrap();
+        assert_eq!(report.strict_problems().len(), 1);
+        assert!(report.strict_problems()[0].starts_with(&format!("{} is inside work's", home.join("code/work/side").display())), "{:?}", report.strict_problems());
+        assert!(!manager.accounts.contains_key("side"));
+        assert!(!home.join(".ssh/id_ed25519_side").exists());
+        assert_eq!(home.read(".gitconfig"), gitconfig);
+        assert!(!GitAccountManager::load(home.path.clone()).unwrap().accounts.contains_key("side"));
+    }
+
+    #[test]
+    fn builder_with_only_the_required_fields() {
+        let account = GitAccountBuilder::new("work", "me@example.com", "/keys/work", PathBuf::from("/code/work"), "github.com-work").build().unwrap();
+        assert_eq!(account.name, "work");
+        assert_eq!(account.hostname(), PROVIDER_HOST);
+        assert!(account.scope.is_dir());
+        assert!(account.keychain.is_none());
+        assert!(account.git_settings.is_empty());
+    }
+
+    #[test]
+    fn builder_with_every_option() {
+        let options = SetupOptions {
+            https_username: Some("me".to_string()),
+            pubkey_algorithms: Some("ssh-ed25519".to_string()),
+            minimal: true,
+            git_settings: BTreeMap::from([("commit.gpgsign".to_string(), "true".to_string())]),
+            lfs: true,
+            multiplexing: Some("10m".to_string()),
+            login: Some("me".to_string()),
+            scope: Scope::RemoteUrl,
+            committer_name: Some("Me".to_string()),
+            committer_email: Some("me@corp.example".to_string()),
+            previous_emails: vec!["old@example.com".to_string()],
+            strict_host_key_checking: true,
+            proxy_jump: Some("bastion".to_string()),
+            canary_repo: Some("me/canary".to_string()),
+            hostname: Some("git.example.com".to_string()),
+            ..SetupOptions::default()
+        };
+        let account = GitAccountBuilder::new("work", "me@example.com", "/keys/work", PathBuf::from("/code/work"), "git.example.com-work")
+            .no_agent(true)
+            .options(options, Keychain::Store, Some(PathBuf::from("/hooks")))
+            .build()
+            .unwrap();
+        assert!(account.no_agent && account.minimal_ssh_block && account.lfs && account.strict_host_key_checking);
+        assert_eq!(account.keychain, Some(Keychain::Store));
+        assert_eq!(account.hooks_path.as_deref(), Some(Path::new("/hooks")));
+        assert_eq!(account.git_settings["commit.gpgsign"], "true");
+        assert!(!account.scope.is_dir());
+        assert_eq!(account.hostname(), "git.example.com");
+        assert_eq!(account.proxy_jump.as_deref(), Some("bastion"));
+    }
+
+    #[test]
+    fn builder_rejects_incomplete_accounts() {
+        let build = |email: &str, key: &str, host: &str| GitAccountBuilder::new("work", email, key, PathBuf::from("/code/work"), host).build();
+        for result in [build("", "/keys/work", "github.com-work"), build("me@example.com", "", "github.com-work"), build("me@example.com", "/keys/work", "github.com")] {
+            match result {
+                Err(MultigitError::InvalidAccount { account, .. }) => assert_eq!(account, "work"),
+                other => panic!("expected InvalidAccount, got {:?}", other.map(|account| account.name)),
+            }
+        }
+        let glob = GitAccountBuilder::new("work", "me@example.com", "/keys/work", PathBuf::from("/code"), "github.com-work")
+            .options(SetupOptions { scope: Scope::RemoteUrl, ..SetupOptions::default() }, Keychain::Off, None)
+            .dir_pattern(Some("*".to_string()))
+            .build();
+        assert!(matches!(glob, Err(MultigitError::InvalidAccount { .. })));
+    }
+
+    #[test]
+    fn unknown_account_is_account_not_found() {
+        let home = TempHome::new();
+        let error = home.manager().ssh_config_update("ghost", "github.com-ghost", "").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        assert!(error.to_string().contains("ghost"));
+    }
+}
@@ -0,0 +1,193 @@
+//! `init-agent-service`: a user-level launchd agent on macOS, or systemd
+//! user service on Linux, that loads the managed keys into ssh-agent at
+//! login by running `multigit reload-agent --managed-only --yes`. With
+//! `--uninstall` the file is removed again. Only files carrying the marker
+//! below are replaced or removed.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+use crate::fsutil;
+use crate::platform;
+
+/// In every file this writes, so one the user made isn't touched.
+const MARKER: &str = "Written by multigit init-agent-service";
+
+const LAUNCHD_LABEL: &str = "com.multigit.agent";
+const SYSTEMD_UNIT: &str = "multigit-agent.service";
+
+#[derive(Clone, Copy, PartialEq)]
+enum ServiceManager {
+    Launchd,
+    Systemd,
+}
+
+fn service_manager() -> Option<ServiceManager> {
+    if platform::is_macos() {
+        Some(ServiceManager::Launchd)
+    } else if cfg!(target_os = "linux") {
+        Some(ServiceManager::Systemd)
+    } else {
+        None
+    }
+}
+
+fn systemd_user_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join(".config").join("systemd").join("user")
+}
+
+/// Where the service file goes, or `None` on a platform without one.
+pub fn unit_path(home_dir: &Path) -> Option<PathBuf> {
+    Some(match service_manager()? {
+        ServiceManager::Launchd => home_dir.join("Library").join("LaunchAgents").join(format!("{}.plist", LAUNCHD_LABEL)),
+        ServiceManager::Systemd => systemd_user_dir(home_dir).join(SYSTEMD_UNIT),
+    })
+}
+
+/// `value` as text in XML.
+fn xml_text(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// `value` as one word of an `ExecStart=` line, quoted when it has to be.
+/// systemd expands `%` specifiers and `$` variables even inside quotes.
+fn systemd_word(value: &str) -> String {
+    let value = value.replace('%', "%%").replace('$', "$$");
+    if value.chars().any(|c| c.is_whitespace() || "\"'\\;".contains(c)) {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value
+    }
+}
+
+/// The service file running `args` at login.
+fn unit(manager: ServiceManager, args: &[&str]) -> String {
+    match manager {
+        ServiceManager::Launchd => {
+            let arguments: String = args.iter().map(|arg| format!("        <string>{}</string>\n", xml_text(arg))).collect();
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+                 <!-- {marker}; remove with multigit init-agent-service --uninstall -->\n\
+                 <plist version=\"1.0\">\n\
+                 <dict>\n\
+                 \x20   <key>Label</key>\n\
+                 \x20   <string>{label}</string>\n\
+                 \x20   <key>ProgramArguments</key>\n\
+                 \x20   <array>\n\
+                 {arguments}\
+                 \x20   </array>\n\
+                 \x20   <key>RunAtLoad</key>\n\
+                 \x20   <true/>\n\
+                 </dict>\n\
+                 </plist>\n",
+                marker = MARKER,
+                label = LAUNCHD_LABEL,
+                arguments = arguments,
+            )
+        }
+        ServiceManager::Systemd => {
+            let command: Vec<String> = args.iter().map(|arg| systemd_word(arg)).collect();
+            format!(
+                "# {}; remove with multigit init-agent-service --uninstall\n\
+                 [Unit]\n\
+                 Description=Load multigit's ssh keys into ssh-agent\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={}\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=default.target\n",
+                MARKER,
+                command.join(" ")
+            )
+        }
+    }
+}
+
+/// Whether `path` is missing or a file this module wrote.
+fn ours(path: &Path) -> Result<bool> {
+    match fsutil::read_to_string(path) {
+        Ok(content) => Ok(content.contains(MARKER)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+impl GitAccountManager {
+    /// `init-agent-service`: writes the service file, or with `uninstall`
+    /// removes it, and says what to run next.
+    pub fn init_agent_service(&self, uninstall: bool) -> Result<bool> {
+        let (Some(manager), Some(path)) = (service_manager(), unit_path(&self.home_dir)) else {
+            return Err(Error::new(ErrorKind::Unsupported, "init-agent-service supports launchd on macOS and systemd on Linux"));
+        };
+        if !ours(&path)? {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("{} wasn't written by multigit; move it away first", path.display())));
+        }
+        if uninstall {
+            return self.uninstall_agent_service(manager, &path);
+        }
+
+        let binary = std::env::current_exe()?;
+        let binary = fs::canonicalize(&binary).unwrap_or(binary);
+        let args = [fsutil::utf8(&binary)?, "--home", fsutil::utf8(&self.home_dir)?, "reload-agent", "--managed-only", "--yes"];
+        fsutil::write_atomic(&path, unit(manager, &args).as_bytes())?;
+        println!("Wrote {}", path.display());
+        match manager {
+            ServiceManager::Launchd => {
+                println!("It runs at every login. To load it now:");
+                println!("  launchctl bootstrap gui/$(id -u) {}", path.display());
+            }
+            ServiceManager::Systemd => {
+                println!("Enable it with:");
+                println!("  systemctl --user daemon-reload");
+                println!("  systemctl --user enable --now {}", SYSTEMD_UNIT);
+                println!("It uses the agent in SSH_AUTH_SOCK of systemd's user environment; if that isn't set,");
+                println!("run `systemctl --user import-environment SSH_AUTH_SOCK` from your login script.");
+            }
+        }
+        Ok(true)
+    }
+
+    fn uninstall_agent_service(&self, manager: ServiceManager, path: &Path) -> Result<bool> {
+        if !path.exists() {
+            println!("No agent service installed at {}", path.display());
+            return Ok(true);
+        }
+        if manager == ServiceManager::Systemd {
+            // What `systemctl --user enable` linked, which would dangle.
+            let wanted = systemd_user_dir(&self.home_dir).join("default.target.wants").join(SYSTEMD_UNIT);
+            if wanted.is_symlink() {
+                fs::remove_file(&wanted).map_err(|e| fsutil::blocked(e, "remove", &wanted))?;
+            }
+        }
+        fs::remove_file(path).map_err(|e| fsutil::blocked(e, "remove", path))?;
+        println!("Removed {}", path.display());
+        match manager {
+            ServiceManager::Launchd => println!("If it's loaded, stop it with: launchctl bootout gui/$(id -u)/{}", LAUNCHD_LABEL),
+            ServiceManager::Systemd => println!("Then run: systemctl --user daemon-reload"),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_are_quoted_for_each_service_manager() {
+        let args = ["/opt/my tools/multigit", "--home", "/home/a&b", "reload-agent", "--managed-only", "--yes"];
+
+        let plist = unit(ServiceManager::Launchd, &args);
+        assert!(plist.contains("        <string>/opt/my tools/multigit</string>\n        <string>--home</string>\n        <string>/home/a&amp;b</string>\n"), "{}", plist);
+        assert!(plist.contains(&format!("<string>{}</string>", LAUNCHD_LABEL)) && plist.contains(MARKER));
+
+        let service = unit(ServiceManager::Systemd, &args);
+        assert!(service.contains("\nExecStart=\"/opt/my tools/multigit\" --home /home/a&b reload-agent --managed-only --yes\n"), "{}", service);
+        assert_eq!(systemd_word("100%$HOME"), "100%%$$HOME");
+    }
+}
@@ -0,0 +1,227 @@
+//! `backups`: the copies multigit sets aside before replacing a file that
+//! wasn't only its own, `<file>.multigit-bak` and `.multigit-bak.<n>` next
+//! to the file, and the `multigit-backup-<time>` directories older versions
+//! of `uninstall` left in the home dir. `--purge` deletes old ones, always keeping the
+//! latest backup of each file unless forced.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account::GitAccountManager;
+use crate::doctor::describe_duration;
+use crate::report::print_table;
+use crate::snapshot::format_time;
+
+const BACKUP_SUFFIX: &str = ".multigit-bak";
+const UNINSTALL_PREFIX: &str = "multigit-backup-";
+
+struct Backup {
+    path: PathBuf,
+    /// The file it's a backup of, or for uninstall backups the home dir.
+    of: PathBuf,
+    /// Order among the backups of the same file, higher is newer.
+    sequence: u64,
+    /// Seconds since the epoch.
+    at: u64,
+}
+
+/// `--purge` criteria.
+pub struct Retention {
+    /// Keep this many of the newest backups of each file.
+    pub keep: Option<usize>,
+    /// Delete backups older than this many days.
+    pub older_than: Option<u64>,
+    /// Allow deleting the newest backup of a file.
+    pub force: bool,
+}
+
+fn modified(path: &Path) -> u64 {
+    fs::symlink_metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs())
+}
+
+/// The backup `name` in `dir` is, if it is one.
+fn parse_backup(dir: &Path, name: &str) -> Option<Backup> {
+    let path = dir.join(name);
+    if let Some(at) = name.strip_prefix(UNINSTALL_PREFIX).and_then(|at| at.parse::<u64>().ok()) {
+        return Some(Backup { path, of: dir.to_path_buf(), sequence: at, at });
+    }
+    let (file, rest) = name.rsplit_once(BACKUP_SUFFIX)?;
+    // `.multigit-bak` is the first backup, `.multigit-bak.<n>` the later ones.
+    let sequence = match rest {
+        "" => 0,
+        rest => rest.strip_prefix('.')?.parse::<u64>().ok()?,
+    };
+    if file.is_empty() {
+        return None;
+    }
+    let at = modified(&path);
+    Some(Backup { path, of: dir.join(file), sequence, at })
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+impl GitAccountManager {
+    /// The directories multigit writes files into.
+    fn backup_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = vec![self.home_dir.clone(), self.home_dir.join(".ssh")];
+        dirs.extend(self.ssh_config_path().parent().map(Path::to_path_buf));
+        dirs.extend(self.global_gitconfig_path().parent().map(Path::to_path_buf));
+        dirs.extend(self.settings.gitconfig_export_dir.clone());
+        dirs.extend(self.sorted_accounts(true).iter().map(|account| account.codebase_dir_path.clone()));
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+
+    /// Every backup, grouped by the file it's of, newest first.
+    fn find_backups(&self) -> BTreeMap<PathBuf, Vec<Backup>> {
+        let mut groups: BTreeMap<PathBuf, Vec<Backup>> = BTreeMap::new();
+        for dir in self.backup_dirs() {
+            let Ok(entries) = fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let name = entry.file_name();
+                let is_uninstall = name.to_string_lossy().starts_with(UNINSTALL_PREFIX);
+                // Uninstall backups only ever go in the home dir.
+                if is_uninstall && dir != self.home_dir {
+                    continue;
+                }
+                if let Some(backup) = parse_backup(&dir, &name.to_string_lossy()) {
+                    groups.entry(backup.of.clone()).or_default().push(backup);
+                }
+            }
+        }
+        for backups in groups.values_mut() {
+            backups.sort_by_key(|backup| std::cmp::Reverse((backup.sequence, backup.at)));
+        }
+        groups
+    }
+
+    pub fn list_backups(&self) -> Result<bool> {
+        let groups = self.find_backups();
+        if groups.is_empty() {
+            println!("No backups.");
+            return Ok(true);
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let mut rows = vec![["BACKUP".to_string(), "OF".to_string(), "TAKEN (UTC)".to_string(), "AGE".to_string()]];
+        for (of, backups) in &groups {
+            let of = if *of == self.home_dir { "uninstall".to_string() } else { of.display().to_string() };
+            for backup in backups {
+                rows.push([backup.path.display().to_string(), of.clone(), format_time(backup.at), describe_duration(now.saturating_sub(backup.at))]);
+            }
+        }
+        print_table(&rows);
+        println!("{} backup(s) of {} file(s)", groups.values().map(Vec::len).sum::<usize>(), groups.len());
+        Ok(true)
+    }
+
+    /// Deletes the backups past `retention.keep` for their file, and with
+    /// `retention.older_than` only those older than that. The newest
+    /// backup of each file stays unless `retention.force`.
+    pub fn purge_backups(&self, retention: &Retention) -> Result<bool> {
+        if retention.keep.is_none() && retention.older_than.is_none() {
+            return Err(Error::new(ErrorKind::InvalidInput, "say which backups to delete with --keep, --older-than or both"));
+        }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let cutoff = retention.older_than.map(|days| now.saturating_sub(days * 86400));
+        let mut keep = retention.keep.unwrap_or(0);
+        if keep == 0 && !retention.force {
+            if retention.keep.is_some() {
+                println!("note: keeping the newest backup of each file; pass --force to delete it too");
+            }
+            keep = 1;
+        }
+
+        let (mut deleted, mut kept, mut failed) = (0, 0, 0);
+        for backups in self.find_backups().values() {
+            for (i, backup) in backups.iter().enumerate() {
+                let old_enough = cutoff.is_none_or(|cutoff| backup.at < cutoff);
+                if i < keep || !old_enough {
+                    kept += 1;
+                    continue;
+                }
+                match remove(&backup.path) {
+                    Ok(()) => {
+                        println!("Deleted {}", backup.path.display());
+                        deleted += 1;
+                    }
+                    Err(e) => {
+                        println!("error: couldn't delete {}: {}", backup.path.display(), e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        println!("{} deleted, {} kept", deleted, kept);
+        Ok(failed == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testutil::TempHome;
+
+    /// Four backups of the gitconfig, 30, 20 and 1 days old and new, and one
+    /// of the ssh config 60 days old.
+    fn seed(home: &TempHome) {
+        let now = SystemTime::now();
+        let backups = [
+            (".gitconfig.multigit-bak", 30),
+            (".gitconfig.multigit-bak.1", 20),
+            (".gitconfig.multigit-bak.2", 1),
+            (".gitconfig.multigit-bak.3", 0),
+            (".ssh/config.multigit-bak", 60),
+        ];
+        for (name, days) in backups {
+            let path = home.write(name, "");
+            File::options().write(true).open(path).unwrap().set_modified(now - Duration::from_secs(days * 86400)).unwrap();
+        }
+    }
+
+    fn left(home: &TempHome) -> Vec<&'static str> {
+        [".gitconfig.multigit-bak", ".gitconfig.multigit-bak.1", ".gitconfig.multigit-bak.2", ".gitconfig.multigit-bak.3", ".ssh/config.multigit-bak"]
+            .into_iter()
+            .filter(|name| home.join(name).exists())
+            .collect()
+    }
+
+    #[test]
+    fn keep_leaves_the_newest_of_each_file() {
+        let home = TempHome::new();
+        seed(&home);
+        assert!(home.manager().purge_backups(&Retention { keep: Some(2), older_than: None, force: false }).unwrap());
+        assert_eq!(left(&home), [".gitconfig.multigit-bak.2", ".gitconfig.multigit-bak.3", ".ssh/config.multigit-bak"]);
+    }
+
+    #[test]
+    fn older_than_spares_the_newest_backup_unless_forced() {
+        let home = TempHome::new();
+        seed(&home);
+        let manager = home.manager();
+        assert!(manager.purge_backups(&Retention { keep: None, older_than: Some(10), force: false }).unwrap());
+        assert_eq!(left(&home), [".gitconfig.multigit-bak.2", ".gitconfig.multigit-bak.3", ".ssh/config.multigit-bak"]);
+
+        assert!(manager.purge_backups(&Retention { keep: Some(0), older_than: Some(10), force: true }).unwrap());
+        assert_eq!(left(&home), [".gitconfig.multigit-bak.2", ".gitconfig.multigit-bak.3"]);
+        assert!(manager.purge_backups(&Retention { keep: None, older_than: None, force: true }).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Client certificates for git servers that want mutual TLS on https
+//! remotes (`setup --ssl-cert` and friends), written to the include file
+//! under `[http "<url>"]` so they are only offered to that server.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::ssh_config;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientTls {
+    /// The server the settings apply to, `https://<hostname>/` of the
+    /// account when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// `http.sslCert`: the client certificate, PEM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    /// `http.sslKey`: its private key, when not in the certificate file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// `http.sslCAInfo`: CA bundle to verify the server with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_info: Option<PathBuf>,
+}
+
+/// `path` from a flag made absolute, with a leading `~/` expanded, which
+/// the shell leaves alone in `--flag=~/path`.
+pub fn expand(path: &Path, home_dir: &Path) -> Result<PathBuf> {
+    std::path::absolute(ssh_config::expand_tilde(&path.to_string_lossy(), home_dir))
+}
+
+/// Whether other users can read or write the key, as `mode {:o}`.
+fn loose_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path).ok()?.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Some(mode);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    None
+}
+
+impl ClientTls {
+    pub fn is_empty(&self) -> bool {
+        *self == ClientTls::default()
+    }
+
+    fn url(&self, hostname: &str) -> String {
+        self.url.clone().unwrap_or_else(|| format!("https://{}/", hostname))
+    }
+
+    fn files(&self) -> [(&'static str, &Option<PathBuf>); 3] {
+        [("--ssl-cert", &self.cert), ("--ssl-key", &self.key), ("--ssl-ca-info", &self.ca_info)]
+    }
+
+    /// Checks the URL and that every file exists and can be named in git
+    /// config, and refuses a key other users can get at.
+    pub fn validate(&self, account_name: &str) -> Result<()> {
+        let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, format!("Account '{}': {}", account_name, message)));
+        if self.url.is_some() && self.cert.is_none() && self.ca_info.is_none() {
+            return invalid("--ssl-url needs --ssl-cert or --ssl-ca-info".to_string());
+        }
+        if let Some(url) = &self.url {
+            let host = url.strip_prefix("https://").map(|rest| rest.split('/').next().unwrap_or(""));
+            if host.is_none_or(str::is_empty) || url.contains(char::is_whitespace) || url.contains('"') {
+                return invalid(format!("--ssl-url {} is not an https:// URL", url));
+            }
+        }
+        for (flag, path) in self.files() {
+            let Some(path) = path else {
+                continue;
+            };
+            if !path.is_file() {
+                return invalid(format!("{} {} does not exist", flag, path.display()));
+            }
+            fsutil::utf8(path)?;
+        }
+        if let Some(mode) = self.key.as_deref().and_then(loose_mode) {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Account '{}': {} is accessible by other users (mode {:o}); run chmod 600 on it first",
+                    account_name,
+                    self.key.as_ref().expect("checked above").display(),
+                    mode
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The `[http "<url>"]` section, for an account on `hostname`.
+    pub fn render(&self, hostname: &str) -> String {
+        if self.cert.is_none() && self.key.is_none() && self.ca_info.is_none() {
+            return String::new();
+        }
+        let mut out = format!("[http \"{}\"]\n", self.url(hostname));
+        for (key, path) in [("sslCert", &self.cert), ("sslKey", &self.key), ("sslCAInfo", &self.ca_info)] {
+            if let Some(path) = path {
+                out.push_str(&format!("    {} = {}\n", key, path.display()));
+            }
+        }
+        out
+    }
+
+    /// For doctor: files that went missing are errors, a key other users
+    /// can read a warning. Each is `(is_error, message)`.
+    pub fn problems(&self) -> Vec<(bool, String)> {
+        let mut problems = Vec::new();
+        for (flag, path) in self.files() {
+            if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+                problems.push((true, format!("{} {} does not exist, so https remotes fail the TLS handshake", flag, path.display())));
+            }
+        }
+        if let Some(key) = &self.key {
+            if let Some(mode) = loose_mode(key) {
+                problems.push((false, format!("TLS client key {} is mode {:o}; other users can read it", key.display(), mode)));
+            }
+        }
+        problems
+    }
+}
@@ -0,0 +1,115 @@
+//! `clone-all`: cloning an organization's repositories into an account's
+//! codebase dir through its Host alias.
+
+use std::io::{Error, Result};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use serde::Deserialize;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::errors::account_not_found;
+use crate::github;
+use crate::report::StepStatus;
+use crate::ssh_config;
+
+#[derive(Deserialize)]
+struct Repo {
+    name: String,
+    #[serde(default)]
+    archived: bool,
+}
+
+fn clone_repo(account: &GitAccount, org: &str, name: &str) -> StepStatus {
+    let target = account.codebase_dir_path.join(name);
+    if target.exists() {
+        return StepStatus::Skipped("already present".to_string());
+    }
+
+    let url = format!("git@{}:{}/{}.git", account.host, org, name);
+    let mut command = Command::new("git");
+    command.arg("clone").arg("--quiet");
+    // The include file isn't in effect until the repository exists.
+    if let Some(template_dir) = &account.template_dir {
+        command.arg("--template").arg(template_dir);
+    }
+    let output = command
+        .arg(&url)
+        .arg(&target)
+        .stdin(Stdio::null())
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output();
+    match output {
+        Ok(output) if output.status.success() => StepStatus::Ok,
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("git clone failed");
+            StepStatus::Failed(reason.trim().to_string())
+        }
+        Err(e) => StepStatus::Failed(format!("Failed to run git: {}", e)),
+    }
+}
+
+impl GitAccountManager {
+    /// Clones the repositories of `org` that match `pattern` into the
+    /// account's codebase dir, `jobs` at a time, and prints one line per
+    /// repository. A failed clone doesn't stop the others.
+    pub fn clone_all(&self, account_name: &str, org: &str, pattern: Option<&str>, include_archived: bool, jobs: usize) -> Result<bool> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if self.offline {
+            return Err(Error::other("clone-all needs the network, and this run is offline"));
+        }
+
+        let token = self.api_token(Some(account_name)).map(|(token, _)| token);
+        let repos: Vec<Repo> = github::get_all(
+            &format!("/orgs/{}/repos?type=all", org),
+            token.as_deref(),
+            &format!("Listing the repositories of {}", org),
+        )?;
+        let mut names: Vec<String> = repos
+            .into_iter()
+            .filter(|repo| include_archived || !repo.archived)
+            .filter(|repo| pattern.is_none_or(|pattern| ssh_config::pattern_matches(pattern, &repo.name)))
+            .map(|repo| repo.name)
+            .collect();
+        names.sort();
+        if names.is_empty() {
+            println!("No repositories of {} to clone.", org);
+            return Ok(true);
+        }
+        std::fs::create_dir_all(&account.codebase_dir_path)?;
+        println!("Cloning {} repositories of {} into {}", names.len(), org, account.codebase_dir_path.display());
+
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<StepStatus>>> = Mutex::new((0..names.len()).map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..jobs.clamp(1, names.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(name) = names.get(i) else {
+                        break;
+                    };
+                    let status = clone_repo(account, org, name);
+                    results.lock().expect("no clone thread panics while holding the lock")[i] = Some(status);
+                });
+            }
+        });
+
+        let results = results.into_inner().expect("clone threads have finished");
+        let width = names.iter().map(String::len).max().unwrap_or(0);
+        let (mut cloned, mut skipped, mut failed) = (0, 0, 0);
+        for (name, status) in names.iter().zip(results) {
+            let status = status.expect("every repository is attempted");
+            match status {
+                StepStatus::Ok => cloned += 1,
+                StepStatus::Failed(_) => failed += 1,
+                _ => skipped += 1,
+            }
+            println!("  {:<width$}  {}", name, status, width = width);
+        }
+        println!("{} cloned, {} skipped, {} failed", cloned, skipped, failed);
+        Ok(failed == 0)
+    }
+}
@@ -0,0 +1,87 @@
+//! `completions`: a completion script for the shell's startup file that
+//! offers multigit's commands and, after a command that takes an account,
+//! the configured account names. The names can't be known when the script
+//! is printed, so it asks the hidden `__complete-accounts` each time.
+//! Loaded with `eval "$(multigit completions <shell>)"`, or for fish
+//! `multigit completions fish | source`.
+
+use std::path::Path;
+
+use clap::Command;
+
+use crate::shell_hook::Shell;
+use crate::store;
+
+const BASH: &str = r#"# multigit: complete commands and account names.
+__multigit_complete() {
+    local cur=${COMP_WORDS[COMP_CWORD]} i command=
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case ${COMP_WORDS[i]} in
+            -*) ;;
+            *) command=${COMP_WORDS[i]}; break ;;
+        esac
+    done
+    if [ -z "$command" ]; then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur"))
+    elif [ "$i" -eq $((COMP_CWORD - 1)) ]; then
+        case $command in
+            @ACCOUNT_COMMANDS@) COMPREPLY=($(compgen -W "$(command multigit __complete-accounts)" -- "$cur")) ;;
+        esac
+    fi
+}
+complete -F __multigit_complete multigit
+"#;
+
+const ZSH: &str = r#"# multigit: complete commands and account names.
+__multigit_complete() {
+    if (( CURRENT == 2 )); then
+        compadd -- @COMMANDS@
+    elif (( CURRENT == 3 )); then
+        case $words[2] in
+            (@ACCOUNT_COMMANDS@) compadd -- ${(f)"$(command multigit __complete-accounts)"} ;;
+        esac
+    fi
+}
+compdef __multigit_complete multigit
+"#;
+
+const FISH: &str = r#"# multigit: complete commands and account names.
+complete -c multigit -f
+complete -c multigit -n __fish_use_subcommand -a '@COMMANDS@'
+complete -c multigit -n '__fish_seen_subcommand_from @ACCOUNT_COMMANDS@' -a '(command multigit __complete-accounts)'
+"#;
+
+/// The script for `shell`, from the visible commands of `cli`. Commands
+/// whose first argument is an account, named `account` or `name`, complete
+/// account names.
+pub fn script(cli: &Command, shell: Shell) -> String {
+    let (mut commands, mut account_commands) = (Vec::new(), Vec::new());
+    for command in cli.get_subcommands().filter(|command| !command.is_hide_set()) {
+        let names: Vec<&str> = std::iter::once(command.get_name()).chain(command.get_visible_aliases()).collect();
+        if command.get_positionals().next().is_some_and(|arg| arg.get_id() == "account" || arg.get_id() == "name") {
+            account_commands.extend(names.iter().copied());
+        }
+        commands.extend(names);
+    }
+    let template = match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    };
+    let separator = if shell == Shell::Fish { " " } else { "|" };
+    template.replace("@COMMANDS@", &commands.join(" ")).replace("@ACCOUNT_COMMANDS@", &account_commands.join(separator))
+}
+
+/// `__complete-accounts`: the account names, one per line. Runs on every
+/// tab press, so it only reads the accounts file, and prints nothing at all
+/// when that fails.
+pub fn print_account_names(home_dir: &Path) {
+    let Ok((_, accounts)) = store::load_accounts(home_dir) else {
+        return;
+    };
+    let mut names: Vec<String> = accounts.into_iter().map(|account| account.name).collect();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+}
@@ -0,0 +1,113 @@
+//! `diff`: what multigit would write for each account now, next to what is
+//! on disk: the Host block, the includeIf entries and the include file.
+//! Only the account's own marked block is compared, so the rest of a large
+//! ssh config or gitconfig doesn't get in the way.
+
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::diff;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::tamper;
+
+impl GitAccountManager {
+    /// The files multigit writes for `account`, each with the body its
+    /// block should have.
+    fn expected_blocks(&self, account: &GitAccount) -> Result<Vec<(PathBuf, String)>> {
+        let mut expected = vec![(self.ssh_config_path(), self.host_block(account)?)];
+        if !self.settings.no_global_edit {
+            let include_path = account.codebase_dir_path.join(".gitconfig");
+            let body = self.include_if_body(&account.name, &account.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy());
+            expected.push((self.global_gitconfig_path(), body));
+        }
+        expected.push((self.include_file_path(account), self.include_file_body(account)?));
+        Ok(expected)
+    }
+
+    /// The blocks of `account` that aren't what multigit would write: the
+    /// file, whether the block differs or is missing, and the diff from the
+    /// block on disk to the expected one.
+    fn drifted_blocks(&self, account: &GitAccount) -> Result<Vec<(PathBuf, &'static str, String)>> {
+        let mut drifted = Vec::new();
+        for (path, expected) in self.expected_blocks(account)? {
+            let content = match fsutil::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(fsutil::blocked(e, "read", &path)),
+            };
+            let (current, state) = match tamper::block_body(&content, &account.name) {
+                Some(body) => (body, "differs"),
+                None => (String::new(), "is missing"),
+            };
+            if current.trim_end() != expected.trim_end() {
+                let diff = diff::unified(&current, &expected);
+                drifted.push((path, state, diff));
+            }
+        }
+        Ok(drifted)
+    }
+
+    /// Prints a diff for every block that isn't what multigit would write,
+    /// then which accounts drifted. Returns false when any did.
+    pub fn diff_config(&self, account_name: Option<&str>) -> Result<bool> {
+        let accounts: Vec<&GitAccount> = match account_name {
+            Some(name) => vec![self.accounts.get(name).ok_or_else(|| account_not_found(name))?],
+            None => self.sorted_accounts(true),
+        };
+
+        let mut drifted = Vec::new();
+        for account in accounts {
+            if account.disabled {
+                println!("skipped: {}: disabled", account.name);
+                continue;
+            }
+            let blocks = self.drifted_blocks(account)?;
+            if blocks.is_empty() {
+                continue;
+            }
+            println!("== {} ==", account.name);
+            for (path, state, diff) in blocks {
+                println!("{}'s block in {} {} (- on disk, + expected):", account.name, path.display(), state);
+                print!("{}", diff);
+            }
+            println!();
+            drifted.push(account.name.clone());
+        }
+
+        if drifted.is_empty() {
+            println!("No drift: every account's blocks are what multigit would write");
+            return Ok(true);
+        }
+        println!("Drifted: {}", drifted.join(", "));
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn hand_edit_is_reported_with_its_diff() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["home", "work"]);
+        assert!(manager.diff_config(None).unwrap());
+
+        let config = home.read(".ssh/config");
+        let (before, work) = config.split_once("Host github.com-work\n").unwrap();
+        home.write(".ssh/config", &format!("{}Host github.com-work\n{}", before, work.replacen("HostName github.com", "HostName ssh.github.com", 1)));
+
+        assert!(manager.drifted_blocks(&manager.accounts["home"]).unwrap().is_empty());
+        let drifted = manager.drifted_blocks(&manager.accounts["work"]).unwrap();
+        assert_eq!(drifted.len(), 1);
+        let (path, state, diff) = &drifted[0];
+        assert_eq!(*path, manager.ssh_config_path());
+        assert_eq!(*state, "differs");
+        assert!(diff.contains("+    HostName github.com\n-    HostName ssh.github.com\n"), "{}", diff);
+        assert!(!manager.diff_config(None).unwrap());
+        assert!(manager.diff_config(Some("home")).unwrap());
+    }
+}
@@ -0,0 +1,213 @@
+//! Spotting other identity-switching setups that fight with multigit: extra
+//! includeIf entries for an account's directory, a global core.sshCommand,
+//! GIT_SSH_COMMAND exported from shell startup files, and ssh Host blocks
+//! that hand their own keys to the account aliases.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::account::GitAccountManager;
+use crate::fsutil;
+use crate::gitconfig;
+use crate::ssh_config;
+
+/// Shell startup files that commonly export git variables.
+const SHELL_RC_FILES: &[&str] = &[
+    ".profile",
+    ".bashrc",
+    ".bash_profile",
+    ".zshenv",
+    ".zprofile",
+    ".zshrc",
+    ".config/fish/config.fish",
+];
+
+pub struct Conflict {
+    pub file: PathBuf,
+    /// 1-based line number, when the origin could be pinned down.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// One entry of `git config --global --show-origin --list`.
+struct GitEntry {
+    file: PathBuf,
+    key: String,
+    value: String,
+}
+
+fn global_git_entries() -> Option<Vec<GitEntry>> {
+    fsutil::flush().ok()?;
+    let output = Command::new("git").args(["config", "--global", "--show-origin", "--list"]).output().ok()?;
+    // Exits 1 when there is no global config at all.
+    if !output.status.success() {
+        return Some(Vec::new());
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (origin, entry) = line.split_once('\t')?;
+                let file = origin.strip_prefix("file:")?.trim_matches('"');
+                let (key, value) = entry.split_once('=').unwrap_or((entry, ""));
+                Some(GitEntry {
+                    file: PathBuf::from(file),
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+            })
+            .collect(),
+    )
+}
+
+/// Line of the first `key` entry in a section accepted by `in_section`.
+fn gitconfig_line(file: &Path, in_section: impl Fn(&str) -> bool, key: &str) -> Option<usize> {
+    let content = fsutil::read_to_string(file).ok()?;
+    let lines: Vec<&str> = content.lines().collect();
+    gitconfig::sections(&lines)
+        .iter()
+        .filter(|section| in_section(&section.header))
+        .find_map(|section| {
+            (section.start + 1..section.end).find(|&i| gitconfig::entry(lines[i]).is_some_and(|(k, _)| k == key))
+        })
+        .map(|i| i + 1)
+}
+
+/// The directory an includeIf `gitdir` condition applies to, if it is one.
+fn gitdir_condition(condition: &str, home_dir: &Path) -> Option<PathBuf> {
+    let dir = condition.strip_prefix("gitdir/i:").or_else(|| condition.strip_prefix("gitdir:"))?;
+    let dir = dir.trim_end_matches("**").trim_end_matches('/');
+    Some(PathBuf::from(ssh_config::expand_tilde(dir, home_dir)))
+}
+
+impl GitAccountManager {
+    fn git_config_conflicts(&self, conflicts: &mut Vec<Conflict>) {
+        let Some(entries) = global_git_entries() else {
+            return;
+        };
+        let accounts = self.sorted_accounts(false);
+
+        for entry in &entries {
+            let key = entry.key.to_ascii_lowercase();
+            if key == "core.sshcommand" {
+                conflicts.push(Conflict {
+                    line: gitconfig_line(&entry.file, |header| header.eq_ignore_ascii_case("core"), "sshcommand"),
+                    file: entry.file.clone(),
+                    message: format!("core.sshCommand = {} overrides the ssh Host aliases for every repository", entry.value),
+                });
+                continue;
+            }
+
+            if !key.starts_with("includeif.") || !key.ends_with(".path") {
+                continue;
+            }
+            // git lowercases the section and key but keeps the condition as written.
+            let condition = &entry.key["includeif.".len()..entry.key.len() - ".path".len()];
+            let Some(dir) = gitdir_condition(condition, &self.home_dir) else {
+                continue;
+            };
+            for account in &accounts {
+                let ours = account.codebase_dir_path.join(".gitconfig");
+                let same_dir = match &account.dir_pattern {
+                    Some(pattern) => condition.split_once(':').is_some_and(|(_, written)| ssh_config::expand_tilde(written, &self.home_dir) == *pattern),
+                    None => dir == account.codebase_dir_path,
+                };
+                if !same_dir || Path::new(&ssh_config::expand_tilde(&entry.value, &self.home_dir)) == ours {
+                    continue;
+                }
+                let header = format!("includeIf \"{}\"", condition);
+                conflicts.push(Conflict {
+                    line: gitconfig_line(&entry.file, |h| h.eq_ignore_ascii_case(&header), "path"),
+                    file: entry.file.clone(),
+                    message: format!(
+                        "includeIf for {}'s directory {} includes {} as well",
+                        account.name,
+                        account.dir_display(),
+                        entry.value
+                    ),
+                });
+            }
+        }
+    }
+
+    fn shell_rc_conflicts(&self, conflicts: &mut Vec<Conflict>) {
+        for rc in SHELL_RC_FILES {
+            let path = self.home_dir.join(rc);
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            for (i, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if !line.starts_with('#') && line.contains("GIT_SSH_COMMAND") {
+                    conflicts.push(Conflict {
+                        file: path.clone(),
+                        line: Some(i + 1),
+                        message: "sets GIT_SSH_COMMAND, which overrides the ssh Host aliases".to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn ssh_config_conflicts(&self, conflicts: &mut Vec<Conflict>) {
+        let mut files = vec![self.home_dir.join(".ssh").join("config")];
+        if self.settings.managed_ssh_config {
+            files.push(self.ssh_config_path());
+        }
+        let accounts = self.sorted_accounts(false);
+
+        for file in files {
+            let Ok(content) = fsutil::read_to_string(&file) else {
+                continue;
+            };
+            let lines: Vec<&str> = content.lines().collect();
+            let blocks = ssh_config::host_blocks(&lines);
+            for block in &blocks {
+                for account in &accounts {
+                    let own_block = file == self.ssh_config_path()
+                        && ssh_config::find_host(&blocks, &account.host).is_some_and(|own| own.start == block.start);
+                    if own_block || !block.matches(&account.host) {
+                        continue;
+                    }
+                    let Some((i, key)) = ssh_config::identity_files(&lines, block).into_iter().next() else {
+                        continue;
+                    };
+                    let message = if block.patterns.contains(&account.host) {
+                        format!("another Host block for {} offers {}", account.host, key)
+                    } else {
+                        format!(
+                            "Host {} also applies to {} and offers {}, which may log in as a different user",
+                            block.patterns.join(" "),
+                            account.host,
+                            key
+                        )
+                    };
+                    conflicts.push(Conflict {
+                        file: file.clone(),
+                        line: Some(i + 1),
+                        message,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Everything found that competes with multigit's own config. Nothing is
+    /// changed; these are for the user to sort out.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        self.git_config_conflicts(&mut conflicts);
+        self.shell_rc_conflicts(&mut conflicts);
+        self.ssh_config_conflicts(&mut conflicts);
+        if let Ok(command) = env::var("GIT_SSH_COMMAND") {
+            conflicts.push(Conflict {
+                file: PathBuf::from("(environment)"),
+                line: None,
+                message: format!("GIT_SSH_COMMAND={} overrides the ssh Host aliases", command),
+            });
+        }
+        conflicts
+    }
+}
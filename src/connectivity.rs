@@ -0,0 +1,185 @@
+use std::net::ToSocketAddrs;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::platform;
+
+pub enum Connectivity {
+    /// The server accepted the key; holds its greeting, e.g. "Hi octocat!...".
+    Authenticated(String),
+    Denied,
+    /// ssh hadn't finished by the deadline.
+    TimedOut,
+    Error(String),
+}
+
+/// Tries to authenticate against the Host alias with `ssh -T`, the way
+/// GitHub documents for checking a new key.
+pub fn test_connection(alias: &str) -> Connectivity {
+    let output = ssh_test_command(alias, &[]).output();
+
+    match output {
+        Ok(output) => classify(&output),
+        Err(e) => Connectivity::Error(format!("failed to run ssh: {}", e)),
+    }
+}
+
+/// `test_connection`, giving up on ssh after `timeout`. ssh is also told to
+/// stop connecting by then, so a host that doesn't answer is reported as
+/// such rather than as a kill.
+pub fn test_connection_within(alias: &str, timeout: Duration) -> Connectivity {
+    let connect_timeout = format!("ConnectTimeout={}", timeout.as_secs().max(1));
+    let mut command = ssh_test_command(alias, &["-o", &connect_timeout]);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Connectivity::Error(format!("failed to run ssh: {}", e)),
+    };
+    let pid = child.id();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(output)) => classify(&output),
+        Ok(Err(e)) => Connectivity::Error(format!("failed to run ssh: {}", e)),
+        Err(_) => {
+            // The waiting thread reaps it.
+            platform::kill(pid);
+            Connectivity::TimedOut
+        }
+    }
+}
+
+/// `ssh -T` against `git@<alias>`, with `options` ahead of the destination.
+fn ssh_test_command(alias: &str, options: &[&str]) -> Command {
+    let mut command = Command::new("ssh");
+    command
+        .args([
+            "-T",
+            "-o", "BatchMode=yes",
+            "-o", "StrictHostKeyChecking=accept-new",
+        ])
+        .args(options)
+        .arg(format!("git@{}", alias));
+    command
+}
+
+fn classify(output: &Output) -> Connectivity {
+    // Providers close the session with a non-zero status even on success, so
+    // the greeting is what tells us the key was accepted. Bitbucket says
+    // "authenticated via ssh key"; Azure DevOps only refuses the shell.
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let text = format!("{}{}", stdout, stderr);
+    let greeted = ["successfully authenticated", "Welcome to GitLab", "authenticated via ssh key", "Shell access is not supported"];
+    if greeted.iter().any(|greeting| text.contains(greeting)) {
+        let greeting = text.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+        Connectivity::Authenticated(greeting.trim().to_string())
+    } else if text.contains("Permission denied") {
+        Connectivity::Denied
+    } else {
+        let first_line = text.lines().find(|line| !line.trim().is_empty()).unwrap_or("no output from ssh");
+        Connectivity::Error(first_line.trim().to_string())
+    }
+}
+
+/// Why a key was turned down, from ssh's verbose log.
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    /// ssh and the server share no signature algorithm for the key's type,
+    /// so it was never offered; a key of another type would be.
+    Algorithm(String),
+    /// The key was offered and refused: it isn't on the account, or the
+    /// log didn't say otherwise.
+    Key,
+}
+
+/// Sorts `ssh -v` output by the messages OpenSSH logs when it can't use a
+/// key's type: no algorithm in common with `server-sig-algs`, or the type
+/// left out of the local `PubkeyAcceptedAlgorithms`.
+pub fn classify_rejection(log: &str) -> Rejection {
+    let markers = ["no mutual signature algorithm", "not in pubkeyacceptedalgorithms", "not in pubkeyacceptedkeytypes"];
+    log.lines()
+        .find(|line| {
+            let line = line.to_ascii_lowercase();
+            markers.iter().any(|marker| line.contains(marker))
+        })
+        .map_or(Rejection::Key, |line| Rejection::Algorithm(line.trim_start_matches("debug1: ").trim().to_string()))
+}
+
+/// Authenticates through `alias` again with `-v`, to tell why the key was
+/// denied.
+pub fn rejection(alias: &str, timeout: Duration) -> Rejection {
+    let connect_timeout = format!("ConnectTimeout={}", timeout.as_secs().max(1));
+    match ssh_test_command(alias, &["-v", "-o", &connect_timeout]).stdin(Stdio::null()).output() {
+        Ok(output) => classify_rejection(&String::from_utf8_lossy(&output.stderr)),
+        Err(_) => Rejection::Key,
+    }
+}
+
+/// The user a greeting such as "Hi octocat! You've successfully
+/// authenticated..." names. GitHub appends `/repo` for deploy keys.
+pub fn greeting_login(greeting: &str) -> Option<&str> {
+    let rest = greeting.strip_prefix("Hi ")?;
+    let (user, _) = rest.split_once('!')?;
+    Some(user.split('/').next().unwrap_or(user))
+}
+
+/// The signature algorithms `host` accepts for public keys, from the
+/// `server-sig-algs` it announces before authentication. No key is offered
+/// and known_hosts is left alone. `None` when ssh couldn't tell.
+pub fn server_sig_algs(host: &str) -> Option<Vec<String>> {
+    let output = Command::new("ssh")
+        .args([
+            "-v",
+            "-o", "BatchMode=yes",
+            "-o", "PreferredAuthentications=none",
+            "-o", "StrictHostKeyChecking=no",
+            "-o", "UserKnownHostsFile=/dev/null",
+            "-o", "ConnectTimeout=5",
+        ])
+        .arg(format!("git@{}", host))
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    parse_server_sig_algs(&String::from_utf8_lossy(&output.stderr))
+}
+
+/// The `server-sig-algs` in the stderr of `ssh -v`.
+pub fn parse_server_sig_algs(stderr: &str) -> Option<Vec<String>> {
+    let algs = stderr.lines().find_map(|line| line.split_once("server-sig-algs=").map(|(_, algs)| algs))?;
+    Some(algs.trim().trim_start_matches('<').trim_end_matches('>').split(',').map(str::to_string).collect())
+}
+
+/// Whether `host` resolves within `timeout`. Used to notice that we're offline
+/// before trying network steps that would each hang on their own.
+pub fn resolves(host: &str, timeout: Duration) -> bool {
+    any_resolves(&[host], timeout)
+}
+
+/// Whether any of `hosts` resolves within `timeout`, looking them all up
+/// at once.
+pub fn any_resolves(hosts: &[&str], timeout: Duration) -> bool {
+    let (tx, rx) = mpsc::channel();
+    for host in hosts {
+        let (tx, host) = (tx.clone(), host.to_string());
+        thread::spawn(move || {
+            let resolved = (host.as_str(), 22).to_socket_addrs().is_ok_and(|mut addrs| addrs.next().is_some());
+            let _ = tx.send(resolved);
+        });
+    }
+    drop(tx);
+    let deadline = Instant::now() + timeout;
+    while let Ok(resolved) = rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        if resolved {
+            return true;
+        }
+    }
+    false
+}
@@ -0,0 +1,118 @@
+//! Line diffs for previews, in the style of `diff -u` without hunk headers.
+
+/// Lines of context kept around each change.
+const CONTEXT: usize = 2;
+
+enum Op<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The longest-common-subsequence edit script from `before` to `after`.
+/// Config files are small enough for the quadratic table.
+fn edits<'a>(before: &[&'a str], after: &[&'a str]) -> Vec<Op<'a>> {
+    let (n, m) = (before.len(), after.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if before[i] == after[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            ops.push(Op::Same(before[i]));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            ops.push(Op::Added(after[j]));
+            j += 1;
+        } else {
+            ops.push(Op::Removed(before[i]));
+            i += 1;
+        }
+    }
+    ops
+}
+
+/// The changed lines prefixed with `-` and `+`, with a little unchanged
+/// context prefixed with a space and `...` where unchanged lines are left
+/// out. Empty when nothing changed.
+pub fn unified(before: &str, after: &str) -> String {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    let ops = edits(&before, &after);
+
+    let changed: Vec<usize> = ops.iter().enumerate().filter(|(_, op)| !matches!(op, Op::Same(_))).map(|(i, _)| i).collect();
+    let near_change = |i: usize| changed.iter().any(|&c| c.abs_diff(i) <= CONTEXT);
+
+    let mut out = String::new();
+    let mut skipped = false;
+    for (i, op) in ops.iter().enumerate() {
+        if !near_change(i) {
+            skipped = true;
+            continue;
+        }
+        if skipped && !out.is_empty() {
+            out.push_str("...\n");
+        }
+        skipped = false;
+        let (prefix, line) = match op {
+            Op::Same(line) => (' ', line),
+            Op::Removed(line) => ('-', line),
+            Op::Added(line) => ('+', line),
+        };
+        out.push(prefix);
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Moves the pending removed and added lines into `rows`, side by side.
+fn pair_up<'a>(rows: &mut Vec<(&'a str, char, &'a str)>, removed: &mut Vec<&'a str>, added: &mut Vec<&'a str>) {
+    for k in 0..removed.len().max(added.len()) {
+        let row = match (removed.get(k), added.get(k)) {
+            (Some(l), Some(r)) => (*l, '|', *r),
+            (Some(l), None) => (*l, '<', ""),
+            (None, r) => ("", '>', r.copied().unwrap_or("")),
+        };
+        rows.push(row);
+    }
+    removed.clear();
+    added.clear();
+}
+
+/// `left` and `right` in two columns `width` characters wide, lined up on
+/// their common lines. Lines that differ have `|` between them, and lines
+/// only one side has `<` or `>`.
+pub fn side_by_side(left: &str, right: &str, width: usize) -> String {
+    let before: Vec<&str> = left.lines().collect();
+    let after: Vec<&str> = right.lines().collect();
+
+    let mut rows = Vec::new();
+    let (mut removed, mut added): (Vec<&str>, Vec<&str>) = (Vec::new(), Vec::new());
+    for op in edits(&before, &after) {
+        match op {
+            Op::Same(line) => {
+                pair_up(&mut rows, &mut removed, &mut added);
+                rows.push((line, ' ', line));
+            }
+            Op::Removed(line) => removed.push(line),
+            Op::Added(line) => added.push(line),
+        }
+    }
+    pair_up(&mut rows, &mut removed, &mut added);
+
+    let mut out = String::new();
+    for (l, marker, r) in rows {
+        let l: String = l.chars().take(width).collect();
+        let line = format!("{:<width$} {} {}", l, marker, r, width = width);
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,148 @@
+//! Deactivating an account without deleting it. The account's blocks in the
+//! live ssh config and global gitconfig are commented out with a marker that
+//! names the account, so enabling can restore exactly those lines.
+
+use std::io::{Error, Result};
+use std::process::Command;
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::gitconfig;
+use crate::multiplex;
+use crate::ssh_config;
+use crate::state::State;
+
+fn marker(account_name: &str) -> String {
+    format!("#multigit-disabled:{}# ", account_name)
+}
+
+/// For a line commented out by `disable`, the account it belongs to and the
+/// original line.
+pub fn disabled_line(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("#multigit-disabled:")?;
+    let (account, original) = rest.split_once("# ")?;
+    Some((account, original))
+}
+
+/// Comments out the given line ranges, leaving trailing blank lines alone.
+fn comment_out(lines: &[&str], ranges: &[(usize, usize)], marker: &str) -> String {
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        let inside = ranges.iter().any(|&(start, end)| {
+            let last = (start..end).rev().find(|&j| !lines[j].trim().is_empty()).unwrap_or(start);
+            i >= start && i <= last
+        });
+        if inside {
+            out.push_str(marker);
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+fn uncomment(content: &str, marker: &str) -> String {
+    let mut out = String::new();
+    for line in content.lines() {
+        out.push_str(line.strip_prefix(marker).unwrap_or(line));
+        out.push('\n');
+    }
+    out
+}
+
+impl GitAccountManager {
+    pub fn disable_account(&mut self, account_name: &str) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            return Err(Error::other(format!("Account '{}' is already disabled", account_name)));
+        }
+        let marker = marker(account_name);
+
+        let ssh_config_path = self.ssh_config_path();
+        fsutil::modify(&ssh_config_path, |content| {
+            let lines: Vec<&str> = content.lines().collect();
+            let blocks = ssh_config::host_blocks(&lines);
+            let Some(block) = ssh_config::find_host(&blocks, &account.host) else {
+                return Ok(None);
+            };
+            println!("Commented out Host {} in {}", account.host, ssh_config_path.display());
+            Ok(Some(comment_out(&lines, &[(block.start, block.end)], &marker)))
+        })?;
+
+        let global_gitconfig_path = self.global_gitconfig_path();
+        if self.settings.no_global_edit {
+            println!("Left {} alone (--no-global-edit); comment out the includeIf for {} yourself", global_gitconfig_path.display(), account.dir_display());
+        }
+        fsutil::modify(&global_gitconfig_path, |content| {
+            if self.settings.no_global_edit {
+                return Ok(None);
+            }
+            let lines: Vec<&str> = content.lines().collect();
+            let sections = gitconfig::sections(&lines);
+            let ranges: Vec<(usize, usize)> = account
+                .include_paths()
+                .iter()
+                .flat_map(|path| gitconfig::include_ifs_for(&lines, &sections, path, &self.home_dir))
+                .map(|section| (section.start, section.end))
+                .collect();
+            if ranges.is_empty() {
+                return Ok(None);
+            }
+            println!("Commented out the includeIf for {} in {}", account.dir_display(), global_gitconfig_path.display());
+            Ok(Some(comment_out(&lines, &ranges, &marker)))
+        })?;
+
+        if !self.skips_agent(account) {
+            for key in account.identity_files() {
+                // Not being loaded in the first place is fine.
+                let _ = Command::new("ssh-add").args(["-d", key]).output();
+            }
+        }
+
+        if multiplex::remove_sockets(&self.home_dir, &account.host) > 0 {
+            println!("Closed the shared connections of {}", account.host);
+        }
+
+        self.record_writes(account_name)?;
+        self.accounts.get_mut(account_name).expect("account looked up above").disabled = true;
+        self.save()
+    }
+
+    pub fn enable_account(&mut self, account_name: &str) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if !account.disabled {
+            return Err(Error::other(format!("Account '{}' is not disabled", account_name)));
+        }
+        let marker = marker(account_name);
+
+        for path in [self.ssh_config_path(), self.global_gitconfig_path()] {
+            fsutil::modify(&path, |content| {
+                if !content.contains(&marker) {
+                    return Ok(None);
+                }
+                println!("Restored {}'s entries in {}", account_name, path.display());
+                Ok(Some(uncomment(content, &marker)))
+            })?;
+        }
+
+        if !self.skips_agent(account) {
+            for key in account.identity_files() {
+                if let Err(e) = self.add_ssh_agent(key, account.keychain()) {
+                    eprintln!("warning: {}", e);
+                }
+            }
+        }
+
+        self.record_writes(account_name)?;
+        self.accounts.get_mut(account_name).expect("account looked up above").disabled = false;
+        self.save()
+    }
+
+    fn record_writes(&self, account_name: &str) -> Result<()> {
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &self.ssh_config_path());
+        state.record_write(account_name, &self.global_gitconfig_path());
+        state.save(&self.home_dir)
+    }
+}
@@ -0,0 +1,212 @@
+//! Finding git repositories under a directory tree.
+
+use std::fs;
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::gitconfig;
+use crate::platform;
+
+/// Directories that never contain repositories worth reporting and can be
+/// enormous.
+const SKIP_DIRS: &[&str] = &["node_modules", "target", ".cache", "Library"];
+
+pub struct Repo {
+    pub path: PathBuf,
+    pub origin: Option<String>,
+}
+
+/// Reads `remote.origin.url` straight from the repo's config file, which is
+/// much cheaper than spawning git for every repository in a large tree.
+pub fn origin_url(repo: &Path) -> Option<String> {
+    let content = fs::read_to_string(repo.join(".git").join("config")).ok()?;
+    let lines: Vec<&str> = content.lines().collect();
+    gitconfig::sections(&lines)
+        .iter()
+        .filter(|section| section.header == "remote \"origin\"")
+        .find_map(|section| gitconfig::values(&lines, section, "url").first().map(|url| url.to_string()))
+}
+
+/// A remote URL in a repository's config.
+pub struct RemoteUrl {
+    pub remote: String,
+    /// `url` or `pushurl`.
+    pub key: String,
+    pub url: String,
+}
+
+/// Every `url` and `pushurl` of every remote in the repository's config.
+pub fn remote_urls(repo: &Path) -> Vec<RemoteUrl> {
+    let Ok(content) = fs::read_to_string(repo.join(".git").join("config")) else {
+        return Vec::new();
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let mut urls = Vec::new();
+    for section in gitconfig::sections(&lines) {
+        let Some(remote) = section.header.strip_prefix("remote ").map(|name| name.trim().trim_matches('"')) else {
+            continue;
+        };
+        for key in ["url", "pushurl"] {
+            for url in gitconfig::values(&lines, &section, key) {
+                urls.push(RemoteUrl {
+                    remote: remote.to_string(),
+                    key: key.to_string(),
+                    url: url.to_string(),
+                });
+            }
+        }
+    }
+    urls
+}
+
+/// `text` as a regex matching only itself, for `git config`'s value
+/// pattern.
+fn regex_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    format!("^{}$", escaped)
+}
+
+/// Rewrites one remote URL in the repository's config file, leaving the
+/// remote's other URLs alone. Goes through the file rather than
+/// `git remote set-url`, which matches the old URL only after applying
+/// insteadOf rewrites such as the ones multigit writes.
+pub fn set_remote_url(repo: &Path, remote: &RemoteUrl, new_url: &str) -> Result<()> {
+    let output = Command::new("git")
+        .arg("config")
+        .arg("--file")
+        .arg(repo.join(".git").join("config"))
+        .arg("--replace-all")
+        .arg(format!("remote.{}.{}", remote.remote, remote.key))
+        .arg(new_url)
+        .arg(regex_escape(&remote.url))
+        .output()
+        .map_err(|e| Error::new(e.kind(), format!("Failed to run git: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "Rewriting remote {} in {} failed: {}",
+            remote.remote,
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Why a walk stopped before the end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    TimedOut,
+    Interrupted,
+}
+
+impl Stop {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Stop::TimedOut => "timed out",
+            Stop::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// When a walk should give up: past a deadline, or once Ctrl-C was pressed
+/// under a `platform::InterruptGuard`.
+#[derive(Clone, Copy, Default)]
+pub struct Cancel {
+    deadline: Option<Instant>,
+}
+
+impl Cancel {
+    pub fn after(timeout: Option<Duration>) -> Self {
+        Cancel { deadline: timeout.map(|timeout| Instant::now() + timeout) }
+    }
+
+    pub fn check(&self) -> Option<Stop> {
+        if platform::interrupted() {
+            Some(Stop::Interrupted)
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Some(Stop::TimedOut)
+        } else {
+            None
+        }
+    }
+}
+
+/// Walks `root` depth-first, calling `on_repo` for every repository as soon
+/// as it is found. Doesn't descend into repositories or follow symlinks.
+pub fn walk(root: &Path, on_repo: &mut dyn FnMut(Repo)) {
+    let _ = walk_until(root, Cancel::default(), on_repo);
+}
+
+/// `walk`, checking `cancel` before each directory. The repositories found
+/// until it says stop have all been passed to `on_repo`.
+pub fn walk_until(root: &Path, cancel: Cancel, on_repo: &mut dyn FnMut(Repo)) -> std::result::Result<(), Stop> {
+    if let Some(stop) = cancel.check() {
+        return Err(stop);
+    }
+    if root.join(".git").exists() {
+        on_repo(Repo {
+            path: root.to_path_buf(),
+            origin: origin_url(root),
+        });
+        return Ok(());
+    }
+
+    let Ok(entries) = fs::read_dir(root) else {
+        return Ok(());
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && !SKIP_DIRS.contains(&name.as_ref())
+        })
+        .map(|entry| entry.path())
+        .collect();
+    dirs.sort();
+
+    for dir in dirs {
+        walk_until(&dir, cancel, on_repo)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn timed_out_walk_keeps_what_it_found() {
+        let home = TempHome::new();
+        for repo in ["a/one", "b/two", "c/three"] {
+            fs::create_dir_all(home.join(repo).join(".git")).unwrap();
+        }
+
+        let mut found = Vec::new();
+        let walked = walk_until(&home.path, Cancel::after(Some(Duration::from_millis(50))), &mut |repo| {
+            found.push(repo.path);
+            thread::sleep(Duration::from_millis(100));
+        });
+        assert!(matches!(walked, Err(Stop::TimedOut)));
+        assert_eq!(found, [home.join("a/one")]);
+
+        let walked = walk_until(&home.path, Cancel::after(Some(Duration::ZERO)), &mut |_| panic!("walked past the deadline"));
+        assert!(matches!(walked, Err(Stop::TimedOut)));
+
+        let mut all = 0;
+        assert!(walk_until(&home.path, Cancel::after(None), &mut |_| all += 1).is_ok());
+        assert_eq!(all, 3);
+    }
+}
@@ -0,0 +1,752 @@
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::account::{self, GitAccountManager, CONNECTIVITY_STEP, PROVIDER_HOST};
+use crate::fsutil;
+use crate::gitconfig;
+use crate::include_if::IncludeOrder;
+use crate::keys::{self, Keychain};
+use crate::known_hosts::{self, KNOWN_HOSTS_STEP};
+use crate::multiplex;
+use crate::ownership;
+use crate::platform;
+use crate::report::StepStatus;
+use crate::ssh_config;
+use crate::state::State;
+use crate::tamper;
+use crate::wsl;
+
+/// How long `doctor --network` waits for each SMTP server.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An account whose ssh Host block no longer points at the account's key.
+pub struct IdentityDrift {
+    pub account: String,
+    pub host: String,
+    pub found: Vec<String>,
+    pub expected: Vec<String>,
+}
+
+/// An account whose include file no longer sends remotes through the
+/// account's Host alias.
+pub struct RewriteDrift {
+    pub account: String,
+    pub path: PathBuf,
+    pub found: Vec<String>,
+    pub expected: Vec<String>,
+}
+
+/// The Host aliases a gitconfig's `url."git@<alias>:".insteadOf` rules send
+/// remotes to.
+fn rewrite_aliases(content: &str) -> Vec<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut aliases = Vec::new();
+    for section in gitconfig::sections(&lines) {
+        let Some(base) = section.header.strip_prefix("url").map(|rest| rest.trim().trim_matches('"')) else {
+            continue;
+        };
+        let Some(alias) = base.strip_prefix("git@").and_then(|rest| rest.strip_suffix(':')) else {
+            continue;
+        };
+        if !gitconfig::values(&lines, &section, "insteadof").is_empty() && !aliases.iter().any(|known| known == alias) {
+            aliases.push(alias.to_string());
+        }
+    }
+    aliases
+}
+
+impl GitAccountManager {
+    fn read_ssh_config(&self) -> Result<String> {
+        match fsutil::read_to_string(&self.ssh_config_path()) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            result => result,
+        }
+    }
+
+    /// Accounts whose Host alias has no block in the ssh config at all.
+    pub fn missing_host_blocks(&self) -> Result<Vec<String>> {
+        let content = self.read_ssh_config()?;
+        let lines: Vec<&str> = content.lines().collect();
+        let blocks = ssh_config::host_blocks(&lines);
+
+        Ok(self.sorted_accounts(false).into_iter()
+            .filter(|account| ssh_config::find_host(&blocks, &account.host).is_none())
+            .map(|account| account.name.clone())
+            .collect())
+    }
+
+    /// Compares each account's Host block IdentityFile against its recorded key.
+    pub fn identity_file_drift(&self) -> Result<Vec<IdentityDrift>> {
+        let content = self.read_ssh_config()?;
+        let lines: Vec<&str> = content.lines().collect();
+        let blocks = ssh_config::host_blocks(&lines);
+
+        let mut drift = Vec::new();
+        for account in self.sorted_accounts(false) {
+            let Some(block) = ssh_config::find_host(&blocks, &account.host) else {
+                continue;
+            };
+
+            let found: Vec<String> = ssh_config::identity_files(&lines, block)
+                .into_iter()
+                .map(|(_, value)| value)
+                .collect();
+            let expected: Vec<String> = account.identity_files().into_iter().map(str::to_string).collect();
+            let matches = found.len() == expected.len()
+                && found.iter().zip(&expected).all(|(f, e)| ssh_config::expand_tilde(f, &self.home_dir) == *e);
+
+            if !matches {
+                drift.push(IdentityDrift {
+                    account: account.name.clone(),
+                    host: account.host.clone(),
+                    found,
+                    expected,
+                });
+            }
+        }
+        Ok(drift)
+    }
+
+    /// Compares the aliases each account's include file rewrites remotes to
+    /// with the ones multigit would write there. Only the account's own
+    /// region counts; a missing include file is a dangling include.
+    pub fn rewrite_drift(&self) -> Result<Vec<RewriteDrift>> {
+        let mut drift = Vec::new();
+        for account in self.sorted_accounts(false) {
+            let path = self.include_file_path(account);
+            let Some(body) = fsutil::read_to_string(&path).ok().and_then(|content| tamper::block_body(&content, &account.name)) else {
+                continue;
+            };
+            let found = rewrite_aliases(&body);
+            let expected = rewrite_aliases(&self.include_file_body(account)?);
+            if found != expected {
+                drift.push(RewriteDrift { account: account.name.clone(), path, found, expected });
+            }
+        }
+        Ok(drift)
+    }
+
+    /// Writes the include file of each drifted account again.
+    pub fn fix_rewrite_drift(&self, drift: &[RewriteDrift]) -> Result<()> {
+        for item in drift {
+            self.restore_include_file(&item.account)?;
+        }
+        Ok(())
+    }
+
+    /// Points each drifted Host block back at the account's keys.
+    pub fn fix_identity_file_drift(&self, drift: &[IdentityDrift]) -> Result<()> {
+        if drift.is_empty() {
+            return Ok(());
+        }
+
+        fsutil::modify(&self.ssh_config_path(), |content| {
+            let mut content = content.to_string();
+            for item in drift {
+                let lines: Vec<&str> = content.lines().collect();
+                let blocks = ssh_config::host_blocks(&lines);
+                if let Some(block) = ssh_config::find_host(&blocks, &item.host) {
+                    let mut rewritten = ssh_config::replace_identity_files(&lines, block, &item.expected).join("\n");
+                    rewritten.push('\n');
+                    content = rewritten;
+                }
+            }
+            Ok(Some(content))
+        })?;
+
+        let mut state = State::load(&self.home_dir)?;
+        for item in drift {
+            state.record_write(&item.account, &self.ssh_config_path());
+        }
+        state.save(&self.home_dir)
+    }
+}
+
+fn describe_found(found: &[String]) -> String {
+    if found.is_empty() {
+        "(none)".to_string()
+    } else {
+        found.join(", ")
+    }
+}
+
+fn report_drift(drift: &[IdentityDrift], fixed: bool) {
+    for item in drift {
+        let verb = if fixed { "fixed" } else { "drift" };
+        println!(
+            "{}: {}: Host {} IdentityFile {} -> {}",
+            verb, item.account, item.host, describe_found(&item.found), item.expected.join(", ")
+        );
+    }
+}
+
+fn report_rewrite_drift(drift: &[RewriteDrift], fixed: bool) {
+    for item in drift {
+        let verb = if fixed { "fixed" } else { "drift" };
+        println!(
+            "{}: {}: {} rewrites remotes to Host {} -> {}",
+            verb, item.account, item.path.display(), describe_found(&item.found), describe_found(&item.expected)
+        );
+    }
+}
+
+/// Before a command changes anything, warns on stderr about hand edits that
+/// broke what multigit relies on: a Host block gone, pointing at other
+/// keys, or no longer the alias the include file rewrites remotes to. Only
+/// a warning, since the command may be the fix, unless `--strict` fails on
+/// the problems returned.
+pub fn warn_inconsistencies(manager: &GitAccountManager) -> Vec<String> {
+    let mut problems = Vec::new();
+    for name in manager.missing_host_blocks().unwrap_or_default() {
+        problems.push(format!("{}: no Host block for {} in {}", name, manager.accounts[&name].host, manager.ssh_config_path().display()));
+    }
+    for item in manager.identity_file_drift().unwrap_or_default() {
+        problems.push(format!("{}: Host {} has IdentityFile {} instead of {}", item.account, item.host, describe_found(&item.found), item.expected.join(", ")));
+    }
+    for item in manager.rewrite_drift().unwrap_or_default() {
+        problems.push(format!("{}: {} rewrites remotes to Host {} instead of {}", item.account, item.path.display(), describe_found(&item.found), describe_found(&item.expected)));
+    }
+    for problem in &problems {
+        eprintln!("warning: {}", problem);
+    }
+    if !problems.is_empty() {
+        eprintln!("warning: the config was edited by hand; run `multigit sync --fix` (or `multigit doctor --repair` for a missing Host block)");
+    }
+    problems
+}
+
+/// Checks that every account's primary key is loaded in ssh-agent, unless
+/// the agent is turned off for that account.
+fn check_agent(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let accounts = manager.sorted_accounts(include_disabled);
+    let loaded = keys::agent_fingerprints();
+    let mut ok = true;
+    for account in accounts {
+        if account.pkcs11_provider.is_some() {
+            println!("skipped: {}: ssh-agent check (ssh reads the PKCS#11 token itself)", account.name);
+            continue;
+        }
+        if manager.skips_agent(account) {
+            println!("skipped: {}: ssh-agent check (agent disabled for this account)", account.name);
+            continue;
+        }
+
+        let Some(loaded) = &loaded else {
+            println!("error: {}: no ssh-agent reachable; start one or use --no-agent", account.name);
+            ok = false;
+            continue;
+        };
+        let in_agent = keys::fingerprint_hash(&account.ssh_key)
+            .is_some_and(|fingerprint| loaded.contains(&fingerprint));
+        if !in_agent {
+            println!("error: {}: key {} is not loaded in ssh-agent", account.name, account.ssh_key);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Warns about agents that handle keys differently from OpenSSH's.
+fn check_agent_kind(manager: &GitAccountManager, include_disabled: bool) {
+    let accounts: Vec<_> = manager.sorted_accounts(include_disabled).into_iter().filter(|account| !manager.skips_agent(account)).collect();
+    if accounts.is_empty() {
+        return;
+    }
+    let agent = keys::current_agent_kind();
+    if agent == keys::AgentKind::GnomeKeyring {
+        println!("warning: SSH_AUTH_SOCK is gnome-keyring's agent; it may not honor `ssh-add -t` lifetimes, so keys can stay loaded for the whole session");
+    }
+    if platform::is_macos() && agent != keys::AgentKind::Launchd {
+        for account in accounts.iter().filter(|account| account.keychain() == Keychain::Store) {
+            println!(
+                "warning: {}: SSH_AUTH_SOCK isn't the launchd agent, so its key is added without --apple-use-keychain",
+                account.name
+            );
+        }
+    }
+}
+
+/// Days before a certificate's expiry that doctor starts warning.
+const CERTIFICATE_WARNING_DAYS: u64 = 7;
+
+/// `n unit` or `n units`.
+fn plural(n: u64, unit: &str) -> String {
+    if n == 1 { format!("1 {}", unit) } else { format!("{} {}s", n, unit) }
+}
+
+/// A duration in whole days, or hours below a day.
+pub fn describe_duration(secs: u64) -> String {
+    if secs >= 86400 { plural(secs / 86400, "day") } else { plural(secs.div_ceil(3600), "hour") }
+}
+
+/// Checks each account's certificate: it exists, was issued for the
+/// account's key, and hasn't expired. Certificates close to expiry get a
+/// warning.
+fn check_certificates(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut ok = true;
+    for account in manager.sorted_accounts(include_disabled) {
+        let Some(path) = &account.certificate_file else {
+            continue;
+        };
+        if !path.exists() {
+            println!("error: {}: certificate {} does not exist", account.name, path.display());
+            ok = false;
+            continue;
+        }
+        let Some(certificate) = keys::read_certificate(path) else {
+            println!("error: {}: {} is not an ssh certificate", account.name, path.display());
+            ok = false;
+            continue;
+        };
+        if keys::fingerprint_hash(&account.ssh_key).is_some_and(|fingerprint| fingerprint != certificate.key_fingerprint) {
+            println!("error: {}: certificate {} was issued for another key than {}", account.name, path.display(), account.ssh_key);
+            ok = false;
+        }
+        match certificate.valid_until {
+            Some(until) if until <= now => {
+                println!("error: {}: certificate {} expired {} ago", account.name, path.display(), describe_duration(now - until));
+                ok = false;
+            }
+            Some(until) if until - now < CERTIFICATE_WARNING_DAYS * 86400 => {
+                println!("warning: {}: certificate {} expires in {}", account.name, path.display(), describe_duration(until - now));
+            }
+            _ => {}
+        }
+    }
+    ok
+}
+
+/// The work tree of a repository `dir` is inside, if any.
+fn enclosing_repo(dir: &Path) -> Option<&Path> {
+    dir.ancestors().skip(1).find(|ancestor| ancestor.join(".git").exists())
+}
+
+/// Warns about codebase dirs inside another repository's work tree. git
+/// run there outside a nested repository works on the outer one, whose
+/// git dir the includeIf doesn't match, so it gets another identity.
+fn check_enclosing_repos(manager: &GitAccountManager, include_disabled: bool) {
+    for account in manager.sorted_accounts(include_disabled).into_iter().filter(|account| account.scope.is_dir()) {
+        let dir = &account.codebase_dir_path;
+        let Some(outer) = enclosing_repo(dir) else {
+            continue;
+        };
+        println!(
+            "warning: {}: codebase dir {} is inside the repository at {}; git there outside a nested repository uses {} and not {}'s identity",
+            account.name,
+            dir.display(),
+            outer.display(),
+            outer.display(),
+            account.name
+        );
+    }
+}
+
+/// Checks that the PKCS#11 library of each account using one is still there.
+fn check_pkcs11_providers(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let mut ok = true;
+    for account in manager.sorted_accounts(include_disabled) {
+        if let Some(provider) = account.pkcs11_provider.as_ref().filter(|provider| !provider.is_file()) {
+            println!("error: {}: PKCS#11 library {} does not exist, so ssh has no key to offer", account.name, provider.display());
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// With `--network`, connects to the SMTP server of each account that has
+/// one, without logging in.
+fn check_smtp_servers(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let accounts: Vec<_> = manager.sorted_accounts(include_disabled).into_iter().filter(|account| account.sendemail.smtp_server.is_some()).collect();
+    if manager.offline {
+        for account in accounts {
+            println!("skipped: {}: SMTP connection (offline)", account.name);
+        }
+        return true;
+    }
+    let mut ok = true;
+    for account in accounts {
+        match account.sendemail.test_connection(SMTP_TIMEOUT) {
+            Ok(answer) => println!("info: {}: SMTP: {}", account.name, answer),
+            Err(e) => {
+                println!("error: {}: SMTP: {}", account.name, e);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Checks the files of each account's TLS client certificate.
+fn check_client_tls(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let mut ok = true;
+    for account in manager.sorted_accounts(include_disabled) {
+        for (error, message) in account.client_tls.problems() {
+            println!("{}: {}: {}", if error { "error" } else { "warning" }, account.name, message);
+            ok &= !error;
+        }
+    }
+    ok
+}
+
+/// Checks that each account's own known hosts file exists and has the
+/// provider's host keys.
+fn check_known_hosts_files(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let mut ok = true;
+    for account in manager.sorted_accounts(include_disabled) {
+        let Some(path) = &account.known_hosts_file else {
+            continue;
+        };
+        if !path.exists() {
+            println!("error: {}: known hosts file {} does not exist", account.name, path.display());
+            ok = false;
+        } else if !known_hosts::has_provider_keys(path) {
+            let result = if account.strict_host_key_checking { "refuses to connect" } else { "asks on first connect" };
+            println!("error: {}: known hosts file {} has no entry for {}, so ssh {}", account.name, path.display(), PROVIDER_HOST, result);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Checks that each account's `core.hooksPath` still has hooks to run.
+fn check_hooks_paths(manager: &GitAccountManager, include_disabled: bool) -> bool {
+    let mut ok = true;
+    for account in manager.sorted_accounts(include_disabled) {
+        let Some(path) = &account.hooks_path else {
+            continue;
+        };
+        if !path.is_dir() {
+            println!("error: {}: core.hooksPath {} does not exist, so its repositories run no hooks", account.name, path.display());
+            ok = false;
+            continue;
+        }
+        match account::executable_hooks(path).as_slice() {
+            [] => println!("warning: {}: core.hooksPath {} has no executable hooks", account.name, path.display()),
+            hooks => println!("info: {}: core.hooksPath {} ({})", account.name, path.display(), hooks.join(", ")),
+        }
+    }
+    ok
+}
+
+/// Checks that the socket dir of multiplexing accounts exists and only its
+/// owner can reach it, creating it or tightening it with `fix`.
+fn check_socket_dir(manager: &GitAccountManager, fix: bool, include_disabled: bool) -> Result<bool> {
+    if manager.sorted_accounts(include_disabled).iter().all(|account| account.multiplexing.is_none()) {
+        return Ok(true);
+    }
+    let Some(problem) = multiplex::socket_dir_problem(&manager.home_dir) else {
+        return Ok(true);
+    };
+    if fix {
+        multiplex::ensure_socket_dir(&manager.home_dir)?;
+        println!("fixed: {}; it is now a directory with mode 700", problem);
+        return Ok(true);
+    }
+    println!("error: {}; run `multigit doctor --fix`", problem);
+    Ok(false)
+}
+
+/// Warns about primary keys stored without a passphrase.
+fn check_encryption(manager: &GitAccountManager, include_disabled: bool) {
+    for account in manager.sorted_accounts(include_disabled) {
+        if keys::is_encrypted(&account.ssh_key) == Some(false) {
+            println!(
+                "warning: {}: key {} is unencrypted; run `multigit key passphrase {}`",
+                account.name, account.ssh_key, account.name
+            );
+        }
+    }
+}
+
+/// Runs the network steps earlier runs deferred while offline, keeping the
+/// ones that still don't succeed for next time.
+fn complete_deferred(manager: &GitAccountManager) -> Result<bool> {
+    let mut state = State::load(&manager.home_dir)?;
+    if state.deferred.is_empty() {
+        return Ok(true);
+    }
+    if manager.offline {
+        println!("Still offline; {} deferred step(s) left.", state.deferred.len());
+        return Ok(false);
+    }
+
+    let mut remaining = Vec::new();
+    for step in state.deferred.drain(..) {
+        if !manager.accounts.contains_key(&step.account) {
+            continue;
+        }
+
+        let status = match step.step.as_str() {
+            CONNECTIVITY_STEP => manager.check_connectivity(&step.account),
+            KNOWN_HOSTS_STEP => known_hosts::host_keys_step(&known_hosts::account_file(&manager.home_dir, &manager.accounts[&step.account])),
+            other => StepStatus::Failed(format!("unknown step '{}'", other)),
+        };
+        println!("{}: {}: {}", step.account, step.step, status);
+        if !matches!(status, StepStatus::Ok) {
+            remaining.push(step);
+        }
+    }
+
+    let ok = remaining.is_empty();
+    state.deferred = remaining;
+    state.save(&manager.home_dir)?;
+    Ok(ok)
+}
+
+/// Runs the health checks, returning whether everything was in order.
+/// With `repair`, missing managed config is written back first.
+pub fn run_doctor(manager: &GitAccountManager, fix: bool, repair: bool, run_deferred: bool, network: bool, include_disabled: bool) -> Result<bool> {
+    let mut healthy = true;
+
+    if repair {
+        healthy &= manager.repair()?;
+        println!();
+    }
+
+    if !include_disabled {
+        for account in manager.sorted_accounts(true).into_iter().filter(|account| account.disabled) {
+            println!("skipped: {}: disabled (use --include-disabled to check it)", account.name);
+        }
+    }
+
+    if run_deferred {
+        healthy &= complete_deferred(manager)?;
+    } else {
+        for step in State::load(&manager.home_dir)?.deferred {
+            println!("deferred: {}: {} (run `multigit doctor --complete-deferred`)", step.account, step.step);
+        }
+    }
+
+    for name in manager.missing_host_blocks()? {
+        println!("error: {}: no Host block for {} in {}", name, manager.accounts[&name].host, manager.ssh_config_path().display());
+        healthy = false;
+    }
+
+    healthy &= manager.report_duplicate_hosts(&manager.duplicate_host_aliases());
+
+    for include in manager.dangling_includes()? {
+        let missing = format!("{}: includeIf path {} doesn't exist, so git quietly uses the global identity there", include.account, include.path.display());
+        match include.owner {
+            Some(owner) if fix => match manager.restore_include_file(&owner) {
+                Ok(()) => println!("fixed: {}: wrote back {}", owner, include.path.display()),
+                Err(e) => {
+                    println!("error: {}; writing it back failed: {}", missing, e);
+                    healthy = false;
+                }
+            },
+            Some(_) => {
+                println!("error: {}; run `multigit doctor --fix` to write it back", missing);
+                healthy = false;
+            }
+            None => {
+                println!("error: {}; it's no account's include file, so remove the entry from the global gitconfig", missing);
+                healthy = false;
+            }
+        }
+    }
+
+    for path in ownership::root_owned_ssh_files(manager) {
+        println!("error: {} is owned by root; ssh will reject it as bad ownership", path.display());
+        healthy = false;
+    }
+
+    if !check_agent(manager, include_disabled) {
+        healthy = false;
+    }
+    check_agent_kind(manager, include_disabled);
+    healthy &= check_certificates(manager, include_disabled);
+    healthy &= check_hooks_paths(manager, include_disabled);
+    healthy &= check_known_hosts_files(manager, include_disabled);
+    healthy &= check_pkcs11_providers(manager, include_disabled);
+    healthy &= check_client_tls(manager, include_disabled);
+    if network {
+        healthy &= check_smtp_servers(manager, include_disabled);
+    }
+    check_enclosing_repos(manager, include_disabled);
+    healthy &= check_socket_dir(manager, fix, include_disabled)?;
+    check_encryption(manager, include_disabled);
+
+    if wsl::is_wsl() {
+        for (message, ok) in manager.wsl_status(include_disabled) {
+            println!("{}: wsl: {}", if ok { "info" } else { "error" }, message);
+            healthy &= ok;
+        }
+    }
+
+    for account in manager.sorted_accounts(include_disabled) {
+        let emails = std::iter::once(account.email.as_str()).chain(account.committer_email.as_deref());
+        for problem in emails.filter_map(|email| manager.email_domain_problem(email, account.hostname())) {
+            let level = if manager.settings.email_domain_warn_only { "warning" } else { "error" };
+            println!("{}: {}: {}", level, account.name, problem);
+            healthy &= manager.settings.email_domain_warn_only;
+        }
+    }
+
+    for (account, key) in manager.missing_pub_keys(include_disabled) {
+        println!("error: {}: {}.pub is missing; run `multigit key regen-pub {}`", account, key, account);
+        healthy = false;
+    }
+
+    let drift = manager.identity_file_drift()?;
+    if fix {
+        manager.fix_identity_file_drift(&drift)?;
+    } else if !drift.is_empty() {
+        healthy = false;
+    }
+    report_drift(&drift, fix);
+    if !fix && !drift.is_empty() {
+        println!("Run `multigit sync --fix` to point the Host blocks back at the account keys.");
+    }
+
+    let rewrites = manager.rewrite_drift()?;
+    if fix {
+        manager.fix_rewrite_drift(&rewrites)?;
+    } else if !rewrites.is_empty() {
+        healthy = false;
+    }
+    report_rewrite_drift(&rewrites, fix);
+    if !fix && !rewrites.is_empty() {
+        println!("Run `multigit sync --fix` to point the insteadOf rewrites back at the Host aliases.");
+    }
+
+    if let Some(order) = manager.include_if_order()? {
+        if fix {
+            manager.fix_include_if_order()?;
+        } else {
+            healthy = false;
+        }
+        report_include_order(&order, fix);
+    }
+
+    let mut mismatches = manager.identity_mismatches()?;
+    if fix && !mismatches.is_empty() {
+        manager.fix_identity_mismatches()?;
+        for mismatch in &mismatches {
+            println!("fixed: repositories under {} now get {}", mismatch.dir.display(), mismatch.expected);
+        }
+        mismatches = manager.identity_mismatches()?;
+    }
+    for mismatch in &mismatches {
+        println!(
+            "error: repositories under {} get {} instead of {}; run `multigit doctor --fix`",
+            mismatch.dir.display(),
+            mismatch.actual.as_deref().unwrap_or("no account"),
+            mismatch.expected
+        );
+        healthy = false;
+    }
+
+    for conflict in manager.conflicts() {
+        let origin = match conflict.line {
+            Some(line) => format!("{}:{}", conflict.file.display(), line),
+            None => conflict.file.display().to_string(),
+        };
+        println!("conflict: {}: {}", origin, conflict.message);
+        healthy = false;
+    }
+
+    if healthy {
+        println!("No problems found.");
+    }
+    Ok(healthy)
+}
+
+fn report_include_order(order: &IncludeOrder, fixed: bool) {
+    let verb = if fixed { "fixed" } else { "drift" };
+    println!("{}: {}", verb, order.describe());
+}
+
+/// Brings the on-disk config back in line with the account records. Without
+/// `fix` it only reports what would change.
+pub fn run_sync(manager: &GitAccountManager, fix: bool) -> Result<bool> {
+    let drift = manager.identity_file_drift()?;
+    let rewrites = manager.rewrite_drift()?;
+    let order = manager.include_if_order()?;
+    let duplicates = manager.duplicate_host_aliases();
+    if drift.is_empty() && rewrites.is_empty() && order.is_none() && duplicates.is_empty() {
+        println!("Everything is in sync.");
+        return Ok(true);
+    }
+
+    let in_sync = fix || (drift.is_empty() && rewrites.is_empty() && order.is_none());
+    if fix {
+        manager.fix_identity_file_drift(&drift)?;
+        manager.fix_rewrite_drift(&rewrites)?;
+        if order.is_some() {
+            manager.fix_include_if_order()?;
+        }
+    }
+    report_drift(&drift, fix);
+    report_rewrite_drift(&rewrites, fix);
+    if let Some(order) = &order {
+        report_include_order(order, fix);
+    }
+    if !in_sync {
+        println!("Run `multigit sync --fix` to bring the files back in line with the accounts.");
+    }
+    // Which block should own an alias is the user's call, so --fix leaves
+    // duplicates alone.
+    let duplicates_ok = manager.report_duplicate_hosts(&duplicates);
+    Ok(in_sync && duplicates_ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::account::SetupOptions;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn sync_fix_corrects_a_drifted_identity_file() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let key = manager.accounts["work"].ssh_key.clone();
+        let config = home.read(".ssh/config");
+        home.write(".ssh/config", &config.replace(&key, "~/.ssh/id_rsa"));
+
+        let drift = manager.identity_file_drift().unwrap();
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].found, ["~/.ssh/id_rsa"]);
+        assert!(!run_sync(&manager, false).unwrap());
+
+        assert!(run_sync(&manager, true).unwrap());
+        assert!(manager.identity_file_drift().unwrap().is_empty());
+        assert_eq!(home.read(".ssh/config"), config);
+    }
+
+    #[test]
+    fn deleted_include_file_is_found_and_written_back_by_fix() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let include = home.join("code/work/.gitconfig");
+        let content = home.read(&include);
+        fs::remove_file(&include).unwrap();
+
+        let dangling = manager.dangling_includes().unwrap();
+        assert_eq!(dangling.len(), 1);
+        assert_eq!((dangling[0].account.as_str(), dangling[0].owner.as_deref()), ("work", Some("work")));
+        assert_eq!(dangling[0].path, include);
+
+        run_doctor(&manager, true, false, false, false, false).unwrap();
+        assert_eq!(home.read(&include), content);
+        assert!(manager.dangling_includes().unwrap().is_empty());
+    }
+
+    #[test]
+    fn codebase_dir_inside_a_repository_is_found() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        fs::create_dir_all(home.join("outer/.git")).unwrap();
+        manager.setup_account("work", "work@example.com", "outer/work", "github.com-work", SetupOptions::default()).unwrap();
+        testutil::set_up(&mut manager, &["home"]);
+
+        assert_eq!(enclosing_repo(&manager.accounts["work"].codebase_dir_path), Some(home.join("outer").as_path()));
+        assert_eq!(enclosing_repo(&manager.accounts["home"].codebase_dir_path), None);
+    }
+}
@@ -0,0 +1,170 @@
+//! `check`: a drift check quick enough for a shell's startup file. Every
+//! doctor and every command that changes files leaves behind what it found
+//! for the managed blocks and keys, with the size and modification time of
+//! each file; `check --cached` only looks again at files where those
+//! changed. Nothing is printed when all is well, one line otherwise.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::state::{self, State};
+use crate::store;
+use crate::tamper;
+
+#[derive(Default, Serialize, Deserialize)]
+struct CheckCache {
+    /// Whether the last doctor found no errors; unset until one runs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    doctor_ok: Option<bool>,
+    /// The accounts and state files, which decide what is checked.
+    #[serde(default)]
+    config: Vec<CachedFile>,
+    #[serde(default)]
+    files: Vec<CachedFile>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+struct CachedFile {
+    path: PathBuf,
+    /// Size, modification seconds and nanoseconds; unset for a missing file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stamp: Option<[u64; 3]>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<String>,
+}
+
+/// What a file is checked for.
+enum Expect {
+    /// The recorded hash of each account's block in it.
+    Blocks(Vec<(String, String)>),
+    /// Key files only have to exist.
+    Key(String),
+}
+
+fn cache_path(home_dir: &Path) -> PathBuf {
+    store::config_dir(home_dir).join("check.toml")
+}
+
+fn stamp(path: &Path) -> Option<[u64; 3]> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some([metadata.len(), modified.as_secs(), u64::from(modified.subsec_nanos())])
+}
+
+fn stamped(path: PathBuf) -> CachedFile {
+    CachedFile { stamp: stamp(&path), path, problems: Vec::new() }
+}
+
+/// The files to check and what for, from the accounts and the state file.
+fn expectations(home_dir: &Path) -> Result<BTreeMap<PathBuf, Expect>> {
+    let mut expect = BTreeMap::new();
+    for record in State::load(home_dir)?.written {
+        let Some(hash) = record.hash else {
+            continue;
+        };
+        if let Expect::Blocks(blocks) = expect.entry(record.path).or_insert_with(|| Expect::Blocks(Vec::new())) {
+            blocks.push((record.account, hash));
+        }
+    }
+    let (_, accounts) = store::load_accounts(home_dir)?;
+    for account in accounts.iter().filter(|account| !account.disabled) {
+        for key in account.identity_files() {
+            expect.entry(PathBuf::from(key)).or_insert_with(|| Expect::Key(account.name.clone()));
+        }
+    }
+    Ok(expect)
+}
+
+fn problems(path: &Path, expect: &Expect) -> Vec<String> {
+    match expect {
+        Expect::Key(account) if !path.exists() => vec![format!("{}'s key {} is missing", account, path.display())],
+        Expect::Key(_) => Vec::new(),
+        Expect::Blocks(blocks) => {
+            let content = match fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return vec![format!("can't read {}: {}", path.display(), e)],
+            };
+            blocks
+                .iter()
+                .filter_map(|(account, hash)| match tamper::block_body(&content, account) {
+                    None => Some(format!("{}'s block in {} is missing", account, path.display())),
+                    Some(body) if tamper::hash(&body) != *hash => Some(format!("{}'s block in {} was edited", account, path.display())),
+                    Some(_) => None,
+                })
+                .collect()
+        }
+    }
+}
+
+fn config_files(home_dir: &Path) -> Vec<CachedFile> {
+    [store::accounts_path(home_dir), state::state_path(home_dir)].into_iter().map(stamped).collect()
+}
+
+/// Every file checked from scratch.
+fn full_check(home_dir: &Path, doctor_ok: Option<bool>) -> Result<CheckCache> {
+    let files = expectations(home_dir)?
+        .into_iter()
+        .map(|(path, expect)| CachedFile { stamp: stamp(&path), problems: problems(&path, &expect), path })
+        .collect();
+    Ok(CheckCache { doctor_ok, config: config_files(home_dir), files })
+}
+
+fn load(home_dir: &Path) -> Option<CheckCache> {
+    toml::from_str(&fs::read_to_string(cache_path(home_dir)).ok()?).ok()
+}
+
+/// Checks everything again and caches the result, after a doctor (with
+/// `doctor_ok` its outcome) or a command that changed files.
+pub fn refresh(home_dir: &Path, doctor_ok: Option<bool>) -> Result<()> {
+    if !store::accounts_path(home_dir).exists() {
+        return Ok(());
+    }
+    let doctor_ok = doctor_ok.or_else(|| load(home_dir).and_then(|cache| cache.doctor_ok));
+    let cache = full_check(home_dir, doctor_ok)?;
+    let content = toml::to_string(&cache).map_err(std::io::Error::other)?;
+    fsutil::write_atomic(&cache_path(home_dir), content.as_bytes())
+}
+
+/// `check`: with `cached`, looks again only at files whose size or
+/// modification time changed since the cache was written. Falls back to a
+/// full check when there's no cache or the accounts or state changed.
+pub fn check(home_dir: &Path, cached: bool) -> Result<bool> {
+    if !store::accounts_path(home_dir).exists() {
+        return Ok(true);
+    }
+    let previous = load(home_dir);
+    let doctor_ok = previous.as_ref().and_then(|cache| cache.doctor_ok);
+    let result = match previous {
+        Some(cache) if cached && cache.config == config_files(home_dir) => {
+            let mut files = cache.files;
+            // Only worked out when a file changed.
+            let mut expect = None;
+            for file in files.iter_mut().filter(|file| file.stamp != stamp(&file.path)) {
+                if expect.is_none() {
+                    expect = Some(expectations(home_dir)?);
+                }
+                if let Some(expect) = expect.as_ref().and_then(|expect| expect.get(&file.path)) {
+                    file.problems = problems(&file.path, expect);
+                }
+            }
+            CheckCache { files, ..cache }
+        }
+        _ => full_check(home_dir, doctor_ok)?,
+    };
+
+    let problems: Vec<&String> = result.files.iter().flat_map(|file| &file.problems).collect();
+    match problems.as_slice() {
+        [] if doctor_ok == Some(false) => println!("multigit: the last doctor found problems; run multigit doctor"),
+        [] => return Ok(true),
+        [only] => println!("multigit: {}; run multigit doctor", only),
+        [first, rest @ ..] => println!("multigit: {} and {} more; run multigit doctor", first, rest.len()),
+    }
+    Ok(false)
+}
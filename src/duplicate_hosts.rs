@@ -0,0 +1,149 @@
+//! Host aliases declared by more than one block. ssh takes each option from
+//! the first block that matches and ignores it in later ones, so for an
+//! alias spelled out in two blocks, whichever comes second is dead for it.
+//! The managed ssh config is read where `~/.ssh/config` includes it, as ssh
+//! does.
+
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+use crate::fsutil;
+use crate::managed;
+use crate::ssh_config;
+use crate::store;
+
+/// A `Host` line naming an alias.
+pub struct Declaration {
+    pub path: PathBuf,
+    /// 1-based.
+    pub line: usize,
+    /// The account whose marked block it's in.
+    pub owner: Option<String>,
+}
+
+impl Declaration {
+    pub fn describe(&self) -> String {
+        match &self.owner {
+            Some(owner) => format!("{}'s block at {}:{}", owner, self.path.display(), self.line),
+            None => format!("the block at {}:{}", self.path.display(), self.line),
+        }
+    }
+}
+
+pub struct DuplicateHost {
+    pub alias: String,
+    /// In the order ssh reads them; the first is the one that applies.
+    pub declarations: Vec<Declaration>,
+}
+
+/// Whether ssh reads `pattern` as one literal alias rather than a wildcard
+/// or a negation.
+fn literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '!'])
+}
+
+/// Every literal alias declared in `path` with where, in reading order,
+/// following the `Include` of `managed_path`.
+fn declarations(path: &Path, managed_path: &Path, home_dir: &Path, out: &mut Vec<(String, Declaration)>) {
+    let Ok(content) = fsutil::read_to_string(path) else {
+        return;
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let blocks = ssh_config::host_blocks(&lines);
+    let marked = managed::blocks(&lines);
+    let mut blocks = blocks.iter().peekable();
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(block) = blocks.next_if(|block| block.start == i) {
+            let owner = managed::enclosing(&marked, block.start, block.end).map(|block| block.account.clone());
+            for pattern in block.patterns.iter().filter(|pattern| literal(pattern)) {
+                out.push((pattern.to_ascii_lowercase(), Declaration { path: path.to_path_buf(), line: i + 1, owner: owner.clone() }));
+            }
+            continue;
+        }
+        let Some((keyword, value)) = ssh_config::directive(line) else {
+            continue;
+        };
+        let includes_managed = value.split_whitespace().any(|value| Path::new(&ssh_config::expand_tilde(value, home_dir)) == managed_path);
+        if keyword == "include" && path != managed_path && includes_managed {
+            declarations(managed_path, managed_path, home_dir, out);
+        }
+    }
+}
+
+impl GitAccountManager {
+    /// Aliases more than one Host block declares, with the blocks in the
+    /// order ssh reads them.
+    pub fn duplicate_host_aliases(&self) -> Vec<DuplicateHost> {
+        let managed_path = store::managed_ssh_config_path(&self.home_dir);
+        let mut found = Vec::new();
+        declarations(&self.home_dir.join(".ssh/config"), &managed_path, &self.home_dir, &mut found);
+
+        let mut duplicates: Vec<DuplicateHost> = Vec::new();
+        for (alias, declaration) in found {
+            match duplicates.iter_mut().find(|duplicate| duplicate.alias.eq_ignore_ascii_case(&alias)) {
+                Some(duplicate) => duplicate.declarations.push(declaration),
+                None => duplicates.push(DuplicateHost { alias, declarations: vec![declaration] }),
+            }
+        }
+        duplicates.retain(|duplicate| duplicate.declarations.len() > 1);
+        duplicates
+    }
+
+    /// Prints each duplicated alias and which block wins. An account's alias
+    /// that resolves to some other block is an error, anything else a
+    /// warning. Returns false when there was an error.
+    pub fn report_duplicate_hosts(&self, duplicates: &[DuplicateHost]) -> bool {
+        let mut ok = true;
+        for duplicate in duplicates {
+            let account = self.accounts.values().find(|account| account.host.eq_ignore_ascii_case(&duplicate.alias));
+            let (winner, ignored) = duplicate.declarations.split_first().expect("duplicates have two declarations");
+            let broken = account.is_some_and(|account| winner.owner.as_deref() != Some(account.name.as_str()));
+            ok &= !broken;
+            println!(
+                "{}: Host {} is declared {} times; ssh uses {} and ignores it in {}",
+                if broken { "error" } else { "warning" },
+                duplicate.alias,
+                duplicate.declarations.len(),
+                winner.describe(),
+                ignored.iter().map(Declaration::describe).collect::<Vec<_>>().join(", ")
+            );
+        }
+        ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn a_hand_written_block_shadowing_an_account_is_an_error() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let config = home.read(".ssh/config");
+        home.write(".ssh/config", &format!("Host github.com-work\n    HostName example.com\n\n{}", config));
+
+        let duplicates = manager.duplicate_host_aliases();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].alias, "github.com-work");
+        let owners: Vec<Option<&str>> = duplicates[0].declarations.iter().map(|declaration| declaration.owner.as_deref()).collect();
+        assert_eq!(owners, [None, Some("work")]);
+        assert_eq!(duplicates[0].declarations[0].line, 1);
+        assert!(!manager.report_duplicate_hosts(&duplicates));
+    }
+
+    #[test]
+    fn duplicates_outside_any_account_are_only_a_warning() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let config = home.read(".ssh/config");
+        home.write(".ssh/config", &format!("{}\nHost box backup\n    User me\n\nHost *.internal BOX\n    User admin\n", config));
+
+        let duplicates = manager.duplicate_host_aliases();
+        assert_eq!(duplicates.len(), 1);
+        assert_eq!(duplicates[0].alias, "box");
+        assert!(manager.report_duplicate_hosts(&duplicates));
+    }
+}
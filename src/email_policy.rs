@@ -0,0 +1,119 @@
+//! `--require-email-domain`: the email domains accounts have to use, on
+//! every provider or on one ssh hostname, so a work account can't be set up
+//! with a personal address by mistake. Kept in the settings; setup and
+//! `validate` refuse an address outside them, or only warn with
+//! `--email-domain-warn-only`, and doctor lists existing accounts that
+//! don't comply.
+
+use std::io::{Error, ErrorKind, Result};
+
+use crate::account::GitAccountManager;
+
+/// The `email_domains` key for domains that apply on every hostname.
+pub const ALL_HOSTS: &str = "*";
+
+/// `--require-email-domain none` drops the domains of its scope.
+pub const NO_DOMAINS: &str = "none";
+
+/// Whether `email` is at `domain` or one of its subdomains.
+fn at_domain(email: &str, domain: &str) -> bool {
+    let Some((_, at)) = email.rsplit_once('@') else {
+        return false;
+    };
+    let (at, domain) = (at.to_ascii_lowercase(), domain.trim_start_matches('@').to_ascii_lowercase());
+    at == domain || at.ends_with(&format!(".{}", domain))
+}
+
+/// Checks a `--require-email-domain` value: a domain such as `acme.com`,
+/// or `none`.
+pub fn validate_domain(domain: &str) -> Result<()> {
+    let domain = domain.trim_start_matches('@');
+    let valid = domain == NO_DOMAINS
+        || (domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') && domain.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.'));
+    if !valid {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("--require-email-domain {}: expected a domain such as acme.com, or none", domain)));
+    }
+    Ok(())
+}
+
+impl GitAccountManager {
+    /// Sets the domains for `hostname`, or every hostname with `None`.
+    /// `none` among them clears that scope.
+    pub fn require_email_domains(&mut self, domains: &[String], hostname: Option<&str>) {
+        let scope = hostname.unwrap_or(ALL_HOSTS).to_ascii_lowercase();
+        if domains.iter().any(|domain| domain == NO_DOMAINS) {
+            self.settings.email_domains.remove(&scope);
+        } else {
+            let domains = domains.iter().map(|domain| domain.trim_start_matches('@').to_ascii_lowercase()).collect();
+            self.settings.email_domains.insert(scope, domains);
+        }
+    }
+
+    /// Why `email` breaks the domain policy for an account on `hostname`,
+    /// or `None` when it doesn't. Domains for the hostname replace the
+    /// ones for every hostname.
+    pub fn email_domain_problem(&self, email: &str, hostname: &str) -> Option<String> {
+        let domains = &self.settings.email_domains;
+        let (scope, allowed) = domains
+            .get(&hostname.to_ascii_lowercase())
+            .map(|allowed| (hostname, allowed))
+            .or_else(|| domains.get(ALL_HOSTS).map(|allowed| ("every provider", allowed)))?;
+        if allowed.iter().any(|domain| at_domain(email, domain)) {
+            return None;
+        }
+        Some(format!("{} isn't at {}, which accounts on {} must use", email, allowed.join(" or "), scope))
+    }
+
+    /// Fails when `email` breaks the domain policy, unless the policy only
+    /// warns; then the warning is returned.
+    pub fn check_email_domain(&self, email: &str, hostname: &str) -> Result<Option<String>> {
+        match self.email_domain_problem(email, hostname) {
+            Some(problem) if self.settings.email_domain_warn_only => Ok(Some(problem)),
+            Some(problem) => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} (--require-email-domain; pass --email-domain-warn-only to only warn)", problem),
+            )),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::SetupOptions;
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn setup_refuses_an_email_outside_the_required_domain() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.require_email_domains(&["acme.com".to_string()], None);
+
+        let Err(error) = manager.setup_account("work", "me@gmail.com", "code/work", "github.com-work", SetupOptions::default()) else {
+            panic!("an email outside acme.com is accepted");
+        };
+        assert!(error.to_string().starts_with("me@gmail.com isn't at acme.com, which accounts on every provider must use"), "{}", error);
+        assert!(!home.join(".ssh/id_ed25519_work").exists());
+
+        manager.setup_account("work", "me@eng.acme.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+        assert!(manager.accounts.contains_key("work"));
+    }
+
+    #[test]
+    fn hostname_domains_replace_the_global_ones_and_warn_only_warns() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.require_email_domains(&["acme.com".to_string()], None);
+        manager.require_email_domains(&["@Example.org".to_string()], Some("gitlab.com"));
+
+        assert_eq!(manager.email_domain_problem("me@example.org", "gitlab.com"), None);
+        assert!(manager.email_domain_problem("me@acme.com", "gitlab.com").is_some());
+        assert!(manager.email_domain_problem("me@example.org", "github.com").is_some());
+        assert!(manager.email_domain_problem("me@notacme.com", "github.com").is_some());
+
+        manager.settings.email_domain_warn_only = true;
+        assert!(manager.check_email_domain("me@gmail.com", "github.com").unwrap().is_some());
+        manager.require_email_domains(&["none".to_string()], None);
+        assert_eq!(manager.check_email_domain("me@gmail.com", "github.com").unwrap(), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! `env`: an account's identity as environment variables, for a shell
+//! session that should commit and push as the account wherever it is,
+//! without touching any config file. Loaded with
+//! `eval "$(multigit env work)"`, or for fish
+//! `multigit env work --shell fish | source`. git prefers the variables to
+//! user.name, user.email and core.sshCommand.
+
+use std::io::Result;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::errors::account_not_found;
+use crate::shell_hook::Shell;
+
+/// `value` in single quotes for a POSIX shell, each `'` as `'\''`.
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// `value` as one word of a `sh` command line, quoted only when it has to
+/// be.
+fn sh_word(value: &str) -> String {
+    let plain = !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        posix_quote(value)
+    }
+}
+
+/// `value` in fish's single quotes, inside which only `\` and `'` are
+/// escaped.
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// What the Host block would give ssh, as options: the account's keys and
+/// nothing else. git runs the command through `sh`, so it's quoted for
+/// that whatever the user's shell.
+fn ssh_command(account: &GitAccount) -> String {
+    let mut words = vec!["ssh".to_string()];
+    for key in account.identity_files() {
+        words.push("-i".to_string());
+        words.push(sh_word(key));
+    }
+    let mut options = Vec::new();
+    if let Some(provider) = &account.pkcs11_provider {
+        options.push(format!("PKCS11Provider={}", provider.display()));
+    }
+    if let Some(certificate) = &account.certificate_file {
+        options.push(format!("CertificateFile={}", certificate.display()));
+    }
+    if let Some(path) = &account.known_hosts_file {
+        options.push(format!("UserKnownHostsFile={}", path.display()));
+    }
+    if let Some(jump) = &account.proxy_jump {
+        options.push(format!("ProxyJump={}", jump));
+    }
+    options.push("IdentitiesOnly=yes".to_string());
+    for option in options {
+        words.push("-o".to_string());
+        words.push(sh_word(&option));
+    }
+    words.join(" ")
+}
+
+impl GitAccountManager {
+    /// The lines `env` prints for `account_name` in `shell`'s syntax.
+    pub fn env_exports(&self, account_name: &str, shell: Shell) -> Result<String> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            eprintln!("warning: {} is disabled", account_name);
+        }
+        let variables = [
+            ("GIT_AUTHOR_NAME", account.name.clone()),
+            ("GIT_AUTHOR_EMAIL", account.email.clone()),
+            ("GIT_COMMITTER_NAME", account.committer_name.clone().unwrap_or_else(|| account.name.clone())),
+            ("GIT_COMMITTER_EMAIL", account.committer_email.clone().unwrap_or_else(|| account.email.clone())),
+            ("GIT_SSH_COMMAND", ssh_command(account)),
+        ];
+        let mut out = String::new();
+        for (name, value) in variables {
+            match shell {
+                Shell::Bash | Shell::Zsh => out.push_str(&format!("export {}={}\n", name, posix_quote(&value))),
+                Shell::Fish => out.push_str(&format!("set -gx {} {}\n", name, fish_quote(&value))),
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn bash_output_is_eval_safe() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let account = manager.accounts.get_mut("work").unwrap();
+        account.committer_name = Some("O'Brien $(touch pwned) `id`".to_string());
+        let exports = manager.env_exports("work", Shell::Bash).unwrap();
+
+        let Ok(output) = Command::new("bash")
+            .arg("-c")
+            .arg("eval \"$1\" && printf '%s\\n' \"$GIT_AUTHOR_EMAIL\" \"$GIT_COMMITTER_NAME\" \"$GIT_SSH_COMMAND\"")
+            .arg("bash")
+            .arg(&exports)
+            .current_dir(&home.path)
+            .output()
+        else {
+            return;
+        };
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let key = &manager.accounts["work"].ssh_key;
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("work@example.com\nO'Brien $(touch pwned) `id`\nssh -i {} -o IdentitiesOnly=yes\n", key)
+        );
+        assert!(!home.join("pwned").exists());
+    }
+
+    #[test]
+    fn fish_output_uses_set_x() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        manager.accounts.get_mut("work").unwrap().committer_name = Some(r"it's a \ test".to_string());
+        let exports = manager.env_exports("work", Shell::Fish).unwrap();
+
+        let lines: Vec<&str> = exports.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines.iter().all(|line| line.starts_with("set -gx GIT_")), "{}", exports);
+        assert!(lines.contains(&"set -gx GIT_AUTHOR_EMAIL 'work@example.com'"));
+        assert!(lines.contains(&r"set -gx GIT_COMMITTER_NAME 'it\'s a \\ test'"));
+    }
+}
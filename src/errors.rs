@@ -0,0 +1,182 @@
+//! `MultigitError`, the errors callers may want to tell apart, and their
+//! `--json` form: a failed command prints its error as a JSON object on
+//! stderr, `{"error":{"kind":...,"message":...}}`, for scripts that branch
+//! on what went wrong rather than on the wording. `kind` is one of a fixed
+//! set of names: the variant, else the class of the underlying I/O error.
+//! Errors that concern one account also carry it as `account`.
+//!
+//! Most of multigit returns `io::Result`; a `MultigitError` travels inside
+//! the `io::Error` there and comes back out with `From`.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use crate::host_conflict::HostConflict;
+
+#[derive(Debug)]
+pub enum MultigitError {
+    /// An account name that isn't in the config.
+    AccountNotFound { account: String },
+    /// A non-interactive setup found a Host block it may not replace.
+    HostConflict(HostConflict),
+    /// Warnings that `--strict` turned into a failure.
+    StrictWarnings { warnings: Vec<String> },
+    /// What `GitAccountBuilder::build` found missing or wrong.
+    InvalidAccount { account: String, message: String },
+    Io(Error),
+}
+
+impl MultigitError {
+    /// The stable name `--json` gives the error.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MultigitError::AccountNotFound { .. } => "AccountNotFound",
+            MultigitError::HostConflict(_) => "HostConflict",
+            MultigitError::StrictWarnings { .. } => "StrictWarnings",
+            MultigitError::InvalidAccount { .. } => "InvalidAccount",
+            MultigitError::Io(e) => io_kind(e.kind()),
+        }
+    }
+
+    fn io_error_kind(&self) -> ErrorKind {
+        match self {
+            MultigitError::AccountNotFound { .. } => ErrorKind::NotFound,
+            MultigitError::HostConflict(_) => ErrorKind::AlreadyExists,
+            MultigitError::StrictWarnings { .. } => ErrorKind::Other,
+            MultigitError::InvalidAccount { .. } => ErrorKind::InvalidInput,
+            MultigitError::Io(e) => e.kind(),
+        }
+    }
+}
+
+impl fmt::Display for MultigitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultigitError::AccountNotFound { account } => write!(f, "Account '{}' not found", account),
+            MultigitError::HostConflict(conflict) => conflict.fmt(f),
+            MultigitError::StrictWarnings { warnings } => write!(f, "--strict: {} warning(s): {}", warnings.len(), warnings.join("; ")),
+            MultigitError::InvalidAccount { message, .. } => f.write_str(message),
+            MultigitError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MultigitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultigitError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MultigitError> for Error {
+    fn from(error: MultigitError) -> Error {
+        match error {
+            MultigitError::Io(e) => e,
+            error => Error::new(error.io_error_kind(), error),
+        }
+    }
+}
+
+impl From<Error> for MultigitError {
+    fn from(error: Error) -> MultigitError {
+        if error.get_ref().is_some_and(|inner| inner.is::<MultigitError>()) {
+            let kind = error.kind();
+            match error.into_inner().map(|inner| inner.downcast::<MultigitError>()) {
+                Some(Ok(error)) => *error,
+                _ => MultigitError::Io(Error::from(kind)),
+            }
+        } else {
+            MultigitError::Io(error)
+        }
+    }
+}
+
+/// The `MultigitError` inside `error`, if it carries one.
+pub fn multigit_error(error: &Error) -> Option<&MultigitError> {
+    error.get_ref().and_then(|inner| inner.downcast_ref::<MultigitError>())
+}
+
+pub fn account_not_found(account: &str) -> Error {
+    MultigitError::AccountNotFound { account: account.to_string() }.into()
+}
+
+pub fn strict_warnings(warnings: Vec<String>) -> Error {
+    MultigitError::StrictWarnings { warnings }.into()
+}
+
+/// The stable name of an I/O error's class. Classes not listed are `Other`,
+/// so a new one in the standard library doesn't change what scripts see.
+fn io_kind(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::NotFound => "NotFound",
+        ErrorKind::PermissionDenied => "PermissionDenied",
+        ErrorKind::AlreadyExists => "AlreadyExists",
+        ErrorKind::InvalidInput => "InvalidInput",
+        ErrorKind::InvalidData => "InvalidData",
+        ErrorKind::TimedOut => "TimedOut",
+        ErrorKind::Interrupted => "Interrupted",
+        ErrorKind::Unsupported => "Unsupported",
+        ErrorKind::WouldBlock => "WouldBlock",
+        _ => "Other",
+    }
+}
+
+/// The `--json` form of `error`.
+#[cfg(feature = "json")]
+pub fn envelope(error: &Error) -> Value {
+    let mut body = json!({ "message": error.to_string(), "kind": io_kind(error.kind()) });
+    if let Some(error) = multigit_error(error) {
+        body["kind"] = json!(error.kind());
+        match error {
+            MultigitError::AccountNotFound { account } | MultigitError::InvalidAccount { account, .. } => body["account"] = json!(account),
+            MultigitError::HostConflict(conflict) => body["host"] = json!(conflict.host()),
+            MultigitError::StrictWarnings { warnings } => body["warnings"] = json!(warnings),
+            MultigitError::Io(_) => {}
+        }
+    }
+    json!({ "error": body })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn envelope_names_each_kind() {
+        let not_found = envelope(&account_not_found("work"));
+        assert_eq!(not_found["error"]["kind"], "AccountNotFound");
+        assert_eq!(not_found["error"]["account"], "work");
+        assert_eq!(not_found["error"]["message"], "Account 'work' not found");
+
+        let strict = envelope(&strict_warnings(vec!["one".to_string(), "two".to_string()]));
+        assert_eq!(strict["error"]["kind"], "StrictWarnings");
+        assert_eq!(strict["error"]["warnings"], json!(["one", "two"]));
+
+        let invalid = envelope(&MultigitError::InvalidAccount { account: "work".to_string(), message: "bad".to_string() }.into());
+        assert_eq!(invalid["error"]["kind"], "InvalidAccount");
+        assert_eq!(invalid["error"]["account"], "work");
+
+        let io = envelope(&Error::new(ErrorKind::PermissionDenied, "denied"));
+        assert_eq!(io["error"]["kind"], "PermissionDenied");
+        assert_eq!(envelope(&Error::new(ErrorKind::BrokenPipe, "pipe"))["error"]["kind"], "Other");
+    }
+
+    #[test]
+    fn multigit_errors_survive_io_error() {
+        let error = account_not_found("work");
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+        match MultigitError::from(error) {
+            MultigitError::AccountNotFound { account } => assert_eq!(account, "work"),
+            other => panic!("expected AccountNotFound, got {}", other.kind()),
+        }
+        let plain = MultigitError::from(Error::new(ErrorKind::TimedOut, "slow"));
+        assert_eq!(plain.kind(), "TimedOut");
+        assert_eq!(Error::from(plain).to_string(), "slow");
+    }
+}
@@ -0,0 +1,55 @@
+//! `--events`: progress as newline-delimited JSON on stdout, one object per
+//! line with an `event` field, for frontends that show it live. The usual
+//! human-readable output moves to stderr so stdout carries nothing else.
+
+use std::fs::File;
+use std::io::{Result, Write};
+use std::sync::{Mutex, OnceLock};
+
+use serde_json::{json, Value};
+
+/// Where events go once enabled: the process's original stdout.
+static SINK: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Turns events on, pointing stdout at stderr from here on.
+#[cfg(unix)]
+pub fn enable() -> Result<()> {
+    use std::os::fd::FromRawFd;
+    // SAFETY: dup and dup2 on the standard descriptors have no memory
+    // preconditions; the duplicate is owned by the File from here on.
+    let sink = unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    let _ = SINK.set(Mutex::new(sink));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn enable() -> Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "--events is only supported on unix"))
+}
+
+/// Writes one event, with `fields` and the account merged in. A frontend
+/// that went away doesn't stop the run.
+pub fn emit(event: &str, account: Option<&str>, fields: Value) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let mut object = json!({ "event": event });
+    if let Some(account) = account {
+        object["account"] = json!(account);
+    }
+    if let Value::Object(fields) = fields {
+        object.as_object_mut().expect("built as an object").extend(fields);
+    }
+    let mut sink = sink.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _ = writeln!(sink, "{}", object).and_then(|_| sink.flush());
+}
+
+pub fn warning(account: Option<&str>, message: &str) {
+    emit("warning", account, json!({ "message": message }));
+}
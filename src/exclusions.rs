@@ -0,0 +1,79 @@
+//! `edit --exclude-dir`: handing a subdirectory of one account's codebase
+//! dir to another account, through an extra includeIf in the owning
+//! account's marked region of the global gitconfig.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::{Exclusion, GitAccountManager};
+use crate::errors::account_not_found;
+use crate::state::State;
+
+impl GitAccountManager {
+    /// Rewrites the account's marked region of the global gitconfig, which
+    /// carries its exclusions after its own includeIf.
+    pub fn rewrite_include_ifs(&self, account_name: &str) -> Result<()> {
+        let account = &self.accounts[account_name];
+        let include_path = account.codebase_dir_path.join(".gitconfig");
+        self.setup_global_gitconfig(account_name, &account.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy())?;
+
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &self.global_gitconfig_path());
+        state.save(&self.home_dir)
+    }
+
+    /// `dir` resolved against the home dir, once it's known to be a valid
+    /// exclusion of `account_name` to `other`.
+    pub fn check_exclusion(&self, account_name: &str, dir: &Path, other: &str) -> Result<PathBuf> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            return Err(Error::other(format!("Account '{}' is disabled; enable it first", account_name)));
+        }
+        if !account.scope.is_dir() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Account '{}' is scoped by remote URL, not by directory", account_name)));
+        }
+        if other == account_name {
+            return Err(Error::new(ErrorKind::InvalidInput, "--exclude-account must be a different account"));
+        }
+        if !self.accounts.contains_key(other) {
+            return Err(account_not_found(other));
+        }
+        let dir = self.home_dir.join(dir);
+        if dir == account.codebase_dir_path || !account.covers(&dir) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("--exclude-dir {} is not inside {}'s codebase dir {}", dir.display(), account_name, account.dir_display()),
+            ));
+        }
+        Ok(dir)
+    }
+
+    /// Makes repositories under `dir`, inside the account's codebase dir,
+    /// use `other` instead. Replaces an earlier exclusion of the same dir.
+    pub fn exclude_dir(&mut self, account_name: &str, dir: &Path, other: &str) -> Result<()> {
+        let dir = self.check_exclusion(account_name, dir, other)?;
+
+        let account = self.accounts.get_mut(account_name).expect("account looked up above");
+        account.exclusions.retain(|exclusion| exclusion.dir != dir);
+        account.exclusions.push(Exclusion { dir: dir.clone(), account: other.to_string() });
+        self.save()?;
+        self.rewrite_include_ifs(account_name)?;
+        println!("Repositories under {} now use account {}", dir.display(), other);
+        Ok(())
+    }
+
+    /// Gives `dir` back to the account it was excluded from.
+    pub fn remove_exclusion(&mut self, account_name: &str, dir: &Path) -> Result<()> {
+        let dir = self.home_dir.join(dir);
+        let account = self.accounts.get_mut(account_name).ok_or_else(|| account_not_found(account_name))?;
+        let before = account.exclusions.len();
+        account.exclusions.retain(|exclusion| exclusion.dir != dir);
+        if account.exclusions.len() == before {
+            return Err(Error::new(ErrorKind::NotFound, format!("{} has no exclusion for {}", account_name, dir.display())));
+        }
+        self.save()?;
+        self.rewrite_include_ifs(account_name)?;
+        println!("Repositories under {} use account {} again", dir.display(), account_name);
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Annotating a config file with the account and setup option behind each
+//! line multigit wrote.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::account::{self, GitAccount, GitAccountManager};
+use crate::disable;
+use crate::gitconfig;
+use crate::managed::{self, Marker};
+use crate::report::{print_json, OutputFormat};
+use crate::ssh_config;
+use crate::state::State;
+
+const UNMANAGED: &str = "unmanaged";
+
+#[derive(Serialize)]
+pub struct LineInfo {
+    /// 1-based line number.
+    pub line: usize,
+    pub text: String,
+    pub account: Option<String>,
+    pub reason: String,
+    /// Seconds since the Unix epoch, when known.
+    pub written: Option<u64>,
+}
+
+enum FileKind<'a> {
+    SshConfig,
+    GlobalGitconfig,
+    Include(&'a GitAccount),
+}
+
+fn resolve(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// `YYYY-MM-DD HH:MM UTC` for a Unix timestamp.
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let (hour, minute) = (secs % 86_400 / 3_600, secs % 3_600 / 60);
+
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, hour, minute)
+}
+
+/// The account named by a managed-block marker line.
+fn marker_owner(line: &str) -> Option<(Option<String>, String)> {
+    match managed::marker(line)? {
+        Marker::Begin(account) | Marker::End(account) => {
+            Some((Some(account.to_string()), "managed block marker".to_string()))
+        }
+    }
+}
+
+impl GitAccountManager {
+    fn file_kind(&self, path: &Path) -> Option<FileKind<'_>> {
+        let path = resolve(path);
+        if path == resolve(&self.ssh_config_path()) || path == resolve(&self.home_dir.join(".ssh").join("config")) {
+            return Some(FileKind::SshConfig);
+        }
+        if path == resolve(&self.global_gitconfig_path()) {
+            return Some(FileKind::GlobalGitconfig);
+        }
+        self.sorted_accounts(true)
+            .into_iter()
+            .find(|account| resolve(&account.codebase_dir_path.join(".gitconfig")) == path)
+            .map(FileKind::Include)
+    }
+
+    fn explain_ssh_config(&self, lines: &[&str]) -> Vec<(Option<String>, String)> {
+        let blocks = ssh_config::host_blocks(lines);
+        let accounts = self.sorted_accounts(true);
+
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if let Some((account, _)) = disable::disabled_line(line) {
+                    return (Some(account.to_string()), "commented out by disable".to_string());
+                }
+                if let Some(owner) = marker_owner(line) {
+                    return owner;
+                }
+                let Some((keyword, value)) = ssh_config::directive(line) else {
+                    return (None, UNMANAGED.to_string());
+                };
+                if keyword == "include" && value.contains(".config/multigit/ssh_config") {
+                    return (None, "include of the managed ssh config (setup --managed-ssh-config)".to_string());
+                }
+
+                let owner = blocks
+                    .iter()
+                    .find(|block| i >= block.start && i < block.end)
+                    .and_then(|block| accounts.iter().find(|account| block.patterns.contains(&account.host)));
+                let Some(account) = owner else {
+                    return (None, UNMANAGED.to_string());
+                };
+
+                let reason = match keyword.as_str() {
+                    "host" => "Host alias (setup --host)".to_string(),
+                    "hostname" => "provider hostname".to_string(),
+                    "pubkeyacceptedalgorithms" => "setup --pubkey-algorithms".to_string(),
+                    "certificatefile" => "setup --certificate-file".to_string(),
+                    "userknownhostsfile" => "setup --known-hosts-file".to_string(),
+                    "pkcs11provider" => "setup --pkcs11-provider".to_string(),
+                    "proxyjump" if account.proxy_jump.is_some() => "setup --proxy-jump".to_string(),
+                    "stricthostkeychecking" if account.strict_host_key_checking => "setup --strict-host-key-checking".to_string(),
+                    "controlmaster" | "controlpath" | "controlpersist" if account.multiplexing.is_some() => "setup --multiplexing".to_string(),
+                    "addkeystoagent" | "usekeychain" if account.keychain.is_some() => "setup --keychain store".to_string(),
+                    "identitiesonly" if account.minimal_ssh_block => "setup --minimal".to_string(),
+                    "identityfile" => {
+                        let path = ssh_config::expand_tilde(value, &self.home_dir);
+                        if path == account.ssh_key {
+                            "primary key (setup)".to_string()
+                        } else if let Some(key) = account.secondary_keys.iter().find(|key| key.path == path) {
+                            format!("key '{}' (key add --label {})", key.label, key.label)
+                        } else {
+                            "key not registered to the account (see sync)".to_string()
+                        }
+                    }
+                    _ => "ssh Host template".to_string(),
+                };
+                (Some(account.name.clone()), reason)
+            })
+            .collect()
+    }
+
+    fn explain_global_gitconfig(&self, lines: &[&str]) -> Vec<(Option<String>, String)> {
+        let sections = gitconfig::sections(lines);
+        let mut owners: Vec<(Option<String>, String)> = lines
+            .iter()
+            .map(|line| match disable::disabled_line(line) {
+                Some((account, _)) => (Some(account.to_string()), "commented out by disable".to_string()),
+                None => marker_owner(line).unwrap_or_else(|| (None, UNMANAGED.to_string())),
+            })
+            .collect();
+
+        for account in self.sorted_accounts(true) {
+            let windows_include = account.windows_include_path();
+            for include_path in account.include_paths() {
+                let header_reason = if windows_include.as_ref() == Some(&include_path) {
+                    "includeIf for the Windows path of the codebase dir (setup --also-windows)"
+                } else if !account.scope.is_dir() {
+                    "includeIf for remotes through the account's alias (setup --scope remote-url)"
+                } else {
+                    "includeIf for the codebase dir (setup --codebase-dir)"
+                };
+                for section in gitconfig::include_ifs_for(lines, &sections, &include_path, &self.home_dir) {
+                    for i in section.start..section.end {
+                        let reason = if i == section.start {
+                            header_reason
+                        } else {
+                            match gitconfig::entry(lines[i]) {
+                                Some((key, _)) if key == "path" => "include file for the account",
+                                Some(_) => "includeIf section",
+                                None => continue,
+                            }
+                        };
+                        owners[i] = (Some(account.name.clone()), reason.to_string());
+                    }
+                }
+            }
+        }
+
+        for account in self.sorted_accounts(true) {
+            for exclusion in &account.exclusions {
+                let pattern = account::gitdir_pattern(&exclusion.dir.to_string_lossy());
+                let headers = [&pattern, &ssh_config::contract_tilde(&pattern, &self.home_dir)].map(|pattern| format!("includeIf \"gitdir/i:{}\"", pattern));
+                for section in sections.iter().filter(|section| headers.contains(&section.header)) {
+                    let reason = format!("{} uses account {} (edit --exclude-dir)", exclusion.dir.display(), exclusion.account);
+                    for i in section.start..section.end {
+                        if i == section.start || gitconfig::entry(lines[i]).is_some() {
+                            owners[i] = (Some(account.name.clone()), reason.clone());
+                        }
+                    }
+                }
+            }
+        }
+        owners
+    }
+
+    fn explain_include(&self, lines: &[&str], account: &GitAccount) -> Vec<(Option<String>, String)> {
+        let mut section = String::new();
+        let mut subsection = String::new();
+        lines
+            .iter()
+            .map(|line| {
+                if let Some(header) = gitconfig::sections(&[line]).first() {
+                    let mut words = header.header.split_whitespace();
+                    section = words.next().unwrap_or("").to_ascii_lowercase();
+                    subsection = words.next().unwrap_or("").to_string();
+                }
+                if line.trim().is_empty() {
+                    return (None, UNMANAGED.to_string());
+                }
+                if let Some(owner) = marker_owner(line) {
+                    return owner;
+                }
+                let entry = gitconfig::entry(line);
+                let key = entry.as_ref().map(|(key, _)| key.clone());
+                let rewrites_https = entry.as_ref().is_some_and(|(_, value)| value.starts_with("https://") || value.starts_with("git://"));
+                let reason = match (section.as_str(), key.as_deref()) {
+                    ("url", Some("insteadof")) if rewrites_https => "rewrite of https:// and git:// remotes (off with setup --no-rewrite-https)",
+                    ("url", _) => "insteadOf rewrite to the Host alias (setup --host)",
+                    ("user", Some("name")) => "setup --name",
+                    ("user", Some("email")) => "setup --email",
+                    ("committer", Some("name")) => "setup --committer-name",
+                    ("committer", _) => "setup --committer-email",
+                    ("sendemail", Some("smtpserver")) => "setup --smtp-server",
+                    ("sendemail", Some("smtpuser")) => "setup --smtp-user",
+                    ("sendemail", Some("smtpencryption")) => "setup --smtp-encryption",
+                    ("sendemail", Some("smtpserverport")) => "setup --smtp-server-port",
+                    ("sendemail", Some("from")) => "setup --sendemail-from",
+                    ("sendemail", _) => "git send-email settings (setup --smtp-server)",
+                    ("credential", _) if subsection.starts_with("\"smtp://") => "setup --smtp-credential-helper",
+                    ("credential", _) => "setup --https-username",
+                    ("core", Some("hookspath")) => "setup --hooks-path",
+                    ("init", Some("templatedir")) => "setup --template-dir",
+                    ("filter", _) if subsection == "\"lfs\"" && account.lfs => "Git LFS filter (setup --enable-lfs)",
+                    ("http", Some("sslcert")) if !subsection.is_empty() => "setup --ssl-cert",
+                    ("http", Some("sslkey")) if !subsection.is_empty() => "setup --ssl-key",
+                    ("http", Some("sslcainfo")) if !subsection.is_empty() => "setup --ssl-ca-info",
+                    ("http", None) if !subsection.is_empty() && !account.client_tls.is_empty() => "TLS client certificate (setup --ssl-cert)",
+                    ("http", Some("proxy")) if account.http_proxy.is_some() => "setup --proxy",
+                    ("http", None) if account.http_proxy.is_some() && account.git_settings.keys().all(|key| !key.starts_with("http.")) => "setup --proxy",
+                    ("core", None) if account.hooks_path.is_some() && account.git_settings.keys().all(|key| !key.starts_with("core.")) => {
+                        "setup --hooks-path"
+                    }
+                    (section, _)
+                        if account.git_settings.keys().any(|key| {
+                            key.split_once('.').is_some_and(|(prefix, _)| prefix.eq_ignore_ascii_case(section))
+                        }) =>
+                    {
+                        "setup --set"
+                    }
+                    _ => "gitconfig include template",
+                };
+                (Some(account.name.clone()), reason.to_string())
+            })
+            .collect()
+    }
+
+    pub fn explain(&self, path: &Path) -> Result<Vec<LineInfo>> {
+        let Some(kind) = self.file_kind(path) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a file multigit writes", path.display()),
+            ));
+        };
+        let content = fs::read_to_string(path)?;
+        let lines: Vec<&str> = content.lines().collect();
+        let owners = match kind {
+            FileKind::SshConfig => self.explain_ssh_config(&lines),
+            FileKind::GlobalGitconfig => self.explain_global_gitconfig(&lines),
+            FileKind::Include(account) => self.explain_include(&lines, account),
+        };
+
+        let state = State::load(&self.home_dir)?;
+        Ok(lines
+            .iter()
+            .zip(owners)
+            .enumerate()
+            .map(|(i, (text, (account, reason)))| LineInfo {
+                line: i + 1,
+                text: text.to_string(),
+                written: account.as_ref().and_then(|account| state.last_written(account, path)),
+                account,
+                reason,
+            })
+            .collect())
+    }
+}
+
+pub fn run_explain(manager: &GitAccountManager, path: &Path, format: OutputFormat) -> Result<bool> {
+    let lines = manager.explain(path)?;
+
+    match format {
+        OutputFormat::Json => {
+            print_json(&lines)?;
+        }
+        OutputFormat::Text => {
+            let labels: Vec<String> = lines
+                .iter()
+                .map(|info| match (&info.account, info.written) {
+                    (None, _) => info.reason.clone(),
+                    (Some(account), None) => format!("{}: {}", account, info.reason),
+                    (Some(account), Some(at)) => format!("{}: {}, written {}", account, info.reason, format_utc(at)),
+                })
+                .collect();
+            let width = labels.iter().map(|label| label.len()).max().unwrap_or(0);
+            for (info, label) in lines.iter().zip(&labels) {
+                println!("{:>4}  {:<width$}  | {}", info.line, label, info.text, width = width);
+            }
+        }
+    }
+    Ok(true)
+}
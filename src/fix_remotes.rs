@@ -0,0 +1,156 @@
+//! `fix-remotes`: rewriting the remotes of an account's repositories to the
+//! form the account expects, whatever mix of canonical, aliased and HTTPS
+//! URLs they were cloned with.
+
+use std::io::Result;
+use std::path::PathBuf;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::discover::{self, RemoteUrl};
+use crate::errors::account_not_found;
+use crate::git_url;
+use crate::report::print_table;
+
+struct Rewrite {
+    repo: PathBuf,
+    remote: RemoteUrl,
+    proposed: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Rewrite(String),
+    AlreadyRight,
+    /// Not a URL on the account's provider.
+    Foreign,
+}
+
+/// What `url` should be for `account`: through its Host alias, or over
+/// HTTPS for accounts set up with `--https-username`.
+pub fn verdict(account: &GitAccount, url: &str) -> Verdict {
+    let hostname = account.hostname().to_ascii_lowercase();
+    let alias_prefix = format!("{}-", hostname);
+    let https = account.https_username.is_some();
+
+    if let Some(ssh) = git_url::parse(url) {
+        let host = ssh.host.to_ascii_lowercase();
+        if host != hostname && !host.starts_with(&alias_prefix) {
+            return Verdict::Foreign;
+        }
+        if https {
+            return Verdict::Rewrite(format!("https://{}/{}", hostname, ssh.path.trim_start_matches('/')));
+        }
+        if host.eq_ignore_ascii_case(&account.host) {
+            return Verdict::AlreadyRight;
+        }
+        return Verdict::Rewrite(ssh.with_host(&account.host));
+    }
+
+    match git_url::parse_https(url) {
+        Some((host, _)) if !host.eq_ignore_ascii_case(&hostname) => Verdict::Foreign,
+        Some(_) if https => Verdict::AlreadyRight,
+        Some((_, path)) => Verdict::Rewrite(format!("git@{}:{}", account.host, path)),
+        None => Verdict::Foreign,
+    }
+}
+
+impl GitAccountManager {
+    /// Rewrites the `origin` URLs (every remote's, with `all_remotes`) of the
+    /// repositories under the account's codebase dir to the account's form.
+    /// Repositories belonging to an account nested inside it are left to
+    /// that account. Each URL is its own `git remote set-url`, so stopping
+    /// part way leaves every repository either done or untouched.
+    pub fn fix_remotes(&self, account_name: &str, dry_run: bool, all_remotes: bool) -> Result<bool> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+
+        let mut rewrites = Vec::new();
+        let (mut unchanged, mut foreign) = (0, 0);
+        discover::walk(&account.codebase_dir_path, &mut |repo| {
+            if self.account_for_path(&repo.path).map(|owner| owner.name.as_str()) != Some(account_name) {
+                return;
+            }
+            for remote in discover::remote_urls(&repo.path) {
+                if !all_remotes && remote.remote != "origin" {
+                    continue;
+                }
+                match verdict(account, &remote.url) {
+                    Verdict::Rewrite(proposed) => rewrites.push(Rewrite { repo: repo.path.clone(), remote, proposed }),
+                    Verdict::AlreadyRight => unchanged += 1,
+                    Verdict::Foreign => foreign += 1,
+                }
+            }
+        });
+
+        if rewrites.is_empty() {
+            println!("Nothing to rewrite under {} ({} already right, {} not on {})", account.codebase_dir_path.display(), unchanged, foreign, account.hostname());
+            return Ok(true);
+        }
+
+        if dry_run {
+            let mut rows = vec![["REPO".to_string(), "CURRENT".to_string(), "PROPOSED".to_string()]];
+            rows.extend(rewrites.iter().map(|rewrite| {
+                let repo = rewrite.repo.strip_prefix(&account.codebase_dir_path).unwrap_or(&rewrite.repo);
+                let current = if rewrite.remote.remote == "origin" && rewrite.remote.key == "url" {
+                    rewrite.remote.url.clone()
+                } else {
+                    format!("{} ({}.{})", rewrite.remote.url, rewrite.remote.remote, rewrite.remote.key)
+                };
+                [repo.display().to_string(), current, rewrite.proposed.clone()]
+            }));
+            print_table(&rows);
+            println!("{} to rewrite, {} already right, {} not on {}", rewrites.len(), unchanged, foreign, account.hostname());
+            return Ok(true);
+        }
+
+        let mut ok = true;
+        let mut rewritten = 0;
+        for rewrite in &rewrites {
+            match discover::set_remote_url(&rewrite.repo, &rewrite.remote, &rewrite.proposed) {
+                Ok(()) => {
+                    println!("{}: {}.{} {} -> {}", rewrite.repo.display(), rewrite.remote.remote, rewrite.remote.key, rewrite.remote.url, rewrite.proposed);
+                    rewritten += 1;
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ok = false;
+                }
+            }
+        }
+        println!("{} of {} remote(s) rewritten", rewritten, rewrites.len());
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn github_account_rewrites_its_urls_and_leaves_gitlab_ones() {
+        let home = TempHome::new();
+        let account = testutil::account(&home, "work");
+        assert_eq!(verdict(&account, "git@github.com:org/repo.git"), Verdict::Rewrite("git@github.com-work:org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "https://github.com/org/repo.git"), Verdict::Rewrite("git@github.com-work:org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "git@github.com-work:org/repo.git"), Verdict::AlreadyRight);
+        assert_eq!(verdict(&account, "git@gitlab.com:org/repo.git"), Verdict::Foreign);
+        assert_eq!(verdict(&account, "https://gitlab.com/org/repo.git"), Verdict::Foreign);
+    }
+
+    #[test]
+    fn gitlab_account_rewrites_gitlab_urls() {
+        let home = TempHome::new();
+        let mut account = testutil::account(&home, "work");
+        account.hostname = Some("gitlab.com".to_string());
+        account.host = "gitlab.com-work".to_string();
+        assert_eq!(verdict(&account, "git@gitlab.com:org/repo.git"), Verdict::Rewrite("git@gitlab.com-work:org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "git@gitlab.com-old:org/repo.git"), Verdict::Rewrite("git@gitlab.com-work:org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "https://gitlab.com/org/repo.git"), Verdict::Rewrite("git@gitlab.com-work:org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "git@gitlab.com-work:org/repo.git"), Verdict::AlreadyRight);
+        assert_eq!(verdict(&account, "git@github.com:org/repo.git"), Verdict::Foreign);
+
+        account.https_username = Some("me".to_string());
+        assert_eq!(verdict(&account, "git@gitlab.com:org/repo.git"), Verdict::Rewrite("https://gitlab.com/org/repo.git".to_string()));
+        assert_eq!(verdict(&account, "https://gitlab.com/org/repo.git"), Verdict::AlreadyRight);
+    }
+}
@@ -0,0 +1,515 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::platform;
+
+/// How long to wait for another process editing the same file.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a command that writes waits for another multigit that does.
+const RUN_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Size and modification time of a file as it was read.
+type Stamp = (u64, Option<SystemTime>);
+
+thread_local! {
+    /// Files read or written during this run, so the ssh config and
+    /// gitconfigs every step of every account looks at are read from disk
+    /// once. An entry only counts while the file keeps the size and
+    /// modification time it had, so changes made by anything else are seen.
+    static DOCUMENTS: RefCell<HashMap<PathBuf, (Stamp, String)>> = RefCell::new(HashMap::new());
+
+    /// While `batch` runs: the files it holds back, by where they're
+    /// written, each with the path it was written as and its new contents.
+    static PENDING: RefCell<Option<Batch>> = const { RefCell::new(None) };
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Reads from and writes to disk of each file, for tests that bound them.
+    static DISK_IO: RefCell<HashMap<PathBuf, (usize, usize)>> = RefCell::new(HashMap::new());
+}
+
+struct Batch {
+    files: Vec<PathBuf>,
+    pending: HashMap<PathBuf, (PathBuf, Vec<u8>)>,
+}
+
+#[cfg(test)]
+fn count_io(path: &Path, write: bool) {
+    DISK_IO.with(|io| {
+        let mut io = io.borrow_mut();
+        let (reads, writes) = io.entry(write_target(path)).or_default();
+        *if write { writes } else { reads } += 1;
+    });
+}
+
+#[cfg(not(test))]
+fn count_io(_path: &Path, _write: bool) {}
+
+/// How often `path` was read from and written to disk on this thread.
+#[cfg(test)]
+pub fn disk_io(path: &Path) -> (usize, usize) {
+    DISK_IO.with(|io| io.borrow().get(&write_target(path)).copied().unwrap_or_default())
+}
+
+/// The contents `batch` holds back for `path`, if any.
+fn pending(path: &Path) -> Option<Vec<u8>> {
+    PENDING.with(|batch| {
+        let batch = batch.borrow();
+        let batch = batch.as_ref()?;
+        batch.pending.get(&write_target(path)).map(|(_, contents)| contents.clone())
+    })
+}
+
+fn batching() -> bool {
+    PENDING.with(|batch| batch.borrow().is_some())
+}
+
+/// Whether a running `batch` holds back writes to `path`.
+fn held(path: &Path) -> bool {
+    let target = write_target(path);
+    PENDING.with(|batch| batch.borrow().as_ref().is_some_and(|batch| batch.files.contains(&target)))
+}
+
+/// Runs `f` with writes to `files` held in memory, then writes each of
+/// them once. Reads through `read_to_string` see the held-back contents.
+/// The files are written even when `f` fails, as whatever it undid on the
+/// way out went through them too. Inside another batch, `f` just runs.
+pub fn batch<T>(files: &[PathBuf], f: impl FnOnce() -> Result<T>) -> Result<T> {
+    if batching() {
+        return f();
+    }
+    let files = files.iter().map(|path| write_target(path)).collect();
+    PENDING.with(|batch| *batch.borrow_mut() = Some(Batch { files, pending: HashMap::new() }));
+    let result = f();
+    let flushed = flush();
+    PENDING.with(|batch| *batch.borrow_mut() = None);
+    match (result, flushed) {
+        (Ok(value), Ok(())) => Ok(value),
+        (Ok(_), Err(e)) => Err(e),
+        (Err(e), flushed) => {
+            if let Err(flush_error) = flushed {
+                eprintln!("warning: {}", flush_error);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Writes what `batch` holds back, for a program about to read the files.
+/// Without a batch there's nothing to write.
+pub fn flush() -> Result<()> {
+    let pending = PENDING.with(|batch| batch.borrow_mut().as_mut().map(|batch| std::mem::take(&mut batch.pending)).unwrap_or_default());
+    let mut result = Ok(());
+    for (target, (link, contents)) in pending {
+        let written = FileLock::acquire(&target).and_then(|_lock| write_file(&link, &target, &contents));
+        if result.is_ok() {
+            result = written;
+        }
+    }
+    result
+}
+
+fn stamp(path: &Path) -> Result<Stamp> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+/// `fs::read_to_string` through the run's cache of files.
+pub fn read_to_string(path: &Path) -> Result<String> {
+    if let Some(contents) = pending(path) {
+        return String::from_utf8(contents).map_err(|e| Error::new(ErrorKind::InvalidData, e));
+    }
+    let stamp = stamp(path)?;
+    let cached = DOCUMENTS.with(|documents| {
+        documents.borrow().get(path).filter(|(cached, _)| *cached == stamp).map(|(_, content)| content.clone())
+    });
+    if let Some(content) = cached {
+        return Ok(content);
+    }
+    let content = fs::read_to_string(path)?;
+    count_io(path, false);
+    DOCUMENTS.with(|documents| documents.borrow_mut().insert(path.to_path_buf(), (stamp, content.clone())));
+    Ok(content)
+}
+
+/// Keeps the cache in step with a write that just replaced `path`.
+fn remember(path: &Path, contents: &[u8]) {
+    let entry = match (stamp(path), std::str::from_utf8(contents)) {
+        (Ok(stamp), Ok(content)) => Some((stamp, content.to_string())),
+        _ => None,
+    };
+    DOCUMENTS.with(|documents| match entry {
+        Some(entry) => documents.borrow_mut().insert(path.to_path_buf(), entry),
+        None => documents.borrow_mut().remove(path),
+    });
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Where writing to `path` ends up: the file at the end of its symlinks,
+/// else `path`. A dotfile linked into a dotfiles repo stays a link.
+pub fn write_target(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    // As many links as the kernel follows before giving up with ELOOP.
+    for _ in 0..40 {
+        match fs::read_link(&path) {
+            Ok(target) => path = path.parent().unwrap_or(Path::new("")).join(target),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Replaces the contents of `path` by writing a sibling temp file and renaming
+/// it over the target, so readers never observe a half-written file. A
+/// symlink is written through, at the file it points to. Inside `batch`,
+/// a file it holds back is only checked for being writable.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let target = write_target(path);
+    if !held(&target) {
+        return write_file(path, &target, contents);
+    }
+    check_writable(&target)?;
+    PENDING.with(|batch| {
+        if let Some(batch) = batch.borrow_mut().as_mut() {
+            batch.pending.insert(target.clone(), (path.to_path_buf(), contents.to_vec()));
+        }
+    });
+    Ok(())
+}
+
+fn check_writable(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    // Replacing the file only needs a writable directory; a file someone
+    // made read-only is left as it is.
+    if path.exists() && !platform::is_writable(path) {
+        return Err(blocked(Error::from(ErrorKind::PermissionDenied), "write", path));
+    }
+    Ok(())
+}
+
+/// `write_atomic` of `link`, whose `write_target` is `path`.
+fn write_file(link: &Path, path: &Path, contents: &[u8]) -> Result<()> {
+    check_writable(path)?;
+
+    let tmp_path = sibling(path, ".multigit-tmp");
+
+    let result = (|| {
+        let mut file = File::create(&tmp_path).map_err(|e| blocked(e, "write", path))?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&tmp_path, metadata.permissions())?;
+            #[cfg(unix)]
+            keep_owner(&file, &metadata, path)?;
+        }
+        fs::rename(&tmp_path, path).map_err(|e| blocked(e, "replace", path))
+    })();
+
+    match result {
+        Ok(()) => {
+            count_io(path, true);
+            remember(link, contents);
+        }
+        Err(_) => {
+            let _ = fs::remove_file(&tmp_path);
+        }
+    }
+    result
+}
+
+/// Whether `e` means the file can't be changed at all: no permission, or a
+/// read-only file system. Retrying won't help, but other files may still be
+/// writable.
+pub fn is_blocked(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem)
+}
+
+/// Names the operation and the file in a permission or read-only error,
+/// which io otherwise reports as a bare "Permission denied (os error 13)".
+/// Other errors pass through unchanged.
+pub fn blocked(e: Error, operation: &str, path: &Path) -> Error {
+    let reason = match e.kind() {
+        ErrorKind::PermissionDenied => "permission denied",
+        ErrorKind::ReadOnlyFilesystem => "read-only file system",
+        _ => return e,
+    };
+    Error::new(e.kind(), format!("Cannot {} {}: {}", operation, path.display(), reason))
+}
+
+/// `path` as text, for the config files that name it. Paths that aren't
+/// UTF-8 are valid on Unix but can't be written into them.
+pub fn utf8(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not valid UTF-8, so it can't be written into ssh or git config", path.to_string_lossy()),
+        )
+    })
+}
+
+pub fn create_dir_all(path: &Path) -> Result<()> {
+    fs::create_dir_all(path).map_err(|e| blocked(e, "create directory", path))
+}
+
+/// Copies `path` to the first free `<path>.multigit-bak`, `.multigit-bak.1`,
+/// ... and returns where it went.
+pub fn backup(path: &Path) -> Result<PathBuf> {
+    let mut target = sibling(path, ".multigit-bak");
+    let mut n = 1;
+    while target.exists() {
+        target = sibling(path, &format!(".multigit-bak.{}", n));
+        n += 1;
+    }
+    fs::copy(path, &target).map_err(|e| blocked(e, "back up", path))?;
+    Ok(target)
+}
+
+/// An advisory lock on `<file>.multigit-lock`, held across a
+/// read-modify-write of a file other processes may edit at the same time.
+/// Not `<file>.lock`, which git takes for its own config writes.
+pub struct FileLock {
+    path: PathBuf,
+    file: File,
+}
+
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, path: &Path) -> bool {
+    path.exists()
+}
+
+impl FileLock {
+    fn acquire(target: &Path) -> Result<FileLock> {
+        let path = sibling(target, ".multigit-lock");
+        Self::acquire_at(path.clone(), target, LOCK_TIMEOUT, || {
+            format!(
+                "Another process has been editing {} for over {}s (lock {}); try again once it finishes",
+                target.display(),
+                LOCK_TIMEOUT.as_secs(),
+                path.display()
+            )
+        })
+    }
+
+    /// Locks `path` on behalf of `target`, giving up after `timeout` with
+    /// the error `busy` describes.
+    fn acquire_at(path: PathBuf, target: &Path, timeout: Duration, busy: impl Fn() -> String) -> Result<FileLock> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .map_err(|e| blocked(e, "lock", target))?;
+            match file.try_lock() {
+                // The previous holder removes the lock file on release, so a
+                // lock on a file that's no longer at `path` doesn't count.
+                Ok(()) if is_same_file(&file, &path) => return Ok(FileLock { path, file }),
+                Ok(()) | Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::new(ErrorKind::TimedOut, busy()));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Removed while still locked; closing the file then releases the lock.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Locks `config_dir` for one multigit run that changes files, so a batch
+/// and a command typed meanwhile don't interleave their edits of the
+/// accounts, the state file and the dotfiles. The lock file holds the pid
+/// of the run that has it.
+pub fn lock_run(config_dir: &Path) -> Result<FileLock> {
+    let path = config_dir.join("multigit.lock");
+    let lock = FileLock::acquire_at(path.clone(), config_dir, RUN_LOCK_TIMEOUT, || {
+        let pid = fs::read_to_string(&path).ok().map(|pid| pid.trim().to_string()).filter(|pid| !pid.is_empty());
+        format!(
+            "Another multigit{} is running and still changing files after {}s; try again once it finishes (lock {})",
+            pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default(),
+            RUN_LOCK_TIMEOUT.as_secs(),
+            path.display()
+        )
+    })?;
+    let mut file = &lock.file;
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    Ok(lock)
+}
+
+/// Locks `path`, reads it (empty if it doesn't exist) and replaces it with
+/// what `edit` returns, or leaves it alone if `edit` returns `None`. Reading
+/// only after the lock is held means the edit never works from stale content.
+pub fn modify(path: &Path, edit: impl FnOnce(&str) -> Result<Option<String>>) -> Result<()> {
+    // A batch takes the lock when it writes; the run lock keeps other
+    // multigits out meanwhile.
+    let _lock = if held(path) { None } else { Some(FileLock::acquire(path)?) };
+    let current = match read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(blocked(e, "read", path)),
+    };
+    match edit(&current)? {
+        Some(updated) => write_atomic(path, updated.as_bytes()),
+        None => Ok(()),
+    }
+}
+
+/// Gives the replacement file the owner of the file it replaces, so editing
+/// someone else's config as root doesn't hand it to root.
+#[cfg(unix)]
+fn keep_owner(file: &File, original: &fs::Metadata, path: &Path) -> Result<()> {
+    use std::os::unix::fs::{fchown, MetadataExt};
+
+    let current = file.metadata()?;
+    if current.uid() == original.uid() && current.gid() == original.gid() {
+        return Ok(());
+    }
+    match fchown(file, Some(original.uid()), Some(original.gid())) {
+        Ok(()) => Ok(()),
+        // Same user, different group: not worth failing the write over.
+        Err(_) if current.uid() == original.uid() => Ok(()),
+        Err(e) => Err(Error::new(
+            e.kind(),
+            format!("Cannot keep {} owned by uid {}: {}", path.display(), original.uid(), e),
+        )),
+    }
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(target: &Path, link: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::managed;
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn concurrent_modifies_lose_nothing() {
+        let home = TempHome::new();
+        let path = home.join(".ssh/config");
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let path = &path;
+                scope.spawn(move || {
+                    for i in 0..20 {
+                        let account = format!("t{}-{}", thread, i);
+                        let block = format!("Host {}\n    User git\n", account);
+                        modify(path, |content| Ok(Some(managed::merge(content, &account, &block)))).unwrap();
+                    }
+                });
+            }
+        });
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(managed::blocks(&lines).len(), 160);
+        for thread in 0..8 {
+            for i in 0..20 {
+                assert!(content.contains(&format!("Host t{}-{}\n", thread, i)));
+            }
+        }
+        assert!(!sibling(&path, ".multigit-lock").exists());
+    }
+
+    #[test]
+    fn contending_runs_take_turns() {
+        let home = TempHome::new();
+        let log = home.join("log");
+        std::thread::scope(|scope| {
+            for run in 0..4 {
+                let (home, log) = (&home, &log);
+                scope.spawn(move || {
+                    let _lock = lock_run(&home.path).unwrap();
+                    let append = |line: String| OpenOptions::new().create(true).append(true).open(log).unwrap().write_all(line.as_bytes()).unwrap();
+                    append(format!("start {}\n", run));
+                    thread::sleep(Duration::from_millis(20));
+                    append(format!("end {}\n", run));
+                });
+            }
+        });
+
+        let log = fs::read_to_string(&log).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 8);
+        for pair in lines.chunks(2) {
+            assert_eq!(pair[0].replace("start", "end"), pair[1], "{}", log);
+        }
+        assert!(!home.join("multigit.lock").exists());
+    }
+
+    #[test]
+    fn a_held_run_lock_times_out_with_the_holders_pid() {
+        let home = TempHome::new();
+        let path = home.join("multigit.lock");
+        let held = lock_run(&home.path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), std::process::id().to_string());
+
+        let Err(error) = FileLock::acquire_at(path.clone(), &home.path, Duration::from_millis(100), || "busy".to_string()) else {
+            panic!("the lock was taken twice");
+        };
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        drop(held);
+        assert!(FileLock::acquire_at(path, &home.path, Duration::from_millis(100), String::new).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_writes_through_symlinks() {
+        let home = TempHome::new();
+        let target = home.write("dotfiles/gitconfig", "[user]\n");
+        symlink(&home.join("dotfiles/gitconfig"), &home.join("link")).unwrap();
+        // A relative link to the first link, resolved against its own dir.
+        symlink(Path::new("link"), &home.join(".gitconfig")).unwrap();
+
+        modify(&home.join(".gitconfig"), |content| Ok(Some(format!("{}    name = me\n", content)))).unwrap();
+
+        assert!(fs::symlink_metadata(home.join(".gitconfig")).unwrap().file_type().is_symlink());
+        assert!(fs::symlink_metadata(home.join("link")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(target).unwrap(), "[user]\n    name = me\n");
+        assert_eq!(read_to_string(&home.join(".gitconfig")).unwrap(), "[user]\n    name = me\n");
+    }
+}
@@ -0,0 +1,352 @@
+//! Performance, display, merge and advice settings an account can add to its include file
+//! with `setup --set key=value`, or `--color`, `--pager`, `--merge-tool`, `--gc-auto`, `--pack-threads`,
+//! `--repack-write-bitmaps` and `--quiet-advice`. Only a curated set of keys is accepted, so a typo
+//! fails at setup instead of being silently ignored by git.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::state::State;
+
+enum Value {
+    Bool,
+    /// A non-negative integer.
+    Count,
+    OneOf(&'static [&'static str]),
+    /// A command from `PAGERS` with plain arguments, or several piped into
+    /// each other.
+    Pager,
+    /// A merge tool name, as git and `mergetool.<tool>` spell it.
+    Tool,
+    /// A one-line shell command with balanced quotes.
+    Command,
+}
+
+/// Programs `core.pager` may run. git hands the value to the shell, so
+/// anything else could run arbitrary commands from the include file.
+const PAGERS: &[&str] = &["less", "more", "most", "cat", "bat", "delta", "diff-so-fancy", "diff-highlight", "moar", "ov"];
+
+fn valid_pager(value: &str) -> bool {
+    value.split('|').all(|command| {
+        let mut words = command.split_whitespace();
+        words.next().is_some_and(|program| PAGERS.contains(&program))
+            && words.all(|word| word.chars().all(|c| c.is_ascii_alphanumeric() || "-=_.,:/+".contains(c)))
+    })
+}
+
+fn valid_tool(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('-') && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Whether `command` would reach the shell as written: a single line whose
+/// quotes are closed. git runs it with the merge's files in $BASE, $LOCAL,
+/// $REMOTE and $MERGED.
+fn valid_command(command: &str) -> bool {
+    let mut quote = None;
+    let mut escaped = false;
+    for c in command.chars() {
+        if c.is_control() {
+            return false;
+        }
+        match (quote, c) {
+            (_, _) if escaped => escaped = false,
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => escaped = true,
+            (None, '\'' | '"') => quote = Some(c),
+            (Some('"'), '"') => quote = None,
+            _ => {}
+        }
+    }
+    !command.trim().is_empty() && quote.is_none() && !escaped
+}
+
+struct Knob {
+    key: &'static str,
+    value: Value,
+    help: &'static str,
+}
+
+const KNOBS: &[Knob] = &[
+    Knob { key: "fetch.parallel", value: Value::Count, help: "fetch submodules and remotes in parallel (0 = one per CPU)" },
+    Knob { key: "fetch.prune", value: Value::Bool, help: "drop remote-tracking branches deleted on the remote" },
+    Knob { key: "fetch.writeCommitGraph", value: Value::Bool, help: "update the commit-graph after each fetch" },
+    Knob { key: "maintenance.auto", value: Value::Bool, help: "run `git maintenance run --auto` after commands that add objects" },
+    Knob {
+        key: "maintenance.strategy",
+        value: Value::OneOf(&["none", "incremental"]),
+        help: "schedule used by `git maintenance start`",
+    },
+    Knob { key: "gc.auto", value: Value::Count, help: "loose object count that triggers auto gc (0 disables)" },
+    Knob { key: "core.fsmonitor", value: Value::Bool, help: "use the built-in filesystem monitor" },
+    Knob { key: "core.untrackedCache", value: Value::Bool, help: "cache untracked file lookups for `git status`" },
+    Knob { key: "feature.manyFiles", value: Value::Bool, help: "defaults tuned for repositories with many files" },
+    Knob { key: "pack.threads", value: Value::Count, help: "threads used when packing (0 = one per CPU)" },
+    Knob { key: "repack.writeBitmaps", value: Value::Bool, help: "write reachability bitmaps when repacking, for faster clones and fetches" },
+    Knob {
+        key: "color.ui",
+        value: Value::OneOf(&["auto", "always", "never", "true", "false"]),
+        help: "when git colors its output",
+    },
+    Knob { key: "core.pager", value: Value::Pager, help: "pager for git's output, e.g. `less -FRX` or `delta`" },
+    Knob { key: "rerere.enabled", value: Value::Bool, help: "record conflict resolutions and replay them" },
+    Knob { key: "merge.tool", value: Value::Tool, help: "tool `git mergetool` runs, e.g. vimdiff or meld" },
+    Knob {
+        key: "mergetool.<tool>.cmd",
+        value: Value::Command,
+        help: "command for a merge tool git doesn't know, using $BASE, $LOCAL, $REMOTE and $MERGED",
+    },
+    Knob { key: "advice.detachedHead", value: Value::Bool, help: "explain detached HEAD on checkout" },
+    Knob { key: "advice.pushNonFastForward", value: Value::Bool, help: "hint at pulling when a push isn't a fast-forward" },
+    Knob { key: "advice.pushUpdateRejected", value: Value::Bool, help: "hint at the reason a pushed ref was rejected" },
+    Knob { key: "advice.statusHints", value: Value::Bool, help: "show the commands to run in `git status`" },
+    Knob { key: "advice.commitBeforeMerge", value: Value::Bool, help: "hint at committing when local changes block a merge" },
+    Knob { key: "advice.resolveConflict", value: Value::Bool, help: "hint at resolving conflicts before a command can run" },
+    Knob { key: "advice.skippedCherryPicks", value: Value::Bool, help: "list commits a rebase skipped as already upstream" },
+    Knob { key: "advice.addIgnoredFile", value: Value::Bool, help: "hint at --force when adding an ignored file" },
+];
+
+/// The advice `--quiet-advice` turns off: the hints that only repeat what
+/// an experienced user knows. `advice.addIgnoredFile` stays, as it catches
+/// files left out by mistake.
+pub const QUIET_ADVICE: &[&str] = &[
+    "advice.detachedHead",
+    "advice.pushNonFastForward",
+    "advice.pushUpdateRejected",
+    "advice.statusHints",
+    "advice.commitBeforeMerge",
+    "advice.resolveConflict",
+    "advice.skippedCherryPicks",
+];
+
+/// The knob for `key`, and the key as it's stored: the knob's spelling
+/// with the tool a `<tool>` subsection names.
+fn knob(key: &str) -> Option<(&'static Knob, String)> {
+    KNOBS.iter().find_map(|knob| match knob.key.split_once(".<tool>.") {
+        Some((section, name)) => {
+            let (prefix, rest) = key.split_once('.')?;
+            let (tool, suffix) = rest.rsplit_once('.')?;
+            (prefix.eq_ignore_ascii_case(section) && suffix.eq_ignore_ascii_case(name) && valid_tool(tool))
+                .then(|| (knob, format!("{}.{}.{}", section, tool, name)))
+        }
+        None => knob.key.eq_ignore_ascii_case(key).then(|| (knob, knob.key.to_string())),
+    })
+}
+
+/// The keys `--set` accepts, one per line with what they do.
+fn describe() -> String {
+    let width = KNOBS.iter().map(|knob| knob.key.len()).max().unwrap_or(0);
+    KNOBS
+        .iter()
+        .map(|knob| format!("  {:<width$}  {}", knob.key, knob.help, width = width))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn long_help() -> String {
+    format!("Add a git setting to the account's include file, as key=value. Repeatable. Supported keys:\n{}", describe())
+}
+
+/// Parses a `key=value` spec into the key's canonical spelling and the value.
+pub fn parse(spec: &str) -> Result<(String, String)> {
+    let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+
+    let (key, value) = spec
+        .split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .ok_or_else(|| invalid(format!("Expected key=value, got '{}'", spec)))?;
+    let (knob, key) = knob(key)
+        .ok_or_else(|| invalid(format!("'{}' is not a setting --set supports; supported settings:\n{}", key, describe())))?;
+
+    let valid = match knob.value {
+        Value::Bool => ["true", "false", "yes", "no", "on", "off", "1", "0"].contains(&value.to_ascii_lowercase().as_str()),
+        Value::Count => value.parse::<u32>().is_ok(),
+        Value::OneOf(choices) => choices.contains(&value),
+        Value::Pager => valid_pager(value),
+        Value::Tool => valid_tool(value),
+        Value::Command => valid_command(value),
+    };
+    if !valid {
+        let expected = match knob.value {
+            Value::Bool => "true or false".to_string(),
+            Value::Count => "a non-negative integer".to_string(),
+            Value::OneOf(choices) => format!("one of {}", choices.join(", ")),
+            Value::Pager => format!("one of {}, with plain arguments", PAGERS.join(", ")),
+            Value::Tool => "a tool name of letters, digits, -, _ and .".to_string(),
+            Value::Command => "a one-line command with closed quotes".to_string(),
+        };
+        return Err(invalid(format!("Invalid value '{}' for {}; expected {}", value, key, expected)));
+    }
+    Ok((key, value.to_string()))
+}
+
+/// Checks settings that didn't come through `parse`, such as a plan
+/// file's, the same way.
+pub fn validate(settings: &BTreeMap<String, String>) -> Result<()> {
+    for (key, value) in settings {
+        parse(&format!("{}={}", key, value))?;
+    }
+    Ok(())
+}
+
+/// `value` as git config reads it back: quoted, with `"` and `\` escaped,
+/// when it has characters git would otherwise strip or take as a comment.
+fn config_value(value: &str) -> String {
+    if value.contains(['"', '\\', '#', ';']) {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// git config sections for `settings`, one section per prefix, or per
+/// prefix and subsection for `section.subsection.name` keys.
+pub fn render(settings: &BTreeMap<String, String>) -> String {
+    type Header<'a> = (&'a str, Option<&'a str>);
+    let mut sections: BTreeMap<Header, Vec<(&str, &str)>> = BTreeMap::new();
+    for (key, value) in settings {
+        let (section, rest) = key.split_once('.').expect("settings keys are section.name");
+        let (subsection, name) = match rest.rsplit_once('.') {
+            Some((subsection, name)) => (Some(subsection), name),
+            None => (None, rest),
+        };
+        sections.entry((section, subsection)).or_default().push((name, value));
+    }
+
+    let mut out = String::new();
+    for ((section, subsection), entries) in sections {
+        match subsection {
+            Some(subsection) => out.push_str(&format!("[{} \"{}\"]\n", section, subsection)),
+            None => out.push_str(&format!("[{}]\n", section)),
+        }
+        for (name, value) in entries {
+            out.push_str(&format!("    {} = {}\n", name, config_value(value)));
+        }
+    }
+    out
+}
+
+impl GitAccountManager {
+    /// `edit --unset`: drops `key` from the account's include file, either
+    /// one of its `--set` settings, `core.hooksPath`, `init.templateDir`,
+    /// `filter.lfs` (all of it), a
+    /// committer value or a `sendemail` one. The SMTP credential helper and
+    /// port go with the server.
+    pub fn unset_setting(&mut self, account_name: &str, key: &str) -> Result<()> {
+        let account = self.accounts.get_mut(account_name).ok_or_else(|| account_not_found(account_name))?;
+        let dedicated = match key.to_ascii_lowercase().as_str() {
+            "core.hookspath" => Some(account.hooks_path.take().is_some()),
+            "init.templatedir" => Some(account.template_dir.take().is_some()),
+            "filter.lfs" => Some(std::mem::take(&mut account.lfs)),
+            "committer.name" => Some(account.committer_name.take().is_some()),
+            "committer.email" => Some(account.committer_email.take().is_some()),
+            "sendemail.smtpserver" => {
+                account.sendemail.credential_helper = None;
+                account.sendemail.smtp_server_port = None;
+                Some(account.sendemail.smtp_server.take().is_some())
+            }
+            "sendemail.smtpuser" => Some(account.sendemail.smtp_user.take().is_some()),
+            "sendemail.smtpencryption" => Some(account.sendemail.smtp_encryption.take().is_some()),
+            "sendemail.smtpserverport" => Some(account.sendemail.smtp_server_port.take().is_some()),
+            "sendemail.from" => Some(account.sendemail.from.take().is_some()),
+            _ => None,
+        };
+        if let Some(was_set) = dedicated {
+            if !was_set {
+                return Err(Error::new(ErrorKind::NotFound, format!("{} doesn't set {}", account_name, key)));
+            }
+        } else {
+            let Some(stored) = account.git_settings.keys().find(|stored| stored.eq_ignore_ascii_case(key)).cloned() else {
+                return Err(Error::new(ErrorKind::NotFound, format!("{} doesn't set {}", account_name, key)));
+            };
+            account.git_settings.remove(&stored);
+        }
+        self.save()?;
+
+        let account = &self.accounts[account_name];
+        self.setup_local_gitconfig(account)?;
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &self.include_file_path(account));
+        state.save(&self.home_dir)?;
+        println!("Removed {} from {}'s include file", key, account_name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::SetupOptions;
+    use crate::testutil::TempHome;
+
+    /// The include file setup writes for an account with `specs`, each
+    /// `key=value` as `--set` takes it.
+    fn include_file(specs: &[&str]) -> Result<String> {
+        let home = TempHome::new();
+        let git_settings = specs.iter().map(|spec| parse(spec)).collect::<Result<_>>()?;
+        let options = SetupOptions { git_settings, ..SetupOptions::default() };
+        home.manager().setup_account("work", "me@example.com", "code/work", "github.com-work", options)?;
+        Ok(home.read("code/work/.gitconfig"))
+    }
+
+    #[test]
+    fn fetch_and_maintenance_settings() {
+        let content = include_file(&["fetch.parallel=8", "maintenance.auto=false", "fetch.prune=true"]).unwrap();
+        assert!(content.contains("[fetch]\n    parallel = 8\n    prune = true\n"));
+        assert!(content.contains("[maintenance]\n    auto = false\n"));
+        assert!(parse("fetch.parallel=-1").is_err());
+        assert!(parse("maintenance.auto=sometimes").is_err());
+    }
+
+    #[test]
+    fn color_and_pager_settings() {
+        let content = include_file(&["color.ui=never", "core.pager=less -FRX"]).unwrap();
+        assert!(content.contains("[color]\n    ui = never\n"));
+        assert!(content.contains("[core]\n    pager = less -FRX\n"));
+        for invalid in ["color.ui=rainbow", "color.ui=", "core.pager=sh -c 'rm -rf ~'", "core.pager=less; rm x"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn merge_and_rerere_settings() {
+        let content = include_file(&["rerere.enabled=true", "merge.tool=mymerge", "mergetool.mymerge.cmd=mymerge \"$LOCAL\" \"$REMOTE\" -o \"$MERGED\""]).unwrap();
+        assert!(content.contains("[merge]\n    tool = mymerge\n"), "{}", content);
+        assert!(content.contains("[mergetool \"mymerge\"]\n    cmd = \"mymerge \\\"$LOCAL\\\" \\\"$REMOTE\\\" -o \\\"$MERGED\\\"\"\n"), "{}", content);
+        assert!(content.contains("[rerere]\n    enabled = true\n"), "{}", content);
+        assert_eq!(parse("MergeTool.meld.Cmd=meld").unwrap().0, "mergetool.meld.cmd");
+        for invalid in ["rerere.enabled=maybe", "merge.tool=-x", "merge.tool=", "mergetool.meld.cmd=meld \"$MERGED", "mergetool.a b.cmd=x"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn quiet_advice_writes_an_advice_section() {
+        let mut specs: Vec<String> = QUIET_ADVICE.iter().map(|key| format!("{}=false", key)).collect();
+        specs.push("advice.statusHints=true".to_string());
+        let specs: Vec<&str> = specs.iter().map(String::as_str).collect();
+        let content = include_file(&specs).unwrap();
+        assert!(
+            content.contains(
+                "[advice]\n    commitBeforeMerge = false\n    detachedHead = false\n    pushNonFastForward = false\n    pushUpdateRejected = false\n    \
+                 resolveConflict = false\n    skippedCherryPicks = false\n    statusHints = true\n"
+            ),
+            "{}",
+            content
+        );
+        assert!(parse("advice.detachedHead=sometimes").is_err());
+    }
+
+    #[test]
+    fn gc_and_repack_settings() {
+        let content = include_file(&["gc.auto=0", "pack.threads=4", "repack.writeBitmaps=true"]).unwrap();
+        assert!(content.contains("[gc]\n    auto = 0\n"), "{}", content);
+        assert!(content.contains("[pack]\n    threads = 4\n"), "{}", content);
+        assert!(content.contains("[repack]\n    writeBitmaps = true\n"), "{}", content);
+        for invalid in ["gc.auto=-1", "gc.auto=lots", "gc.auto=", "pack.threads=1.5", "repack.writeBitmaps=2"] {
+            assert!(parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
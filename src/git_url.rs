@@ -0,0 +1,78 @@
+//! The ssh forms of git remote URLs: scp-like `git@host:org/repo.git` and
+//! `ssh://git@host[:port]/org/repo.git`, plus `https://host/org/repo.git`.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Scp,
+    Ssh,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SshUrl<'a> {
+    pub syntax: Syntax,
+    pub user: Option<&'a str>,
+    pub host: &'a str,
+    pub port: Option<&'a str>,
+    /// Everything after the host (and port), without the separating `:` or `/`.
+    pub path: &'a str,
+}
+
+/// Splits `user@host` into its parts.
+fn user_host(authority: &str) -> (Option<&str>, &str) {
+    match authority.rsplit_once('@') {
+        Some((user, host)) => (Some(user), host),
+        None => (None, authority),
+    }
+}
+
+/// Parses an ssh remote URL; `None` for other schemes and local paths.
+pub fn parse(url: &str) -> Option<SshUrl<'_>> {
+    let url = url.trim();
+    if let Some(rest) = url.strip_prefix("ssh://").or_else(|| url.strip_prefix("git+ssh://")) {
+        let (authority, path) = rest.split_once('/')?;
+        let (user, host_port) = user_host(authority);
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => (host, Some(port)),
+            _ => (host_port, None),
+        };
+        return (!host.is_empty()).then_some(SshUrl { syntax: Syntax::Ssh, user, host, port, path });
+    }
+
+    // scp-like syntax: a colon before any slash, and no scheme.
+    if url.contains("://") {
+        return None;
+    }
+    let (authority, path) = url.split_once(':')?;
+    if authority.contains('/') {
+        return None;
+    }
+    let (user, host) = user_host(authority);
+    // A single letter is a Windows drive, as in `C:/repos/x`.
+    if host.len() <= 1 {
+        return None;
+    }
+    Some(SshUrl { syntax: Syntax::Scp, user, host, port: None, path })
+}
+
+impl SshUrl<'_> {
+    /// The same URL, in the same syntax, with `host` in place of the original.
+    pub fn with_host(&self, host: &str) -> String {
+        let user = self.user.map(|user| format!("{}@", user)).unwrap_or_default();
+        match self.syntax {
+            Syntax::Scp => format!("{}{}:{}", user, host, self.path),
+            Syntax::Ssh => {
+                let port = self.port.map(|port| format!(":{}", port)).unwrap_or_default();
+                format!("ssh://{}{}{}/{}", user, host, port, self.path)
+            }
+        }
+    }
+}
+
+/// Host and path of an `https://[user@]host/path` URL, without the
+/// separating `/`.
+pub fn parse_https(url: &str) -> Option<(&str, &str)> {
+    let rest = url.trim().strip_prefix("https://")?;
+    let (authority, path) = rest.split_once('/')?;
+    let (_, host) = user_host(authority);
+    (!host.is_empty()).then_some((host, path))
+}
@@ -0,0 +1,172 @@
+//! Minimal line-oriented view of a git config file.
+
+use std::path::Path;
+
+use crate::managed;
+use crate::ssh_config;
+
+/// A `[section]` and the lines up to the next section header.
+pub struct Section {
+    /// Text between the brackets, e.g. `includeIf "gitdir/i:/home/me/work"`.
+    pub header: String,
+    /// Index of the header line.
+    pub start: usize,
+    /// Index one past the last line belonging to the section.
+    pub end: usize,
+}
+
+fn header(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let inner = line.strip_prefix('[')?;
+    let close = inner.rfind(']')?;
+    Some(inner[..close].trim())
+}
+
+pub fn sections(lines: &[&str]) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        // A managed-block marker closes the section before it.
+        if managed::is_marker(line) {
+            if let Some(last) = sections.last_mut().filter(|last| last.end == lines.len()) {
+                last.end = i;
+            }
+            continue;
+        }
+        let Some(name) = header(line) else {
+            continue;
+        };
+        if let Some(last) = sections.last_mut().filter(|last| last.end == lines.len()) {
+            last.end = i;
+        }
+        sections.push(Section {
+            header: name.to_string(),
+            start: i,
+            end: lines.len(),
+        });
+    }
+
+    sections
+}
+
+/// `content` with `entries` added to its `[<header>]` section after the
+/// section's last line that isn't blank, or in a new section at the end
+/// when it has none.
+pub fn add_to_section(content: &str, header: &str, entries: &[String]) -> String {
+    let mut lines: Vec<&str> = content.lines().collect();
+    let at = sections(&lines).iter().find(|section| section.header == header).map(|section| {
+        (section.start + 1..section.end).rev().find(|&i| !lines[i].trim().is_empty()).map_or(section.start + 1, |i| i + 1)
+    });
+    let header_line = format!("[{}]", header);
+    let at = match at {
+        Some(at) => at,
+        None => {
+            lines.push(&header_line);
+            lines.len()
+        }
+    };
+    lines.splice(at..at, entries.iter().map(String::as_str));
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+/// Splits `key = value` into the lowercased key and the value, skipping
+/// comments and section headers.
+pub fn entry(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') || line.starts_with(';') || line.starts_with('[') {
+        return None;
+    }
+    match line.split_once('=') {
+        Some((key, value)) => Some((key.trim().to_ascii_lowercase(), value.trim())),
+        None => Some((line.to_ascii_lowercase(), "")),
+    }
+}
+
+/// Values of `key` inside the section.
+pub fn values<'a>(lines: &[&'a str], section: &Section, key: &str) -> Vec<&'a str> {
+    (section.start + 1..section.end)
+        .filter_map(|i| match entry(lines[i]) {
+            Some((k, value)) if k == key => Some(value),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `includeIf` sections whose `path` is `include_path`, written out or
+/// with `~/` for the home dir.
+pub fn include_ifs_for<'a>(lines: &[&str], sections: &'a [Section], include_path: &str, home_dir: &Path) -> Vec<&'a Section> {
+    sections
+        .iter()
+        .filter(|section| section.header.starts_with("includeIf"))
+        .filter(|section| values(lines, section, "path").iter().any(|path| ssh_config::expand_tilde(path, home_dir) == include_path))
+        .collect()
+}
+
+/// git's wildmatch as used for `gitdir` conditions: `*` and `?` stop at `/`,
+/// `**` crosses directories and `**/` also matches no directory at all.
+fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            wildmatch(rest, text) || (0..text.len()).any(|i| text[i] == b'/' && wildmatch(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| wildmatch(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let component = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=component).any(|i| wildmatch(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => text.first().is_some_and(|&b| b != b'/') && wildmatch(rest, &text[1..]),
+        [b, rest @ ..] => text.first() == Some(b) && wildmatch(rest, &text[1..]),
+    }
+}
+
+/// Whether the condition of an `includeIf` header holds for a repository
+/// whose git dir is `gitdir`. Only `gitdir:` and `gitdir/i:` are
+/// understood; other conditions never match.
+fn include_if_matches(header: &str, gitdir: &Path, home_dir: &Path) -> bool {
+    let Some(condition) = header.strip_prefix("includeIf").map(|rest| rest.trim().trim_matches('"')) else {
+        return false;
+    };
+    match (condition.strip_prefix("gitdir/i:"), condition.strip_prefix("gitdir:")) {
+        (Some(pattern), _) => gitdir_matches(pattern, true, gitdir, home_dir),
+        (None, Some(pattern)) => gitdir_matches(pattern, false, gitdir, home_dir),
+        (None, None) => false,
+    }
+}
+
+/// Whether the pattern of a `hasconfig:remote.*.url:` condition matches a
+/// remote URL.
+pub fn remote_url_matches(pattern: &str, url: &str) -> bool {
+    wildmatch(pattern.as_bytes(), url.as_bytes())
+}
+
+/// Whether the pattern of a `gitdir:` condition, or with `icase` of a
+/// `gitdir/i:` one, matches the git dir `gitdir`.
+pub fn gitdir_matches(pattern: &str, icase: bool, gitdir: &Path, home_dir: &Path) -> bool {
+    let mut pattern = match pattern.strip_prefix("~/") {
+        Some(rest) => home_dir.join(rest).to_string_lossy().into_owned(),
+        None if pattern.starts_with('/') => pattern.to_string(),
+        None => format!("**/{}", pattern),
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    let mut text = gitdir.to_string_lossy().into_owned();
+    if icase {
+        pattern = pattern.to_lowercase();
+        text = text.to_lowercase();
+    }
+    wildmatch(pattern.as_bytes(), text.as_bytes())
+}
+
+/// The last `path` of the last `includeIf` section applying to a repository
+/// whose git dir is `gitdir`, which is the include that wins.
+pub fn effective_include_if<'a>(lines: &[&'a str], gitdir: &Path, home_dir: &Path) -> Option<&'a str> {
+    sections(lines)
+        .iter()
+        .rev()
+        .filter(|section| include_if_matches(&section.header, gitdir, home_dir))
+        .find_map(|section| values(lines, section, "path").last().copied())
+}
@@ -0,0 +1,196 @@
+//! Calls to the GitHub REST API, made with curl so multigit doesn't need an
+//! HTTP client of its own.
+
+use std::env;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+const API: &str = "https://api.github.com";
+
+/// Largest page size the API allows.
+const PER_PAGE: usize = 100;
+
+/// `GITHUB_TOKEN_<ACCOUNT>`, upper-cased with other characters as `_`.
+pub fn account_token_var(account: &str) -> String {
+    let suffix: String = account
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("GITHUB_TOKEN_{}", suffix)
+}
+
+/// An API token for `account` from the environment and the variable it
+/// came from: its `account_token_var`, else `GITHUB_TOKEN`, else `GH_TOKEN`.
+pub fn env_token(account: Option<&str>) -> Option<(String, String)> {
+    account
+        .map(account_token_var)
+        .into_iter()
+        .chain(["GITHUB_TOKEN".to_string(), "GH_TOKEN".to_string()])
+        .find_map(|name| env::var(&name).ok().filter(|token| !token.is_empty()).map(|token| (name, token)))
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The `rel="next"` URL of the `Link` header.
+    fn next_page(&self) -> Option<String> {
+        self.header("link")?.split(',').find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            params.contains("rel=\"next\"").then(|| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+        })
+    }
+
+    /// An error naming `what` failed, explaining the usual causes: a bad or
+    /// missing token, a missing scope, or the rate limit.
+    fn error(&self, what: &str, token: Option<&str>) -> Error {
+        #[derive(Deserialize)]
+        struct Body {
+            message: String,
+        }
+        let message = parse::<Body>(&self.body).map(|body| body.message).unwrap_or_else(|_| format!("HTTP {}", self.status));
+
+        let explanation = if self.status == 401 {
+            "the token was rejected; see which one is used with multigit token status".to_string()
+        } else if self.status == 403 && self.header("x-ratelimit-remaining") == Some("0") {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let reset = self.header("x-ratelimit-reset").and_then(|reset| reset.parse::<u64>().ok());
+            let hint = if token.is_none() { "; set GITHUB_TOKEN for a higher limit" } else { "" };
+            match reset {
+                Some(reset) => format!("rate limit exhausted, resets in {} min{}", reset.saturating_sub(now).div_ceil(60), hint),
+                None => format!("rate limit exhausted{}", hint),
+            }
+        } else if let Some(scopes) = self.header("x-accepted-oauth-scopes").filter(|scopes| !scopes.is_empty()) {
+            match token {
+                None => format!("this needs a token with the {} scope in GITHUB_TOKEN", scopes),
+                Some(_) => format!(
+                    "the token needs the {} scope (it has: {})",
+                    scopes,
+                    self.header("x-oauth-scopes").filter(|scopes| !scopes.is_empty()).unwrap_or("none")
+                ),
+            }
+        } else {
+            message.clone()
+        };
+
+        if explanation == message {
+            Error::other(format!("{} failed: {}", what, message))
+        } else {
+            Error::other(format!("{} failed: {} ({})", what, message, explanation))
+        }
+    }
+}
+
+fn request(method: &str, url: &str, token: Option<&str>) -> Result<Response> {
+    // The token goes in on stdin so it doesn't show up in `ps`.
+    let mut child = Command::new("curl")
+        .args(["-sS", "-X", method, "-D", "-", "-H", "Accept: application/vnd.github+json", "-H", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::new(e.kind(), format!("Failed to run curl: {}", e)))?;
+    if let (Some(mut stdin), Some(token)) = (child.stdin.take(), token) {
+        writeln!(stdin, "Authorization: Bearer {}", token)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("curl {} failed: {}", url, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    let split = output
+        .stdout
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("No HTTP response from {}", url)))?;
+    let head = String::from_utf8_lossy(&output.stdout[..split]).into_owned();
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Malformed HTTP response from {}", url)))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Ok(Response { status, headers, body: output.stdout[split + 4..].to_vec() })
+}
+
+/// The `gh` CLI's token for the provider, when it's installed and logged in.
+pub fn gh_token() -> Option<String> {
+    let output = Command::new("gh").args(["auth", "token"]).stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !token.is_empty()).then_some(token)
+}
+
+/// A JSON reply of the API. Without the `json` feature no reply can be
+/// read, so every API call fails.
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    #[cfg(feature = "json")]
+    {
+        serde_json::from_slice(body).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+    #[cfg(not(feature = "json"))]
+    {
+        let _ = body;
+        Err(crate::report::no_json())
+    }
+}
+
+#[derive(Deserialize)]
+struct User {
+    login: String,
+}
+
+/// The login of the user `token` belongs to, from `/user`.
+pub fn current_login(token: &str) -> Result<String> {
+    let what = "Looking up the token's user";
+    let response = request("GET", &format!("{}/user", API), Some(token))?;
+    if !(200..300).contains(&response.status) {
+        return Err(response.error(what, Some(token)));
+    }
+    let user: User = parse(&response.body).map_err(|e| Error::new(e.kind(), format!("Unexpected response to {}: {}", what, e)))?;
+    Ok(user.login)
+}
+
+/// Every item of a list endpoint such as `/user/keys`, following the
+/// `Link` header through all pages.
+pub fn get_all<T: DeserializeOwned>(path: &str, token: Option<&str>, what: &str) -> Result<Vec<T>> {
+    let separator = if path.contains('?') { '&' } else { '?' };
+    let mut url = Some(format!("{}{}{}per_page={}", API, path, separator, PER_PAGE));
+    let mut items = Vec::new();
+
+    while let Some(current) = url {
+        let response = request("GET", &current, token)?;
+        if !(200..300).contains(&response.status) {
+            return Err(response.error(what, token));
+        }
+        let page: Vec<T> = parse(&response.body).map_err(|e| Error::new(e.kind(), format!("Unexpected response to {}: {}", what, e)))?;
+        items.extend(page);
+        url = response.next_page();
+    }
+    Ok(items)
+}
+
+pub fn delete(path: &str, token: Option<&str>, what: &str) -> Result<()> {
+    let response = request("DELETE", &format!("{}{}", API, path), token)?;
+    if !(200..300).contains(&response.status) {
+        return Err(response.error(what, token));
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+//! Host blocks for a new account's alias that multigit didn't write, such as
+//! a hand-written `Host github.com-work` or one left by another tool. setup
+//! used to leave such a block alone, and when it names another key the
+//! account never authenticates; now the block is compared field by field
+//! with the one setup would write, and a mismatch has to be resolved first.
+
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind, IsTerminal, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::{GitAccount, GitAccountManager, SetupOptions, DEFAULT_KEY_TYPE};
+use crate::diff;
+use crate::errors::MultigitError;
+use crate::fsutil;
+use crate::keys::{self, prompt};
+use crate::managed;
+use crate::ssh_config;
+
+/// Exit code of a setup that stopped at a conflicting Host block.
+pub const EXIT_CODE: u8 = 3;
+
+/// Width of each column when the two blocks are shown side by side.
+const COLUMN: usize = 38;
+
+/// The directives that decide which server and key a connection through
+/// the alias uses.
+const FIELDS: [(&str, &str); 3] = [("hostname", "HostName"), ("user", "User"), ("identityfile", "IdentityFile")];
+
+/// What to do with a conflicting Host block, chosen up front with
+/// `--adopt-host-block` or `--replace-host-block`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostBlockChoice {
+    /// Keep the block and give the account the key it names.
+    Adopt,
+    /// Remove the block, keeping a backup of the file, and write setup's.
+    Replace,
+}
+
+/// The error of a non-interactive setup that found a conflicting block;
+/// `main` exits with `EXIT_CODE` for it.
+#[derive(Debug)]
+pub struct HostConflict {
+    host: String,
+    path: PathBuf,
+}
+
+impl fmt::Display for HostConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} already has a Host {} block that differs from multigit's; rerun with --adopt-host-block to use it and its key, \
+             --replace-host-block to replace it (keeping a backup), or --host <alias> to set the account up under another alias",
+            self.path.display(),
+            self.host
+        )
+    }
+}
+
+impl std::error::Error for HostConflict {}
+
+impl HostConflict {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+}
+
+/// A Host block for the alias outside multigit's managed regions.
+struct ForeignBlock {
+    path: PathBuf,
+    start: usize,
+    end: usize,
+    text: String,
+    identity_files: Vec<String>,
+}
+
+/// The values of each of `FIELDS` in the lines of a Host block, with
+/// IdentityFile paths expanded and HostName lowercased.
+fn field_values(lines: &[&str], home_dir: &Path) -> Vec<Vec<String>> {
+    FIELDS
+        .iter()
+        .map(|(keyword, _)| {
+            lines
+                .iter()
+                .filter_map(|line| ssh_config::directive(line).filter(|(key, _)| key == keyword).map(|(_, value)| value.trim_matches('"')))
+                .map(|value| match *keyword {
+                    "identityfile" => ssh_config::expand_tilde(value, home_dir),
+                    "hostname" => value.to_ascii_lowercase(),
+                    _ => value.to_string(),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn show(values: &[String]) -> String {
+    if values.is_empty() {
+        "(unset)".to_string()
+    } else {
+        values.join(" ")
+    }
+}
+
+impl GitAccountManager {
+    /// The first Host block naming `host` in the files ssh reads for it, when
+    /// multigit didn't write it.
+    fn foreign_host_block(&self, host: &str) -> Result<Option<ForeignBlock>> {
+        let mut paths = vec![self.home_dir.join(".ssh/config")];
+        if self.settings.managed_ssh_config {
+            paths.push(self.ssh_config_path());
+        }
+        for path in paths {
+            let content = match fsutil::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let lines: Vec<&str> = content.lines().collect();
+            let regions = managed::blocks(&lines);
+            let blocks = ssh_config::host_blocks(&lines);
+            let foreign = blocks.iter().find(|block| {
+                block.patterns.iter().any(|pattern| pattern.eq_ignore_ascii_case(host))
+                    && !regions.iter().any(|region| region.start < block.start && block.start < region.end)
+            });
+            if let Some(block) = foreign {
+                let block_lines = &lines[block.start..block.end];
+                return Ok(Some(ForeignBlock {
+                    path: path.clone(),
+                    start: block.start,
+                    end: block.end,
+                    text: block_lines.join("\n") + "\n",
+                    identity_files: ssh_config::identity_files(&lines, block)
+                        .into_iter()
+                        .map(|(_, value)| ssh_config::expand_tilde(value.trim_matches('"'), &self.home_dir))
+                        .collect(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Looks for a Host block for `host` that setup didn't write and that
+    /// disagrees with the block it would write on HostName, User or
+    /// IdentityFile. Such a block is adopted, replaced, or swapped for
+    /// another alias, as `options.host_block` says or, on a terminal, as the
+    /// user picks; without either setup fails with a `HostConflict`.
+    /// Returns the alias to set the account up under, which the caller
+    /// validates again when the user picked another one.
+    pub fn resolve_host_conflict(&self, name: &str, codebase_dir: &Path, host: &str, options: &mut SetupOptions) -> Result<String> {
+        let mut host = host.to_string();
+        loop {
+            let Some(existing) = self.foreign_host_block(&host)? else {
+                return Ok(host);
+            };
+            let key = match &options.existing_key {
+                Some(path) => path.clone(),
+                None => self.default_key_path(name, &host, options.key_type.as_deref().unwrap_or(DEFAULT_KEY_TYPE))?,
+            };
+            let mut provisional = GitAccount::new(name, "", &key.to_string_lossy(), codebase_dir.to_path_buf(), &host);
+            provisional.minimal_ssh_block = options.minimal;
+            provisional.keychain = Some(options.keychain.unwrap_or_else(keys::default_keychain));
+            let intended = self.host_block(&provisional)?;
+            let existing_lines: Vec<&str> = existing.text.lines().collect();
+            let intended_lines: Vec<&str> = intended.lines().collect();
+            let ours = field_values(&existing_lines, &self.home_dir);
+            let theirs = field_values(&intended_lines, &self.home_dir);
+            if ours == theirs {
+                return Ok(host);
+            }
+
+            let choice = match options.host_block {
+                Some(choice) => choice,
+                None if std::io::stdin().is_terminal() => {
+                    println!("{}:{} has a Host {} block that differs from multigit's:", existing.path.display(), existing.start + 1, host);
+                    for ((_, label), (ours, theirs)) in FIELDS.iter().zip(ours.iter().zip(&theirs)) {
+                        if ours != theirs {
+                            println!("  {:<13} {} (multigit: {})", label, show(ours), show(theirs));
+                        }
+                    }
+                    println!("{:<width$}   multigit's version", "existing", width = COLUMN);
+                    print!("{}", diff::side_by_side(&existing.text, &intended, COLUMN));
+                    let answer = prompt(&format!(
+                        "[a]dopt the existing block and its key, [r]eplace it (backing up {}), or use another [h]ost alias",
+                        existing.path.display()
+                    ))?;
+                    match answer.to_ascii_lowercase().as_str() {
+                        "a" | "adopt" => HostBlockChoice::Adopt,
+                        "r" | "replace" => HostBlockChoice::Replace,
+                        "h" | "host" => {
+                            host = prompt("Host alias for the account")?;
+                            continue;
+                        }
+                        _ => return Err(Error::new(ErrorKind::Interrupted, format!("Left Host {} alone; nothing was set up", host))),
+                    }
+                }
+                None => return Err(MultigitError::HostConflict(HostConflict { host, path: existing.path }).into()),
+            };
+
+            match choice {
+                HostBlockChoice::Adopt => {
+                    let Some(identity_file) = existing.identity_files.first() else {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Host {} in {} names no IdentityFile to adopt", host, existing.path.display()),
+                        ));
+                    };
+                    let identity_file = PathBuf::from(identity_file);
+                    keys::check_existing_key(&identity_file)?;
+                    println!("Adopting Host {} from {} with key {}", host, existing.path.display(), identity_file.display());
+                    options.existing_key = Some(identity_file);
+                }
+                HostBlockChoice::Replace => {
+                    let backup = existing.path.with_extension("multigit-bak");
+                    fs::copy(&existing.path, &backup).map_err(|e| fsutil::blocked(e, "back up", &existing.path))?;
+                    fsutil::modify(&existing.path, |content| {
+                        let lines: Vec<&str> = content.lines().collect();
+                        let mut out: Vec<&str> = lines[..existing.start].to_vec();
+                        out.extend(lines[existing.end.min(lines.len())..].iter().skip_while(|line| line.trim().is_empty()));
+                        let mut out = out.join("\n");
+                        out.push('\n');
+                        Ok(Some(out))
+                    })?;
+                    println!("Removed Host {} from {}; the file as it was is in {}", host, existing.path.display(), backup.display());
+                }
+            }
+            return Ok(host);
+        }
+    }
+}
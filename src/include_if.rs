@@ -0,0 +1,492 @@
+//! Ordering of the managed includeIf entries in the global gitconfig. git
+//! applies includes last-match-wins, so when codebase dirs nest the more
+//! specific entry has to come later to take effect. For the same reason
+//! where the entries sit relative to the user's own includes matters;
+//! `--include-placement` keeps them at the top or bottom of the file.
+
+use std::collections::HashSet;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::fsutil;
+use crate::gitconfig;
+use crate::managed;
+use crate::ssh_config;
+use crate::state::State;
+
+/// Where the managed includeIf entries go in the global gitconfig. When
+/// it's unset, new entries are appended and existing ones stay put.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Placement {
+    /// Before the rest of the file, whose includes then win.
+    Top,
+    /// After the rest of the file, overriding it.
+    Bottom,
+}
+
+impl Placement {
+    fn name(self) -> &'static str {
+        match self {
+            Placement::Top => "top",
+            Placement::Bottom => "bottom",
+        }
+    }
+}
+
+/// Managed includeIf entries, by account name, as they are and as they
+/// should be ordered, and the placement they're not at.
+pub struct IncludeOrder {
+    pub current: Vec<String>,
+    pub expected: Vec<String>,
+    pub misplaced: Option<Placement>,
+}
+
+impl IncludeOrder {
+    pub fn describe(&self) -> String {
+        let mut problems = Vec::new();
+        if self.current != self.expected {
+            problems.push(format!("includeIf order {} -> {} (most specific directory last)", self.current.join(", "), self.expected.join(", ")));
+        }
+        if let Some(placement) = self.misplaced {
+            problems.push(format!("includeIf entries not at the {} of the global gitconfig (--include-placement)", placement.name()));
+        }
+        problems.join("; ")
+    }
+}
+
+/// A directory whose repositories get another account's include than the
+/// one multigit assigns them.
+pub struct IdentityMismatch {
+    pub dir: PathBuf,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+/// A managed includeIf whose `path` doesn't exist. git skips such an
+/// include without a word, and the repositories get the global identity.
+pub struct DanglingInclude {
+    /// The account whose marked region holds the entry.
+    pub account: String,
+    pub path: PathBuf,
+    /// The account the missing file is the include file of, which can be
+    /// written back.
+    pub owner: Option<String>,
+}
+
+/// Sort key putting broader directories first and more specific ones last.
+/// Sorting by it is stable, so accounts whose directories don't nest keep
+/// their order.
+fn specificity(account: &GitAccount) -> (usize, bool) {
+    account.specificity()
+}
+
+/// Whether an entry for `inner` comes before one for an account whose
+/// directory contains it, so the broader one wins.
+fn misordered(current: &[&GitAccount]) -> bool {
+    current.iter().enumerate().any(|(i, inner)| {
+        current[i + 1..].iter().any(|outer| outer.covers(&inner.probe_dir()) && outer.specificity() < inner.specificity())
+    })
+}
+
+impl GitAccountManager {
+    pub fn global_gitconfig_path(&self) -> PathBuf {
+        self.home_dir.join(".gitconfig")
+    }
+
+    /// Line range and owning account of every managed includeIf section, in
+    /// file order. The range covers the section's markers when it has them,
+    /// and a marked region is listed once even when it holds several
+    /// sections, such as an account's exclusions.
+    fn managed_include_ifs(&self, lines: &[&str]) -> Vec<(usize, usize, &GitAccount)> {
+        let sections = gitconfig::sections(lines);
+        let marked = managed::blocks(lines);
+        let accounts = self.sorted_accounts(false);
+
+        let mut found = Vec::new();
+        for section in &sections {
+            if !section.header.starts_with("includeIf") {
+                continue;
+            }
+            let paths = gitconfig::values(lines, section, "path");
+            let owner = accounts.iter().find(|account| {
+                let include_path = account.codebase_dir_path.join(".gitconfig");
+                paths.iter().any(|path| ssh_config::expand_tilde(path, &self.home_dir) == include_path.to_string_lossy())
+            });
+            let region = managed::enclosing(&marked, section.start, section.end)
+                .and_then(|block| Some((block, accounts.iter().find(|account| account.name == block.account)?)));
+            match (region, owner) {
+                (Some((block, account)), _) => {
+                    if found.last().is_none_or(|(start, _, _)| *start != block.start) {
+                        found.push((block.start, block.end, *account));
+                    }
+                }
+                (None, Some(account)) => found.push((section.start, section.end, *account)),
+                (None, None) => {}
+            }
+        }
+        found
+    }
+
+    /// Lines the managed entries take up, each with the blank lines after
+    /// it, which move along with it.
+    fn managed_lines(lines: &[&str], managed: &[(usize, usize, &GitAccount)]) -> HashSet<usize> {
+        managed
+            .iter()
+            .flat_map(|(start, end, _)| {
+                let blank_after = lines[*end..].iter().take_while(|line| line.trim().is_empty()).count();
+                *start..*end + blank_after
+            })
+            .collect()
+    }
+
+    /// Whether other content comes before (for the top) or after (for the
+    /// bottom) the managed entries, or between them.
+    fn misplaced(&self, lines: &[&str], managed: &[(usize, usize, &GitAccount)]) -> Option<Placement> {
+        let placement = self.settings.include_placement?;
+        let first = managed.iter().map(|(start, _, _)| *start).min()?;
+        let last = managed.iter().map(|(_, end, _)| *end).max()?;
+        let taken = Self::managed_lines(lines, managed);
+        let range = match placement {
+            Placement::Top => 0..last,
+            Placement::Bottom => first..lines.len(),
+        };
+        range.into_iter().any(|i| !taken.contains(&i) && !lines[i].trim().is_empty()).then_some(placement)
+    }
+
+    /// Returns the current and expected order when a nested directory's
+    /// entry comes before the broader one, or the entries aren't where
+    /// `--include-placement` puts them.
+    pub fn include_if_order(&self) -> Result<Option<IncludeOrder>> {
+        let path = self.global_gitconfig_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = fsutil::read_to_string(&path)?;
+        let lines: Vec<&str> = content.lines().collect();
+
+        let managed = self.managed_include_ifs(&lines);
+        let current: Vec<&GitAccount> = managed.iter().map(|(_, _, account)| *account).collect();
+        let misplaced = self.misplaced(&lines, &managed);
+        if !misordered(&current) && misplaced.is_none() {
+            return Ok(None);
+        }
+        let mut expected = current.clone();
+        expected.sort_by_key(|account| specificity(account));
+
+        let names = |accounts: &[&GitAccount]| accounts.iter().map(|a| a.name.clone()).collect::<Vec<_>>();
+        Ok(Some(IncludeOrder { current: names(&current), expected: names(&expected), misplaced }))
+    }
+
+    /// Every `path` of the managed includeIf sections that points at no
+    /// file, such as an include file deleted by hand.
+    pub fn dangling_includes(&self) -> Result<Vec<DanglingInclude>> {
+        let content = match fsutil::read_to_string(&self.global_gitconfig_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        let marked = managed::blocks(&lines);
+
+        let mut dangling = Vec::new();
+        for section in gitconfig::sections(&lines).iter().filter(|section| section.header.starts_with("includeIf")) {
+            let Some(block) = managed::enclosing(&marked, section.start, section.end) else {
+                continue;
+            };
+            for path in gitconfig::values(&lines, section, "path") {
+                let path = PathBuf::from(ssh_config::expand_tilde(path.trim_matches('"'), &self.home_dir));
+                if path.exists() {
+                    continue;
+                }
+                let owner = self
+                    .sorted_accounts(false)
+                    .into_iter()
+                    .find(|account| account.include_paths().iter().any(|include| Path::new(include) == path))
+                    .map(|account| account.name.clone());
+                dangling.push(DanglingInclude { account: block.account.clone(), path, owner });
+            }
+        }
+        Ok(dangling)
+    }
+
+    /// Writes back the include file of `account_name`, for a dangling
+    /// includeIf.
+    pub fn restore_include_file(&self, account_name: &str) -> Result<()> {
+        let account = &self.accounts[account_name];
+        self.setup_local_gitconfig(account)?;
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &self.include_file_path(account));
+        state.save(&self.home_dir)
+    }
+
+    /// Directories whose repositories git would give the wrong include:
+    /// every enabled codebase dir and exclusion, checked against the
+    /// includeIf sections as git evaluates them, last match winning.
+    pub fn identity_mismatches(&self) -> Result<Vec<IdentityMismatch>> {
+        let content = match fsutil::read_to_string(&self.global_gitconfig_path()) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let lines: Vec<&str> = content.lines().collect();
+
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        // A remote URL scope has no directory to check.
+        for account in self.sorted_accounts(false).into_iter().filter(|account| account.scope.is_dir()) {
+            dirs.push(account.probe_dir());
+            dirs.extend(account.exclusions.iter().map(|exclusion| exclusion.dir.clone()));
+        }
+
+        let mut mismatches = Vec::new();
+        for dir in dirs {
+            let Some(expected) = self.account_for_path(&dir) else {
+                continue;
+            };
+            // A repository that doesn't exist: git matches the path only.
+            let gitdir = dir.join("multigit-probe/.git");
+            let include = gitconfig::effective_include_if(&lines, &gitdir, &self.home_dir)
+                .map(|path| PathBuf::from(ssh_config::expand_tilde(path, &self.home_dir)));
+            let actual = self
+                .sorted_accounts(true)
+                .into_iter()
+                .find(|account| include.as_ref() == Some(&account.codebase_dir_path.join(".gitconfig")));
+            if actual.map(|account| &account.name) != Some(&expected.name) {
+                mismatches.push(IdentityMismatch {
+                    dir,
+                    expected: expected.name.clone(),
+                    actual: actual.map(|account| account.name.clone()),
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Rewrites every enabled account's includeIf entries and puts them in
+    /// order.
+    pub fn fix_identity_mismatches(&self) -> Result<()> {
+        for account in self.sorted_accounts(false) {
+            let include_path = account.codebase_dir_path.join(".gitconfig");
+            self.setup_global_gitconfig(&account.name, &account.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy())?;
+        }
+        self.fix_include_if_order()
+    }
+
+    /// Other accounts whose codebase dir or glob contains `account_name`'s
+    /// or sits inside it, as warnings saying which account repositories get.
+    /// Globs that overlap without one being more specific, such as
+    /// `clients/*/` and `*/acme/`, can't be ordered, so those are warned
+    /// about as ambiguous.
+    pub fn nesting_warnings(&self, account_name: &str) -> Vec<String> {
+        let Some(account) = self.accounts.get(account_name).filter(|account| account.scope.is_dir()) else {
+            return Vec::new();
+        };
+        let mut warnings = Vec::new();
+        for other in self.sorted_accounts(true) {
+            if other.name == account_name || other.dir_display() == account.dir_display() || !other.scope.is_dir() {
+                continue;
+            }
+            let covers_account = other.covers(&account.probe_dir());
+            let covers_other = account.covers(&other.probe_dir());
+            let (outer, inner) = if covers_account && other.specificity() < account.specificity() {
+                (other, account)
+            } else if covers_other && account.specificity() < other.specificity() {
+                (account, other)
+            } else if covers_account || covers_other {
+                warnings.push(format!(
+                    "{} and {}'s {} overlap: which of {} and {} a repository in both gets depends on the order of the includeIfs",
+                    account.dir_display(),
+                    other.name,
+                    other.dir_display(),
+                    account.name,
+                    other.name
+                ));
+                continue;
+            } else {
+                continue;
+            };
+            warnings.push(format!(
+                "{} is inside {}'s {}: repositories under it use {}, the rest of {} uses {}",
+                inner.dir_display(),
+                outer.name,
+                outer.dir_display(),
+                inner.name,
+                outer.dir_display(),
+                outer.name
+            ));
+        }
+        warnings
+    }
+
+    /// Rewrites the managed includeIf entries as one group, ordered from
+    /// broadest to most specific directory: at the top or bottom of the file
+    /// with `--include-placement`, else at the position of the first one.
+    /// Other content of the file is left in its order.
+    pub fn fix_include_if_order(&self) -> Result<()> {
+        fsutil::modify(&self.global_gitconfig_path(), |content| {
+            let lines: Vec<&str> = content.lines().collect();
+
+            let mut managed = self.managed_include_ifs(&lines);
+            let Some(first_start) = managed.iter().map(|(start, _, _)| *start).min() else {
+                return Ok(None);
+            };
+            let removed = Self::managed_lines(&lines, &managed);
+            managed.sort_by_key(|(_, _, account)| specificity(account));
+
+            let mut group = String::new();
+            for (start, end, _) in &managed {
+                let last = (*start..*end).rev().find(|&j| !lines[j].trim().is_empty()).unwrap_or(*start);
+                for section_line in &lines[*start..=last] {
+                    group.push_str(section_line);
+                    group.push('\n');
+                }
+                group.push('\n');
+            }
+            let at = match self.settings.include_placement {
+                None => Some(first_start),
+                Some(Placement::Top) => Some(0),
+                Some(Placement::Bottom) => None,
+            };
+
+            let mut out = String::new();
+            for (i, line) in lines.iter().enumerate() {
+                if Some(i) == at {
+                    out.push_str(&group);
+                }
+                if !removed.contains(&i) {
+                    out.push_str(line);
+                    out.push('\n');
+                }
+            }
+            if at.is_none() {
+                if !out.is_empty() && !out.ends_with("\n\n") {
+                    out.push('\n');
+                }
+                out.push_str(group.trim_end_matches('\n'));
+                out.push('\n');
+            }
+            Ok((out != content).then_some(out))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::Placement;
+    use crate::account::SetupOptions;
+    use crate::doctor::run_sync;
+    use crate::testutil::TempHome;
+
+    /// The `user.email` git resolves in `repo` with only the temp home's
+    /// global config.
+    fn email_in(home: &TempHome, repo: &str) -> String {
+        let repo = home.join(repo);
+        assert!(Command::new("git").args(["init", "-q"]).arg(&repo).status().unwrap().success());
+        let output = Command::new("git")
+            .args(["config", "user.email"])
+            .current_dir(&repo)
+            .env("HOME", &home.path)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("GIT_CONFIG_GLOBAL")
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn unmanaged_sections_survive_setup() {
+        let home = TempHome::new();
+        let mine = "[user]\n    name = Me\n    email = me@personal.example\n[includeIf \"gitdir:~/oss/\"]\n    path = ~/oss/.gitconfig\n";
+        home.write(".gitconfig", mine);
+        let mut manager = home.manager();
+        // The nested account makes setup reorder the managed entries.
+        manager.setup_account("client", "me@client.example", "code/work/client", "github.com-client", SetupOptions::default()).unwrap();
+        manager.setup_account("work", "me@work.example", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+
+        let gitconfig = home.read(".gitconfig");
+        assert!(gitconfig.starts_with(mine), "{}", gitconfig);
+        assert!(gitconfig.find("# BEGIN multigit: work").unwrap() < gitconfig.find("# BEGIN multigit: client").unwrap());
+        assert_eq!(email_in(&home, "oss/tool"), "me@personal.example");
+    }
+
+    #[test]
+    fn the_more_specific_dir_takes_precedence() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.setup_account("client", "me@client.example", "code/work/client", "github.com-client", SetupOptions::default()).unwrap();
+        manager.setup_account("work", "me@work.example", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+        assert!(manager.include_if_order().unwrap().is_none());
+        assert_eq!(email_in(&home, "code/work/client/app"), "me@client.example");
+        assert_eq!(email_in(&home, "code/work/api"), "me@work.example");
+
+        // Hand-moved so the broader entry comes last, and wins.
+        let gitconfig = home.read(".gitconfig");
+        let (work, client) = gitconfig.split_once("\n\n").unwrap();
+        home.write(".gitconfig", &format!("{}\n{}\n", client.trim_end(), work));
+        assert_eq!(email_in(&home, "code/work/client/app"), "me@work.example");
+        let order = manager.include_if_order().unwrap().expect("misordered");
+        assert_eq!(order.current, ["client", "work"]);
+        assert_eq!(order.expected, ["work", "client"]);
+
+        manager.fix_include_if_order().unwrap();
+        assert!(manager.include_if_order().unwrap().is_none());
+        assert_eq!(email_in(&home, "code/work/client/app"), "me@client.example");
+    }
+
+    #[test]
+    fn home_relative_paths_are_written_with_a_tilde() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.settings.home_relative_paths = true;
+        manager.setup_account("work", "me@work.example", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+
+        let gitconfig = home.read(".gitconfig");
+        assert!(gitconfig.contains(":~/code/work/\"]\n    path = ~/code/work/.gitconfig\n"), "{}", gitconfig);
+        assert!(!gitconfig.contains(&home.path.display().to_string()), "{}", gitconfig);
+        assert_eq!(email_in(&home, "code/work/api"), "me@work.example");
+    }
+
+    #[test]
+    fn entries_are_kept_at_the_chosen_placement_by_sync() {
+        let mine = "[user]\n    name = Me\n";
+        for placement in [Placement::Top, Placement::Bottom] {
+            let home = TempHome::new();
+            home.write(".gitconfig", mine);
+            let mut manager = home.manager();
+            manager.settings.include_placement = Some(placement);
+            manager.setup_account("work", "me@work.example", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+
+            let gitconfig = home.read(".gitconfig");
+            let (start, end) = (gitconfig.find("# BEGIN multigit: work").unwrap(), gitconfig.find("# END multigit: work").unwrap());
+            let region = &gitconfig[start..gitconfig[end..].find('\n').map_or(gitconfig.len(), |i| end + i + 1)];
+            match placement {
+                Placement::Top => assert!(gitconfig.starts_with(region), "{}", gitconfig),
+                Placement::Bottom => assert!(gitconfig.trim_end().ends_with(region.trim_end()), "{}", gitconfig),
+            }
+            assert!(manager.include_if_order().unwrap().is_none());
+
+            // Moved by hand to the other end.
+            let rest = gitconfig.replace(region, "");
+            let moved = match placement {
+                Placement::Top => format!("{}\n{}", rest.trim_end(), region),
+                Placement::Bottom => format!("{}\n{}", region, rest.trim_start()),
+            };
+            home.write(".gitconfig", &moved);
+            assert_eq!(manager.include_if_order().unwrap().expect("misplaced").misplaced, Some(placement));
+
+            assert!(run_sync(&manager, true).unwrap());
+            assert!(manager.include_if_order().unwrap().is_none(), "{}", home.read(".gitconfig"));
+            let synced = home.read(".gitconfig");
+            match placement {
+                Placement::Top => assert!(synced.starts_with("# BEGIN multigit: work"), "{}", synced),
+                Placement::Bottom => assert!(synced.trim_end().ends_with("# END multigit: work"), "{}", synced),
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Guessing which account is meant. Commands that take an account use the
+//! one whose codebase dir the current directory is in when it's left out.
+//! For a repository, its origin remote also tells: a remote through an
+//! account's Host alias names the account outright; otherwise the remote's
+//! organization is matched to the account whose codebase dir already holds
+//! that organization's repositories. `status` shows the guess next to the
+//! identity git uses, and `use` mentions it when told otherwise.
+
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::discover;
+use crate::git_url;
+use crate::worktree;
+
+/// Host and lowercased organization of a remote URL, ssh or https.
+pub fn remote_org(url: &str) -> Option<(String, String)> {
+    let (host, path) = match git_url::parse(url) {
+        Some(ssh) => (ssh.host, ssh.path),
+        None => git_url::parse_https(url)?,
+    };
+    let org = path.trim_start_matches('/').split('/').next().filter(|org| !org.is_empty())?;
+    Some((host.to_ascii_lowercase(), org.to_ascii_lowercase()))
+}
+
+/// The account a repository most likely belongs to, and why.
+pub struct Inference<'a> {
+    pub account: &'a GitAccount,
+    pub reason: String,
+}
+
+impl GitAccountManager {
+    /// `account`, or when it's left out the account of the current
+    /// directory, saying so. Fails when no account, or more than one
+    /// equally specific one, covers the directory.
+    pub fn account_or_cwd(&self, account: Option<String>) -> Result<String> {
+        match account {
+            Some(account) => Ok(account),
+            None => self.account_of_dir(&std::env::current_dir()?),
+        }
+    }
+
+    /// The account whose codebase dir `cwd` is in, for `account_or_cwd`.
+    fn account_of_dir(&self, cwd: &Path) -> Result<String> {
+        let Some(chosen) = self.account_for_path(cwd) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is in no account's codebase dir; name the account", cwd.display()),
+            ));
+        };
+        let tied: Vec<&str> = self
+            .sorted_accounts(false)
+            .into_iter()
+            .filter(|account| account.scope.is_dir() && account.covers(cwd) && account.specificity() == chosen.specificity())
+            .map(|account| account.name.as_str())
+            .collect();
+        if tied.len() > 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is in the codebase dirs of {}; name the account", cwd.display(), tied.join(" and ")),
+            ));
+        }
+        eprintln!("note: using account {}, whose codebase dir this is", chosen.name);
+        Ok(chosen.name.clone())
+    }
+
+    /// The guess for the repository containing `dir`, whose origin is
+    /// `url`. The repository itself doesn't count towards the codebase dir
+    /// it's in, which may be the wrong one.
+    pub fn infer_account(&self, url: &str, dir: &Path) -> Option<Inference<'_>> {
+        let (host, org) = remote_org(url)?;
+        let accounts = self.sorted_accounts(false);
+        if let Some(account) = accounts.iter().find(|account| account.host.eq_ignore_ascii_case(&host)) {
+            return Some(Inference { account, reason: format!("the remote goes through its Host alias {}", account.host) });
+        }
+
+        // Repositories of the organization already under each codebase dir.
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for account in accounts.iter().filter(|account| account.scope.is_dir()) {
+            discover::walk(&account.codebase_dir_path, &mut |repo| {
+                let same_org = repo.origin.as_deref().and_then(remote_org).is_some_and(|(_, repo_org)| repo_org == org);
+                // A nested account's repositories are its own.
+                if same_org && !dir.starts_with(&repo.path) && self.account_for_path(&repo.path).is_some_and(|owner| owner.name == account.name) {
+                    *counts.entry(account.name.as_str()).or_default() += 1;
+                }
+            });
+        }
+        let most = counts.values().copied().max().unwrap_or(0);
+        let leaders: Vec<&str> = counts.iter().filter(|(_, count)| **count == most).map(|(name, _)| *name).collect();
+        if let [name] = leaders.as_slice() {
+            let account = self.accounts.get(*name)?;
+            return Some(Inference { account, reason: format!("{} of its repositories are in {}", most, org) });
+        }
+        if leaders.len() > 1 {
+            return None;
+        }
+
+        // No repositories yet: an account named for the organization.
+        accounts
+            .iter()
+            .find(|account| {
+                account.login.as_deref().is_some_and(|login| login.eq_ignore_ascii_case(&org))
+                    || account.codebase_dir_path.file_name().is_some_and(|dir| dir.to_string_lossy().eq_ignore_ascii_case(&org))
+            })
+            .map(|account| Inference { account, reason: format!("its login or codebase dir is named {}", org) })
+    }
+
+    /// `status`: the repository in `dir`, the identity git uses there, the
+    /// account its directory gives and the account its remote suggests.
+    /// With `quiet` only problems are printed, and outside a repository
+    /// nothing. With `warn_on_mismatch` an identity other than the
+    /// directory's account is a warning, and fails the command.
+    pub fn repo_status(&self, dir: &Path, quiet: bool, warn_on_mismatch: bool) -> Result<bool> {
+        let Some(top) = worktree::git(dir, &["rev-parse", "--show-toplevel"]).ok().flatten() else {
+            if !quiet {
+                println!("{} is not inside a git repository", dir.display());
+            }
+            return Ok(quiet);
+        };
+        let top = Path::new(&top);
+        let origin = worktree::git(top, &["config", "--get", "remote.origin.url"])?;
+        let name = worktree::git(top, &["config", "--get", "user.name"])?;
+        let email = worktree::git(top, &["config", "--get", "user.email"])?;
+        let by_dir = self.account_for_path(top);
+        let inferred = origin.as_deref().and_then(|url| self.infer_account(url, top));
+
+        if !quiet {
+            println!("Repository:   {}", top.display());
+            println!("Origin:       {}", origin.as_deref().unwrap_or("(none)"));
+            match (&name, &email) {
+                (Some(name), Some(email)) => println!("Identity:     {} <{}>", name, email),
+                (None, Some(email)) => println!("Identity:     <{}>", email),
+                _ => println!("Identity:     (no user.email; git will refuse to commit)"),
+            }
+            println!("By directory: {}", by_dir.map_or("(no account)", |account| account.name.as_str()));
+            match &inferred {
+                Some(inferred) => println!("By remote:    {} ({})", inferred.account.name, inferred.reason),
+                None => println!("By remote:    (no account matches the remote)"),
+            }
+        }
+
+        let commits_as = |account: &GitAccount| email.as_deref().is_some_and(|email| email.eq_ignore_ascii_case(&account.email));
+        let mut ok = true;
+        if warn_on_mismatch {
+            if let Some(account) = by_dir.filter(|account| !commits_as(account)) {
+                eprintln!(
+                    "warning: {} commits as {}, not as {} <{}> whose codebase dir it's in",
+                    top.display(),
+                    email.as_deref().unwrap_or("nobody"),
+                    account.name,
+                    account.email
+                );
+                ok = false;
+            }
+        }
+        // Warned about above when it's also the directory's account.
+        let same_as_dir = |inferred: &Inference| warn_on_mismatch && by_dir.is_some_and(|account| account.name == inferred.account.name);
+        if let Some(inferred) = inferred.filter(|inferred| !commits_as(inferred.account) && !same_as_dir(inferred)) {
+            let suggestion = format!(
+                "this repository commits as {} but looks like {}'s; run multigit use {}",
+                email.as_deref().unwrap_or("nobody"),
+                inferred.account.name,
+                inferred.account.name
+            );
+            if warn_on_mismatch {
+                eprintln!("warning: {}", suggestion);
+                ok = false;
+            } else if !quiet {
+                println!("suggestion: {}", suggestion);
+            }
+        }
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::account::SetupOptions;
+    use crate::testutil::{self, TempHome};
+
+    fn repo(home: &TempHome, path: &str, origin: &str) -> std::path::PathBuf {
+        let dir = home.join(path);
+        fs::create_dir_all(&dir).unwrap();
+        worktree::git(&dir, &["init", "-q"]).unwrap();
+        worktree::git(&dir, &["remote", "add", "origin", origin]).unwrap();
+        dir
+    }
+
+    #[test]
+    fn account_is_taken_from_the_codebase_dir() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["home", "work"]);
+        let inside = home.join("code/work/api/src");
+        fs::create_dir_all(&inside).unwrap();
+
+        assert_eq!(manager.account_of_dir(&inside).unwrap(), "work");
+        assert_eq!(manager.account_or_cwd(Some("home".to_string())).unwrap(), "home");
+        let error = manager.account_of_dir(&home.join("elsewhere")).unwrap_err();
+        assert!(error.to_string().contains("name the account"), "{}", error);
+
+        // Two accounts sharing a glob at the same depth can't be told apart.
+        manager.setup_account("a", "a@example.com", "shared/*/", "github.com-a", SetupOptions::default()).unwrap();
+        manager.setup_account("b", "b@example.com", "shared/*/", "github.com-b", SetupOptions::default()).unwrap();
+        let error = manager.account_of_dir(&home.join("shared/x")).unwrap_err();
+        assert!(error.to_string().contains("a and b"), "{}", error);
+    }
+
+    #[test]
+    fn remote_org_suggests_the_account_holding_its_repositories() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["home", "work"]);
+        repo(&home, "code/work/api", "git@github.com-work:acme/api.git");
+        repo(&home, "code/home/dotfiles", "git@github.com-home:me/dotfiles.git");
+        let new = repo(&home, "elsewhere/web", "https://github.com/Acme/web.git");
+
+        let inferred = manager.infer_account("https://github.com/Acme/web.git", &new).unwrap();
+        assert_eq!(inferred.account.name, "work");
+        assert_eq!(inferred.reason, "1 of its repositories are in acme");
+        assert_eq!(manager.infer_account("git@github.com-home:acme/web.git", &new).unwrap().account.name, "home");
+        assert!(manager.infer_account("git@github.com:nobody/web.git", &new).is_none());
+        // The repository has no identity of its own, so the guess is a suggestion.
+        assert!(manager.repo_status(&new, false, false).unwrap());
+    }
+}
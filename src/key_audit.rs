@@ -0,0 +1,243 @@
+//! `key audit`: a read-only look at every managed key, reporting weak
+//! algorithms, keys without a passphrase, old keys and keys the provider
+//! doesn't know. Each finding has a severity and a stable ID that scripts
+//! can match on.
+
+use std::fs;
+use std::io::Result;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::account::{GitAccountManager, PROVIDER_HOST};
+use crate::doctor::describe_duration;
+use crate::github;
+use crate::keys;
+use crate::remote_keys::fingerprint_of;
+use crate::report::{print_json, OutputFormat};
+use crate::state::State;
+
+/// Keys older than this get `key-old`.
+const OLD_KEY_DAYS: u64 = 730;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Finding {
+    id: &'static str,
+    severity: Severity,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct AuditedKey {
+    account: String,
+    label: String,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bits: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encrypted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_days: Option<u64>,
+    /// `None` when it couldn't be checked: offline, or no token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registered: Option<bool>,
+    findings: Vec<Finding>,
+}
+
+impl AuditedKey {
+    fn find(&mut self, id: &'static str, severity: Severity, message: String) {
+        self.findings.push(Finding { id, severity, message });
+    }
+}
+
+/// Seconds since the epoch the key has existed for, from what multigit
+/// recorded when it first saw the key or the file's modification time,
+/// whichever is earlier.
+pub fn key_created(path: &str, fingerprint: Option<&str>, state: &State) -> Option<u64> {
+    let recorded = state.keys.iter().find(|record| Some(record.fingerprint.as_str()) == fingerprint).and_then(|record| record.first_seen);
+    let modified = fs::metadata(path).ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs());
+    match (recorded, modified) {
+        (Some(recorded), Some(modified)) => Some(recorded.min(modified)),
+        (recorded, modified) => recorded.or(modified),
+    }
+}
+
+impl GitAccountManager {
+    /// The fingerprints of the keys on the account's provider login, or
+    /// why they couldn't be listed.
+    fn registered_fingerprints(&self, account_name: &str) -> std::result::Result<Vec<String>, String> {
+        if self.offline {
+            return Err("offline".to_string());
+        }
+        let hostname = self.accounts.get(account_name).map_or(PROVIDER_HOST, |account| account.hostname());
+        if !hostname.eq_ignore_ascii_case(PROVIDER_HOST) {
+            return Err(format!("multigit can only list the keys on {}", PROVIDER_HOST));
+        }
+        let Some((token, _)) = self.api_token(Some(account_name)) else {
+            return Err("no token".to_string());
+        };
+        #[derive(serde::Deserialize)]
+        struct RemoteKey {
+            key: String,
+        }
+        let remote: Vec<RemoteKey> = github::get_all("/user/keys", Some(&token), "Listing the account's ssh keys").map_err(|e| e.to_string())?;
+        Ok(remote.iter().filter_map(|key| fingerprint_of(&key.key)).collect())
+    }
+
+    fn audit_accounts(&self) -> Result<Vec<AuditedKey>> {
+        let state = State::load(&self.home_dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+        let mut audited = Vec::new();
+        for account in self.sorted_accounts(true) {
+            let mut keys: Vec<(String, &str)> = Vec::new();
+            if !account.ssh_key.is_empty() {
+                keys.push(("primary".to_string(), account.ssh_key.as_str()));
+            }
+            keys.extend(account.secondary_keys.iter().map(|key| (key.label.clone(), key.path.as_str())));
+            if keys.is_empty() {
+                continue;
+            }
+            let registered = self.registered_fingerprints(&account.name);
+            if let Err(reason) = &registered {
+                eprintln!("note: {}: not checking which keys are on {} ({})", account.name, account.hostname(), reason);
+            }
+
+            for (label, path) in keys {
+                let mut key = AuditedKey {
+                    account: account.name.clone(),
+                    label,
+                    path: path.to_string(),
+                    key_type: None,
+                    bits: None,
+                    encrypted: None,
+                    age_days: None,
+                    registered: None,
+                    findings: Vec::new(),
+                };
+                if !Path::new(path).exists() {
+                    key.find("key-missing", Severity::High, format!("{} does not exist", path));
+                    audited.push(key);
+                    continue;
+                }
+
+                match keys::key_strength(path) {
+                    Some((bits, key_type)) => {
+                        match key_type.as_str() {
+                            "dsa" => key.find("key-dsa", Severity::High, "DSA keys are broken and refused by current OpenSSH".to_string()),
+                            "rsa" if bits < 2048 => key.find("key-rsa-weak", Severity::High, format!("RSA with {} bits is too short to be safe", bits)),
+                            "rsa" if bits < 3072 => key.find("key-rsa-short", Severity::Medium, format!("RSA with {} bits is below the 3072 recommended", bits)),
+                            _ => {}
+                        }
+                        key.key_type = Some(key_type);
+                        key.bits = Some(bits);
+                    }
+                    None => key.find("key-unreadable", Severity::Info, "ssh-keygen can't read the key's type and size".to_string()),
+                }
+
+                key.encrypted = keys::is_encrypted(path);
+                if key.encrypted == Some(false) {
+                    key.find(
+                        "key-unencrypted",
+                        Severity::Medium,
+                        format!("the private key has no passphrase; add one with multigit key passphrase {}", account.name),
+                    );
+                }
+
+                let fingerprint = keys::fingerprint_hash(path);
+                if let Some(created) = key_created(path, fingerprint.as_deref(), &state) {
+                    let age = now.saturating_sub(created);
+                    key.age_days = Some(age / 86400);
+                    if age / 86400 >= OLD_KEY_DAYS {
+                        let advice = if key.label == "primary" {
+                            format!("replace it with multigit key rotate {}", account.name)
+                        } else {
+                            format!("consider a new one with multigit key add {} and retiring this one", account.name)
+                        };
+                        key.find("key-old", Severity::Low, format!("{} old; {}", describe_duration(age), advice));
+                    }
+                }
+
+                if let (Ok(remote), Some(fingerprint)) = (&registered, &fingerprint) {
+                    let on_provider = remote.contains(fingerprint);
+                    key.registered = Some(on_provider);
+                    if !on_provider {
+                        key.find("key-not-registered", Severity::Medium, format!("not on the {} account, so it can't authenticate", account.hostname()));
+                    }
+                }
+                audited.push(key);
+            }
+        }
+        Ok(audited)
+    }
+
+    /// `key audit`. Fails with `strict` when any finding is high severity.
+    pub fn audit_keys(&self, format: OutputFormat, strict: bool) -> Result<bool> {
+        let audited = self.audit_accounts()?;
+        let high = audited.iter().flat_map(|key| &key.findings).any(|finding| finding.severity == Severity::High);
+
+        match format {
+            OutputFormat::Json => print_json(&audited)?,
+            OutputFormat::Text => {
+                if audited.is_empty() {
+                    println!("No managed keys.");
+                    return Ok(true);
+                }
+                for key in &audited {
+                    let hostname = self.accounts.get(&key.account).map_or(PROVIDER_HOST, |account| account.hostname());
+                    let mut facts = Vec::new();
+                    if let (Some(key_type), Some(bits)) = (&key.key_type, key.bits) {
+                        facts.push(format!("{} {}", key_type, bits));
+                    }
+                    match key.encrypted {
+                        Some(true) => facts.push("passphrase".to_string()),
+                        Some(false) => facts.push("no passphrase".to_string()),
+                        None => {}
+                    }
+                    match key.age_days {
+                        Some(0) => facts.push("less than a day old".to_string()),
+                        Some(days) => facts.push(format!("{} old", describe_duration(days * 86400))),
+                        None => {}
+                    }
+                    match key.registered {
+                        Some(true) => facts.push(format!("on {}", hostname)),
+                        Some(false) => facts.push(format!("not on {}", hostname)),
+                        None => {}
+                    }
+                    println!("{} {}: {}", key.account, key.label, key.path);
+                    if !facts.is_empty() {
+                        println!("  {}", facts.join(", "));
+                    }
+                    for finding in &key.findings {
+                        println!("  {}: {}: {}", finding.severity.as_str(), finding.id, finding.message);
+                    }
+                }
+                let findings = audited.iter().map(|key| key.findings.len()).sum::<usize>();
+                println!("{} key(s) audited, {} finding(s)", audited.len(), findings);
+            }
+        }
+        Ok(!(strict && high))
+    }
+}
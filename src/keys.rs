@@ -0,0 +1,1011 @@
+use std::env;
+use std::io::{self, BufRead, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::{run_ssh_keygen, GitAccountManager, SecondaryKey};
+use crate::connectivity;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::list::describe_created;
+use crate::passphrase::{self, PassphraseSource};
+use crate::platform;
+use crate::provider::Provider;
+use crate::ssh_config;
+use crate::state::State;
+use crate::templates;
+
+/// Key types ssh-keygen accepts with `-t`.
+pub const KEY_TYPES: &[&str] = &["ed25519", "ed25519-sk", "ecdsa", "ecdsa-sk", "rsa"];
+
+/// `--key-type` that asks for one the provider and the local ssh both take.
+pub const AUTO_KEY_TYPE: &str = "auto";
+
+pub fn validate_key_type(key_type: &str) -> Result<()> {
+    if key_type != AUTO_KEY_TYPE && !KEY_TYPES.contains(&key_type) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown key type '{}', expected {} or one of: {}", key_type, AUTO_KEY_TYPE, KEY_TYPES.join(", ")),
+        ));
+    }
+    Ok(())
+}
+
+/// The key types the local ssh supports, from `ssh -Q key`.
+pub fn local_key_types() -> Option<Vec<String>> {
+    let output = Command::new("ssh").args(["-Q", "key"]).stdin(Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// The signature algorithms the local ssh can sign with, from `ssh -Q sig`.
+fn local_sig_algs() -> Option<Vec<String>> {
+    let output = Command::new("ssh").args(["-Q", "sig"]).stdin(Stdio::null()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+/// RSA signatures GitHub accepts; it stopped taking SHA-1 `ssh-rsa` ones
+/// in March 2022 (see `Provider::refuses_sha1_rsa`).
+const RSA_SHA2: &[&str] = &["rsa-sha2-256", "rsa-sha2-512"];
+
+/// Why an RSA key on `hostname` may be refused, for providers that refuse
+/// SHA-1 signatures: an ssh that
+/// can only sign with SHA-1, or a `PubkeyAcceptedAlgorithms` list that
+/// leaves the SHA-2 signatures out. Otherwise a reminder not to bring back
+/// `ssh-rsa`, which only servers older than GitHub need.
+pub fn rsa_sha1_warning(key_path: &str, hostname: &str, pubkey_algorithms: Option<&str>) -> Option<String> {
+    if !Provider::from_hostname(hostname).is_some_and(Provider::refuses_sha1_rsa) || key_strength(key_path)?.1 != "rsa" {
+        return None;
+    }
+    // `+`, `-` and `^` lists edit OpenSSH's defaults, which have SHA-2.
+    let replaced = pubkey_algorithms.filter(|list| !list.starts_with(['+', '-', '^']));
+    if replaced.is_some_and(|list| !list.split(',').any(|alg| RSA_SHA2.contains(&alg.trim()))) {
+        return Some(format!(
+            "--pubkey-algorithms leaves out {}, and {} refuses SHA-1 ssh-rsa signatures; the RSA key won't authenticate",
+            RSA_SHA2.join(" and "),
+            hostname
+        ));
+    }
+    if local_sig_algs().is_some_and(|algs| !algs.iter().any(|alg| RSA_SHA2.contains(&alg.as_str()))) {
+        return Some(format!(
+            "this ssh can't sign with {}, which {} requires of RSA keys; upgrade to OpenSSH 7.2 or later, or use an ed25519 key",
+            RSA_SHA2.join(" or "),
+            hostname
+        ));
+    }
+    Some(format!(
+        "{} only accepts RSA keys signed with {}, which OpenSSH 7.2 and later negotiate; don't add PubkeyAcceptedAlgorithms +ssh-rsa for it, only servers that still want SHA-1 need that",
+        hostname,
+        RSA_SHA2.join(" or ")
+    ))
+}
+
+/// The key type to generate given what the local ssh (`ssh -Q key`) and the
+/// server (`server-sig-algs`) support, each `None` when unknown, and why:
+/// ed25519 unless one of them is known not to take it, then RSA.
+pub fn choose_key_type(local: Option<&[String]>, server: Option<&[String]>, host: &str) -> (&'static str, String) {
+    let has_ed25519 = |algs: &[String]| algs.iter().any(|alg| alg == "ssh-ed25519");
+    match (local, server) {
+        (Some(local), _) if !has_ed25519(local) => ("rsa", "this ssh doesn't support ed25519 keys".to_string()),
+        (_, Some(server)) if !has_ed25519(server) => ("rsa", format!("{} doesn't accept ed25519 keys", host)),
+        (_, Some(_)) => ("ed25519", format!("{} accepts ed25519 keys", host)),
+        (_, None) => ("ed25519", format!("couldn't tell which keys {} accepts", host)),
+    }
+}
+
+/// `--key-type auto` resolved by probing the server at `hostname`, which is
+/// skipped when offline.
+pub fn negotiate_key_type(offline: bool, hostname: &str) -> (&'static str, String) {
+    let server = if offline { None } else { connectivity::server_sig_algs(hostname) };
+    choose_key_type(local_key_types().as_deref(), server.as_deref(), hostname)
+}
+
+/// Public key signature algorithms OpenSSH accepts in
+/// `PubkeyAcceptedAlgorithms`.
+pub const PUBKEY_ALGORITHMS: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-ed25519-cert-v01@openssh.com",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ssh-ed25519-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp256-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp384-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp521",
+    "ecdsa-sha2-nistp521-cert-v01@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com",
+    "rsa-sha2-256",
+    "rsa-sha2-256-cert-v01@openssh.com",
+    "rsa-sha2-512",
+    "rsa-sha2-512-cert-v01@openssh.com",
+    "ssh-rsa",
+    "ssh-rsa-cert-v01@openssh.com",
+];
+
+/// Checks a comma-separated algorithm list, allowing the `+`, `-` and `^`
+/// prefixes ssh_config uses to modify the default set.
+pub fn validate_pubkey_algorithms(list: &str) -> Result<()> {
+    let names = list.strip_prefix(['+', '-', '^']).unwrap_or(list);
+    for name in names.split(',') {
+        if !PUBKEY_ALGORITHMS.contains(&name) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown pubkey algorithm '{}', expected one of: {}", name, PUBKEY_ALGORITHMS.join(", ")),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// File name of generated keys unless `setup --key-filename` says otherwise.
+pub const DEFAULT_KEY_FILENAME: &str = "id_{type}_{account}";
+
+/// The key file name `template` gives an account. The result has to be a
+/// single path component inside `~/.ssh` that doesn't look like a public key.
+pub fn key_filename(template: &str, account: &str, host: &str, key_type: &str) -> Result<String> {
+    let name = templates::render(template, &[("account", account), ("host", host), ("type", &key_type.replace('-', "_"))])
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("--key-filename: {}", e)))?;
+    let unsafe_char = name.chars().any(|c| c == '/' || c == '\\' || c.is_control() || c.is_whitespace());
+    if name.is_empty() || name == "." || name == ".." || name.starts_with('-') || unsafe_char || name.ends_with(".pub") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("--key-filename '{}' gives '{}', which isn't a plain file name", template, name),
+        ));
+    }
+    Ok(name)
+}
+
+/// Checks a `--key-filename` template before anything is generated with it.
+/// It has to name `{account}` or `{host}` so accounts get different keys.
+pub fn validate_key_filename(template: &str) -> Result<()> {
+    if !template.contains("{account}") && !template.contains("{host}") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("--key-filename '{}' needs {{account}} or {{host}}, or every account would get the same key", template),
+        ));
+    }
+    key_filename(template, "account", "github.com-account", "ed25519").map(|_| ())
+}
+
+/// Checks a `--comment-prefix`: the comment is the rest of the `.pub` line,
+/// so the prefix can't hold line breaks.
+pub fn validate_comment_prefix(prefix: &str) -> Result<()> {
+    if prefix.trim().is_empty() || prefix.chars().any(|c| c.is_control()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("--comment-prefix {:?} must be non-empty and on one line", prefix),
+        ));
+    }
+    Ok(())
+}
+
+/// The `-C` comment of a generated key: the email, after the prefix if one
+/// is configured.
+pub fn key_comment(prefix: Option<&str>, email: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{} {}", prefix.trim(), email),
+        None => email.to_string(),
+    }
+}
+
+/// The email in a key comment written by `key_comment`.
+pub fn comment_email<'a>(prefix: Option<&str>, comment: &'a str) -> &'a str {
+    prefix.and_then(|prefix| comment.strip_prefix(prefix.trim())).unwrap_or(comment).trim()
+}
+
+fn validate_label(label: &str) -> Result<()> {
+    let valid_chars = label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if label.is_empty() || !valid_chars {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid key label '{}': use letters, digits, '-' and '_'", label),
+        ));
+    }
+    if label == "primary" {
+        return Err(Error::new(ErrorKind::InvalidInput, "The label 'primary' is reserved for the account's main key"));
+    }
+    Ok(())
+}
+
+fn keygen_fingerprint(key_path: &str) -> Option<String> {
+    let pub_path = format!("{}.pub", key_path);
+    let target = if Path::new(&pub_path).exists() { pub_path.as_str() } else { key_path };
+
+    let output = Command::new("ssh-keygen").args(["-l", "-f", target]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Returns `ssh-keygen -l` output for the key, or a note if it can't be read.
+pub fn fingerprint(key_path: &str) -> String {
+    keygen_fingerprint(key_path).unwrap_or_else(|| "(fingerprint unavailable)".to_string())
+}
+
+/// The key's size in bits and its lowercased type, e.g. `(256, "ed25519")`.
+pub fn key_strength(key_path: &str) -> Option<(u32, String)> {
+    let line = keygen_fingerprint(key_path)?;
+    let bits = line.split_whitespace().next()?.parse().ok()?;
+    let key_type = line.rsplit_once('(')?.1.trim_end_matches(')').to_ascii_lowercase();
+    Some((bits, key_type))
+}
+
+/// Just the `SHA256:...` part of the key's fingerprint.
+pub fn fingerprint_hash(key_path: &str) -> Option<String> {
+    keygen_fingerprint(key_path)?.split_whitespace().nth(1).map(str::to_string)
+}
+
+/// What `ssh-keygen -L` says about an ssh certificate.
+pub struct Certificate {
+    /// Fingerprint of the public key the certificate was issued for.
+    pub key_fingerprint: String,
+    /// End of the validity period in seconds since the Unix epoch, `None`
+    /// for certificates valid forever.
+    pub valid_until: Option<u64>,
+}
+
+/// Seconds since the Unix epoch of a UTC `YYYY-MM-DDTHH:MM:SS`.
+fn parse_utc(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.split_once('T')?;
+    let mut date = date.split('-').map(|part| part.parse::<i64>());
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let mut time = time.split(':').map(|part| part.parse::<i64>());
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next().unwrap_or(Ok(0)).ok()?);
+    // Howard Hinnant's days-from-civil.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Reads a certificate with `ssh-keygen -L`, in UTC so the validity period
+/// doesn't depend on the local time zone. `None` if it isn't one.
+pub fn read_certificate(path: &Path) -> Option<Certificate> {
+    let output = Command::new("ssh-keygen").env("TZ", "UTC").arg("-L").arg("-f").arg(path).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    let field = |name: &str| text.lines().find_map(|line| line.trim().strip_prefix(name).map(str::trim));
+    let key_fingerprint = field("Public key:")?.split_whitespace().nth(1)?.to_string();
+    let valid = field("Valid:")?;
+    // `forever`, `from X to Y`, `after X` or `before Y`.
+    let valid_until = match valid.split_once(" to ").map(|(_, to)| to).or_else(|| valid.strip_prefix("before ")) {
+        Some(to) => Some(parse_utc(to.trim())?),
+        None => None,
+    };
+    Some(Certificate { key_fingerprint, valid_until })
+}
+
+/// Checks that a `--pkcs11-provider` library is there for ssh to load.
+pub fn check_pkcs11_provider(path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Err(Error::new(ErrorKind::NotFound, format!("PKCS#11 library {} does not exist", path.display())));
+    }
+    fsutil::utf8(path)?;
+    Ok(())
+}
+
+/// Checks that an existing private key and its `.pub` are there, and that
+/// the private key isn't readable by other users, which ssh refuses.
+pub fn check_existing_key(key_path: &Path) -> Result<()> {
+    let pub_path = PathBuf::from(format!("{}.pub", key_path.display()));
+    for path in [key_path, pub_path.as_path()] {
+        if !path.is_file() {
+            return Err(Error::new(ErrorKind::NotFound, format!("{} does not exist", path.display())));
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(key_path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "{} is accessible by other users (mode {:o}); ssh will refuse it. Run chmod 600 on it first",
+                    key_path.display(),
+                    mode & 0o777
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `<key>-cert.pub`, where ssh-keygen -s puts the certificate it signs
+/// for a key, if there is one.
+pub fn colocated_certificate(key_path: &Path) -> Option<PathBuf> {
+    let path = PathBuf::from(format!("{}-cert.pub", key_path.display()));
+    path.is_file().then_some(path)
+}
+
+/// Checks that `certificate` is an ssh certificate issued for `key_path`.
+pub fn check_certificate(certificate: &Path, key_path: &Path) -> Result<()> {
+    if !certificate.is_file() {
+        return Err(Error::new(ErrorKind::NotFound, format!("certificate {} does not exist", certificate.display())));
+    }
+    let Some(read) = read_certificate(certificate) else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} is not an ssh certificate", certificate.display())));
+    };
+    if fingerprint_hash(&key_path.to_string_lossy()).is_some_and(|fingerprint| fingerprint != read.key_fingerprint) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("certificate {} was issued for another key than {}", certificate.display(), key_path.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// The comment of the key's `.pub`, which ssh-keygen conventionally sets to
+/// the owner's email.
+pub fn pub_comment(key_path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(format!("{}.pub", key_path.display())).ok()?;
+    let comment = content.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+    (!comment.is_empty()).then_some(comment)
+}
+
+/// `--name-from-ssh-key`'s pattern when none is given: the comment up to
+/// its last `-`, so `work-ed25519` names the account `work`.
+pub const NAME_FROM_COMMENT: &str = "{name}-*";
+
+/// The part of `comment` that `{name}` stands for in `pattern`, whose other
+/// characters match as in an ssh Host pattern (`*`, `?`). `{name}` takes
+/// as much as it can.
+pub fn name_from_comment(comment: &str, pattern: &str) -> Result<Option<String>> {
+    let Some((before, after)) = pattern.split_once("{name}").filter(|(_, after)| !after.contains("{name}")) else {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("--name-from-ssh-key {}: the pattern needs exactly one {{name}}", pattern)));
+    };
+    let bounds: Vec<usize> = comment.char_indices().map(|(i, _)| i).chain([comment.len()]).collect();
+    for length in (1..bounds.len()).rev() {
+        for start in 0..bounds.len() - length {
+            let (from, to) = (bounds[start], bounds[start + length]);
+            if ssh_config::pattern_matches(before, &comment[..from]) && ssh_config::pattern_matches(after, &comment[to..]) {
+                return Ok(Some(comment[from..to].to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Fingerprints of the keys loaded in ssh-agent, or `None` when no agent is
+/// reachable.
+pub fn agent_fingerprints() -> Option<Vec<String>> {
+    let output = Command::new("ssh-add").arg("-l").output().ok()?;
+    // ssh-add exits 1 for an empty agent and 2 when it can't connect at all.
+    if output.status.code() == Some(2) {
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(1).map(str::to_string))
+            .collect(),
+    )
+}
+
+/// Which agent `SSH_AUTH_SOCK` points at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AgentKind {
+    /// OpenSSH's ssh-agent, including one started by `keychain`.
+    OpenSsh,
+    /// gnome-keyring's ssh component.
+    GnomeKeyring,
+    /// The agent launchd starts for each login on macOS.
+    Launchd,
+    /// No socket, or one nothing above is known to use.
+    Unknown,
+}
+
+/// The agent behind `socket`, judged by the paths each one puts its socket
+/// at: `/tmp/ssh-XXXX/agent.<pid>` for ssh-agent, `$XDG_RUNTIME_DIR/keyring/ssh`
+/// (`/tmp/keyring-XXXX/ssh` on older releases) for gnome-keyring and
+/// `/private/tmp/com.apple.launchd.XXXX/Listeners` for launchd.
+pub fn agent_kind(socket: Option<&str>) -> AgentKind {
+    let Some(socket) = socket.filter(|socket| !socket.is_empty()) else {
+        return AgentKind::Unknown;
+    };
+    let path = Path::new(socket);
+    let parent = path.parent().and_then(Path::file_name).map(|name| name.to_string_lossy()).unwrap_or_default();
+    let file = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if parent.starts_with("com.apple.launchd.") {
+        AgentKind::Launchd
+    } else if (parent == "keyring" || parent.starts_with("keyring-")) && file == "ssh" {
+        AgentKind::GnomeKeyring
+    } else if parent.starts_with("ssh-") && file.starts_with("agent.") {
+        AgentKind::OpenSsh
+    } else {
+        AgentKind::Unknown
+    }
+}
+
+pub fn current_agent_kind() -> AgentKind {
+    agent_kind(env::var("SSH_AUTH_SOCK").ok().as_deref())
+}
+
+/// Whether the private key is protected by a passphrase, judged by whether
+/// ssh-keygen can read it with an empty one. `None` if the key can't be read.
+pub fn is_encrypted(key_path: &str) -> Option<bool> {
+    let output = Command::new("ssh-keygen")
+        .args(["-y", "-P", "", "-f", key_path])
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        return Some(false);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr.contains("passphrase").then_some(true)
+}
+
+/// Whether an account's keys go through the macOS keychain (`setup --keychain`).
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Keychain {
+    /// `UseKeychain yes` in the Host block and `ssh-add --apple-use-keychain`.
+    Store,
+    /// Neither keychain directive, and plain `ssh-add`.
+    #[serde(rename = "none")]
+    #[cfg_attr(feature = "cli", value(name = "none"))]
+    Off,
+}
+
+/// `store` on macOS releases whose ssh understands `UseKeychain` (Sierra
+/// and later), `none` elsewhere; other ssh builds reject the directive.
+pub fn default_keychain() -> Keychain {
+    match platform::macos_version() {
+        Some(version) if version >= (10, 12) => Keychain::Store,
+        _ => Keychain::Off,
+    }
+}
+
+pub fn validate_keychain(keychain: Keychain) -> Result<()> {
+    if keychain == Keychain::Store && !platform::is_macos() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--keychain store needs macOS; other ssh builds reject UseKeychain",
+        ));
+    }
+    Ok(())
+}
+
+/// Flags for `ssh-add` under `keychain` with the agent `agent`. Only the
+/// launchd agent reads passphrases back from the keychain, so others get
+/// plain `ssh-add`.
+pub fn ssh_add_args_for(keychain: Keychain, agent: AgentKind) -> &'static [&'static str] {
+    match keychain {
+        Keychain::Store if platform::is_macos() && agent == AgentKind::Launchd => &["--apple-use-keychain"],
+        _ => &[],
+    }
+}
+
+/// `ssh_add_args_for` the agent `SSH_AUTH_SOCK` points at.
+pub fn ssh_add_args(keychain: Keychain) -> &'static [&'static str] {
+    ssh_add_args_for(keychain, current_agent_kind())
+}
+
+/// What taking a key out of the agent removed.
+pub struct AgentRemoval {
+    pub from_agent: bool,
+    /// The passphrase saved in the macOS login keychain, which would
+    /// otherwise load the key again at the next login.
+    pub from_keychain: bool,
+}
+
+/// Whether the macOS login keychain holds a passphrase for the key, which
+/// `ssh-add --apple-use-keychain` saves under the label `SSH: <path>`.
+fn in_macos_keychain(key_path: &str) -> bool {
+    platform::is_macos()
+        && Command::new("security")
+            .args(["find-generic-password", "-l", &format!("SSH: {}", key_path)])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+}
+
+/// The `ssh-add` arguments before the key that remove it from the agent,
+/// and with `in_keychain` its passphrase from the keychain too.
+fn ssh_add_delete_args(in_keychain: bool) -> &'static [&'static str] {
+    if in_keychain {
+        &["--apple-use-keychain", "-d"]
+    } else {
+        &["-d"]
+    }
+}
+
+/// Removes a key from the agent and, on macOS, its passphrase from the
+/// login keychain when one is saved there. A key that wasn't loaded is
+/// fine.
+pub fn remove_from_agent(key_path: &str) -> AgentRemoval {
+    let in_keychain = in_macos_keychain(key_path);
+    let args = ssh_add_delete_args(in_keychain);
+    let from_agent = Command::new("ssh-add").args(args).arg(key_path).output().is_ok_and(|output| output.status.success());
+    AgentRemoval { from_agent, from_keychain: in_keychain && !in_macos_keychain(key_path) }
+}
+
+/// Loads a key into the agent, storing its passphrase in the macOS keychain
+/// if `keychain` says so.
+fn add_to_agent(key_path: &str, keychain: Keychain) -> Result<()> {
+    let status = Command::new("ssh-add").args(ssh_add_args(keychain)).arg(key_path).status()?;
+    if !status.success() {
+        return Err(Error::other(format!("Failed to add {} to ssh-agent", key_path)));
+    }
+    Ok(())
+}
+
+/// Asks for a line of input; empty when stdin is closed.
+pub fn prompt(question: &str) -> Result<String> {
+    prompt_from(&mut io::stdin().lock(), question)
+}
+
+/// [`prompt`], reading the answer from `input`.
+pub fn prompt_from(input: &mut impl BufRead, question: &str) -> Result<String> {
+    print!("{}: ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+pub fn confirm(prompt: &str) -> Result<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// One ssh-add run of `reload-agent`.
+#[derive(Debug, PartialEq)]
+enum AgentStep<'a> {
+    /// `ssh-add -d <key>`
+    Remove(&'a str),
+    /// `ssh-add -D`
+    RemoveAll,
+    Add(&'a str, Keychain),
+}
+
+/// What `reload-agent` runs for `managed`: out goes every key, or with
+/// `managed_only` the managed keys `is_loaded` says are in the agent, then
+/// each managed key is added.
+fn reload_steps<'a>(managed: &[(&'a str, Keychain)], is_loaded: impl Fn(&str) -> bool, managed_only: bool) -> Vec<AgentStep<'a>> {
+    let removals = if managed_only {
+        managed.iter().filter(|(key, _)| is_loaded(key)).map(|(key, _)| AgentStep::Remove(key)).collect()
+    } else {
+        vec![AgentStep::RemoveAll]
+    };
+    removals.into_iter().chain(managed.iter().map(|(key, keychain)| AgentStep::Add(key, *keychain))).collect()
+}
+
+impl GitAccountManager {
+    /// Keys of every enabled account that uses the agent, with the account's
+    /// keychain mode.
+    fn agent_managed_keys(&self) -> Vec<(&str, Keychain)> {
+        self.sorted_accounts(false)
+            .into_iter()
+            .filter(|account| !self.skips_agent(account))
+            .flat_map(|account| account.identity_files().into_iter().map(|key| (key, account.keychain())))
+            .collect()
+    }
+
+    /// Reloads every managed key into ssh-agent. Clears the whole agent first
+    /// (after confirming unless `yes`), or with `managed_only` removes just the
+    /// managed keys so other keys stay loaded.
+    pub fn reload_agent(&self, managed_only: bool, yes: bool) -> Result<bool> {
+        let Some(loaded) = agent_fingerprints() else {
+            return Err(Error::other("No ssh-agent reachable"));
+        };
+        let managed = self.agent_managed_keys();
+        let is_loaded = |key: &str| fingerprint_hash(key).is_some_and(|fingerprint| loaded.contains(&fingerprint));
+        let steps = reload_steps(&managed, is_loaded, managed_only);
+
+        if !managed_only {
+            let prompt = format!("This removes all {} keys from ssh-agent, including ones multigit doesn't manage. Continue?", loaded.len());
+            if !yes && !confirm(&prompt)? {
+                println!("Aborted.");
+                return Ok(false);
+            }
+        }
+
+        let mut ok = true;
+        for step in steps {
+            match step {
+                AgentStep::Remove(key) => {
+                    let status = Command::new("ssh-add").args(["-d", key]).status()?;
+                    if !status.success() {
+                        return Err(Error::other(format!("Failed to remove {} from ssh-agent", key)));
+                    }
+                }
+                AgentStep::RemoveAll => {
+                    let status = Command::new("ssh-add").arg("-D").status()?;
+                    if !status.success() {
+                        return Err(Error::other("ssh-add -D failed"));
+                    }
+                }
+                AgentStep::Add(key, keychain) => {
+                    if let Err(e) = add_to_agent(key, keychain) {
+                        eprintln!("error: {}", e);
+                        ok = false;
+                    }
+                }
+            }
+        }
+        println!("Reloaded {} managed key(s)", managed.len());
+        Ok(ok)
+    }
+
+    /// Sets, changes or removes the passphrase on an account's primary key in
+    /// place with `ssh-keygen -p`, then reloads it into the agent.
+    pub fn change_passphrase(&self, account_name: &str, source: PassphraseSource) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.pkcs11_provider.is_some() {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{}'s keys are on a PKCS#11 token; change its PIN with the token's own tools", account_name),
+            ));
+        }
+
+        let mut command = Command::new("ssh-keygen");
+        command.args(["-p", "-f", &account.ssh_key]);
+
+        match source.read()? {
+            // ssh-keygen talks to the terminal itself, so its own messages
+            // (e.g. a wrong old passphrase) reach the user unchanged.
+            None => {
+                let status = command.status()?;
+                if !status.success() {
+                    return Err(Error::other("ssh-keygen -p failed"));
+                }
+            }
+            Some(new_passphrase) => {
+                let output = command
+                    .args(["-P", &passphrase::old_passphrase(), "-N", &new_passphrase])
+                    .stdin(Stdio::null())
+                    .output()?;
+                if !output.status.success() {
+                    return Err(Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+                }
+            }
+        }
+
+        if !self.skips_agent(account) {
+            add_to_agent(&account.ssh_key, account.keychain())?;
+        }
+        Ok(())
+    }
+
+    /// Generates (or, with `import`, registers) an additional key for an
+    /// account and lists it in the account's Host block after the existing ones.
+    pub fn add_secondary_key(&mut self, account_name: &str, label: &str, key_type: &str, import: Option<&Path>) -> Result<String> {
+        validate_label(label)?;
+        validate_key_type(key_type)?;
+        if key_type == AUTO_KEY_TYPE && import.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "--type auto picks the type of a generated key; pass the imported key's type instead"));
+        }
+        let key_type = if key_type == AUTO_KEY_TYPE {
+            let hostname = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?.hostname();
+            let (key_type, reason) = negotiate_key_type(self.offline, hostname);
+            println!("Key type: {} ({})", key_type, reason);
+            key_type
+        } else {
+            key_type
+        };
+
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.secondary_keys.iter().any(|key| key.label == label) {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Account '{}' already has a key labelled '{}'", account_name, label),
+            ));
+        }
+
+        let key_path = match import {
+            Some(path) => {
+                let pub_path = format!("{}.pub", path.display());
+                if !path.is_file() || !Path::new(&pub_path).is_file() {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        format!("Expected both {} and {} to exist", path.display(), pub_path),
+                    ));
+                }
+                fsutil::utf8(path)?.to_string()
+            }
+            None => {
+                let file_name = format!("id_{}_{}_{}", key_type.replace('-', "_"), account_name, label);
+                let key_file = self.home_dir.join(".ssh").join(file_name);
+                let key_file_str = fsutil::utf8(&key_file)?.to_string();
+                let comment = key_comment(self.settings.comment_prefix.as_deref(), &account.email);
+                run_ssh_keygen(key_type, &comment, &key_file, self.key_passphrase.as_deref().unwrap_or(""))?;
+                key_file_str
+            }
+        };
+
+        if !self.skips_agent(account) {
+            if let Err(e) = self.add_ssh_agent(&key_path, account.keychain()) {
+                eprintln!("warning: {}", e);
+            }
+        }
+
+        let account = self.accounts.get_mut(account_name).expect("account looked up above");
+        account.secondary_keys.push(SecondaryKey {
+            label: label.to_string(),
+            path: key_path.clone(),
+            key_type: key_type.to_string(),
+        });
+
+        let drift = self.identity_file_drift()?;
+        self.fix_identity_file_drift(&drift)?;
+        self.save()?;
+        self.remember_keys(account_name)?;
+        Ok(key_path)
+    }
+
+    pub fn show_keys(&self, account_name: &str) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+
+        println!("{} (Host {})", account.name, account.host);
+        println!("  created: {}", describe_created(account));
+        match &account.pkcs11_provider {
+            Some(provider) => println!("  primary: the keys of PKCS#11 library {}", provider.display()),
+            None => {
+                println!("  primary: {}", account.ssh_key);
+                println!("    {}", fingerprint(&account.ssh_key));
+            }
+        }
+        for key in &account.secondary_keys {
+            println!("  {} ({}): {}", key.label, key.key_type, key.path);
+            println!("    {}", fingerprint(&key.path));
+        }
+        if account.committer_name.is_some() || account.committer_email.is_some() {
+            println!(
+                "  committer: {} <{}> (author: {} <{}>)",
+                account.committer_name.as_deref().unwrap_or(&account.name),
+                account.committer_email.as_deref().unwrap_or(&account.email),
+                account.name,
+                account.email
+            );
+        }
+        if let Some(sendemail) = account.sendemail.describe() {
+            println!("  send-email: {}", sendemail);
+        }
+        if let Some(hooks_path) = &account.hooks_path {
+            let state = if hooks_path.is_dir() { "" } else { " (missing)" };
+            println!("  hooks: {}{}", hooks_path.display(), state);
+        }
+        if let Some(template_dir) = &account.template_dir {
+            let state = if template_dir.is_dir() { "" } else { " (missing)" };
+            println!("  template: {}{}", template_dir.display(), state);
+        }
+        Ok(())
+    }
+
+    /// Records the fingerprints of the account's current keys, so they can
+    /// be recognised on the provider after the key files are gone.
+    pub fn remember_keys(&self, account_name: &str) -> Result<()> {
+        let Some(account) = self.accounts.get(account_name) else {
+            return Ok(());
+        };
+        let mut state = State::load(&self.home_dir)?;
+        for key in account.identity_files() {
+            if let Some(fingerprint) = fingerprint_hash(key) {
+                state.record_key(account_name, key, &fingerprint);
+            }
+        }
+        state.save(&self.home_dir)
+    }
+
+    /// Account keys whose private half is there but whose `.pub` isn't.
+    pub fn missing_pub_keys(&self, include_disabled: bool) -> Vec<(String, String)> {
+        self.sorted_accounts(include_disabled)
+            .into_iter()
+            .flat_map(|account| {
+                account
+                    .identity_files()
+                    .into_iter()
+                    .filter(|key| Path::new(key).is_file() && !Path::new(&format!("{}.pub", key)).exists())
+                    .map(|key| (account.name.clone(), key.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Rewrites the missing `.pub` files of an account's keys from the
+    /// private keys, with the comment setup would have given them. Returns the files written.
+    pub fn regenerate_pub_keys(&self, account_name: &str) -> Result<Vec<String>> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+
+        let mut written = Vec::new();
+        for key in account.identity_files() {
+            let pub_path = format!("{}.pub", key);
+            if Path::new(&pub_path).exists() {
+                continue;
+            }
+            if !Path::new(key).is_file() {
+                return Err(Error::new(ErrorKind::NotFound, format!("{} does not exist", key)));
+            }
+
+            // ssh-keygen prompts on the terminal itself if the key has a
+            // passphrase.
+            let output = Command::new("ssh-keygen")
+                .args(["-y", "-f", key])
+                .stdin(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .output()?;
+            if !output.status.success() {
+                return Err(Error::other(format!("ssh-keygen -y failed for {}", key)));
+            }
+
+            // Newer ssh-keygen versions carry over the comment stored in the
+            // private key; replace it with the recorded one either way.
+            let public = String::from_utf8_lossy(&output.stdout);
+            let fields: Vec<&str> = public.split_whitespace().take(2).collect();
+            if fields.len() != 2 {
+                return Err(Error::other(format!("ssh-keygen -y printed no public key for {}", key)));
+            }
+            let line = format!("{} {} {}\n", fields[0], fields[1], key_comment(self.settings.comment_prefix.as_deref(), &account.email));
+
+            fsutil::write_atomic(Path::new(&pub_path), line.as_bytes())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&pub_path, std::fs::Permissions::from_mode(0o644))?;
+            }
+            written.push(pub_path);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::GitAccount;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    #[cfg_attr(not(target_os = "macos"), ignore = "the keychain modes only differ on macOS")]
+    fn keychain_modes_on_macos() {
+        let home = TempHome::new();
+        let manager = home.manager();
+        let block = |keychain| manager.host_block(&GitAccount { keychain: Some(keychain), ..testutil::account(&home, "work") }).unwrap();
+
+        assert!(validate_keychain(Keychain::Store).is_ok());
+        assert!(block(Keychain::Store).contains("    AddKeysToAgent yes\n    UseKeychain yes\n"));
+        assert_eq!(ssh_add_args_for(Keychain::Store, AgentKind::Launchd), ["--apple-use-keychain"]);
+        // Only launchd's agent reads the keychain back.
+        assert!(ssh_add_args_for(Keychain::Store, AgentKind::OpenSsh).is_empty());
+
+        assert!(!block(Keychain::Off).contains("Keychain") && !block(Keychain::Off).contains("AddKeysToAgent"));
+        assert!(ssh_add_args_for(Keychain::Off, AgentKind::Launchd).is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(not(target_os = "macos"), ignore = "only macOS keeps passphrases in the keychain")]
+    fn removal_deletes_a_keychain_passphrase_too() {
+        assert_eq!(ssh_add_delete_args(true), ["--apple-use-keychain", "-d"]);
+        assert_eq!(ssh_add_delete_args(false), ["-d"]);
+        // A key nothing saved in the keychain is only taken out of the agent.
+        let home = TempHome::new();
+        let key = home.join(".ssh/id_ed25519_work");
+        assert!(!in_macos_keychain(&key.to_string_lossy()));
+        assert!(!remove_from_agent(&key.to_string_lossy()).from_keychain);
+    }
+
+    #[test]
+    #[cfg_attr(target_os = "macos", ignore = "macOS ssh understands UseKeychain")]
+    fn keychain_store_needs_macos() {
+        assert!(validate_keychain(Keychain::Store).is_err());
+        assert_eq!(default_keychain(), Keychain::Off);
+        assert!(ssh_add_args_for(Keychain::Store, AgentKind::Launchd).is_empty());
+    }
+
+    #[test]
+    fn auto_key_type_falls_back_to_rsa_when_ed25519_is_refused() {
+        let local: Vec<String> = ["ssh-ed25519", "ssh-rsa", "ecdsa-sha2-nistp256"].map(String::from).to_vec();
+        let probe = "debug1: SSH2_MSG_EXT_INFO received\n\
+                     debug1: kex_input_ext_info: server-sig-algs=<rsa-sha2-512,rsa-sha2-256,ssh-rsa>\n";
+        let server = connectivity::parse_server_sig_algs(probe).unwrap();
+        assert_eq!(server, ["rsa-sha2-512", "rsa-sha2-256", "ssh-rsa"]);
+        assert_eq!(choose_key_type(Some(&local), Some(&server), "git.example.com").0, "rsa");
+
+        let server = connectivity::parse_server_sig_algs("debug1: kex_input_ext_info: server-sig-algs=<ssh-ed25519,rsa-sha2-512>").unwrap();
+        assert_eq!(choose_key_type(Some(&local), Some(&server), "github.com").0, "ed25519");
+        // An inconclusive probe keeps the default.
+        assert_eq!(connectivity::parse_server_sig_algs("ssh: connect to host github.com port 22: Network is unreachable"), None);
+        assert_eq!(choose_key_type(Some(&local), None, "github.com").0, "ed25519");
+        assert_eq!(choose_key_type(Some(&local[1..]), None, "github.com").0, "rsa");
+    }
+
+    #[test]
+    fn key_filename_template_names_the_key_and_the_config() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.settings.key_filename = Some("{host}_{type}".to_string());
+        testutil::set_up(&mut manager, &["work"]);
+
+        let key = home.join(".ssh/github.com-work_ed25519");
+        assert!(key.exists() && home.join(".ssh/github.com-work_ed25519.pub").exists());
+        assert_eq!(manager.accounts["work"].ssh_key, key.to_string_lossy());
+        assert!(home.read(".ssh/config").contains(&format!("    IdentityFile {}\n", key.display())));
+
+        assert!(validate_key_filename("id_{type}").is_err());
+        assert!(validate_key_filename("../{account}").is_err());
+        assert!(validate_key_filename("{account}.pub").is_err());
+    }
+
+    #[test]
+    fn comment_prefix_tags_every_generated_key() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        manager.settings.comment_prefix = Some("multigit:laptop".to_string());
+        testutil::set_up(&mut manager, &["work", "home"]);
+        let deploy = manager.add_secondary_key("work", "deploy", "ed25519", None).unwrap();
+
+        for (key, email) in [(manager.accounts["work"].ssh_key.as_str(), "work@example.com"), (manager.accounts["home"].ssh_key.as_str(), "home@example.com"), (deploy.as_str(), "work@example.com")] {
+            let comment = pub_comment(Path::new(key)).unwrap();
+            assert_eq!(comment, format!("multigit:laptop {}", email));
+            assert_eq!(comment_email(Some("multigit:laptop"), &comment), email);
+        }
+        assert!(validate_comment_prefix("two\nlines").is_err());
+    }
+
+    #[test]
+    fn agent_kind_from_the_socket_path() {
+        let cases = [
+            ("/tmp/ssh-XXXXabcd/agent.1234", AgentKind::OpenSsh),
+            ("/run/user/1000/keyring/ssh", AgentKind::GnomeKeyring),
+            ("/tmp/keyring-Ab12Cd/ssh", AgentKind::GnomeKeyring),
+            ("/private/tmp/com.apple.launchd.a1B2c3/Listeners", AgentKind::Launchd),
+            ("/run/user/1000/gnupg/S.gpg-agent.ssh", AgentKind::Unknown),
+            ("/run/user/1000/keyring/control", AgentKind::Unknown),
+        ];
+        for (socket, kind) in cases {
+            assert_eq!(agent_kind(Some(socket)), kind, "{}", socket);
+        }
+        assert_eq!(agent_kind(Some("")), AgentKind::Unknown);
+        assert_eq!(agent_kind(None), AgentKind::Unknown);
+    }
+
+    #[test]
+    fn reload_removes_only_loaded_managed_keys_or_everything() {
+        let managed = [("/keys/work", Keychain::Off), ("/keys/home", Keychain::Store)];
+        let loaded = |key: &str| key == "/keys/home";
+
+        assert_eq!(
+            reload_steps(&managed, loaded, true),
+            [AgentStep::Remove("/keys/home"), AgentStep::Add("/keys/work", Keychain::Off), AgentStep::Add("/keys/home", Keychain::Store)]
+        );
+        assert_eq!(
+            reload_steps(&managed, loaded, false),
+            [AgentStep::RemoveAll, AgentStep::Add("/keys/work", Keychain::Off), AgentStep::Add("/keys/home", Keychain::Store)]
+        );
+    }
+
+    #[test]
+    fn rsa_key_on_github_is_warned_about_sha1() {
+        let home = TempHome::new();
+        let rsa = home.join("id_rsa");
+        let ed25519 = home.join("id_ed25519");
+        crate::account::run_ssh_keygen("rsa", "work@example.com", &rsa, "").unwrap();
+        crate::account::run_ssh_keygen("ed25519", "work@example.com", &ed25519, "").unwrap();
+        let (rsa, ed25519) = (rsa.to_str().unwrap(), ed25519.to_str().unwrap());
+
+        let warning = rsa_sha1_warning(rsa, "github.com", None).unwrap();
+        assert!(warning.contains("rsa-sha2-256"), "{}", warning);
+        let warning = rsa_sha1_warning(rsa, "github.com", Some("ssh-rsa")).unwrap();
+        assert!(warning.starts_with("--pubkey-algorithms leaves out rsa-sha2-256 and rsa-sha2-512"), "{}", warning);
+        assert!(!rsa_sha1_warning(rsa, "github.com", Some("+ssh-rsa")).unwrap().starts_with("--pubkey-algorithms"));
+        assert_eq!(rsa_sha1_warning(ed25519, "github.com", None), None);
+        assert_eq!(rsa_sha1_warning(rsa, "gitlab.com", None), None);
+    }
+}
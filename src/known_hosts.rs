@@ -0,0 +1,215 @@
+//! `setup --check-remote-host-key`: adding the provider's host keys to
+//! `~/.ssh/known_hosts` ahead of the first clone, so it doesn't stop at
+//! "authenticity of host can't be established". The keys come from
+//! `ssh-keyscan` and are only written when their fingerprints are the ones
+//! the provider publishes. Accounts set up with `--known-hosts-file` get
+//! them in their own file instead.
+
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::account::{GitAccount, PROVIDER_HOST};
+use crate::fsutil;
+use crate::report::StepStatus;
+
+/// The deferred step of an offline setup that asked for the host keys.
+pub const KNOWN_HOSTS_STEP: &str = "known_hosts";
+
+/// The host key fingerprints GitHub publishes, by key type.
+const PUBLISHED_FINGERPRINTS: [(&str, &str); 3] = [
+    ("ssh-ed25519", "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU"),
+    ("ecdsa-sha2-nistp256", "SHA256:p2QAMXNIC1TJYWeIOttrVc98/R1BUFWu3/LiyKgUfQM"),
+    ("ssh-rsa", "SHA256:uNiVztksCsDhcc0u9e8BujQXVUpKZIDTMczCvj3tD2s"),
+];
+
+pub fn known_hosts_path(home_dir: &Path) -> PathBuf {
+    home_dir.join(".ssh/known_hosts")
+}
+
+/// The known hosts file ssh uses for `account`'s Host block.
+pub fn account_file(home_dir: &Path, account: &GitAccount) -> PathBuf {
+    account.known_hosts_file.clone().unwrap_or_else(|| known_hosts_path(home_dir))
+}
+
+/// Creates an empty known hosts file only its owner can read, unless there
+/// is one. Returns whether it was created.
+pub fn ensure_file(path: &Path) -> Result<bool> {
+    if path.exists() {
+        return Ok(false);
+    }
+    if let Some(parent) = path.parent() {
+        fsutil::create_dir_all(parent)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path).map_err(|e| fsutil::blocked(e, "create", path))?;
+    Ok(true)
+}
+
+/// Whether `path` has a host key for the provider.
+pub fn has_provider_keys(path: &Path) -> bool {
+    !known_keys(path, PROVIDER_HOST).is_empty()
+}
+
+/// `<host> <type> <key>` lines `ssh-keyscan` prints for `host`.
+fn keyscan(host: &str) -> Result<Vec<String>> {
+    let output = Command::new("ssh-keyscan")
+        .args(["-T", "10", "-t", "ed25519,ecdsa,rsa", host])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::new(ErrorKind::NotFound, format!("Failed to run ssh-keyscan: {}", e)))?;
+    let lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#') && line.split_whitespace().count() == 3)
+        .map(str::to_string)
+        .collect();
+    if lines.is_empty() {
+        return Err(Error::other(format!(
+            "ssh-keyscan {} returned no host keys: {}",
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(lines)
+}
+
+/// The `SHA256:` fingerprint of a known_hosts line, from `ssh-keygen -lf -`.
+fn fingerprint(line: &str) -> Option<String> {
+    let mut child = Command::new("ssh-keygen")
+        .args(["-l", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?.write_all(format!("{}\n", line).as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).split_whitespace().nth(1).map(str::to_string)
+}
+
+/// The `<type> <key>` of every entry for `host` in `path`, hashed entries
+/// included, through `ssh-keygen -F`.
+fn known_keys(path: &Path, host: &str) -> Vec<String> {
+    if !path.exists() {
+        return Vec::new();
+    }
+    let Ok(output) = Command::new("ssh-keygen").arg("-F").arg(host).arg("-f").arg(path).stdin(Stdio::null()).output() else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' ').map(|(_, key)| key.trim().to_string()))
+        .collect()
+}
+
+/// Scans the provider's host keys and appends those the known hosts file at
+/// `path` doesn't have yet, returning how many were added. A key whose fingerprint isn't
+/// the published one for its type is left out and fails the step: the scan
+/// may have reached something other than the provider.
+pub fn add_provider_host_keys(path: &Path) -> Result<usize> {
+    add_host_keys(path, keyscan(PROVIDER_HOST)?)
+}
+
+/// `add_provider_host_keys` for the lines a scan returned.
+fn add_host_keys(path: &Path, scanned: Vec<String>) -> Result<usize> {
+    let known = known_keys(path, PROVIDER_HOST);
+    let mut verified = Vec::new();
+    let mut mismatches = Vec::new();
+    for line in scanned {
+        let key_type = line.split_whitespace().nth(1).unwrap_or_default();
+        let published = PUBLISHED_FINGERPRINTS.iter().find(|(published_type, _)| *published_type == key_type).map(|(_, fp)| *fp);
+        let actual = fingerprint(&line);
+        match (published, &actual) {
+            (Some(published), Some(actual)) if published == actual => verified.push(line),
+            // A type the provider doesn't publish a fingerprint for can't be checked.
+            (None, _) => continue,
+            _ => mismatches.push(format!("{} {} (expected {})", key_type, actual.as_deref().unwrap_or("unreadable"), published.unwrap_or_default())),
+        }
+    }
+    let added: Vec<String> = verified
+        .into_iter()
+        .filter(|line| line.split_once(' ').is_some_and(|(_, key)| !known.iter().any(|known| known == key)))
+        .collect();
+    if !added.is_empty() {
+        if let Some(parent) = path.parent() {
+            fsutil::create_dir_all(parent)?;
+        }
+        fsutil::modify(path, |content| {
+            let mut content = content.to_string();
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            for line in &added {
+                content.push_str(line);
+                content.push('\n');
+            }
+            Ok(Some(content))
+        })?;
+    }
+    if !mismatches.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{}'s scanned host keys don't match the published fingerprints, so they weren't added: {}",
+                PROVIDER_HOST,
+                mismatches.join(", ")
+            ),
+        ));
+    }
+    Ok(added.len())
+}
+
+/// `add_provider_host_keys` as a setup or deferred step.
+pub fn host_keys_step(path: &Path) -> StepStatus {
+    match add_provider_host_keys(path) {
+        Ok(0) => StepStatus::Skipped(format!("{} is already in {}", PROVIDER_HOST, path.display())),
+        Ok(_) => StepStatus::Ok,
+        Err(e) => StepStatus::Failed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempHome;
+
+    const ED25519: &str = "github.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    #[test]
+    fn scanned_keys_are_added_once() {
+        let home = TempHome::new();
+        let path = home.write(".ssh/known_hosts", "example.com ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl");
+
+        assert_eq!(add_host_keys(&path, vec![ED25519.to_string()]).unwrap(), 1);
+        assert_eq!(add_host_keys(&path, vec![ED25519.to_string()]).unwrap(), 0);
+        assert_eq!(home.read(".ssh/known_hosts").matches(ED25519).count(), 1);
+        assert!(home.read(".ssh/known_hosts").starts_with("example.com "));
+        assert!(has_provider_keys(&path));
+    }
+
+    #[test]
+    fn a_key_with_the_wrong_fingerprint_isnt_added() {
+        let home = TempHome::new();
+        let key = home.join("scanned");
+        let status = Command::new("ssh-keygen").args(["-q", "-t", "ed25519", "-N", "", "-f"]).arg(&key).status().unwrap();
+        assert!(status.success());
+        let public = std::fs::read_to_string(key.with_extension("pub")).unwrap();
+        let forged: Vec<&str> = public.split_whitespace().take(2).collect();
+        let path = home.join(".ssh/known_hosts");
+
+        let error = add_host_keys(&path, vec![format!("github.com {}", forged.join(" "))]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,80 @@
+//! multigit's library: `account::GitAccountManager` and the modules that
+//! set up, check and repair accounts. The `multigit` binary is the command
+//! line on top of it.
+
+pub mod account;
+pub mod agent_service;
+pub mod backups;
+pub mod client_tls;
+pub mod clone;
+#[cfg(feature = "cli")]
+pub mod completions;
+pub mod config_diff;
+pub mod conflicts;
+pub mod connectivity;
+pub mod diff;
+pub mod disable;
+pub mod discover;
+pub mod doctor;
+pub mod drift;
+pub mod duplicate_hosts;
+pub mod email_policy;
+pub mod env;
+pub mod errors;
+#[cfg(feature = "json")]
+pub mod events;
+pub mod exclusions;
+pub mod explain;
+pub mod fix_remotes;
+pub mod fsutil;
+pub mod git_settings;
+pub mod git_url;
+pub mod gitconfig;
+pub mod github;
+pub mod host_conflict;
+pub mod include_if;
+pub mod infer;
+pub mod key_audit;
+pub mod keys;
+pub mod known_hosts;
+pub mod list;
+pub mod machine_report;
+pub mod mailmap;
+pub mod managed;
+pub mod migrate;
+pub mod multiplex;
+pub mod orphan_keys;
+pub mod ownership;
+pub mod passphrase;
+pub mod permissions;
+pub mod plan;
+pub mod platform;
+pub mod proxy;
+pub mod provider;
+pub mod prune;
+pub mod reinit;
+pub mod relocate;
+pub mod remote_keys;
+pub mod remove;
+pub mod repair;
+pub mod report;
+pub mod resolve;
+pub mod rotate;
+pub mod scan;
+pub mod sendemail;
+pub mod shell_hook;
+pub mod snapshot;
+pub mod ssh_config;
+pub mod state;
+pub mod store;
+pub mod tamper;
+pub mod templates;
+#[cfg(test)]
+mod testutil;
+pub mod test_all;
+pub mod test_push;
+pub mod token_store;
+pub mod upgrade;
+pub mod validate;
+pub mod worktree;
+pub mod wsl;
@@ -0,0 +1,162 @@
+use std::io::Result;
+
+use serde::Serialize;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::report::{print_json, print_table};
+use crate::snapshot::format_time;
+
+/// `list --format`.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ListFormat {
+    /// Aligned columns with a header
+    #[default]
+    Table,
+    /// Tab-separated fields, one account per line, no header
+    Plain,
+    /// RFC 4180 CSV with a header row
+    Csv,
+    Json,
+}
+
+const HEADER: [&str; 7] = ["name", "email", "host", "dir", "key", "disabled", "created"];
+
+/// When the account was created, `YYYY-MM-DD HH:MM` in UTC, marked when
+/// it's the key file's date standing in for it.
+pub fn describe_created(account: &GitAccount) -> String {
+    match account.created() {
+        Some((at, true)) => format_time(at),
+        Some((at, false)) => format!("{} (key file)", format_time(at)),
+        None => "unknown".to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    name: &'a str,
+    email: &'a str,
+    host: &'a str,
+    dir: String,
+    key: String,
+    disabled: bool,
+    /// Seconds since the epoch; `null` for accounts from before it was
+    /// recorded.
+    created_at: Option<u64>,
+    #[serde(skip)]
+    created: String,
+}
+
+impl<'a> Row<'a> {
+    fn new(account: &'a GitAccount) -> Self {
+        Row {
+            name: &account.name,
+            email: &account.email,
+            host: &account.host,
+            dir: account.dir_display(),
+            key: account.key_display(),
+            disabled: account.disabled,
+            created_at: account.created_at,
+            created: describe_created(account),
+        }
+    }
+
+    fn fields(&self) -> [String; 7] {
+        [
+            self.name.to_string(),
+            self.email.to_string(),
+            self.host.to_string(),
+            self.dir.clone(),
+            self.key.clone(),
+            self.disabled.to_string(),
+            self.created.clone(),
+        ]
+    }
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_line<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter().map(|field| csv_field(field.as_ref())).collect::<Vec<_>>().join(",")
+}
+
+pub fn print_accounts(manager: &GitAccountManager, format: ListFormat) -> Result<()> {
+    let accounts = manager.sorted_accounts(true);
+    let rows: Vec<Row> = accounts.iter().map(|account| Row::new(account)).collect();
+
+    match format {
+        ListFormat::Table => {
+            if rows.is_empty() {
+                println!("No accounts configured.");
+                return Ok(());
+            }
+            let mut table: Vec<[String; 7]> = vec![HEADER.map(|title| title.to_ascii_uppercase())];
+            for row in &rows {
+                let mut fields = row.fields();
+                fields[5] = if row.disabled { "yes" } else { "" }.to_string();
+                table.push(fields);
+            }
+            print_table(&table);
+        }
+        ListFormat::Plain => {
+            for row in &rows {
+                println!("{}", row.fields().join("\t"));
+            }
+        }
+        ListFormat::Csv => {
+            println!("{}", csv_line(&HEADER));
+            for row in &rows {
+                println!("{}", csv_line(&row.fields()));
+            }
+        }
+        ListFormat::Json => {
+            print_json(&rows)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    /// RFC 4180 fields of one record.
+    fn parse_csv(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                ('"', _) => quoted = !quoted,
+                (',', false) => fields.push(String::new()),
+                (c, _) => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    #[test]
+    fn csv_fields_round_trip() {
+        let home = TempHome::new();
+        let account = GitAccount { codebase_dir_path: home.join("code/acme, inc"), email: "\"me\"@example.com".to_string(), ..testutil::account(&home, "work") };
+        let row = Row::new(&account);
+
+        let line = csv_line(&row.fields());
+        assert!(line.contains(&format!(",\"{}\",", row.dir)));
+        assert!(line.contains(",\"\"\"me\"\"@example.com\","));
+        assert_eq!(parse_csv(&line), row.fields());
+        assert_eq!(parse_csv(&csv_line(&HEADER)), HEADER);
+    }
+}
@@ -0,0 +1,210 @@
+//! `report`: the whole setup of this machine as one Markdown document, for
+//! onboarding notes or a support ticket: every account with its keys and
+//! the blocks multigit manages for it, then what the offline doctor checks
+//! find. Nothing secret goes in; keys appear as paths and fingerprints.
+
+use std::io::{ErrorKind, Result};
+use std::path::PathBuf;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::fsutil;
+use crate::keys;
+use crate::ownership;
+use crate::state::State;
+use crate::tamper;
+
+/// `report --format`.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ReportFormat {
+    #[default]
+    Markdown,
+}
+
+/// A table cell, with the characters that would end it escaped.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// `body` in a fenced code block, the fence longer than any run of
+/// backticks inside.
+fn fenced(body: &str) -> String {
+    let longest = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}\n{}\n{}\n", fence, body.trim_end(), fence)
+}
+
+fn fingerprint(key: &str) -> String {
+    keys::fingerprint_hash(key).unwrap_or_else(|| "fingerprint unavailable".to_string())
+}
+
+impl GitAccountManager {
+    /// The account's marked block in each file multigit writes for it,
+    /// `None` where it's missing.
+    fn managed_blocks(&self, account: &GitAccount) -> Result<Vec<(PathBuf, Option<String>)>> {
+        let mut paths = vec![self.ssh_config_path()];
+        if !self.settings.no_global_edit {
+            paths.push(self.global_gitconfig_path());
+        }
+        paths.push(self.include_file_path(account));
+
+        let mut blocks = Vec::new();
+        for path in paths {
+            let content = match fsutil::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+                Err(e) => return Err(fsutil::blocked(e, "read", &path)),
+            };
+            let body = tamper::block_body(&content, &account.name);
+            blocks.push((path, body));
+        }
+        Ok(blocks)
+    }
+
+    fn account_section(&self, account: &GitAccount) -> Result<String> {
+        let mut out = format!("## {}\n\n", account.name);
+        out.push_str(&format!("- Email: {}\n", account.email));
+        out.push_str(&format!("- Host alias: `{}`\n", account.host));
+        out.push_str(&format!("- Codebase dir: `{}`\n", account.dir_display()));
+        match &account.pkcs11_provider {
+            Some(provider) => out.push_str(&format!("- Key: PKCS#11 library `{}`\n", provider.display())),
+            None => out.push_str(&format!("- Key: `{}` ({})\n", account.ssh_key, fingerprint(&account.ssh_key))),
+        }
+        for key in &account.secondary_keys {
+            out.push_str(&format!("- Key {}: `{}` ({})\n", key.label, key.path, fingerprint(&key.path)));
+        }
+        if account.disabled {
+            out.push_str("- Disabled\n");
+        }
+
+        out.push_str("\n### Managed config\n\n");
+        for (path, body) in self.managed_blocks(account)? {
+            match body {
+                Some(body) => out.push_str(&format!("`{}`:\n\n{}\n", path.display(), fenced(&body))),
+                None => out.push_str(&format!("`{}`: no block\n\n", path.display())),
+            }
+        }
+        Ok(out)
+    }
+
+    /// The problems the doctor checks that need no network or agent find,
+    /// one line each.
+    fn health_problems(&self) -> Result<Vec<String>> {
+        let mut problems = Vec::new();
+        for name in self.missing_host_blocks()? {
+            problems.push(format!("{}: no Host block for {} in `{}`", name, self.accounts[&name].host, self.ssh_config_path().display()));
+        }
+        for duplicate in self.duplicate_host_aliases() {
+            problems.push(format!("Host {} is declared {} times; ssh uses {}", duplicate.alias, duplicate.declarations.len(), duplicate.declarations[0].describe()));
+        }
+        for path in ownership::root_owned_ssh_files(self) {
+            problems.push(format!("`{}` is owned by root", path.display()));
+        }
+        for (account, key) in self.missing_pub_keys(false) {
+            problems.push(format!("{}: `{}.pub` is missing", account, key));
+        }
+        for drift in self.identity_file_drift()? {
+            problems.push(format!("{}: Host {} doesn't point at the account's keys", drift.account, drift.host));
+        }
+        for drift in self.rewrite_drift()? {
+            problems.push(format!("{}: `{}` doesn't rewrite remotes to Host {}", drift.account, drift.path.display(), drift.expected.join(", ")));
+        }
+        if let Some(order) = self.include_if_order()? {
+            problems.push(order.describe());
+        }
+        for mismatch in self.identity_mismatches()? {
+            problems.push(format!(
+                "repositories under `{}` get {} instead of {}",
+                mismatch.dir.display(),
+                mismatch.actual.as_deref().unwrap_or("no account"),
+                mismatch.expected
+            ));
+        }
+        for conflict in self.conflicts() {
+            problems.push(format!("`{}`: {}", conflict.file.display(), conflict.message));
+        }
+        Ok(problems)
+    }
+
+    fn health_section(&self) -> Result<String> {
+        let mut out = "## Health\n\n".to_string();
+        let problems = self.health_problems()?;
+        if problems.is_empty() {
+            out.push_str("No problems found.\n");
+        } else {
+            out.push_str(&format!("{} problem{} found:\n\n", problems.len(), if problems.len() == 1 { "" } else { "s" }));
+            for problem in &problems {
+                out.push_str(&format!("- {}\n", problem));
+            }
+        }
+        let deferred = State::load(&self.home_dir)?.deferred;
+        if !deferred.is_empty() {
+            out.push_str("\nDeferred steps:\n\n");
+            for step in &deferred {
+                out.push_str(&format!("- {}: {}\n", step.account, step.step));
+            }
+        }
+        out.push_str("\nThe ssh-agent, connectivity and provider checks aren't included; `multigit doctor` runs them.\n");
+        Ok(out)
+    }
+
+    /// The report as a Markdown document.
+    pub fn markdown_report(&self) -> Result<String> {
+        let accounts = self.sorted_accounts(true);
+        let mut out = "# multigit report\n\n".to_string();
+        out.push_str(&format!("Home directory: `{}`\n\n", self.home_dir.display()));
+
+        if accounts.is_empty() {
+            out.push_str("No accounts configured.\n\n");
+        } else {
+            out.push_str("| Account | Email | Host alias | Codebase dir | Disabled |\n");
+            out.push_str("| --- | --- | --- | --- | --- |\n");
+            for account in &accounts {
+                out.push_str(&format!(
+                    "| {} | {} | `{}` | `{}` | {} |\n",
+                    cell(&account.name),
+                    cell(&account.email),
+                    cell(&account.host),
+                    cell(&account.dir_display()),
+                    if account.disabled { "yes" } else { "" }
+                ));
+            }
+            out.push('\n');
+        }
+        for account in accounts {
+            out.push_str(&self.account_section(account)?);
+        }
+        out.push_str(&self.health_section()?);
+        Ok(out)
+    }
+}
+
+pub fn print_report(manager: &GitAccountManager, format: ReportFormat) -> Result<()> {
+    match format {
+        ReportFormat::Markdown => print!("{}", manager.markdown_report()?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn markdown_has_a_section_per_account_and_the_health() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["home", "work"]);
+        let report = manager.markdown_report().unwrap();
+        for name in ["home", "work"] {
+            assert!(report.contains(&format!("\n## {}\n\n- Email: {}@example.com\n- Host alias: `github.com-{}`\n", name, name, name)), "{}", report);
+            assert!(report.contains(&format!("| {} | {}@example.com | `github.com-{}` |", name, name, name)));
+        }
+        assert!(report.contains("SHA256:"));
+        assert!(report.contains("\n## Health\n\nNo problems found.\n"), "{}", report);
+
+        home.write(".ssh/config", "");
+        let report = manager.markdown_report().unwrap();
+        assert!(report.contains("\n## Health\n\n2 problems found:\n\n- home: no Host block for github.com-home"), "{}", report);
+    }
+}
@@ -0,0 +1,106 @@
+//! `mailmap generate`: `.mailmap` lines that map the addresses an account
+//! committed under before multigit, its `previous_emails` and any given
+//! with `--extra`, to the account's name and email, so `git shortlog` and
+//! `git log --use-mailmap` count them as one person.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::errors::account_not_found;
+use crate::fsutil;
+
+/// The address a mailmap line maps commits from: the last `<...>` on it.
+fn commit_email(line: &str) -> Option<&str> {
+    let line = line.split('#').next().unwrap_or_default();
+    let start = line.rfind('<')?;
+    let end = start + line[start..].find('>')?;
+    Some(line[start + 1..end].trim())
+}
+
+/// `path` itself, or the `.mailmap` in it when it's a directory such as a
+/// repository.
+fn target_path(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.join(".mailmap")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// One line per old address of `account`, leaving out its current email.
+fn lines_for(account: &GitAccount, extra: &[String]) -> Vec<String> {
+    let mut emails: Vec<&str> = Vec::new();
+    for email in account.previous_emails.iter().chain(extra).map(|email| email.trim()) {
+        if !email.eq_ignore_ascii_case(&account.email) && !emails.iter().any(|seen| seen.eq_ignore_ascii_case(email)) {
+            emails.push(email);
+        }
+    }
+    emails.into_iter().map(|email| format!("{} <{}> <{}>", account.name, account.email, email)).collect()
+}
+
+impl GitAccountManager {
+    /// Prints the mailmap lines for `account_name`, or every account, or
+    /// with `write` appends those the target doesn't map yet. `extra`
+    /// addresses belong to the one account they're given for.
+    pub fn generate_mailmap(&self, account_name: Option<&str>, extra: &[String], write: Option<&Path>) -> Result<bool> {
+        let accounts: Vec<&GitAccount> = match account_name {
+            Some(name) => vec![self.accounts.get(name).ok_or_else(|| account_not_found(name))?],
+            None => self.sorted_accounts(true),
+        };
+        if !extra.is_empty() && accounts.len() != 1 {
+            return Err(Error::new(ErrorKind::InvalidInput, "--extra needs the account the addresses belong to"));
+        }
+        if let Some(email) = extra.iter().find(|email| !email.contains('@') || email.contains(['<', '>', ' '])) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("--extra {} is not an email address", email)));
+        }
+        let lines: Vec<String> = accounts.iter().flat_map(|account| lines_for(account, extra)).collect();
+        if lines.is_empty() {
+            println!("No previous emails recorded; pass --extra or set up the account with --previous-email");
+            return Ok(true);
+        }
+
+        let Some(write) = write else {
+            for line in &lines {
+                println!("{}", line);
+            }
+            return Ok(true);
+        };
+        let path = target_path(write);
+        let existing = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(fsutil::blocked(e, "read", &path)),
+        };
+        let mut added = Vec::new();
+        for line in lines {
+            let email = commit_email(&line).expect("generated lines end in an address");
+            let mapped = existing.lines().find(|current| commit_email(current).is_some_and(|current| current.eq_ignore_ascii_case(email)));
+            match mapped {
+                Some(current) if current.trim() != line => {
+                    println!("warning: {} already maps {}: {}", path.display(), email, current.trim());
+                }
+                Some(_) => {}
+                None => added.push(line),
+            }
+        }
+        if added.is_empty() {
+            println!("{} already has every mapping", path.display());
+            return Ok(true);
+        }
+        fsutil::modify(&path, |content| {
+            let mut content = content.to_string();
+            if !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            for line in &added {
+                content.push_str(line);
+                content.push('\n');
+            }
+            Ok(Some(content))
+        })?;
+        println!("Added {} line(s) to {}", added.len(), path.display());
+        Ok(true)
+    }
+}
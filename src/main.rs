@@ -1,211 +1,1394 @@
-use std::collections::HashMap;
-use std::env::var;
-use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
 use std::path::PathBuf;
-use std::io::{Error, Read, Result, Write};
-use std::process::Command;
+use std::process::ExitCode;
+use std::time::Duration;
 
+use clap::{CommandFactory, Parser, Subcommand};
+use serde_json::json;
 
-struct GitAccount {
-    name: String,
-    email: String,
-    ssh_key: String,
-    codebase_dir_path: PathBuf,
-}
+use multigit::account::{self, GitAccountManager, Scope, SetupOptions, PROVIDER_HOST};
+use multigit::client_tls::ClientTls;
+use multigit::errors::MultigitError;
+use multigit::host_conflict::HostBlockChoice;
+use multigit::include_if::Placement;
+use multigit::keys::Keychain;
+use multigit::list::ListFormat;
+use multigit::machine_report::ReportFormat;
+use multigit::passphrase::PassphraseSource;
+use multigit::provider::Provider;
+use multigit::plan::RecordFormat;
+use multigit::report::OutputFormat;
+use multigit::sendemail::{SendEmail, SmtpEncryption};
+use multigit::shell_hook::Shell;
+use multigit::tamper::{Resolution, UserSection};
+use multigit::worktree::ConfigScope;
+use multigit::{backups, client_tls, completions, connectivity, doctor, drift, email_policy, errors, events, explain, fsutil, git_settings, host_conflict, keys, list, machine_report, multiplex, ownership, plan, platform, rotate, scan, shell_hook, store, templates, upgrade, validate};
 
-struct GitAccountManager {
-    accounts: HashMap<String, GitAccount>,
-    home_dir: PathBuf,
-}
+#[derive(Parser)]
+#[command(name = "multigit", about = "Manage multiple git identities on one machine")]
+struct Cli {
+    /// Don't use ssh-agent; recorded on the account when given to setup
+    #[arg(long, global = true)]
+    no_agent: bool,
 
-impl GitAccountManager {
-    fn new(home_dir: PathBuf) -> Self {
-        GitAccountManager {
-            accounts: HashMap::new(),
-            home_dir,
-        }
-    }
+    /// Skip every network step, recording it to be finished later
+    #[arg(long, global = true)]
+    offline: bool,
 
-    fn add_account(&mut self, name: &str, email: &str, ssh_key: &str, codebase_dir_path: PathBuf) {
-        let account = GitAccount { 
-            name: name.to_string(),
-            email: email.to_string(),
-            ssh_key: ssh_key.to_string(),
-            codebase_dir_path,
-        };
-        self.accounts.insert(name.to_string(), account);
-    }
+    /// Allow commands that write keys or config to run as root
+    #[arg(long, global = true)]
+    allow_root: bool,
 
-    fn generate_ssh_key(&mut self, account_name: &str, email: &str) -> Result<String> {
-        let ssh_dir = self.home_dir.join(".ssh");
-        fs::create_dir_all(&ssh_dir).unwrap();
+    /// Configure this home directory instead of $HOME
+    #[arg(long, global = true)]
+    home: Option<PathBuf>,
 
-        let key_file = ssh_dir.join(format!("id_ed25519_{}", account_name));
-        let key_file_str = key_file.to_str().unwrap();
+    /// User to own the files created; defaults to the owner of the home directory
+    #[arg(long, global = true)]
+    owner: Option<String>,
 
-        let output = Command::new("ssh-keygen")
-            .args([
-                "-t", "ed25519",
-                "-C", email,
-                "-f", key_file_str,
-                "-N", "", // TODO: add passphrase support
-            ])
-            .output().unwrap();
+    /// Put this in front of the comment of generated keys, e.g. "[multigit]"; kept for later runs
+    #[arg(long, global = true, value_name = "PREFIX")]
+    comment_prefix: Option<String>,
 
-        if !output.status.success() {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to generate SSH key: {}, {}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
-            ));
-        }
-        println!("Key file: {}", key_file_str);
-        Ok(key_file_str.to_string())
-    }
+    /// Keep multigit's includeIf entries at the top or bottom of ~/.gitconfig; kept for later runs
+    #[arg(long, global = true, value_enum, value_name = "WHERE")]
+    include_placement: Option<Placement>,
 
-    fn add_ssh_agent(&self, ssh_key_file: &str) -> Result<()> {
-        let output = Command::new("ssh-add")
-        .args([
-            ssh_key_file
-        ])
-        .output().unwrap();
+    /// Email domain accounts must use (repeatable; "none" drops the requirement); kept for later runs
+    #[arg(long, global = true, value_name = "DOMAIN")]
+    require_email_domain: Vec<String>,
 
-        if !output.status.success() {
-            return Err(Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to add SSH key to agent: {}, {}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr))
-            ));
-        }
+    /// Apply --require-email-domain only to accounts on this ssh hostname, e.g. gitlab.com
+    #[arg(long, global = true, value_name = "HOSTNAME", requires = "require_email_domain")]
+    email_domain_host: Option<String>,
 
-        Ok(())
-    }
-    
-    fn setup_local_gitconfig(&self, account: &GitAccount) -> Result<()> {
-        let config_content = format!(
-            "[url \"git@github.com-{}:\"]\n    insteadOf = git@github.com:\n[user]\n    name = {}\n    email = {}\"\n",
-            account.name, account.name, account.email
-        );
+    /// Only warn about emails outside the required domains; kept for later runs
+    #[arg(long, global = true, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    email_domain_warn_only: Option<bool>,
 
-        let gitconfig_path = account.codebase_dir_path.join(".gitconfig");
-        
-        
-        if let Some(parent) = gitconfig_path.parent() {
-            fs::create_dir_all(parent).unwrap();
-        }
-        
-        println!("gitconfig path: {}", gitconfig_path.to_str().unwrap());
-        println!("config content: {}", config_content.to_string());
-        let mut file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&gitconfig_path).unwrap();
+    /// Write includeIf paths inside the home dir as ~/...; kept for later runs
+    #[arg(long, global = true, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    home_relative_paths: Option<bool>,
 
-        file.write_all(config_content.as_bytes()).unwrap();
+    /// Keep managed blocks you edited by hand instead of asking
+    #[arg(long, global = true, conflicts_with = "force_theirs")]
+    force_ours: bool,
 
-        Ok(())
-    }
+    /// Overwrite managed blocks you edited by hand instead of asking
+    #[arg(long, global = true)]
+    force_theirs: bool,
 
-    fn setup_global_gitconfig(&self, codebase_path_str: &str, global_gitconfig_path_str: &str) -> Result<()> {
-        // Create or append to the global .gitconfig
-        let global_gitconfig_path = self.home_dir.join(".gitconfig");
-        let include_if_content = format!(
-            "[includeIf \"gitdir/i:{}\"]\n    path = {}\n\n",
-            codebase_path_str,
-            global_gitconfig_path_str
-        );
+    /// Whether rewriting an include file puts back a [user] section you edited inside multigit's block, or keeps it
+    #[arg(long, global = true, value_enum, default_value_t)]
+    user_section: UserSection,
 
-        let mut global_gitconfig = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&global_gitconfig_path).unwrap();
+    /// Provider API token for this run, ahead of the environment and the keyring
+    #[arg(long, global = true, value_name = "TOKEN")]
+    token: Option<String>,
 
-        println!("global gitconfig path: {}", global_gitconfig_path.to_str().unwrap());
-        println!("includeIf content: {}", include_if_content.to_string());
+    /// Write progress to stdout as newline-delimited JSON events; other output goes to stderr
+    #[arg(long, global = true)]
+    events: bool,
 
-        global_gitconfig.write_all(include_if_content.as_bytes()).unwrap();
+    /// Fail the run on any warning, e.g. in CI provisioning; a setup that warns is rolled back
+    #[arg(long, global = true)]
+    strict: bool,
 
-        Ok(())
-    }
+    /// On failure, print the error to stderr as JSON with a stable `kind` instead of as text
+    #[arg(long, global = true)]
+    json: bool,
 
-    fn associate_account_with_dir(&mut self, account_name: &str) -> Result<()> {
-        println!("Account name: {}", account_name);
-        let account = self.accounts.get(account_name).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::NotFound, "Account not found")
-        }).unwrap();
+    #[command(subcommand)]
+    command: Commands,
+}
 
-        let codebase_path = &account.codebase_dir_path;
+// Parsed once per run, so the size of the largest variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+enum Commands {
+    /// Generate a key and write the ssh and git config for a new account
+    #[command(visible_alias = "add")]
+    Setup {
+        #[arg(long, required_unless_present_any = ["name_from_ssh_key", "stdin"])]
+        name: Option<String>,
+        /// Take the name from the --existing-key public key's comment, where the pattern has {name} (default: {name}-*, so work-ed25519 gives work)
+        #[arg(long, value_name = "PATTERN", num_args = 0..=1, default_missing_value = keys::NAME_FROM_COMMENT, requires = "existing_key", conflicts_with = "name")]
+        name_from_ssh_key: Option<String>,
+        /// Defaults to the comment of the --existing-key public key
+        #[arg(long, required_unless_present_any = ["existing_key", "stdin"])]
+        email: Option<String>,
+        /// Directory holding the account's repositories, or a glob ending in / such as clients/*/
+        #[arg(long, required_unless_present = "stdin")]
+        codebase_dir: Option<String>,
+        /// Read account records from stdin instead, with the fields of a batch file's [[accounts]], and set up each; as apply --stdin
+        #[arg(long, conflicts_with_all = ["name", "name_from_ssh_key", "email", "codebase_dir", "passphrase_stdin"])]
+        stdin: bool,
+        /// How --stdin records are written
+        #[arg(long, value_enum, default_value_t = RecordFormat::Json, requires = "stdin")]
+        format: RecordFormat,
+        /// ssh Host alias; defaults to <hostname>-<name>, e.g. github.com-work
+        #[arg(long)]
+        host: Option<String>,
+        /// Preset for the provider's hostname and, for Azure DevOps, RSA keys
+        #[arg(long, value_enum, default_value_t = Provider::Github)]
+        provider: Provider,
+        /// Where the Host alias connects, e.g. a self-hosted gitlab.example.com; defaults to the provider's
+        #[arg(long)]
+        hostname: Option<String>,
+        /// Take the hostname, key type, ssh and git options of this account for what isn't given; a new key is still generated
+        #[arg(long, value_name = "ACCOUNT", conflicts_with = "pkcs11_provider")]
+        copy_from: Option<String>,
+        /// Keep Host blocks in ~/.config/multigit/ssh_config, included from ~/.ssh/config
+        #[arg(long)]
+        managed_ssh_config: bool,
+        /// Never edit the global gitconfig; print the includeIf to add to it yourself
+        #[arg(long)]
+        no_global_edit: bool,
+        /// Read the key passphrase from the first line of stdin
+        #[arg(long)]
+        passphrase_stdin: bool,
+        /// Read the key passphrase from a file that only you can read
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+        /// Keep per-directory gitconfigs in this directory and symlink them into place
+        #[arg(long)]
+        export_gitconfig_dir: Option<PathBuf>,
+        /// Don't warn when another account already uses this email
+        #[arg(long)]
+        allow_duplicate_email: bool,
+        /// When a step fails, keep what setup already wrote instead of removing it, to finish by hand
+        #[arg(long)]
+        no_rollback: bool,
+        /// Login to pre-fill for HTTPS remotes on the provider
+        #[arg(long)]
+        https_username: Option<String>,
+        /// The account's user on the provider; setup checks the token and key belong to it
+        #[arg(long)]
+        login: Option<String>,
+        /// Committer name for the account's commits, when it isn't the author's (git 2.22+)
+        #[arg(long)]
+        committer_name: Option<String>,
+        /// Committer email for the account's commits, such as a team or bot address (git 2.22+)
+        #[arg(long)]
+        committer_email: Option<String>,
+        /// An address you committed under before, for `mailmap generate` (repeatable)
+        #[arg(long = "previous-email", value_name = "EMAIL")]
+        previous_emails: Vec<String>,
+        /// SMTP server for git send-email
+        #[arg(long, value_name = "HOST")]
+        smtp_server: Option<String>,
+        /// SMTP login for git send-email
+        #[arg(long, value_name = "USER")]
+        smtp_user: Option<String>,
+        #[arg(long, value_enum)]
+        smtp_encryption: Option<SmtpEncryption>,
+        /// SMTP port for git send-email, if not the usual one for the encryption (587 tls, 465 ssl, 25 none)
+        #[arg(long, value_name = "PORT", requires = "smtp_server")]
+        smtp_server_port: Option<u16>,
+        /// From address for git send-email, e.g. "Jane Doe <jane@example.com>"
+        #[arg(long, value_name = "ADDRESS")]
+        sendemail_from: Option<String>,
+        /// Credential helper git send-email gets the SMTP password from; the password itself is never stored
+        #[arg(long, value_name = "HELPER", requires = "smtp_server")]
+        smtp_credential_helper: Option<String>,
+        /// Only send git@ remotes through the alias, leaving https:// and git:// ones as they are
+        #[arg(long)]
+        no_rewrite_https: bool,
+        /// Comma-separated PubkeyAcceptedAlgorithms for the Host block, e.g. ssh-ed25519
+        #[arg(long)]
+        pubkey_algorithms: Option<String>,
+        /// Write a Host block without the agent and keychain directives
+        #[arg(long)]
+        minimal: bool,
+        /// Register this private key instead of generating one; its .pub must exist too
+        #[arg(long)]
+        existing_key: Option<PathBuf>,
+        /// Type of the generated key, or auto to pick one the provider and this ssh accept (ed25519 unless either doesn't)
+        #[arg(long, value_name = "TYPE", conflicts_with = "existing_key")]
+        key_type: Option<String>,
+        /// If the alias already has a Host block multigit didn't write, keep it and use the key it names
+        #[arg(long, conflicts_with = "replace_host_block")]
+        adopt_host_block: bool,
+        /// If the alias already has a Host block multigit didn't write, replace it, keeping a backup of the file
+        #[arg(long)]
+        replace_host_block: bool,
+        /// Keep key passphrases in the macOS keychain (default: store on macOS, none elsewhere)
+        #[arg(long, value_enum)]
+        keychain: Option<Keychain>,
+        /// Which repositories get the account's identity: those under the codebase dir, or those with a remote through the alias (git 2.36+)
+        #[arg(long, value_enum, default_value_t = Scope::Dir)]
+        scope: Scope,
+        /// Add the provider's host keys to ~/.ssh/known_hosts (or --known-hosts-file) after checking their published fingerprints
+        #[arg(long)]
+        check_remote_host_key: bool,
+        /// Known hosts file for the account's Host block (UserKnownHostsFile), created if missing
+        #[arg(long, value_name = "PATH")]
+        known_hosts_file: Option<PathBuf>,
+        /// Only connect to hosts already in --known-hosts-file (StrictHostKeyChecking yes)
+        #[arg(long, requires = "known_hosts_file")]
+        strict_host_key_checking: bool,
+        /// Under WSL, also match the codebase dir's Windows path for git for Windows
+        #[arg(long)]
+        also_windows: bool,
+        /// Shared git hooks directory for repositories under the codebase dir (core.hooksPath)
+        #[arg(long)]
+        hooks_path: Option<PathBuf>,
+        /// Template directory for new repositories under the codebase dir (init.templateDir); clone-all clones with it
+        #[arg(long, value_name = "DIR")]
+        template_dir: Option<PathBuf>,
+        /// Configure Git LFS's clean/smudge filter for repositories under the codebase dir
+        #[arg(long = "enable-lfs")]
+        lfs: bool,
+        /// Offer the keys of this PKCS#11 library (PKCS11Provider) instead of generating a key file
+        #[arg(long, value_name = "LIBRARY", conflicts_with_all = ["existing_key", "key_type", "key_filename", "certificate_file"])]
+        pkcs11_provider: Option<PathBuf>,
+        /// ssh certificate for the key, added to the Host block as CertificateFile; found as <key>-cert.pub next to --existing-key
+        #[arg(long)]
+        certificate_file: Option<PathBuf>,
+        /// Proxy for https remotes under the codebase dir (http.proxy), e.g. http://proxy.example.com:3128
+        #[arg(long, value_name = "URL")]
+        proxy: Option<String>,
+        /// Reach the provider over ssh through this jump host (ProxyJump), as [user@]host[:port]
+        #[arg(long, value_name = "HOST")]
+        proxy_jump: Option<String>,
+        /// TLS client certificate for https remotes (http.sslCert), for servers that require mutual TLS
+        #[arg(long, value_name = "PATH")]
+        ssl_cert: Option<PathBuf>,
+        /// Private key of --ssl-cert, if the certificate file doesn't have it (http.sslKey)
+        #[arg(long, value_name = "PATH", requires = "ssl_cert")]
+        ssl_key: Option<PathBuf>,
+        /// CA bundle to verify the https server with (http.sslCAInfo)
+        #[arg(long, value_name = "PATH")]
+        ssl_ca_info: Option<PathBuf>,
+        /// Server the TLS settings apply to; defaults to the provider, e.g. https://git.corp.example.com/
+        #[arg(long, value_name = "URL")]
+        ssl_url: Option<String>,
+        /// Repository test-push checks write access with, as owner/repo or a URL
+        #[arg(long, value_name = "REPO")]
+        canary_repo: Option<String>,
+        /// Share one ssh connection between git commands, kept open this long after the last (default 10m)
+        #[arg(long, value_name = "PERSIST", num_args = 0..=1, default_missing_value = multiplex::DEFAULT_PERSIST)]
+        multiplexing: Option<String>,
+        /// Name for generated keys, kept for later accounts; placeholders {account}, {host} and {type}
+        #[arg(long, value_name = "TEMPLATE", conflicts_with = "existing_key")]
+        key_filename: Option<String>,
+        #[arg(long = "set", value_name = "KEY=VALUE", value_parser = git_settings::parse, long_help = git_settings::long_help())]
+        git_settings: Vec<(String, String)>,
+        /// When git colors output in the account's repositories (color.ui): auto, always or never
+        #[arg(long, value_name = "WHEN")]
+        color: Option<String>,
+        /// Pager for git in the account's repositories (core.pager), e.g. "less -FRX" or delta
+        #[arg(long)]
+        pager: Option<String>,
+        /// Tool `git mergetool` runs in the account's repositories (merge.tool), e.g. vimdiff or meld
+        #[arg(long, value_name = "TOOL")]
+        merge_tool: Option<String>,
+        /// Loose object count that triggers auto gc in the account's repositories (gc.auto; 0 disables)
+        #[arg(long, value_name = "COUNT")]
+        gc_auto: Option<String>,
+        /// Threads git uses when packing in the account's repositories (pack.threads; 0 = one per CPU)
+        #[arg(long, value_name = "N")]
+        pack_threads: Option<String>,
+        /// Whether repacking in the account's repositories writes bitmaps (repack.writeBitmaps): true or false
+        #[arg(long, value_name = "BOOL")]
+        repack_write_bitmaps: Option<String>,
+        /// Turn off git's advice hints for experienced users in the account's repositories (advice.*); --set overrides single ones
+        #[arg(long)]
+        quiet_advice: bool,
+    },
+    /// Report in one line whether managed blocks or keys changed since the last doctor; silent when nothing did
+    Check {
+        /// Only look again at files whose size or modification time changed, for shell startup files
+        #[arg(long)]
+        cached: bool,
+    },
+    /// Show how each account's blocks on disk differ from what multigit would write; fails when any do
+    Diff {
+        /// Only this account
+        account: Option<String>,
+    },
+    /// Check the on-disk config against the stored accounts
+    Doctor {
+        /// Correct drifted Host blocks in place
+        #[arg(long)]
+        fix: bool,
+        /// Write back missing Host blocks, includeIfs and include files from the stored accounts, and load their keys
+        #[arg(long)]
+        repair: bool,
+        /// Run the network steps skipped by earlier offline runs
+        #[arg(long)]
+        complete_deferred: bool,
+        /// Also connect to each account's SMTP server for git send-email
+        #[arg(long)]
+        network: bool,
+        /// Also check disabled accounts
+        #[arg(long)]
+        include_disabled: bool,
+    },
+    /// Rewrite the on-disk config to match the stored accounts
+    Sync {
+        /// Apply the changes instead of only reporting them
+        #[arg(long, alias = "replace-host-on-change")]
+        fix: bool,
+    },
+    /// List the configured accounts
+    List {
+        #[arg(long, value_enum, default_value_t)]
+        format: ListFormat,
+    },
+    /// Print every account, its keys and managed blocks, and a health summary as one document
+    Report {
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+    },
+    /// Find git repositories and show which account governs each
+    Scan {
+        /// Directory to search; defaults to the home directory
+        path: Option<PathBuf>,
+        /// Print one JSON object per repository as it is found
+        #[arg(long)]
+        json_lines: bool,
+        /// Stop after this many seconds and report what was found so far
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Deactivate an account's config and key without deleting anything
+    Disable {
+        name: String,
+    },
+    /// Reactivate an account turned off with `disable`
+    Enable {
+        name: String,
+    },
+    /// Remove one account: its Host block, includeIf, include file and records
+    Remove {
+        name: String,
+        /// Also delete the account's ssh keys
+        #[arg(long)]
+        delete_keys: bool,
+    },
+    /// Show which account and setup option produced each line of a config file
+    Explain {
+        /// The ssh config, the global gitconfig, or an account's include file
+        path: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Clear ssh-agent and load the managed keys again
+    ReloadAgent {
+        /// Remove only managed keys before reloading, keeping all others
+        #[arg(long)]
+        managed_only: bool,
+        /// Don't ask before clearing the agent
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Install a launchd agent (macOS) or systemd user service (Linux) that loads the managed keys at login
+    InitAgentService {
+        /// Remove the service file instead
+        #[arg(long)]
+        uninstall: bool,
+    },
+    /// Bring config written by older multigit versions up to date
+    UpgradeSetup {
+        /// Make the changes; without this only the report is printed
+        #[arg(long)]
+        apply: bool,
+        /// Skip entries missing an account name or email instead of asking for them
+        #[arg(long)]
+        non_interactive: bool,
+    },
+    /// Manage the keys attached to an account
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+    /// Clone an organization's repositories into an account's codebase dir
+    CloneAll {
+        account: String,
+        /// Organization on the provider; set GITHUB_TOKEN (or GITHUB_TOKEN_<ACCOUNT>) to include private repositories
+        #[arg(long)]
+        org: String,
+        /// Only repositories whose name matches this glob (* and ?)
+        #[arg(long = "match", value_name = "GLOB")]
+        pattern: Option<String>,
+        /// Also clone archived repositories
+        #[arg(long)]
+        include_archived: bool,
+        /// Clones to run at once
+        #[arg(long, default_value_t = 4)]
+        jobs: usize,
+    },
+    /// Set ~/.ssh to 700, ssh configs and private keys to 600 and public keys to 644
+    FixPermissions,
+    /// List the backups multigit kept of files it replaced, or delete old ones
+    Backups {
+        /// List them (the default)
+        #[arg(long, conflicts_with = "purge")]
+        list: bool,
+        /// Delete the backups --keep and --older-than select
+        #[arg(long)]
+        purge: bool,
+        /// Keep this many of the newest backups of each file
+        #[arg(long, value_name = "N", requires = "purge")]
+        keep: Option<usize>,
+        /// Only delete backups older than this
+        #[arg(long, value_name = "DAYS", requires = "purge")]
+        older_than: Option<u64>,
+        /// Also delete the newest backup of a file
+        #[arg(long, requires = "purge")]
+        force: bool,
+    },
+    /// Write the current accounts as a batch file for `apply`
+    ExportPlan {
+        /// Write to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Set up every account in a batch file that isn't set up yet; --strict stops at the first record that is invalid or fails
+    Apply {
+        #[arg(required_unless_present = "stdin")]
+        file: Option<PathBuf>,
+        /// Read account records from stdin instead, with the fields of the file's [[accounts]]
+        #[arg(long, conflicts_with = "file")]
+        stdin: bool,
+        /// How --stdin records are written
+        #[arg(long, value_enum, default_value_t = RecordFormat::Json, requires = "stdin")]
+        format: RecordFormat,
+        /// Carry on past accounts that can't be set up, as --stdin does unless --strict
+        #[arg(long, conflicts_with = "strict")]
+        keep_going: bool,
+        /// How to print the summary; json includes every account's setup report
+        #[arg(long, value_enum, default_value_t)]
+        report_format: OutputFormat,
+        /// Print the steps and file changes for every account without making them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check a batch file for `apply` and report every problem in it, without setting anything up
+    Validate {
+        file: PathBuf,
+    },
+    /// Change an existing account
+    #[command(group = clap::ArgGroup::new("change").required(true).multiple(false))]
+    Edit {
+        name: String,
+        /// Hand this subdirectory of the codebase dir to another account
+        #[arg(long, group = "change", requires = "exclude_account")]
+        exclude_dir: Option<PathBuf>,
+        /// The account repositories under --exclude-dir use
+        #[arg(long, requires = "exclude_dir")]
+        exclude_account: Option<String>,
+        /// Give a previously excluded subdirectory back to the account
+        #[arg(long, group = "change", value_name = "DIR")]
+        remove_exclusion: Option<PathBuf>,
+        /// Remove a setting from the account's include file: core.hooksPath, committer.name, committer.email, a sendemail key or a --set key
+        #[arg(long, group = "change", value_name = "KEY")]
+        unset: Option<String>,
+    },
+    /// Rewrite the remotes of an account's repositories to its Host alias (or HTTPS, with --https-username)
+    FixRemotes {
+        account: String,
+        /// Print what would change without changing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Rewrite every remote, not just origin
+        #[arg(long)]
+        all_remotes: bool,
+    },
+    /// Find and fix leftovers of removed accounts
+    Prune {
+        /// Repositories whose remotes use the Host alias of a removed account, under the codebase dirs
+        #[arg(long)]
+        remotes: bool,
+        /// Also look for repositories under this directory
+        #[arg(long)]
+        path: Vec<PathBuf>,
+        /// Rewrite every stale remote without asking: `canonical` for the provider host, or an account name
+        #[arg(long, value_name = "canonical|ACCOUNT")]
+        to: Option<String>,
+        /// Stop searching after this many seconds and go on with the remotes found so far
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Remove every account and multigit's own configuration, after backing them up
+    Uninstall {
+        /// Also delete the accounts' ssh keys
+        #[arg(long)]
+        remove_keys: bool,
+        /// Don't ask first
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Move the accounts' stored paths to a new home directory, such as after a username change, and rewrite their config
+    MigratePaths {
+        /// The home directory the accounts were set up under (default: worked out from where their keys are)
+        #[arg(long)]
+        from: Option<PathBuf>,
+        /// Where those paths are now (default: the current home directory)
+        #[arg(long)]
+        to: Option<PathBuf>,
+    },
+    /// Rebuild an account's Host block, includeIf and include file from its stored record
+    Reinit {
+        account: String,
+        /// Generate a new key first, as key rotate does
+        #[arg(long)]
+        new_key: bool,
+    },
+    /// Point an account at its codebase dir's new location, rewriting the include file and includeIf
+    Relocate {
+        account: String,
+        new_dir: PathBuf,
+        /// Move the directory there first (a rename, so on the same filesystem)
+        #[arg(long = "move")]
+        move_dir: bool,
+    },
+    /// Show the rewritten URL, ssh Host block and key git would use for a remote URL
+    Resolve {
+        url: String,
+        /// Resolve as for a repository in this directory (default: the current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Show which ssh key a remote URL authenticates with
+    WhichKey {
+        url: String,
+        /// Resolve as for a repository in this directory (default: the current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Connect with ssh -v and report the keys offered and the one the server accepted
+        #[arg(long)]
+        probe: bool,
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Show the identity of the repository in the current directory and the account its remote suggests
+    Status {
+        /// The repository's directory (default: the current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// Print only warnings, and nothing outside a repository
+        #[arg(long)]
+        quiet: bool,
+        /// Warn and fail when the repository's identity isn't its account's
+        #[arg(long)]
+        warn_on_mismatch: bool,
+    },
+    /// Print a shell hook that runs status --quiet --warn-on-mismatch on every directory change, for eval in the shell's startup file
+    ShellHook {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print a completion script offering commands and account names, for eval in the shell's startup file
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// The account names, one per line, for the completion scripts
+    #[command(name = "__complete-accounts", hide = true)]
+    CompleteAccounts,
+    /// Give the repository in the current directory an account's identity, or with --scope worktree only this worktree
+    #[command(visible_alias = "use")]
+    Switch {
+        account: String,
+        /// Which git config to write: the repository's (local) or this worktree's own, turning on extensions.worktreeConfig if needed
+        #[arg(long, value_enum, default_value_t = ConfigScope::Local)]
+        scope: ConfigScope,
+        /// Same as --scope worktree
+        #[arg(long, conflicts_with = "scope")]
+        worktree: bool,
+        /// Don't ask before turning on extensions.worktreeConfig
+        #[arg(short, long)]
+        yes: bool,
+        /// Also point origin's pushurl at the account's Host alias
+        #[arg(long)]
+        push_url: bool,
+        /// The repository or worktree (default: the current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Check that the provider accepts each account's key through its Host alias
+    Test {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+        /// Test every enabled account
+        #[arg(long, conflicts_with = "account")]
+        all: bool,
+        /// Tests to run at once
+        #[arg(long, default_value_t = 4)]
+        jobs: usize,
+        /// Seconds to wait for each account before counting it as failed
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        timeout: u64,
+        /// Rotate the key of accounts whose key type the provider or ssh rejects to a type that works
+        #[arg(long)]
+        auto_fix: bool,
+        /// Print each key the provider doesn't know and open the provider's ssh keys page in a browser
+        #[arg(long)]
+        web: bool,
+    },
+    /// Print an account's identity as environment variables, for eval "$(multigit env <account>)" in a shell session
+    Env {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+        #[arg(long, value_enum, default_value_t = Shell::Bash)]
+        shell: Shell,
+    },
+    /// Check that the provider would accept a push from an account, with a dry run from a throwaway clone
+    TestPush {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+        /// Repository to check, as owner/repo or a URL; defaults to the account's --canary-repo
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Map the addresses you committed under before to the accounts, for git shortlog
+    Mailmap {
+        #[command(subcommand)]
+        command: MailmapCommands,
+    },
+    /// Save and restore copies of every file multigit touches
+    Snapshot {
+        #[command(subcommand)]
+        command: SnapshotCommands,
+    },
+    /// Inspect the templates used for generated config blocks
+    Templates {
+        #[command(subcommand)]
+        command: TemplatesCommands,
+    },
+    /// Keep provider API tokens in the OS keyring
+    Token {
+        #[command(subcommand)]
+        command: TokenCommands,
+    },
+}
 
-        let gitconfig_path = codebase_path.join(".gitconfig");
+#[derive(Subcommand)]
+enum MailmapCommands {
+    /// Print .mailmap lines for each account's previous emails, or add them to a .mailmap
+    Generate {
+        /// Only this account (required with --extra when there are several)
+        account: Option<String>,
+        /// Another old address of the account (repeatable)
+        #[arg(long, value_name = "EMAIL")]
+        extra: Vec<String>,
+        /// Append the lines a .mailmap doesn't have yet: the file, or a repository to use the .mailmap of
+        #[arg(long, value_name = "PATH")]
+        write: Option<PathBuf>,
+    },
+}
 
-        let _ = self.setup_local_gitconfig(account);
+#[derive(Subcommand)]
+enum TokenCommands {
+    /// Store a token for the account, read from stdin without echoing it
+    Set {
+        account: String,
+    },
+    /// Delete the account's stored token
+    Remove {
+        account: String,
+    },
+    /// Show where each account's token comes from, without printing it
+    Status {
+        account: Option<String>,
+    },
+}
 
-        let codebase_path = codebase_path.to_str();
+#[derive(Subcommand)]
+enum SnapshotCommands {
+    /// Copy the gitconfigs, ssh config and multigit's config into a new snapshot
+    Create {
+        /// What the snapshot is for, e.g. "before cleanup"
+        label: String,
+        /// Also copy the accounts' private keys
+        #[arg(long)]
+        include_keys: bool,
+    },
+    /// List the snapshots, oldest first
+    List,
+    /// Put every file of a snapshot back as it was
+    Restore {
+        id: String,
+        /// Restore even over changes made outside multigit since the snapshot
+        #[arg(long)]
+        force: bool,
+    },
+}
 
-        let gitconfig_path = gitconfig_path.to_str();
+#[derive(Subcommand)]
+enum TemplatesCommands {
+    /// Print the built-in templates, to start a custom one from
+    Show {
+        /// Only print this template (ssh_host, ssh_host_minimal or gitconfig_include)
+        name: Option<String>,
+    },
+}
 
-        if let (Some(cb_path), Some(gc_path)) = (codebase_path, gitconfig_path) {
-            let _ = self.setup_global_gitconfig(cb_path, gc_path);
-        } else {
-            eprintln!("One or both paths could not be converted to &str");
-        }
+#[derive(Subcommand)]
+enum KeyCommands {
+    /// Generate or import an additional key for an account
+    Add {
+        account: String,
+        /// Name for the key, e.g. backup
+        #[arg(long)]
+        label: String,
+        /// Key type, or auto to pick one the provider accepts
+        #[arg(long = "type", default_value = "ed25519")]
+        key_type: String,
+        /// Register an existing private key instead of generating one
+        #[arg(long)]
+        import: Option<PathBuf>,
+    },
+    /// Replace an account's key with a new one and point its Host block at it
+    Rotate {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+        /// Rotate the key of every enabled account
+        #[arg(long, conflicts_with = "account")]
+        all: bool,
+        /// Rotate the key added under this label with `key add` instead of the primary key
+        #[arg(long, conflicts_with = "all")]
+        label: Option<String>,
+        /// Only rotate keys at least this old, e.g. 365d, 52w or 1y
+        #[arg(long, value_name = "AGE", value_parser = rotate::parse_age)]
+        older_than: Option<u64>,
+        /// Key type, or auto to pick one the provider accepts; defaults to the current key's
+        #[arg(long = "type", value_name = "TYPE")]
+        key_type: Option<String>,
+        /// Show the new key file and the ssh config change without making them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the keys attached to an account
+    Show {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+    },
+    /// List the ssh keys on the provider account and match them to local keys
+    RemoteList {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+        /// Delete remote keys whose local file multigit created and that has since been deleted
+        #[arg(long)]
+        prune_remote: bool,
+        /// Don't ask before deleting
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// List the private keys in ~/.ssh and what uses each of them
+    Orphans {
+        /// Delete the keys nothing uses, after listing their fingerprints
+        #[arg(long)]
+        delete_unused: bool,
+        /// Don't ask before deleting
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Recreate missing .pub files from an account's private keys
+    RegenPub {
+        /// Defaults to the account of the current directory
+        account: Option<String>,
+    },
+    /// Report weak, unprotected, old or unregistered keys, without changing anything; --strict fails on a high-severity finding
+    Audit {
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Add or change the passphrase on an account's key
+    Passphrase {
+        account: String,
+        /// Read the new passphrase from the first line of stdin
+        #[arg(long)]
+        passphrase_stdin: bool,
+        /// Read the new passphrase from a file that only you can read
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+    },
+}
 
-        Ok(())
+impl Commands {
+    /// Whether the command has steps that talk to the provider.
+    fn uses_network(&self) -> bool {
+        matches!(self, Commands::Setup { .. } | Commands::Doctor { complete_deferred: true, .. } | Commands::CloneAll { .. } | Commands::Apply { dry_run: false, .. } | Commands::Key { command: KeyCommands::RemoteList { .. } | KeyCommands::Audit { .. } } | Commands::Test { .. } | Commands::TestPush { .. } | Commands::WhichKey { probe: true, .. })
     }
 
-    fn setup_ssh_config(&self, name: &str, host: &str) -> Result<()> {
-        let ssh_config_path = self.home_dir.join(".ssh/config");
-
-        let account = self.accounts.get(name).ok_or_else(|| {
-            std::io::Error::new(std::io::ErrorKind::NotFound, "Account not found")
-        }).unwrap();
+    /// Whether the command creates keys or edits config files.
+    fn writes(&self) -> bool {
+        match self {
+            Commands::Setup { .. } | Commands::CloneAll { .. } | Commands::Prune { .. } => true,
+            Commands::Apply { dry_run, .. } => !dry_run,
+            Commands::ExportPlan { output } => output.is_some(),
+            Commands::Backups { purge, .. } => *purge,
+            Commands::FixPermissions => true,
+            Commands::FixRemotes { dry_run, .. } => !dry_run,
+            Commands::Switch { .. } => true,
+            Commands::Edit { .. } | Commands::Uninstall { .. } | Commands::MigratePaths { .. } | Commands::Reinit { .. } | Commands::Relocate { .. } => true,
+            Commands::Doctor { fix, repair, .. } => *fix || *repair,
+            Commands::Sync { fix } => *fix,
+            Commands::Key { command: KeyCommands::Add { .. } | KeyCommands::Passphrase { .. } | KeyCommands::RegenPub { .. } } => true,
+            Commands::Key { command: KeyCommands::Rotate { dry_run, .. } } => !dry_run,
+            // remote-list records key fingerprints in the state file.
+            Commands::Key { command: KeyCommands::RemoteList { .. } } => true,
+            Commands::Key { command: KeyCommands::Show { .. } | KeyCommands::Audit { .. } } => false,
+            Commands::Key { command: KeyCommands::Orphans { delete_unused, .. } } => *delete_unused,
+            Commands::List { .. } | Commands::Scan { .. } | Commands::Check { .. } | Commands::Diff { .. } => false,
+            Commands::Report { .. } | Commands::Validate { .. } | Commands::Status { .. } | Commands::ShellHook { .. } => false,
+            Commands::Completions { .. } | Commands::CompleteAccounts => false,
+            Commands::Templates { .. } | Commands::Explain { .. } | Commands::Resolve { .. } | Commands::WhichKey { .. } | Commands::Env { .. } | Commands::TestPush { .. } => false,
+            Commands::Test { auto_fix, .. } => *auto_fix,
+            Commands::Snapshot { command } => !matches!(command, SnapshotCommands::List),
+            Commands::Mailmap { command: MailmapCommands::Generate { write, .. } } => write.is_some(),
+            // Tokens live in the OS keyring, not in files.
+            Commands::Token { .. } => false,
+            Commands::Disable { .. } | Commands::Enable { .. } | Commands::Remove { .. } => true,
+            Commands::ReloadAgent { .. } => false,
+            Commands::InitAgentService { .. } => true,
+            Commands::UpgradeSetup { apply, .. } => *apply,
+        }
+    }
+}
 
-        let config_content = format!(
-            "\nHost {}\n    HostName github.com\n    User git\n    AddKeysToAgent yes\n    UseKeychain yes\n    IdentityFile {}\n",
-            host, account.ssh_key
-        );
+/// Keys and config written as root end up owned by root and unusable by
+/// the real user, so refuse that unless explicitly allowed. Runs that hand
+/// their files to another owner are fine.
+fn check_root(cli: &Cli, root: bool, chowning: bool) -> Result<()> {
+    if !root || !cli.command.writes() || chowning {
+        return Ok(());
+    }
+    if !cli.allow_root {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "refusing to run as root: keys and config would be owned by root. \
+             Run as the user who will use them, or pass --allow-root",
+        ));
+    }
+    eprintln!("warning: running as root; files created will be owned by root");
+    Ok(())
+}
 
-        // Read existing config
-        let mut existing_config = String::new();
+fn run(cli: Cli) -> Result<bool> {
+    let (home_dir, warning) = ownership::home_dir(cli.home.as_deref())?;
+    if let Some(warning) = warning {
+        eprintln!("warning: {}", warning);
+    }
+    if cli.events {
+        events::enable()?;
+    }
+    // Meant for shell startup files, so it prints nothing else and doesn't
+    // load the accounts while the cache is current.
+    if let Commands::Check { cached } = cli.command {
+        return drift::check(&home_dir, cached);
+    }
+    if let Commands::ShellHook { shell } = cli.command {
+        print!("{}", shell_hook::hook(shell));
+        return Ok(true);
+    }
+    if let Commands::Completions { shell } = cli.command {
+        print!("{}", completions::script(&Cli::command(), shell));
+        return Ok(true);
+    }
+    if let Commands::CompleteAccounts = cli.command {
+        completions::print_account_names(&home_dir);
+        return Ok(true);
+    }
+    let owner = if cli.command.writes() {
+        ownership::target_owner(&home_dir, cli.owner.as_deref())?
+    } else {
+        None
+    };
+    check_root(&cli, platform::is_root(), owner.is_some())?;
+    // status --quiet runs from a shell hook on every cd.
+    if !matches!(cli.command, Commands::Status { quiet: true, .. }) {
+        eprintln!("Home directory: {}", home_dir.display());
+    }
+    // Held until the run ends, ownership fixes included.
+    let _run_lock = if cli.command.writes() { Some(fsutil::lock_run(&store::config_dir(&home_dir))?) } else { None };
+    if let Some(owner) = owner {
+        eprintln!("note: files created will be owned by uid {}, gid {}", owner.uid, owner.gid);
+    }
 
-        if ssh_config_path.exists() {
-            let mut file = fs::File::open(&ssh_config_path).unwrap();
-            file.read_to_string(&mut existing_config).unwrap();
+    let mut account_manager = GitAccountManager::load(home_dir)?;
+    account_manager.no_agent = cli.no_agent;
+    account_manager.offline = cli.offline;
+    account_manager.token = cli.token.clone();
+    account_manager.tamper = match (cli.force_ours, cli.force_theirs) {
+        (true, _) => Some(Resolution::Ours),
+        (_, true) => Some(Resolution::Theirs),
+        _ => None,
+    };
+    account_manager.user_section = cli.user_section;
+    account_manager.strict = cli.strict;
+    if let Some(prefix) = &cli.comment_prefix {
+        keys::validate_comment_prefix(prefix)?;
+        if !cli.command.writes() {
+            eprintln!("note: --comment-prefix is only saved by commands that change the config");
         }
-        
-        // Check if the configuration already exists
-        if !existing_config.contains(&format!("Host {}", host)) {
-            let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&ssh_config_path).unwrap();
-
-            file.write_all(config_content.as_bytes()).unwrap();
+        account_manager.settings.comment_prefix = Some(prefix.clone());
+    }
+    if let Some(placement) = cli.include_placement {
+        account_manager.settings.include_placement = Some(placement);
+        // sync --fix and doctor --fix only rewrite the files they fix.
+        if cli.command.writes() {
+            account_manager.save()?;
+        } else {
+            eprintln!("note: --include-placement is only saved by commands that change the config");
         }
-
-        Ok(())
-
     }
-
-    fn setup_account(&mut self, name: &str, email: &str, codebase_dir: &str, host: &str) -> Result<()> {
-        let ssh_key =self.generate_ssh_key(name, email).unwrap();
-        self.add_ssh_agent(&ssh_key).unwrap();
-        self.add_account(name, email, &ssh_key, self.home_dir.join(codebase_dir));
-        let _ = self.associate_account_with_dir(name);
-        let _ = self.setup_ssh_config(name, host);
-        Ok(())
+    if !cli.require_email_domain.is_empty() || cli.email_domain_warn_only.is_some() {
+        for domain in &cli.require_email_domain {
+            email_policy::validate_domain(domain)?;
+        }
+        if !cli.require_email_domain.is_empty() {
+            account_manager.require_email_domains(&cli.require_email_domain, cli.email_domain_host.as_deref());
+        }
+        if let Some(warn_only) = cli.email_domain_warn_only {
+            account_manager.settings.email_domain_warn_only = warn_only;
+        }
+        if cli.command.writes() {
+            account_manager.save()?;
+        } else {
+            eprintln!("note: --require-email-domain and --email-domain-warn-only are only saved by commands that change the config");
+        }
+    }
+    if let Some(home_relative) = cli.home_relative_paths {
+        let changed = account_manager.settings.home_relative_paths != home_relative;
+        account_manager.settings.home_relative_paths = home_relative;
+        if !cli.command.writes() {
+            eprintln!("note: --home-relative-paths is only saved by commands that change the config");
+        } else if changed {
+            account_manager.save()?;
+            account_manager.rewrite_all_include_ifs()?;
+        }
+    }
+    // sync and doctor report these themselves, next to the fix.
+    if cli.command.writes() && !matches!(cli.command, Commands::Sync { .. } | Commands::Doctor { .. } | Commands::Uninstall { .. }) {
+        let problems = doctor::warn_inconsistencies(&account_manager);
+        if cli.strict && !problems.is_empty() {
+            return Err(errors::strict_warnings(problems));
+        }
+    }
+    if !cli.offline && cli.command.uses_network() {
+        let hostnames = account_manager.provider_hostnames();
+        let hostnames: Vec<&str> = hostnames.iter().map(String::as_str).collect();
+        if !connectivity::any_resolves(&hostnames, Duration::from_secs(2)) {
+            eprintln!("note: none of {} resolve; continuing in offline mode", hostnames.join(", "));
+            account_manager.offline = true;
+        }
     }
 
+    let doctor = matches!(cli.command, Commands::Doctor { .. });
+    let writes = cli.command.writes();
+    let result = dispatch(cli.command, &mut account_manager);
+    if doctor || writes {
+        let doctor_ok = doctor.then_some(matches!(result, Ok(true)));
+        if let Err(e) = drift::refresh(&account_manager.home_dir, doctor_ok) {
+            eprintln!("warning: couldn't update the cached check: {}", e);
+        }
+    }
+    // Also after a failure, for whatever was created before it.
+    if let Some(owner) = owner {
+        account_manager.fix_ownership(owner)?;
+    }
+    result
 }
 
-fn main() -> Result<()> {
+/// `apply`, and `setup --stdin`: sets up the accounts of `plan` after
+/// listing the `rejected` records. Records from stdin carry on past an
+/// account that fails unless `--strict`.
+fn apply_batch(account_manager: &mut GitAccountManager, plan: plan::Plan, rejected: Vec<plan::Rejection>, stdin: bool, keep_going: bool, report_format: OutputFormat, dry_run: bool) -> Result<bool> {
+    let strict = account_manager.strict;
+    for rejection in &rejected {
+        println!("error: {}", rejection.describe());
+    }
+    if strict && !rejected.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} invalid record(s); nothing was set up (--strict)", rejected.len())));
+    }
+    if dry_run {
+        plan::print_preview(&account_manager.preview_plan(&plan)?);
+        return Ok(rejected.is_empty());
+    }
+    let ok = account_manager.apply_plan(plan, keep_going || (stdin && !strict), report_format)?;
+    if !rejected.is_empty() {
+        println!("Rejected: {} record(s)", rejected.len());
+    }
+    Ok(ok && rejected.is_empty())
+}
 
-    let home_dir = var("HOME").expect("$HOME directory not found");
-    println!("Home directory: {}", home_dir);
+fn dispatch(command: Commands, account_manager: &mut GitAccountManager) -> Result<bool> {
+    match command {
+        Commands::Setup { stdin: true, format, .. } => {
+            let (plan, rejected) = plan::read_records(&std::io::read_to_string(std::io::stdin())?, format)?;
+            apply_batch(account_manager, plan, rejected, true, false, OutputFormat::Text, false)
+        }
+        Commands::Setup { name, name_from_ssh_key, email, codebase_dir, stdin: _, format: _, host, managed_ssh_config, no_global_edit, passphrase_stdin, passphrase_file, export_gitconfig_dir, allow_duplicate_email, no_rollback, https_username, login, committer_name, committer_email, previous_emails, smtp_server, smtp_user, smtp_encryption, smtp_server_port, sendemail_from, smtp_credential_helper, no_rewrite_https, pubkey_algorithms, minimal, existing_key, key_type, adopt_host_block, replace_host_block, mut git_settings, color, pager, keychain, scope, check_remote_host_key, known_hosts_file, strict_host_key_checking, pkcs11_provider, also_windows, hooks_path, template_dir, lfs, key_filename, certificate_file, proxy, proxy_jump, ssl_cert, ssl_key, ssl_ca_info, ssl_url, canary_repo, multiplexing, merge_tool, gc_auto, pack_threads, repack_write_bitmaps, quiet_advice, provider, hostname, copy_from } => {
+            account_manager.allow_duplicate_email = allow_duplicate_email;
+            account_manager.no_rollback = no_rollback;
+            if let Some(template) = key_filename {
+                keys::validate_key_filename(&template)?;
+                account_manager.settings.key_filename = Some(template);
+            }
+            if managed_ssh_config {
+                account_manager.settings.managed_ssh_config = true;
+            }
+            if no_global_edit {
+                account_manager.settings.no_global_edit = true;
+            }
+            if let Some(dir) = export_gitconfig_dir {
+                // The symlink target has to be absolute to resolve from the codebase dir.
+                account_manager.settings.gitconfig_export_dir = Some(std::path::absolute(dir)?);
+            }
+            account_manager.key_passphrase = PassphraseSource::from_flags(passphrase_stdin, passphrase_file).read()?;
+            let existing_key = existing_key.map(std::path::absolute).transpose()?;
+            let codebase_dir = codebase_dir.expect("clap requires --codebase-dir without --stdin");
+            let name = match (name, name_from_ssh_key) {
+                (Some(name), _) => name,
+                (None, pattern) => {
+                    let pattern = pattern.expect("clap requires --name without --name-from-ssh-key");
+                    let key = existing_key.as_deref().expect("clap requires --existing-key with --name-from-ssh-key");
+                    let comment = keys::pub_comment(key)
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{}.pub has no comment to take the name from; pass --name", key.display())))?;
+                    let name = keys::name_from_comment(&comment, &pattern)?.ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidInput, format!("the comment '{}' of {}.pub doesn't match {}; pass --name", comment, key.display(), pattern))
+                    })?;
+                    if let Some(problem) = validate::name_problem(&name) {
+                        return Err(Error::new(ErrorKind::InvalidInput, format!("'{}' from the key comment can't be an account name: {}", name, problem)));
+                    }
+                    eprintln!("note: naming the account {} after the key comment '{}'", name, comment);
+                    name
+                }
+            };
+            let email = match email {
+                Some(email) => email,
+                None => {
+                    let key = existing_key.as_deref().expect("clap requires --email without --existing-key");
+                    let prefix = account_manager.settings.comment_prefix.as_deref();
+                    let comment = keys::pub_comment(key).map(|comment| keys::comment_email(prefix, &comment).to_string());
+                    let email = comment.filter(|comment| comment.contains('@')).ok_or_else(|| {
+                        Error::new(ErrorKind::InvalidInput, format!("{}.pub has no email in its comment; pass --email", key.display()))
+                    })?;
+                    eprintln!("warning: no --email given; using {} from the key comment", email);
+                    email
+                }
+            };
+            if let Some(color) = color {
+                git_settings.push(git_settings::parse(&format!("color.ui={}", color))?);
+            }
+            if let Some(pager) = pager {
+                git_settings.push(git_settings::parse(&format!("core.pager={}", pager))?);
+            }
+            if let Some(merge_tool) = merge_tool {
+                git_settings.push(git_settings::parse(&format!("merge.tool={}", merge_tool))?);
+            }
+            for (key, value) in [("gc.auto", gc_auto), ("pack.threads", pack_threads), ("repack.writeBitmaps", repack_write_bitmaps)] {
+                if let Some(value) = value {
+                    git_settings.push(git_settings::parse(&format!("{}={}", key, value))?);
+                }
+            }
+            if quiet_advice {
+                // Ahead of the rest, so a --set for the same key wins.
+                let quiet = git_settings::QUIET_ADVICE.iter().map(|key| (key.to_string(), "false".to_string()));
+                git_settings = quiet.chain(git_settings).collect();
+            }
+            let source = copy_from.map(|source| account_manager.accounts.get(&source).cloned().ok_or_else(|| errors::account_not_found(&source))).transpose()?;
+            // The source's hostname, unless --hostname says otherwise;
+            // --provider then only picks the key type.
+            let hostname = hostname
+                .or_else(|| source.as_ref().map(|source| source.hostname().to_string()))
+                .unwrap_or_else(|| provider.hostname().to_string());
+            let host = host.unwrap_or_else(|| account::default_host(&hostname, &name));
+            let key_type = key_type.or_else(|| existing_key.is_none().then(|| provider.key_type()).flatten().map(str::to_string));
+            let mut options = SetupOptions {
+                https_username,
+                pubkey_algorithms,
+                minimal,
+                existing_key,
+                key_type,
+                host_block: if adopt_host_block {
+                    Some(HostBlockChoice::Adopt)
+                } else if replace_host_block {
+                    Some(HostBlockChoice::Replace)
+                } else {
+                    None
+                },
+                git_settings: git_settings.into_iter().collect(),
+                keychain,
+                also_windows,
+                hooks_path,
+                template_dir: template_dir.map(std::path::absolute).transpose()?,
+                lfs,
+                certificate_file: certificate_file.map(std::path::absolute).transpose()?,
+                multiplexing,
+                no_rewrite_https,
+                login,
+                scope,
+                committer_name,
+                committer_email,
+                previous_emails,
+                sendemail: SendEmail {
+                    smtp_server,
+                    smtp_user,
+                    smtp_encryption,
+                    smtp_server_port,
+                    from: sendemail_from,
+                    credential_helper: smtp_credential_helper,
+                },
+                check_remote_host_key,
+                known_hosts_file: known_hosts_file.map(std::path::absolute).transpose()?,
+                strict_host_key_checking,
+                pkcs11_provider: pkcs11_provider.map(std::path::absolute).transpose()?,
+                http_proxy: proxy,
+                proxy_jump,
+                client_tls: ClientTls {
+                    url: ssl_url,
+                    cert: ssl_cert.map(|path| client_tls::expand(&path, &account_manager.home_dir)).transpose()?,
+                    key: ssl_key.map(|path| client_tls::expand(&path, &account_manager.home_dir)).transpose()?,
+                    ca_info: ssl_ca_info.map(|path| client_tls::expand(&path, &account_manager.home_dir)).transpose()?,
+                },
+                canary_repo,
+                hostname: (hostname != PROVIDER_HOST).then_some(hostname),
+            };
+            if let Some(source) = &source {
+                options.inherit(source);
+                account_manager.no_agent |= source.no_agent;
+            }
+            let report = account_manager.setup_account(&name, &email, &codebase_dir, &host, options)?;
+            report.print();
+            let problems = report.strict_problems();
+            if account_manager.strict && !problems.is_empty() {
+                return Err(errors::strict_warnings(problems));
+            }
+            Ok(report.config_written())
+        }
+        Commands::Check { .. } | Commands::ShellHook { .. } | Commands::Completions { .. } | Commands::CompleteAccounts => {
+            unreachable!("run handles these before loading the accounts")
+        }
+        Commands::Diff { account } => account_manager.diff_config(account.as_deref()),
+        Commands::Doctor { fix, repair, complete_deferred, network, include_disabled } => {
+            doctor::run_doctor(account_manager, fix, repair, complete_deferred, network, include_disabled)
+        }
+        Commands::Sync { fix } => doctor::run_sync(account_manager, fix),
+        Commands::List { format } => {
+            list::print_accounts(account_manager, format)?;
+            Ok(true)
+        }
+        Commands::Report { format } => {
+            machine_report::print_report(account_manager, format)?;
+            Ok(true)
+        }
+        Commands::Scan { path, json_lines, timeout } => {
+            let root = path.unwrap_or_else(|| account_manager.home_dir.clone());
+            scan::run_scan(account_manager, &root, json_lines, timeout.map(Duration::from_secs))
+        }
+        Commands::Remove { name, delete_keys } => {
+            account_manager.remove_account(&name, delete_keys)?;
+            Ok(true)
+        }
+        Commands::Disable { name } => {
+            account_manager.disable_account(&name)?;
+            println!("Disabled {}", name);
+            Ok(true)
+        }
+        Commands::Enable { name } => {
+            account_manager.enable_account(&name)?;
+            println!("Enabled {}", name);
+            Ok(true)
+        }
+        Commands::UpgradeSetup { apply, non_interactive } => upgrade::run_upgrade(account_manager, apply, non_interactive),
+        Commands::ReloadAgent { managed_only, yes } => account_manager.reload_agent(managed_only, yes),
+        Commands::InitAgentService { uninstall } => account_manager.init_agent_service(uninstall),
+        Commands::Key { command: KeyCommands::Add { account, label, key_type, import } } => {
+            let key_path = account_manager.add_secondary_key(&account, &label, &key_type, import.as_deref())?;
+            println!("Added key '{}' to {}: {}", label, account, key_path);
+            Ok(true)
+        }
+        Commands::Key { command: KeyCommands::Rotate { account, all, label, older_than, key_type, dry_run } } => {
+            let account = if all { None } else { Some(account_manager.account_or_cwd(account)?) };
+            account_manager.rotate_keys(account.as_deref(), label.as_deref(), older_than, key_type.as_deref(), dry_run)
+        }
+        Commands::FixPermissions => account_manager.fix_permissions(),
+        Commands::Backups { list: _, purge, keep, older_than, force } => {
+            if purge {
+                account_manager.purge_backups(&backups::Retention { keep, older_than, force })
+            } else {
+                account_manager.list_backups()
+            }
+        }
+        Commands::ExportPlan { output } => {
+            plan::write(&account_manager.export_plan(), output.as_ref())?;
+            Ok(true)
+        }
+        Commands::Apply { file, stdin, format, keep_going, report_format, dry_run } => {
+            let (plan, rejected) = match file {
+                Some(file) => (plan::load(&file)?, Vec::new()),
+                None => plan::read_records(&std::io::read_to_string(std::io::stdin())?, format)?,
+            };
+            apply_batch(account_manager, plan, rejected, stdin, keep_going, report_format, dry_run)
+        }
+        Commands::Validate { file } => account_manager.validate_plan(&file),
+        Commands::CloneAll { account, org, pattern, include_archived, jobs } => {
+            account_manager.clone_all(&account, &org, pattern.as_deref(), include_archived, jobs)
+        }
+        Commands::Edit { name, exclude_dir, exclude_account, remove_exclusion, unset } => {
+            if let (Some(dir), Some(other)) = (&exclude_dir, &exclude_account) {
+                account_manager.exclude_dir(&name, dir, other)?;
+            }
+            if let Some(dir) = &remove_exclusion {
+                account_manager.remove_exclusion(&name, dir)?;
+            }
+            if let Some(key) = &unset {
+                account_manager.unset_setting(&name, key)?;
+            }
+            Ok(true)
+        }
+        Commands::Uninstall { remove_keys, yes } => account_manager.uninstall(remove_keys, yes),
+        Commands::FixRemotes { account, dry_run, all_remotes } => account_manager.fix_remotes(&account, dry_run, all_remotes),
+        Commands::Prune { remotes, path, to, timeout } => {
+            if !remotes {
+                return Err(Error::new(ErrorKind::InvalidInput, "nothing to prune; pass --remotes"));
+            }
+            account_manager.prune_remotes(&path, to.as_deref(), timeout.map(Duration::from_secs))
+        }
+        Commands::Key { command: KeyCommands::Show { account } } => {
+            account_manager.show_keys(&account_manager.account_or_cwd(account)?)?;
+            Ok(true)
+        }
+        Commands::Key { command: KeyCommands::Audit { format } } => account_manager.audit_keys(format, account_manager.strict),
+        Commands::Key { command: KeyCommands::RemoteList { account, prune_remote, yes } } => {
+            account_manager.remote_list_keys(&account_manager.account_or_cwd(account)?, prune_remote, yes)
+        }
+        Commands::Key { command: KeyCommands::Orphans { delete_unused, yes } } => account_manager.orphan_keys(delete_unused, yes),
+        Commands::Key { command: KeyCommands::RegenPub { account } } => {
+            let account = account_manager.account_or_cwd(account)?;
+            let written = account_manager.regenerate_pub_keys(&account)?;
+            if written.is_empty() {
+                println!("No public keys missing for {}", account);
+            }
+            for path in written {
+                println!("Wrote {}", path);
+            }
+            Ok(true)
+        }
+        Commands::Key { command: KeyCommands::Passphrase { account, passphrase_stdin, passphrase_file } } => {
+            account_manager.change_passphrase(&account, PassphraseSource::from_flags(passphrase_stdin, passphrase_file))?;
+            println!("Updated the passphrase for {}", account);
+            Ok(true)
+        }
+        Commands::Explain { path, format } => explain::run_explain(account_manager, &path, format),
+        Commands::MigratePaths { from, to } => account_manager.migrate_paths(from, to),
+        Commands::Reinit { account, new_key } => {
+            account_manager.reinit(&account, new_key)?;
+            Ok(true)
+        }
+        Commands::Relocate { account, new_dir, move_dir } => {
+            account_manager.relocate(&account, &std::path::absolute(new_dir)?, move_dir)?;
+            Ok(true)
+        }
+        Commands::Resolve { url, path, format } => {
+            let dir = match path {
+                Some(path) => std::path::absolute(path)?,
+                None => std::env::current_dir()?,
+            };
+            account_manager.resolve_url(&url, &dir, format)
+        }
+        Commands::WhichKey { url, path, probe, format } => {
+            let dir = match path {
+                Some(path) => std::path::absolute(path)?,
+                None => std::env::current_dir()?,
+            };
+            account_manager.which_key(&url, &dir, probe, format)
+        }
+        Commands::Switch { account, scope, worktree, yes, push_url, path } => {
+            let dir = match path {
+                Some(path) => std::path::absolute(path)?,
+                None => std::env::current_dir()?,
+            };
+            let scope = if worktree { ConfigScope::Worktree } else { scope };
+            account_manager.switch_identity(&account, &dir, scope, push_url, yes)
+        }
+        Commands::Test { account, all, jobs, timeout, auto_fix, web } => {
+            let account = if all { None } else { Some(account_manager.account_or_cwd(account)?) };
+            account_manager.test_accounts(account.as_deref(), jobs, Duration::from_secs(timeout), auto_fix, web)
+        }
+        Commands::Status { path, quiet, warn_on_mismatch } => {
+            let dir = match path {
+                Some(path) => std::path::absolute(path)?,
+                None => std::env::current_dir()?,
+            };
+            account_manager.repo_status(&dir, quiet, warn_on_mismatch)
+        }
+        Commands::Env { account, shell } => {
+            print!("{}", account_manager.env_exports(&account_manager.account_or_cwd(account)?, shell)?);
+            Ok(true)
+        }
+        Commands::TestPush { account, repo } => account_manager.test_push(&account_manager.account_or_cwd(account)?, repo.as_deref()),
+        Commands::Snapshot { command: SnapshotCommands::Create { label, include_keys } } => {
+            account_manager.create_snapshot(&label, include_keys)?;
+            Ok(true)
+        }
+        Commands::Snapshot { command: SnapshotCommands::List } => {
+            account_manager.list_snapshots()?;
+            Ok(true)
+        }
+        Commands::Snapshot { command: SnapshotCommands::Restore { id, force } } => account_manager.restore_snapshot(&id, force),
+        Commands::Mailmap { command: MailmapCommands::Generate { account, extra, write } } => {
+            account_manager.generate_mailmap(account.as_deref(), &extra, write.as_deref())
+        }
+        Commands::Token { command: TokenCommands::Set { account } } => account_manager.set_token(&account),
+        Commands::Token { command: TokenCommands::Remove { account } } => account_manager.remove_token(&account),
+        Commands::Token { command: TokenCommands::Status { account } } => account_manager.token_status(account.as_deref()),
+        Commands::Templates { command: TemplatesCommands::Show { name } } => {
+            templates::show(&account_manager.home_dir, name.as_deref())?;
+            Ok(true)
+        }
+    }
+}
 
-    let config_path = PathBuf::from(home_dir);
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let json_errors = cli.json;
+    let result = run(cli);
+    if let Err(e) = &result {
+        events::emit("error", None, json!({ "message": e.to_string() }));
+    }
+    events::emit("done", None, json!({ "ok": matches!(result, Ok(true)) }));
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            if json_errors {
+                eprintln!("{}", errors::envelope(&e));
+            } else {
+                eprintln!("error: {}", e);
+            }
+            if matches!(errors::multigit_error(&e), Some(MultigitError::HostConflict(_))) {
+                ExitCode::from(host_conflict::EXIT_CODE)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    }
+}
 
-    let mut account_manager = GitAccountManager::new(config_path);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let _ = account_manager.setup_account("AkhilKamath", "akhilkamath97@gmail.com", "/Users/akhil/Code/acc1/", "github.com-pers");
+    #[cfg(unix)]
+    #[test]
+    fn root_is_refused_unless_allowed() {
+        let remove = Cli::parse_from(["multigit", "remove", "work"]);
+        let error = check_root(&remove, true, false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+        assert!(error.to_string().starts_with("refusing to run as root"));
 
-    Ok(())
-}
\ No newline at end of file
+        assert!(check_root(&remove, false, false).is_ok());
+        assert!(check_root(&remove, true, true).is_ok());
+        assert!(check_root(&Cli::parse_from(["multigit", "--allow-root", "remove", "work"]), true, false).is_ok());
+        assert!(check_root(&Cli::parse_from(["multigit", "list"]), true, false).is_ok());
+    }
+}
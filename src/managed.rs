@@ -0,0 +1,144 @@
+//! Marker comments around every block multigit writes, so its own content
+//! can be told apart from the user's. Both ssh config and git config treat
+//! `#` lines as comments.
+
+use std::collections::HashSet;
+
+const BEGIN: &str = "# BEGIN multigit: ";
+const END: &str = "# END multigit: ";
+
+pub enum Marker<'a> {
+    Begin(&'a str),
+    End(&'a str),
+}
+
+/// A marked block, from its BEGIN line to one past its END line.
+pub struct ManagedBlock {
+    pub account: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+pub fn marker(line: &str) -> Option<Marker<'_>> {
+    let line = line.trim();
+    if let Some(account) = line.strip_prefix(BEGIN) {
+        Some(Marker::Begin(account.trim()))
+    } else {
+        line.strip_prefix(END).map(|account| Marker::End(account.trim()))
+    }
+}
+
+pub fn is_marker(line: &str) -> bool {
+    marker(line).is_some()
+}
+
+/// `body` between BEGIN and END lines for `account`.
+pub fn wrap(account: &str, body: &str) -> String {
+    let mut out = format!("{}{}\n{}", BEGIN, account, body);
+    if !body.is_empty() && !body.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&format!("{}{}\n", END, account));
+    out
+}
+
+/// Every complete BEGIN/END pair. An END without its BEGIN, or a BEGIN
+/// that is never closed, is ignored.
+pub fn blocks(lines: &[&str]) -> Vec<ManagedBlock> {
+    let mut blocks = Vec::new();
+    let mut open: Option<(&str, usize)> = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        match marker(line) {
+            Some(Marker::Begin(account)) => open = Some((account, i)),
+            Some(Marker::End(account)) => {
+                if let Some((begun, start)) = open.take() {
+                    if begun == account {
+                        blocks.push(ManagedBlock {
+                            account: account.to_string(),
+                            start,
+                            end: i + 1,
+                        });
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    blocks
+}
+
+/// Which of `lines` fall inside one of `blocks`, so callers don't scan the
+/// blocks again for every line.
+fn covered(lines: &[&str], blocks: &[ManagedBlock]) -> Vec<bool> {
+    let mut inside = vec![false; lines.len()];
+    for block in blocks {
+        inside[block.start..block.end].fill(true);
+    }
+    inside
+}
+
+/// The marked block containing lines `start..end`, if there is one.
+pub fn enclosing(blocks: &[ManagedBlock], start: usize, end: usize) -> Option<&ManagedBlock> {
+    blocks.iter().find(|block| block.start < start && end <= block.end)
+}
+
+/// `content` with `account`'s marked region holding `body`. An existing
+/// region is replaced where it is, and any repeats of it from earlier runs
+/// are dropped; otherwise the region is appended after everything else.
+/// Lines outside the account's own regions, including other tools'
+/// entries, are kept as they are and in their order.
+pub fn merge(content: &str, account: &str, body: &str) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let own: Vec<ManagedBlock> = blocks(&lines).into_iter().filter(|block| block.account == account).collect();
+
+    let Some(first) = own.first() else {
+        let mut out = content.to_string();
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+        if !out.is_empty() && !out.ends_with("\n\n") {
+            out.push('\n');
+        }
+        out.push_str(&wrap(account, body));
+        return out;
+    };
+
+    let inside = covered(&lines, &own);
+    let mut out = String::with_capacity(content.len() + body.len());
+    for (i, line) in lines.iter().enumerate() {
+        if i == first.start {
+            out.push_str(&wrap(account, body));
+        }
+        if inside[i] {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// `content` without the marked regions of the accounts `remove` picks,
+/// along with the blank line that separated each from what came before.
+pub fn strip(content: &str, remove: impl Fn(&str) -> bool) -> String {
+    let lines: Vec<&str> = content.lines().collect();
+    let dropped: Vec<ManagedBlock> = blocks(&lines).into_iter().filter(|block| remove(&block.account)).collect();
+    let inside = covered(&lines, &dropped);
+    let starts: HashSet<usize> = dropped.iter().map(|block| block.start).collect();
+
+    let mut out = String::with_capacity(content.len());
+    for (i, line) in lines.iter().enumerate() {
+        if inside[i] {
+            continue;
+        }
+        let separates_dropped = line.trim().is_empty() && starts.contains(&(i + 1));
+        if separates_dropped {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,187 @@
+//! `migrate-paths`: moving a setup to a machine where the home directory is
+//! somewhere else, such as after a change of username. Every stored path
+//! under the old home is moved under the new one, the include files,
+//! includeIf entries and Host blocks are written again from the result, and
+//! whatever the new paths don't find yet is listed.
+
+use std::collections::BTreeSet;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+use crate::fsutil;
+use crate::managed;
+use crate::state::State;
+
+/// `path` moved from under `from` to under `to`, or `None` when it isn't
+/// under `from`.
+fn rebase(path: &Path, from: &Path, to: &Path) -> Option<PathBuf> {
+    path.strip_prefix(from).ok().map(|rest| if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) })
+}
+
+pub fn rebase_in_place(path: &mut PathBuf, from: &Path, to: &Path) -> bool {
+    match rebase(path, from, to) {
+        Some(moved) => {
+            *path = moved;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Like `rebase_in_place` for paths stored as strings, which can be globs
+/// whose trailing `/` matters, so the rest is kept as it's written.
+fn rebase_string(path: &mut String, from: &Path, to: &Path) -> bool {
+    let from = from.to_string_lossy();
+    let from = from.trim_end_matches('/');
+    let Some(rest) = path.strip_prefix(from).filter(|rest| rest.is_empty() || rest.starts_with('/')) else {
+        return false;
+    };
+    *path = format!("{}{}", to.to_string_lossy().trim_end_matches('/'), rest);
+    true
+}
+
+impl GitAccountManager {
+    /// The home directory the accounts' keys were set up under, when they
+    /// all agree on one that isn't the current home.
+    fn detect_old_home(&self) -> Option<PathBuf> {
+        let homes: BTreeSet<PathBuf> = self
+            .accounts
+            .values()
+            .flat_map(|account| account.identity_files())
+            .filter_map(|key| key.split_once("/.ssh/").map(|(home, _)| PathBuf::from(home)))
+            .collect();
+        match homes.into_iter().collect::<Vec<_>>().as_slice() {
+            [home] if *home != self.home_dir => Some(home.clone()),
+            _ => None,
+        }
+    }
+
+    /// Moves every stored path under `from` to under `to`, which defaults
+    /// to the current home, and writes each enabled account's files again.
+    /// Without `from`, the old home is worked out from where the accounts'
+    /// keys were. Prints the new paths that don't exist yet.
+    pub fn migrate_paths(&mut self, from: Option<PathBuf>, to: Option<PathBuf>) -> Result<bool> {
+        let to = to.unwrap_or_else(|| self.home_dir.clone());
+        let from = match from.or_else(|| self.detect_old_home()) {
+            Some(from) => from,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Can't tell which home directory the accounts were set up under; pass --from <old home>",
+                ))
+            }
+        };
+        if from == to {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("--from and --to are both {}", to.display())));
+        }
+        if from.is_relative() || to.is_relative() {
+            return Err(Error::new(ErrorKind::InvalidInput, "--from and --to must be absolute paths"));
+        }
+
+        let mut moved = 0;
+        for account in self.accounts.values_mut() {
+            let mut changed = rebase_in_place(&mut account.codebase_dir_path, &from, &to);
+            changed |= rebase_string(&mut account.ssh_key, &from, &to);
+            for key in &mut account.secondary_keys {
+                changed |= rebase_string(&mut key.path, &from, &to);
+            }
+            for path in [&mut account.hooks_path, &mut account.template_dir, &mut account.certificate_file, &mut account.known_hosts_file].into_iter().flatten() {
+                changed |= rebase_in_place(path, &from, &to);
+            }
+            for exclusion in &mut account.exclusions {
+                changed |= rebase_in_place(&mut exclusion.dir, &from, &to);
+            }
+            if let Some(pattern) = &mut account.dir_pattern {
+                changed |= rebase_string(pattern, &from, &to);
+            }
+            if changed {
+                moved += 1;
+                println!("Moved {}'s paths to {}", account.name, to.display());
+            }
+        }
+        if let Some(export_dir) = &mut self.settings.gitconfig_export_dir {
+            if rebase_in_place(export_dir, &from, &to) {
+                println!("Moved the gitconfig export dir to {}", export_dir.display());
+            }
+        }
+        if moved == 0 {
+            println!("No account has paths under {}", from.display());
+        }
+        self.save()?;
+
+        // The hashes recorded for the old paths still describe the blocks
+        // as they are, so the tamper check keeps working for the new ones.
+        let mut state = State::load(&self.home_dir)?;
+        for record in &mut state.written {
+            rebase_in_place(&mut record.path, &from, &to);
+        }
+        for record in &mut state.keys {
+            rebase_string(&mut record.path, &from, &to);
+        }
+        state.save(&self.home_dir)?;
+
+        let mut missing: Vec<(PathBuf, String)> = Vec::new();
+        for account in self.sorted_accounts(true) {
+            if account.disabled {
+                println!(
+                    "warning: {} is disabled, so its commented-out entries still name {}; enable it and run migrate-paths --from {} again",
+                    account.name,
+                    from.display(),
+                    from.display()
+                );
+                continue;
+            }
+            if account.codebase_dir_path.is_dir() {
+                self.setup_local_gitconfig(account)?;
+            } else {
+                missing.push((
+                    account.codebase_dir_path.clone(),
+                    format!("{}'s codebase dir; its include file wasn't written", account.name),
+                ));
+            }
+            let include_path = account.codebase_dir_path.join(".gitconfig");
+            self.setup_global_gitconfig(&account.name, &account.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy())?;
+
+            let ssh_config_path = self.ssh_config_path();
+            let block = self.host_block(account)?;
+            if self.resolve_tamper(&account.name, &ssh_config_path, &block)? {
+                fsutil::modify(&ssh_config_path, |content| {
+                    let merged = managed::merge(content, &account.name, &block);
+                    Ok((merged != content).then_some(merged))
+                })?;
+                let mut state = State::load(&self.home_dir)?;
+                state.record_write(&account.name, &ssh_config_path);
+                state.save(&self.home_dir)?;
+            }
+
+            for key in account.identity_files() {
+                for path in [PathBuf::from(key), PathBuf::from(format!("{}.pub", key))] {
+                    if !path.exists() {
+                        missing.push((path, format!("{}'s key", account.name)));
+                    }
+                }
+            }
+            for (path, what) in [(&account.hooks_path, "hooks dir"), (&account.template_dir, "template dir"), (&account.certificate_file, "certificate"), (&account.known_hosts_file, "known hosts file")] {
+                if let Some(path) = path.as_ref().filter(|path| !path.exists()) {
+                    missing.push((path.clone(), format!("{}'s {}", account.name, what)));
+                }
+            }
+        }
+        if let Some(export_dir) = self.settings.gitconfig_export_dir.as_ref().filter(|dir| !dir.is_dir()) {
+            missing.push((export_dir.clone(), "the gitconfig export dir".to_string()));
+        }
+        self.fix_include_if_order()?;
+
+        if missing.is_empty() {
+            println!("Every migrated path exists; run multigit doctor to check the setup");
+            return Ok(true);
+        }
+        println!("Not there yet:");
+        for (path, what) in &missing {
+            println!("  missing: {} ({})", path.display(), what);
+        }
+        println!("Copy these over from the old machine, then run multigit doctor");
+        Ok(false)
+    }
+}
@@ -0,0 +1,121 @@
+//! ssh connection sharing for accounts set up with `--multiplexing`: their
+//! Host block makes the first connection a master that later ones reuse, so
+//! pushing many repositories in a row handshakes once.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::fsutil;
+
+/// How long a master stays up after its last session, unless setup was given
+/// another duration.
+pub const DEFAULT_PERSIST: &str = "10m";
+
+/// `%n` is the alias as typed rather than the HostName, which every account
+/// on the provider shares, so each account gets its own master and never
+/// pushes over another account's authenticated connection.
+const CONTROL_PATH: &str = "~/.ssh/multigit-sockets/%r@%n-%p";
+
+pub fn socket_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join(".ssh/multigit-sockets")
+}
+
+/// The Host block lines for a master that persists for `persist`.
+pub fn host_block_lines(persist: &str) -> String {
+    format!("    ControlMaster auto\n    ControlPath {}\n    ControlPersist {}\n", CONTROL_PATH, persist)
+}
+
+/// Checks that multiplexing works here and that `persist` is a time ssh
+/// accepts for `ControlPersist`: a number of seconds, or amounts with
+/// s, m, h, d or w such as `1h30m`.
+pub fn validate(persist: &str) -> Result<()> {
+    if cfg!(windows) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "--multiplexing needs ControlMaster, which the Windows build of OpenSSH doesn't support",
+        ));
+    }
+    let mut digits = false;
+    let valid = persist.chars().all(|c| {
+        if c.is_ascii_digit() {
+            digits = true;
+            true
+        } else {
+            std::mem::replace(&mut digits, false) && "smhdwSMHDW".contains(c)
+        }
+    });
+    if persist.is_empty() || !valid {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid --multiplexing duration '{}'; use seconds or a time like 10m or 1h30m", persist),
+        ));
+    }
+    Ok(())
+}
+
+/// Creates the socket dir, readable by the owner only: anyone who can reach
+/// a master socket can use its connection.
+pub fn ensure_socket_dir(home_dir: &Path) -> Result<()> {
+    let dir = socket_dir(home_dir);
+    fsutil::create_dir_all(&dir)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).map_err(|e| fsutil::blocked(e, "change the mode of", &dir))?;
+    }
+    Ok(())
+}
+
+/// What's wrong with the socket dir, if anything.
+pub fn socket_dir_problem(home_dir: &Path) -> Option<String> {
+    let dir = socket_dir(home_dir);
+    let Ok(metadata) = fs::metadata(&dir) else {
+        return Some(format!("{} does not exist, so ssh can't create master sockets", dir.display()));
+    };
+    if !metadata.is_dir() {
+        return Some(format!("{} is not a directory", dir.display()));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Some(format!("{} is mode {:o}; other users could use its master connections", dir.display(), mode));
+        }
+    }
+    None
+}
+
+/// Stops the masters of `alias` and removes their sockets, returning how many
+/// were removed. Sockets of masters that already exited are just removed.
+pub fn remove_sockets(home_dir: &Path, alias: &str) -> usize {
+    let Ok(entries) = fs::read_dir(socket_dir(home_dir)) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // <user>@<alias>-<port>
+        let for_alias = name
+            .split_once('@')
+            .and_then(|(_, rest)| rest.rsplit_once('-'))
+            .is_some_and(|(host, port)| host.eq_ignore_ascii_case(alias) && port.chars().all(|c| c.is_ascii_digit()));
+        if !for_alias {
+            continue;
+        }
+        let path = entry.path();
+        // Fails for a stale socket, which is then just a file to remove.
+        let _ = Command::new("ssh")
+            .arg("-o")
+            .arg(format!("ControlPath={}", path.display()))
+            .args(["-O", "exit", alias])
+            .stdin(Stdio::null())
+            .output();
+        if fs::symlink_metadata(&path).is_err() || fs::remove_file(&path).is_ok() {
+            removed += 1;
+        }
+    }
+    removed
+}
@@ -0,0 +1,253 @@
+//! Keeping files owned by the user whose home multigit is configuring, for
+//! runs via sudo or provisioning scripts that pass `--home`. ssh refuses
+//! keys and config owned by anyone else.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+use crate::agent_service;
+use crate::store;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Who new files should belong to: the `--owner` user if given, otherwise
+/// the owner of the home directory. `None` when that is the current user and
+/// nothing needs chowning.
+#[cfg(unix)]
+pub fn target_owner(home_dir: &Path, owner: Option<&str>) -> Result<Option<Owner>> {
+    use std::os::unix::fs::MetadataExt;
+
+    use crate::platform;
+
+    let owner = match owner {
+        Some(name) => {
+            let (uid, gid) = platform::lookup_user(name)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("No such user '{}'", name)))?;
+            Owner { uid, gid }
+        }
+        None => match fs::metadata(home_dir) {
+            Ok(metadata) => Owner { uid: metadata.uid(), gid: metadata.gid() },
+            Err(_) => return Ok(None),
+        },
+    };
+
+    if owner.uid == platform::euid() {
+        return Ok(None);
+    }
+    if !platform::is_root() {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{} belongs to uid {}; run as that user, or as root so multigit can hand over the files it creates",
+                home_dir.display(),
+                owner.uid
+            ),
+        ));
+    }
+    Ok(Some(owner))
+}
+
+/// The home directory to configure, and a warning when that isn't `$HOME`:
+/// `home_flag` (`--home`) if given, otherwise the home of the user who ran
+/// multigit through sudo, from `$SUDO_USER` when `is_root`, otherwise
+/// `$HOME`. Depending on sudo's configuration `$HOME` is that user's home
+/// or root's, so it isn't trusted either way; the files then go to the
+/// user's home and are handed to them. `env` reads the environment and
+/// `user_home` looks a user up in the passwd database.
+pub fn resolve_home(home_flag: Option<&Path>, env: impl Fn(&str) -> Option<String>, is_root: bool, user_home: impl Fn(&str) -> Option<PathBuf>) -> Result<(PathBuf, Option<String>)> {
+    if let Some(home) = home_flag {
+        return Ok((std::path::absolute(home)?, None));
+    }
+    let home_var = env("HOME").map(PathBuf::from);
+    let sudo_user = env("SUDO_USER").filter(|user| is_root && !user.is_empty() && user != "root");
+    let Some(user) = sudo_user else {
+        return Ok((home_var.ok_or_else(|| Error::new(ErrorKind::NotFound, "$HOME is not set; pass --home"))?, None));
+    };
+    let home = user_home(&user).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("running through sudo for {}, but their home directory can't be looked up; pass --home", user),
+        )
+    })?;
+    let warning = (home_var.as_ref() != Some(&home))
+        .then(|| format!("running through sudo for {}: configuring their home, {}, not $HOME; pass --home to pick another", user, home.display()));
+    Ok((home, warning))
+}
+
+/// [`resolve_home`] with this process's environment and the real passwd
+/// database.
+pub fn home_dir(home_flag: Option<&Path>) -> Result<(PathBuf, Option<String>)> {
+    #[cfg(unix)]
+    let user_home = crate::platform::user_home;
+    #[cfg(not(unix))]
+    let user_home = |_: &str| None;
+    resolve_home(home_flag, |name| std::env::var(name).ok(), crate::platform::is_root(), user_home)
+}
+
+#[cfg(not(unix))]
+pub fn target_owner(_home_dir: &Path, owner: Option<&str>) -> Result<Option<Owner>> {
+    match owner {
+        Some(_) => Err(Error::new(ErrorKind::Unsupported, "--owner is only supported on Unix")),
+        None => Ok(None),
+    }
+}
+
+/// Hands `path` to `owner` if the current user owns it, i.e. this run
+/// created or replaced it. Files that already belong to someone are left
+/// alone. Symlinks are changed themselves, not followed.
+#[cfg(unix)]
+fn claim(path: &Path, owner: Owner) -> Result<()> {
+    use std::os::unix::fs::{lchown, MetadataExt};
+
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if metadata.uid() != crate::platform::euid() || (metadata.uid(), metadata.gid()) == (owner.uid, owner.gid) {
+        return Ok(());
+    }
+    lchown(path, Some(owner.uid), Some(owner.gid))
+        .map_err(|e| Error::new(e.kind(), format!("Failed to chown {} to uid {}: {}", path.display(), owner.uid, e)))
+}
+
+#[cfg(not(unix))]
+fn claim(_path: &Path, _owner: Owner) -> Result<()> {
+    Ok(())
+}
+
+fn claim_tree(path: &Path, owner: Owner) -> Result<()> {
+    claim(path, owner)?;
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir());
+    if is_dir {
+        for entry in fs::read_dir(path)? {
+            claim_tree(&entry?.path(), owner)?;
+        }
+    }
+    Ok(())
+}
+
+/// `path` and each of its ancestors up to, not including, `home_dir`.
+fn ancestors_below(home_dir: &Path, path: &Path) -> Vec<PathBuf> {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != home_dir && ancestor.starts_with(home_dir))
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+impl GitAccountManager {
+    /// Hands every file and directory multigit may have created during this
+    /// run to `owner`.
+    pub fn fix_ownership(&self, owner: Owner) -> Result<()> {
+        let ssh_dir = self.home_dir.join(".ssh");
+        claim(&ssh_dir, owner)?;
+        if let Ok(entries) = fs::read_dir(&ssh_dir) {
+            for entry in entries {
+                claim(&entry?.path(), owner)?;
+            }
+        }
+
+        let global_gitconfig = self.global_gitconfig_path();
+        claim(&global_gitconfig, owner)?;
+        claim(Path::new(&format!("{}.multigit-bak", global_gitconfig.display())), owner)?;
+
+        for path in ancestors_below(&self.home_dir, &store::config_dir(&self.home_dir)) {
+            claim(&path, owner)?;
+        }
+        claim_tree(&store::config_dir(&self.home_dir), owner)?;
+
+        if let Some(unit) = agent_service::unit_path(&self.home_dir) {
+            for path in ancestors_below(&self.home_dir, &unit) {
+                claim(&path, owner)?;
+            }
+        }
+
+        if let Some(export_dir) = &self.settings.gitconfig_export_dir {
+            claim_tree(export_dir, owner)?;
+        }
+
+        for account in self.sorted_accounts(true) {
+            for path in ancestors_below(&self.home_dir, &account.codebase_dir_path) {
+                claim(&path, owner)?;
+            }
+            let include = account.codebase_dir_path.join(".gitconfig");
+            claim(&include, owner)?;
+            claim(Path::new(&format!("{}.multigit-bak", include.display())), owner)?;
+            for key in account.identity_files() {
+                claim(Path::new(key), owner)?;
+                claim(Path::new(&format!("{}.pub", key)), owner)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Files in the user's ssh dir, and account keys, that are owned by root
+/// while the home directory isn't. ssh rejects these with "bad ownership".
+#[cfg(unix)]
+pub fn root_owned_ssh_files(manager: &GitAccountManager) -> Vec<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let home_owned_by_root = fs::metadata(&manager.home_dir).map_or(true, |metadata| metadata.uid() == 0);
+    if home_owned_by_root {
+        return Vec::new();
+    }
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(manager.home_dir.join(".ssh"))
+        .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    for account in manager.sorted_accounts(true) {
+        for key in account.identity_files() {
+            let key = PathBuf::from(key);
+            if !paths.contains(&key) {
+                paths.push(key);
+            }
+        }
+    }
+    paths.sort();
+    paths.retain(|path| fs::symlink_metadata(path).is_ok_and(|metadata| metadata.uid() == 0));
+    paths
+}
+
+#[cfg(not(unix))]
+pub fn root_owned_ssh_files(_manager: &GitAccountManager) -> Vec<PathBuf> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An environment with just `vars`, and a passwd database where only
+    /// alice has a home.
+    fn resolve(home_flag: Option<&Path>, vars: &[(&str, &str)], is_root: bool) -> Result<(PathBuf, Option<String>)> {
+        let env = |name: &str| vars.iter().find(|(var, _)| *var == name).map(|(_, value)| value.to_string());
+        resolve_home(home_flag, env, is_root, |user| (user == "alice").then(|| PathBuf::from("/home/alice")))
+    }
+
+    #[test]
+    fn sudo_user_home_is_configured_with_a_warning() {
+        let (home, warning) = resolve(None, &[("HOME", "/root"), ("SUDO_USER", "alice")], true).unwrap();
+        assert_eq!(home, PathBuf::from("/home/alice"));
+        assert_eq!(warning.unwrap(), "running through sudo for alice: configuring their home, /home/alice, not $HOME; pass --home to pick another");
+
+        // sudo -H, or a sudoers that keeps $HOME pointing at the user's.
+        assert_eq!(resolve(None, &[("HOME", "/home/alice"), ("SUDO_USER", "alice")], true).unwrap(), (PathBuf::from("/home/alice"), None));
+
+        let error = resolve(None, &[("HOME", "/root"), ("SUDO_USER", "bob")], true).unwrap_err();
+        assert_eq!(error.to_string(), "running through sudo for bob, but their home directory can't be looked up; pass --home");
+    }
+
+    #[test]
+    fn home_flag_wins_and_sudo_only_counts_as_root() {
+        let sudo = [("HOME", "/root"), ("SUDO_USER", "bob")];
+        assert_eq!(resolve(Some(Path::new("/srv/home")), &sudo, true).unwrap(), (PathBuf::from("/srv/home"), None));
+        assert_eq!(resolve(None, &sudo, false).unwrap(), (PathBuf::from("/root"), None));
+        assert_eq!(resolve(None, &[("HOME", "/root"), ("SUDO_USER", "root")], true).unwrap(), (PathBuf::from("/root"), None));
+        assert!(resolve(None, &[], false).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Holds the passphrase for new or re-encrypted keys.
+pub const PASSPHRASE_ENV: &str = "MULTIGIT_KEY_PASSPHRASE";
+
+/// Holds the current passphrase when changing it non-interactively.
+pub const OLD_PASSPHRASE_ENV: &str = "MULTIGIT_KEY_OLD_PASSPHRASE";
+
+/// Where a key passphrase comes from.
+pub enum PassphraseSource {
+    /// Let ssh-keygen ask on the terminal.
+    Prompt,
+    Env,
+    /// First line of stdin, for scripts.
+    Stdin,
+    /// Contents of a file, e.g. a mounted secret.
+    File(PathBuf),
+}
+
+impl PassphraseSource {
+    /// Picks the source by precedence: stdin, then the environment, then a
+    /// file.
+    pub fn from_flags(stdin: bool, file: Option<PathBuf>) -> Self {
+        if stdin {
+            PassphraseSource::Stdin
+        } else if env::var_os(PASSPHRASE_ENV).is_some() {
+            if let Some(file) = file {
+                eprintln!("note: {} is set; ignoring --passphrase-file {}", PASSPHRASE_ENV, file.display());
+            }
+            PassphraseSource::Env
+        } else if let Some(file) = file {
+            PassphraseSource::File(file)
+        } else {
+            PassphraseSource::Prompt
+        }
+    }
+
+    /// The passphrase, or `None` when ssh-keygen should prompt for it.
+    pub fn read(&self) -> Result<Option<String>> {
+        match self {
+            PassphraseSource::Prompt => Ok(None),
+            PassphraseSource::Env => Ok(env::var(PASSPHRASE_ENV).ok()),
+            PassphraseSource::Stdin => {
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+                Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+            }
+            PassphraseSource::File(path) => read_file(path).map(Some),
+        }
+    }
+}
+
+/// Reads a passphrase file, dropping one trailing newline. Files other
+/// users can read are refused rather than trusted.
+fn read_file(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o004 != 0 {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("Refusing to read a passphrase from world-readable {}; chmod o-r it first", path.display()),
+            ));
+        }
+    }
+
+    let content = fs::read_to_string(path)?;
+    let content = content.strip_suffix('\n').unwrap_or(&content);
+    Ok(content.strip_suffix('\r').unwrap_or(content).to_string())
+}
+
+pub fn old_passphrase() -> String {
+    env::var(OLD_PASSPHRASE_ENV).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn ssh_keygen_gets_the_passphrase_from_the_file() {
+        let home = TempHome::new();
+        let file = home.write("secret", "correct horse\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let error = read_file(&file).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::PermissionDenied);
+            fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let mut manager = home.manager();
+        manager.key_passphrase = PassphraseSource::File(file).read().unwrap();
+        assert_eq!(manager.key_passphrase.as_deref(), Some("correct horse"));
+        testutil::set_up(&mut manager, &["work"]);
+
+        let key = &manager.accounts["work"].ssh_key;
+        let opens = |passphrase: &str| Command::new("ssh-keygen").args(["-y", "-P", passphrase, "-f", key]).output().unwrap().status.success();
+        assert!(opens("correct horse"));
+        assert!(!opens(""));
+    }
+}
@@ -0,0 +1,126 @@
+//! `fix-permissions`: puts back the modes ssh insists on, which a restore
+//! from backup or a copy between machines often loses. ssh ignores a config
+//! and refuses a private key that others can read or write.
+
+#[cfg(unix)]
+use std::fs;
+use std::io::Result;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
+#[cfg(unix)]
+const PRIVATE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const PUBLIC_MODE: u32 = 0o644;
+
+impl GitAccountManager {
+    /// The ssh dir, the ssh configs, and every private key with its public
+    /// half: the accounts' keys and any file in the ssh dir next to a
+    /// `.pub` of the same name. Each with the mode it should have.
+    #[cfg(unix)]
+    fn permission_targets(&self) -> Vec<(PathBuf, u32)> {
+        let ssh_dir = self.home_dir.join(".ssh");
+        let mut targets = vec![(ssh_dir.clone(), DIR_MODE), (ssh_dir.join("config"), PRIVATE_MODE), (self.ssh_config_path(), PRIVATE_MODE)];
+        let mut keys: Vec<PathBuf> = fs::read_dir(&ssh_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_none_or(|extension| extension != "pub"))
+                    .filter(|path| Path::new(&format!("{}.pub", path.display())).is_file())
+                    .collect()
+            })
+            .unwrap_or_default();
+        keys.sort();
+        for account in self.sorted_accounts(true) {
+            keys.extend(account.identity_files().into_iter().map(PathBuf::from));
+        }
+        for key in keys {
+            let public = PathBuf::from(format!("{}.pub", key.display()));
+            targets.push((key, PRIVATE_MODE));
+            targets.push((public, PUBLIC_MODE));
+        }
+        let mut seen = Vec::new();
+        targets.retain(|(path, _)| {
+            let new = !seen.contains(path);
+            seen.push(path.clone());
+            new
+        });
+        targets
+    }
+
+    /// Sets each file's mode, saying which ones changed. Files that don't
+    /// exist are skipped; symlinks are followed, so a key kept elsewhere is
+    /// fixed where it is.
+    #[cfg(unix)]
+    pub fn fix_permissions(&self) -> Result<bool> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (mut changed, mut failed) = (0, 0);
+        for (path, mode) in self.permission_targets() {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let current = metadata.permissions().mode() & 0o777;
+            if current == mode {
+                continue;
+            }
+            match fs::set_permissions(&path, fs::Permissions::from_mode(mode)) {
+                Ok(()) => {
+                    println!("{}: {:o} -> {:o}", path.display(), current, mode);
+                    changed += 1;
+                }
+                Err(e) => {
+                    println!("error: couldn't change the mode of {}: {}", path.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+        if changed == 0 && failed == 0 {
+            println!("All ssh files already have the right permissions.");
+        } else {
+            println!("{} file(s) fixed", changed);
+        }
+        Ok(failed == 0)
+    }
+
+    #[cfg(not(unix))]
+    pub fn fix_permissions(&self) -> Result<bool> {
+        println!("note: fix-permissions only applies to Unix file modes; nothing to do on this platform");
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn wrong_modes_are_put_back() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let stray = home.write(".ssh/id_rsa_old", "");
+        home.write(".ssh/id_rsa_old.pub", "");
+        let mode = |path: &str| fs::metadata(home.join(path)).unwrap().permissions().mode() & 0o777;
+        let wrong = [(".ssh", 0o755), (".ssh/config", 0o666), (".ssh/id_ed25519_work", 0o644), (".ssh/id_ed25519_work.pub", 0o600), (".ssh/id_rsa_old", 0o640)];
+        for (path, wrong) in wrong {
+            fs::set_permissions(home.join(path), fs::Permissions::from_mode(wrong)).unwrap();
+        }
+
+        assert!(manager.fix_permissions().unwrap());
+        assert_eq!(mode(".ssh"), 0o700);
+        assert_eq!(mode(".ssh/config"), 0o600);
+        assert_eq!(mode(".ssh/id_ed25519_work"), 0o600);
+        assert_eq!(mode(".ssh/id_ed25519_work.pub"), 0o644);
+        assert_eq!(fs::metadata(stray).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(manager.fix_permissions().unwrap());
+    }
+}
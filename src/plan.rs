@@ -0,0 +1,844 @@
+//! Batch files describing accounts declaratively: `export-plan` writes one
+//! from the current setup and `apply` sets up every account in one. Paths
+//! under the home directory are stored relative to it, so a plan carries
+//! over to a machine with a different home. `apply --stdin` takes the same
+//! account records as JSON, for scripts.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::{split_dir_glob, Exclusion, GitAccount, GitAccountBuilder, GitAccountManager, Scope, SetupOptions, CONNECTIVITY_STEP, DEFAULT_KEY_TYPE, LOGIN_STEP, PROVIDER_HOST};
+use crate::client_tls::ClientTls;
+use crate::diff;
+use crate::errors::strict_warnings;
+use crate::fsutil;
+use crate::keys::{self, Keychain};
+use crate::known_hosts::KNOWN_HOSTS_STEP;
+use crate::managed;
+use crate::report::{self, print_table, OutputFormat, SetupReport, StepStatus};
+use crate::sendemail::SendEmail;
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanSettings {
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub managed_ssh_config: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gitconfig_export_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_filename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment_prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_global_edit: bool,
+}
+
+impl PlanSettings {
+    fn is_empty(&self) -> bool {
+        !self.managed_ssh_config
+            && self.gitconfig_export_dir.is_none()
+            && self.key_filename.is_none()
+            && self.comment_prefix.is_none()
+            && !self.no_global_edit
+    }
+}
+
+/// A subdirectory handed to another account of the plan.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanExclusion {
+    pub dir: String,
+    pub account: String,
+}
+
+/// One account, with the options `setup` takes. Keys aren't part of a plan:
+/// applying generates new ones unless `existing_key` names one.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanAccount {
+    pub name: String,
+    pub email: String,
+    pub host: String,
+    pub codebase_dir: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub existing_key: Option<String>,
+    /// Type of the generated key, or `auto`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_agent: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub https_username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey_algorithms: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub minimal: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keychain: Option<Keychain>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub also_windows: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub lfs: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate_file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplexing: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_rewrite_https: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<String>,
+    #[serde(default, skip_serializing_if = "Scope::is_dir")]
+    pub scope: Scope,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub committer_email: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_emails: Vec<String>,
+    #[serde(default, skip_serializing_if = "SendEmail::is_empty")]
+    pub sendemail: SendEmail,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub known_hosts_file: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_host_key_checking: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkcs11_provider: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+    #[serde(default, skip_serializing_if = "ClientTls::is_empty")]
+    pub client_tls: ClientTls,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary_repo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub git_settings: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclusions: Vec<PlanExclusion>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Plan {
+    #[serde(default, skip_serializing_if = "PlanSettings::is_empty")]
+    pub settings: PlanSettings,
+    #[serde(default)]
+    pub accounts: Vec<PlanAccount>,
+}
+
+/// `path` relative to `home_dir` if it's inside it.
+fn portable(home_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(home_dir).unwrap_or(path).to_string_lossy().into_owned()
+}
+
+impl PlanAccount {
+    fn from_account(home_dir: &Path, account: &GitAccount) -> Self {
+        PlanAccount {
+            name: account.name.clone(),
+            email: account.email.clone(),
+            host: account.host.clone(),
+            codebase_dir: match &account.dir_pattern {
+                Some(pattern) => {
+                    let home = format!("{}/", home_dir.display());
+                    pattern.strip_prefix(&home).unwrap_or(pattern).to_string()
+                }
+                None => portable(home_dir, &account.codebase_dir_path),
+            },
+            existing_key: None,
+            key_type: None,
+            no_agent: account.no_agent,
+            https_username: account.https_username.clone(),
+            pubkey_algorithms: account.pubkey_algorithms.clone(),
+            minimal: account.minimal_ssh_block,
+            keychain: account.keychain,
+            also_windows: account.also_windows,
+            hooks_path: account.hooks_path.as_deref().map(|path| portable(home_dir, path)),
+            template_dir: account.template_dir.as_deref().map(|path| portable(home_dir, path)),
+            lfs: account.lfs,
+            certificate_file: account.certificate_file.as_deref().map(|path| portable(home_dir, path)),
+            multiplexing: account.multiplexing.clone(),
+            no_rewrite_https: account.no_rewrite_https,
+            login: account.login.clone(),
+            committer_name: account.committer_name.clone(),
+            committer_email: account.committer_email.clone(),
+            previous_emails: account.previous_emails.clone(),
+            sendemail: account.sendemail.clone(),
+            known_hosts_file: account.known_hosts_file.as_deref().map(|path| portable(home_dir, path)),
+            strict_host_key_checking: account.strict_host_key_checking,
+            pkcs11_provider: account.pkcs11_provider.as_ref().map(|path| path.to_string_lossy().into_owned()),
+            http_proxy: account.http_proxy.clone(),
+            proxy_jump: account.proxy_jump.clone(),
+            client_tls: account.client_tls.clone(),
+            canary_repo: account.canary_repo.clone(),
+            hostname: account.hostname.clone(),
+            scope: account.scope,
+            git_settings: account.git_settings.clone(),
+            exclusions: account
+                .exclusions
+                .iter()
+                .map(|exclusion| PlanExclusion { dir: portable(home_dir, &exclusion.dir), account: exclusion.account.clone() })
+                .collect(),
+        }
+    }
+
+    pub fn options(&self, home_dir: &Path) -> SetupOptions {
+        SetupOptions {
+            https_username: self.https_username.clone(),
+            pubkey_algorithms: self.pubkey_algorithms.clone(),
+            minimal: self.minimal,
+            existing_key: self.existing_key.as_ref().map(|path| home_dir.join(path)),
+            key_type: self.key_type.clone(),
+            host_block: None,
+            git_settings: self.git_settings.clone(),
+            keychain: self.keychain,
+            also_windows: self.also_windows,
+            hooks_path: self.hooks_path.as_ref().map(PathBuf::from),
+            template_dir: self.template_dir.as_ref().map(|path| home_dir.join(path)),
+            lfs: self.lfs,
+            certificate_file: self.certificate_file.as_ref().map(|path| home_dir.join(path)),
+            multiplexing: self.multiplexing.clone(),
+            no_rewrite_https: self.no_rewrite_https,
+            login: self.login.clone(),
+            committer_name: self.committer_name.clone(),
+            committer_email: self.committer_email.clone(),
+            previous_emails: self.previous_emails.clone(),
+            sendemail: self.sendemail.clone(),
+            scope: self.scope,
+            check_remote_host_key: false,
+            known_hosts_file: self.known_hosts_file.as_ref().map(|path| home_dir.join(path)),
+            strict_host_key_checking: self.strict_host_key_checking,
+            pkcs11_provider: self.pkcs11_provider.as_ref().map(PathBuf::from),
+            http_proxy: self.http_proxy.clone(),
+            proxy_jump: self.proxy_jump.clone(),
+            client_tls: self.client_tls.clone(),
+            canary_repo: self.canary_repo.clone(),
+            hostname: self.hostname.clone(),
+        }
+    }
+}
+
+/// What applying a plan would do for one account.
+pub struct AccountPreview {
+    name: String,
+    steps: Vec<String>,
+    /// Changed files, each with its content before and after this account.
+    files: Vec<(PathBuf, String, String)>,
+}
+
+/// File contents as the preview has changed them so far, so that each
+/// account's changes build on the ones before it.
+#[derive(Default)]
+struct Files(HashMap<PathBuf, String>);
+
+impl Files {
+    fn read(&self, path: &Path) -> String {
+        match self.0.get(path) {
+            Some(content) => content.clone(),
+            None => fs::read_to_string(path).unwrap_or_default(),
+        }
+    }
+
+    fn change(&mut self, preview: &mut AccountPreview, path: PathBuf, after: Option<String>) {
+        let before = self.read(&path);
+        if let Some(after) = after.filter(|after| *after != before) {
+            self.0.insert(path.clone(), after.clone());
+            preview.files.push((path, before, after));
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Plan> {
+    let content = fs::read_to_string(path)?;
+    toml::from_str(&content)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+/// How `setup --stdin` and `apply --stdin` read account records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum RecordFormat {
+    /// One record, or an array of them.
+    Json,
+    /// One record per line.
+    JsonLines,
+}
+
+/// A record `read_records` couldn't use.
+pub struct Rejection {
+    /// 1-based position in the input.
+    pub index: usize,
+    pub name: Option<String>,
+    pub error: String,
+}
+
+impl Rejection {
+    pub fn describe(&self) -> String {
+        match &self.name {
+            Some(name) => format!("record {} ({}): {}", self.index, name, self.error),
+            None => format!("record {}: {}", self.index, self.error),
+        }
+    }
+}
+
+/// Account records with the fields of a plan's `[[accounts]]`, as a plan;
+/// records that don't parse, or repeat an earlier record's name, are
+/// returned on their own. `host` can be left out, for `setup`'s default.
+/// Fails only when the input as a whole isn't JSON.
+#[cfg(feature = "json")]
+pub fn read_records(content: &str, format: RecordFormat) -> Result<(Plan, Vec<Rejection>)> {
+    let mut values = Vec::new();
+    let mut rejected = Vec::new();
+    match format {
+        RecordFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(content)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("stdin is not JSON: {}", e)))?;
+            match value {
+                serde_json::Value::Array(items) => values.extend(items.into_iter().map(Ok)),
+                value => values.push(Ok(value)),
+            }
+        }
+        RecordFormat::JsonLines => {
+            for line in content.lines().filter(|line| !line.trim().is_empty()) {
+                values.push(serde_json::from_str(line).map_err(|e| e.to_string()));
+            }
+        }
+    }
+
+    let mut plan = Plan::default();
+    for (i, value) in values.into_iter().enumerate() {
+        let name = value.as_ref().ok().and_then(|value| value.get("name")).and_then(|name| name.as_str()).map(str::to_string);
+        let reject = |error: String| Rejection { index: i + 1, name: name.clone(), error };
+        let value = value.map(|mut value| {
+            if let (Some(record), Some(name)) = (value.as_object_mut(), &name) {
+                let hostname = record.get("hostname").and_then(|hostname| hostname.as_str()).unwrap_or(PROVIDER_HOST);
+                let host = crate::account::default_host(hostname, name);
+                record.entry("host").or_insert(serde_json::Value::String(host));
+            }
+            value
+        });
+        let record = value.and_then(|value| serde_json::from_value::<PlanAccount>(value).map_err(|e| e.to_string()));
+        match record {
+            Err(e) => rejected.push(reject(e)),
+            Ok(account) if plan.accounts.iter().any(|earlier| earlier.name == account.name) => {
+                rejected.push(reject("an earlier record has the same name".to_string()));
+            }
+            Ok(account) => plan.accounts.push(account),
+        }
+    }
+    Ok((plan, rejected))
+}
+
+impl GitAccountManager {
+    /// The current accounts as a plan, including disabled ones.
+    pub fn export_plan(&self) -> Plan {
+        Plan {
+            settings: PlanSettings {
+                managed_ssh_config: self.settings.managed_ssh_config,
+                gitconfig_export_dir: self.settings.gitconfig_export_dir.as_deref().map(|dir| portable(&self.home_dir, dir)),
+                key_filename: self.settings.key_filename.clone(),
+                comment_prefix: self.settings.comment_prefix.clone(),
+                no_global_edit: self.settings.no_global_edit,
+            },
+            accounts: self
+                .sorted_accounts(true)
+                .into_iter()
+                .map(|account| PlanAccount::from_account(&self.home_dir, account))
+                .collect(),
+        }
+    }
+
+    /// The failure of one account that would fail every account after it
+    /// too: a file all accounts share that can't be written.
+    fn systemic_failure(&self, result: &Result<SetupReport>) -> Option<String> {
+        let report = match result {
+            Err(e) if fsutil::is_blocked(e) => return Some(e.to_string()),
+            Err(_) => return None,
+            Ok(report) => report,
+        };
+        let shared = [("includeIf", self.global_gitconfig_path()), ("ssh config", self.ssh_config_path())];
+        report.steps.iter().find_map(|(step, status)| match status {
+            StepStatus::Failed(reason) => shared
+                .iter()
+                .any(|(shared_step, path)| step == shared_step && reason.contains(&format!(" {}: ", path.display())))
+                .then(|| reason.clone()),
+            _ => None,
+        })
+    }
+
+    /// Sets up every account in `plan` that doesn't exist yet, then their
+    /// exclusions, which can name accounts later in the plan. Without
+    /// `keep_going` it stops at the first account that can't be set up;
+    /// with it that account is reported and left out, unless the failure
+    /// would repeat for every later account, which stops the run. Ends with
+    /// a table of each account's outcome, or all the setup reports as JSON.
+    pub fn apply_plan(&mut self, plan: Plan, keep_going: bool, format: OutputFormat) -> Result<bool> {
+        if plan.settings.managed_ssh_config {
+            self.settings.managed_ssh_config = true;
+        }
+        if plan.settings.no_global_edit {
+            self.settings.no_global_edit = true;
+        }
+        if let Some(dir) = &plan.settings.gitconfig_export_dir {
+            self.settings.gitconfig_export_dir = Some(self.home_dir.join(dir));
+        }
+        if let Some(template) = &plan.settings.key_filename {
+            keys::validate_key_filename(template)?;
+            self.settings.key_filename = Some(template.clone());
+        }
+        if let Some(prefix) = &plan.settings.comment_prefix {
+            keys::validate_comment_prefix(prefix)?;
+            self.settings.comment_prefix = Some(prefix.clone());
+        }
+
+        // Every account's setup edits these; they're written once, at the end.
+        let shared = [self.ssh_config_path(), self.home_dir.join(".ssh/config"), self.global_gitconfig_path()];
+        let (outcomes, systemic) = fsutil::batch(&shared, || self.apply_accounts(&plan, keep_going, format))?;
+        let succeeded = outcomes.iter().all(|(_, outcome)| outcome.succeeded());
+
+        match format {
+            #[cfg(feature = "json")]
+            OutputFormat::Json => {
+                let accounts: Vec<serde_json::Value> = outcomes.iter().map(|(name, outcome)| outcome.to_json(name)).collect();
+                report::print_json(&serde_json::json!({ "accounts": accounts, "systemic_failure": systemic }))?;
+            }
+            #[cfg(not(feature = "json"))]
+            OutputFormat::Json => return Err(report::no_json()),
+            OutputFormat::Text => {
+                if let Some(reason) = &systemic {
+                    let skipped = outcomes.iter().filter(|(_, outcome)| matches!(outcome, Applied::NotAttempted)).count();
+                    // The hint, if any, is in the table.
+                    println!("systemic failure: {}", reason.split_once("; ").map_or(reason.as_str(), |(error, _)| error));
+                    println!("Every account needs that file, so the remaining {} account(s) were not attempted", skipped);
+                }
+                print_outcomes(&outcomes);
+                let count = |applied: fn(&Applied) -> bool| outcomes.iter().filter(|(_, outcome)| applied(outcome)).count();
+                let mut summary = vec![format!("{} set up", count(|outcome| outcome.succeeded() && !matches!(outcome, Applied::AlreadySetUp)))];
+                let already = count(|outcome| matches!(outcome, Applied::AlreadySetUp));
+                if already > 0 {
+                    summary.push(format!("{} already set up", already));
+                }
+                let attempted: Vec<&str> = outcomes
+                    .iter()
+                    .filter(|(_, outcome)| !outcome.succeeded() && !matches!(outcome, Applied::NotAttempted))
+                    .map(|(name, _)| *name)
+                    .collect();
+                if !attempted.is_empty() {
+                    summary.push(format!("{} failed ({})", attempted.len(), attempted.join(", ")));
+                }
+                let not_attempted = count(|outcome| matches!(outcome, Applied::NotAttempted));
+                if not_attempted > 0 {
+                    summary.push(format!("{} not attempted", not_attempted));
+                }
+                println!("Applied: {}", summary.join(", "));
+            }
+        }
+        if self.strict {
+            let problems: Vec<String> = outcomes
+                .iter()
+                .filter_map(|(name, outcome)| match outcome {
+                    Applied::SetUp(report) => Some(report.strict_problems().into_iter().map(move |problem| format!("{}: {}", name, problem))),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            if succeeded && !problems.is_empty() {
+                return Err(strict_warnings(problems));
+            }
+        }
+        Ok(succeeded)
+    }
+
+    /// `apply_plan`'s setups and exclusions, with the outcome of each
+    /// account and the systemic failure that stopped them, if one did.
+    fn apply_accounts<'a>(&mut self, plan: &'a Plan, keep_going: bool, format: OutputFormat) -> Result<(Outcomes<'a>, Option<String>)> {
+        let no_agent = self.no_agent;
+        let mut outcomes: Vec<(&str, Applied)> = Vec::new();
+        let mut systemic = None;
+        for account in &plan.accounts {
+            if systemic.is_some() {
+                outcomes.push((&account.name, Applied::NotAttempted));
+                continue;
+            }
+            if self.accounts.contains_key(&account.name) {
+                println!("{}: already set up, skipping", account.name);
+                outcomes.push((&account.name, Applied::AlreadySetUp));
+                continue;
+            }
+            self.no_agent = no_agent || account.no_agent;
+            let result = self.setup_account(&account.name, &account.email, &account.codebase_dir, &account.host, account.options(&self.home_dir));
+            self.no_agent = no_agent;
+
+            systemic = self.systemic_failure(&result);
+            match result {
+                Err(e) if !keep_going => return Err(Error::new(e.kind(), format!("{}: {}", account.name, e))),
+                Err(e) => {
+                    println!("error: {}: {}", account.name, e);
+                    outcomes.push((&account.name, Applied::Failed(e.to_string())));
+                }
+                Ok(report) => {
+                    if matches!(format, OutputFormat::Text) {
+                        report.print();
+                    }
+                    outcomes.push((&account.name, Applied::SetUp(report)));
+                }
+            }
+        }
+
+        let failed: Vec<&str> = outcomes.iter().filter(|(_, outcome)| !outcome.succeeded()).map(|(name, _)| *name).collect();
+        for account in plan.accounts.iter().filter(|account| !failed.contains(&account.name.as_str())) {
+            for exclusion in &account.exclusions {
+                let dir = self.home_dir.join(&exclusion.dir);
+                if self.accounts[&account.name].exclusions.iter().any(|existing| existing.dir == dir) {
+                    continue;
+                }
+                self.exclude_dir(&account.name, &dir, &exclusion.account)
+                    .map_err(|e| Error::new(e.kind(), format!("{}: {}", account.name, e)))?;
+            }
+        }
+        Ok((outcomes, systemic))
+    }
+}
+
+/// What `apply` did with one account of the plan.
+enum Applied {
+    SetUp(SetupReport),
+    AlreadySetUp,
+    /// Setup stopped with this error before the account was recorded.
+    Failed(String),
+    /// Left alone after a systemic failure.
+    NotAttempted,
+}
+
+impl Applied {
+    fn succeeded(&self) -> bool {
+        match self {
+            Applied::SetUp(report) => report.config_written(),
+            Applied::AlreadySetUp => true,
+            Applied::Failed(_) | Applied::NotAttempted => false,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Applied::SetUp(report) if report.config_written() => "set up",
+            Applied::SetUp(_) => "incomplete",
+            Applied::AlreadySetUp => "already set up",
+            Applied::Failed(_) => "failed",
+            Applied::NotAttempted => "not attempted",
+        }
+    }
+
+    /// The STEP and HINT columns: the first step that failed and what to
+    /// do about it.
+    fn failure(&self, account: &str) -> (String, String) {
+        match self {
+            Applied::SetUp(report) => match report.first_failure() {
+                Some((step, reason)) => (step.to_string(), failure_hint(account, step, reason)),
+                None => (String::new(), String::new()),
+            },
+            Applied::Failed(error) => (String::new(), format!("{}; fix that and run apply again", error)),
+            Applied::AlreadySetUp | Applied::NotAttempted => (String::new(), String::new()),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn to_json(&self, account: &str) -> serde_json::Value {
+        let mut value = match self {
+            Applied::SetUp(report) => report.to_json(),
+            Applied::Failed(error) => serde_json::json!({ "account": account, "error": error }),
+            Applied::AlreadySetUp | Applied::NotAttempted => serde_json::json!({ "account": account }),
+        };
+        value["result"] = self.label().into();
+        value
+    }
+}
+
+fn failure_hint(account: &str, step: &str, reason: &str) -> String {
+    // Steps that failed on a file they couldn't write carry their own hint.
+    if let Some((_, hint)) = reason.split_once("; ") {
+        return hint.to_string();
+    }
+    match step {
+        CONNECTIVITY_STEP => format!("add the public key on {}, then run multigit test {}", PROVIDER_HOST, account),
+        LOGIN_STEP => format!("check the token with multigit token status {}", account),
+        KNOWN_HOSTS_STEP => "run multigit doctor --complete-deferred".to_string(),
+        _ => reason.to_string(),
+    }
+}
+
+/// What `apply` did with each account of the plan, in plan order.
+type Outcomes<'a> = Vec<(&'a str, Applied)>;
+
+fn print_outcomes(outcomes: &[(&str, Applied)]) {
+    let mut rows = vec![["ACCOUNT".to_string(), "RESULT".to_string(), "STEP".to_string(), "HINT".to_string()]];
+    for (account, outcome) in outcomes {
+        let (step, hint) = outcome.failure(account);
+        rows.push([account.to_string(), outcome.label().to_string(), step, hint]);
+    }
+    print_table(&rows);
+}
+
+impl GitAccountManager {
+    /// What `apply_plan` would do, account by account, worked out on a copy
+    /// of the current setup. Nothing is written, and no key is generated.
+    pub fn preview_plan(&self, plan: &Plan) -> Result<Vec<AccountPreview>> {
+        let mut sim = self.clone();
+        if plan.settings.managed_ssh_config {
+            sim.settings.managed_ssh_config = true;
+        }
+        if plan.settings.no_global_edit {
+            sim.settings.no_global_edit = true;
+        }
+        if let Some(dir) = &plan.settings.gitconfig_export_dir {
+            sim.settings.gitconfig_export_dir = Some(sim.home_dir.join(dir));
+        }
+        if let Some(template) = &plan.settings.key_filename {
+            keys::validate_key_filename(template)?;
+            sim.settings.key_filename = Some(template.clone());
+        }
+
+        let mut files = Files::default();
+        let mut previews = Vec::new();
+        let mut added = Vec::new();
+        for account in &plan.accounts {
+            let mut preview = AccountPreview { name: account.name.clone(), steps: Vec::new(), files: Vec::new() };
+            if sim.accounts.contains_key(&account.name) {
+                preview.steps.push("already set up, skipping".to_string());
+                previews.push(preview);
+                continue;
+            }
+
+            let options = account.options(&sim.home_dir);
+            let (keychain, hooks_path) = sim
+                .validate_setup(&account.name, &account.codebase_dir, &account.host, &options)
+                .map_err(|e| Error::new(e.kind(), format!("{}: {}", account.name, e)))?;
+            let glob = split_dir_glob(&sim.home_dir, &account.codebase_dir)?;
+            let codebase_dir = match &glob {
+                Some((base, _)) => base.clone(),
+                None => sim.home_dir.join(&account.codebase_dir),
+            };
+            let key = match (&options.existing_key, &options.pkcs11_provider) {
+                (_, Some(provider)) => {
+                    preview.steps.push(format!("use the keys of PKCS#11 library {}", provider.display()));
+                    PathBuf::new()
+                }
+                (Some(path), None) => {
+                    preview.steps.push(format!("use existing key {}", path.display()));
+                    path.clone()
+                }
+                (None, None) => {
+                    // Probing the provider is left to the apply itself.
+                    let key_type = match options.key_type.as_deref() {
+                        Some(keys::AUTO_KEY_TYPE) => {
+                            preview.steps.push(format!("pick the key type {} accepts (ed25519 unless it doesn't)", options.hostname.as_deref().unwrap_or(PROVIDER_HOST)));
+                            DEFAULT_KEY_TYPE
+                        }
+                        key_type => key_type.unwrap_or(DEFAULT_KEY_TYPE),
+                    };
+                    let path = sim.default_key_path(&account.name, &account.host, key_type)?;
+                    preview.steps.push(format!("generate key {}", path.display()));
+                    path
+                }
+            };
+            if !(sim.no_agent || account.no_agent || account.pkcs11_provider.is_some()) {
+                preview.steps.push("add the key to the ssh agent".to_string());
+            }
+            preview.steps.push("register the account in accounts.toml".to_string());
+
+            let registered = GitAccountBuilder::new(&account.name, &account.email, &key.to_string_lossy(), codebase_dir, &account.host)
+                .no_agent(sim.no_agent || account.no_agent)
+                .options(options, keychain, hooks_path)
+                .dir_pattern(glob.map(|(_, pattern)| pattern))
+                .build()?;
+            if let Some(warning) = sim.add_account(registered.clone()) {
+                preview.steps.push(format!("warning: {}", warning));
+            }
+
+            // The same files, in the same order, as setup writes them.
+            let include_file = sim.include_file_path(&registered);
+            let existing = files.read(&include_file);
+            let after = managed::merge(&existing, &account.name, &sim.include_file_body(&registered)?);
+            files.change(&mut preview, include_file, (after != existing).then_some(after));
+
+            let gitconfig = sim.global_gitconfig_path();
+            if sim.settings.no_global_edit {
+                preview.steps.push(format!("print the includeIf to add to {} (--no-global-edit)", gitconfig.display()));
+            } else {
+                let include_path = registered.codebase_dir_path.join(".gitconfig");
+                let after = sim.global_gitconfig_update(&account.name, &registered.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy(), &files.read(&gitconfig));
+                files.change(&mut preview, gitconfig, after);
+            }
+
+            if sim.settings.managed_ssh_config {
+                let main_config = sim.home_dir.join(".ssh/config");
+                let after = sim.ssh_include_update(&files.read(&main_config));
+                files.change(&mut preview, main_config, after);
+            }
+            let ssh_config = sim.ssh_config_path();
+            let after = sim.ssh_config_update(&account.name, &account.host, &files.read(&ssh_config))?;
+            files.change(&mut preview, ssh_config, after);
+
+            added.push(account.name.clone());
+            previews.push(preview);
+        }
+
+        for account in &plan.accounts {
+            if !added.contains(&account.name) {
+                continue;
+            }
+            let preview = previews.iter_mut().find(|preview| preview.name == account.name).expect("every account has a preview");
+            for exclusion in &account.exclusions {
+                let dir = sim
+                    .check_exclusion(&account.name, Path::new(&exclusion.dir), &exclusion.account)
+                    .map_err(|e| Error::new(e.kind(), format!("{}: {}", account.name, e)))?;
+                preview.steps.push(format!("hand {} to account {}", dir.display(), exclusion.account));
+                let owner = sim.accounts.get_mut(&account.name).expect("previewed accounts are registered");
+                owner.exclusions.push(Exclusion { dir, account: exclusion.account.clone() });
+            }
+            if !account.exclusions.is_empty() && !sim.settings.no_global_edit {
+                let owner = &sim.accounts[&account.name];
+                let gitconfig = sim.global_gitconfig_path();
+                let include_path = owner.codebase_dir_path.join(".gitconfig");
+                let after = sim.global_gitconfig_update(&owner.name, &owner.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy(), &files.read(&gitconfig));
+                files.change(preview, gitconfig, after);
+            }
+        }
+        Ok(previews)
+    }
+}
+
+pub fn print_preview(previews: &[AccountPreview]) {
+    for preview in previews {
+        println!("== {} ==", preview.name);
+        for step in &preview.steps {
+            println!("{}", step);
+        }
+        for (path, before, after) in &preview.files {
+            let verb = if before.is_empty() { "create" } else { "change" };
+            println!("{} {}:", verb, path.display());
+            print!("{}", diff::unified(before, after));
+        }
+        println!();
+    }
+    println!("Dry run; nothing was changed.");
+}
+
+/// Writes `plan` to `output`, or prints it.
+pub fn write(plan: &Plan, output: Option<&PathBuf>) -> Result<()> {
+    let content = toml::to_string_pretty(plan).map_err(Error::other)?;
+    match output {
+        Some(path) => fsutil::write_atomic(path, content.as_bytes()),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn records_without_a_host_get_setups_default_alias() {
+        let records = r#"[
+            {"name": "work", "email": "work@example.com", "codebase_dir": "code/work"},
+            {"name": "lab", "email": "lab@example.com", "codebase_dir": "code/lab", "hostname": "gitlab.com"},
+            {"name": "own", "email": "own@example.com", "codebase_dir": "code/own", "host": "mine"},
+            {"email": "nameless@example.com", "codebase_dir": "code/nameless"}
+        ]"#;
+        let (plan, rejected) = read_records(records, RecordFormat::Json).unwrap();
+        let hosts: Vec<&str> = plan.accounts.iter().map(|account| account.host.as_str()).collect();
+        assert_eq!(hosts, ["github.com-work", "gitlab.com-lab", "mine"]);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].index, 4);
+        assert!(rejected[0].error.contains("missing field `name`"), "{}", rejected[0].error);
+    }
+
+    #[test]
+    fn dry_run_previews_every_account_and_writes_nothing() {
+        let home = TempHome::new();
+        home.write(".gitconfig", "[user]\n    name = Me\n");
+        let plan: Plan = toml::from_str(
+            "[[accounts]]\nname = \"work\"\nemail = \"me@work.example\"\nhost = \"github.com-work\"\ncodebase_dir = \"code/work\"\n\n\
+             [[accounts]]\nname = \"home\"\nemail = \"me@home.example\"\nhost = \"github.com-home\"\ncodebase_dir = \"code/home\"\n",
+        )
+        .unwrap();
+        let before = testutil::tree(&home.path);
+
+        let previews = home.manager().preview_plan(&plan).unwrap();
+
+        assert_eq!(testutil::tree(&home.path), before);
+        assert_eq!(previews.iter().map(|preview| preview.name.as_str()).collect::<Vec<_>>(), ["work", "home"]);
+        for preview in &previews {
+            let name = &preview.name;
+            assert!(preview.steps.contains(&format!("generate key {}", home.join(format!(".ssh/id_ed25519_{}", name)).display())));
+            let changed: Vec<&PathBuf> = preview.files.iter().map(|(path, _, _)| path).collect();
+            assert_eq!(changed, [&home.join(format!("code/{}/.gitconfig", name)), &home.join(".gitconfig"), &home.join(".ssh/config")]);
+            let (_, _, ssh_config) = &preview.files[2];
+            assert!(ssh_config.contains(&format!("Host github.com-{}\n", name)));
+        }
+        let (_, before, after) = &previews[1].files[2];
+        assert!(before.contains("Host github.com-work") && after.contains("Host github.com-work"), "home's preview builds on work's");
+    }
+
+    #[test]
+    fn exported_plan_sets_up_the_same_config_elsewhere() {
+        let original = TempHome::new();
+        let mut manager = original.manager();
+        let options = SetupOptions {
+            pubkey_algorithms: Some("ssh-ed25519".to_string()),
+            git_settings: BTreeMap::from([("fetch.prune".to_string(), "true".to_string())]),
+            ..SetupOptions::default()
+        };
+        // A plan lists accounts by name, so set up in that order.
+        manager.setup_account("home", "me@home.example", "code/home", "github.com-home", SetupOptions { minimal: true, ..SetupOptions::default() }).unwrap();
+        manager.setup_account("work", "me@work.example", "code/work", "github.com-work", options).unwrap();
+
+        let exported = toml::to_string(&manager.export_plan()).unwrap();
+        let fresh = TempHome::new();
+        assert!(fresh.manager().apply_plan(toml::from_str(&exported).unwrap(), false, OutputFormat::Text).unwrap());
+
+        let relocated = |content: String| content.replace(&original.path.display().to_string(), &fresh.path.display().to_string());
+        for file in [".ssh/config", ".gitconfig", "code/work/.gitconfig", "code/home/.gitconfig"] {
+            assert_eq!(fresh.read(file), relocated(original.read(file)), "{}", file);
+        }
+    }
+
+    #[test]
+    fn apply_reads_and_writes_the_shared_files_once() {
+        let home = TempHome::new();
+        let fleet: String = (0..2500).map(|n| format!("Host server{}\n    HostName 10.0.{}.{}\n    User deploy\n\n", n, n / 256, n % 256)).collect();
+        assert_eq!(fleet.lines().count(), 10_000);
+        let ssh_config = home.write(".ssh/config", &fleet);
+        let gitconfig = home.write(".gitconfig", "[user]\n    name = Me\n");
+        let accounts: String = (0..10)
+            .map(|n| format!("[[accounts]]\nname = \"acct{n}\"\nemail = \"acct{n}@example.com\"\nhost = \"github.com-acct{n}\"\ncodebase_dir = \"code/acct{n}\"\n\n"))
+            .collect();
+        let plan: Plan = toml::from_str(&accounts).unwrap();
+
+        let mut manager = home.manager();
+        assert!(manager.apply_plan(plan, false, OutputFormat::Text).unwrap());
+
+        assert_eq!(fsutil::disk_io(&ssh_config), (1, 1));
+        assert_eq!(fsutil::disk_io(&gitconfig), (1, 1));
+        let written = home.read(".ssh/config");
+        assert!(written.starts_with(&fleet));
+        for n in 0..10 {
+            assert!(written.contains(&format!("Host github.com-acct{}\n", n)));
+            assert!(home.read(".gitconfig").contains(&format!("code/acct{}/", n)));
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Small OS-specific queries.
+
+/// Whether the process runs with an effective uid of 0.
+#[cfg(unix)]
+pub fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_root() -> bool {
+    false
+}
+
+pub fn is_macos() -> bool {
+    cfg!(target_os = "macos")
+}
+
+/// Major and minor version of git, from `git --version`.
+pub fn git_version() -> Option<(u32, u32)> {
+    let output = std::process::Command::new("git").arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    // git version 2.39.2 (Apple Git-143)
+    let mut parts = version.split_whitespace().nth(2)?.split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor))
+}
+
+/// Whether `git-lfs` runs, as the `filter "lfs"` commands need.
+pub fn git_lfs_installed() -> bool {
+    std::process::Command::new("git-lfs").arg("version").output().is_ok_and(|output| output.status.success())
+}
+
+/// Opens `url` in the default browser: `open` on macOS, `start` on
+/// Windows, `wslview` under WSL and `xdg-open` elsewhere.
+pub fn open_url(url: &str) -> std::io::Result<()> {
+    let mut command = if is_macos() {
+        std::process::Command::new("open")
+    } else if cfg!(windows) {
+        let mut command = std::process::Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else if crate::wsl::is_wsl() {
+        std::process::Command::new("wslview")
+    } else {
+        std::process::Command::new("xdg-open")
+    };
+    let status = command.arg(url).stdin(std::process::Stdio::null()).status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("couldn't open {} in a browser", url)));
+    }
+    Ok(())
+}
+
+/// Major and minor macOS version, from `sw_vers`.
+pub fn macos_version() -> Option<(u32, u32)> {
+    if !is_macos() {
+        return None;
+    }
+    let output = std::process::Command::new("sw_vers").arg("-productVersion").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    let mut parts = version.trim().split('.').map(|part| part.parse::<u32>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor))
+}
+
+#[cfg(unix)]
+pub fn euid() -> u32 {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() }
+}
+
+/// uid and primary gid of the named user.
+#[cfg(unix)]
+pub fn lookup_user(name: &str) -> Option<(u32, u32)> {
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: `name` is NUL-terminated. getpwnam returns null or a pointer to
+    // static storage, which is read before any other passwd call.
+    unsafe {
+        let entry = libc::getpwnam(name.as_ptr());
+        if entry.is_null() {
+            return None;
+        }
+        Some(((*entry).pw_uid, (*entry).pw_gid))
+    }
+}
+
+/// Home directory of the named user, from the passwd database.
+#[cfg(unix)]
+pub fn user_home(name: &str) -> Option<std::path::PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let name = std::ffi::CString::new(name).ok()?;
+    // SAFETY: as in `lookup_user`; `pw_dir` is copied out before any other
+    // passwd call.
+    unsafe {
+        let entry = libc::getpwnam(name.as_ptr());
+        if entry.is_null() || (*entry).pw_dir.is_null() {
+            return None;
+        }
+        let dir = std::ffi::CStr::from_ptr((*entry).pw_dir);
+        Some(std::path::PathBuf::from(std::ffi::OsStr::from_bytes(dir.to_bytes())))
+    }
+}
+
+/// Whether the current user may write to the existing file at `path`.
+#[cfg(unix)]
+pub fn is_writable(path: &std::path::Path) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is NUL-terminated and outlives the call.
+    unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn is_writable(path: &std::path::Path) -> bool {
+    std::fs::metadata(path).map_or(true, |metadata| !metadata.permissions().readonly())
+}
+
+/// Sends SIGKILL to the process `pid`, e.g. a child that ran past its
+/// deadline.
+#[cfg(unix)]
+pub fn kill(pid: u32) {
+    // SAFETY: kill only sends a signal; a pid that has exited is an error
+    // we ignore.
+    unsafe {
+        libc::kill(pid as libc::pid_t, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn kill(_pid: u32) {}
+
+static INTERRUPTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// While alive, Ctrl-C only sets a flag that `interrupted` reads, so a
+/// long walk can stop between directories and report what it found.
+/// Dropping it puts back the default, for the prompts that may follow.
+pub struct InterruptGuard;
+
+impl InterruptGuard {
+    #[cfg(unix)]
+    pub fn install() -> Self {
+        INTERRUPTED.store(false, std::sync::atomic::Ordering::Relaxed);
+        // SAFETY: the handler only stores to an atomic, which is
+        // async-signal-safe.
+        unsafe {
+            libc::signal(libc::SIGINT, on_interrupt as *const () as libc::sighandler_t);
+        }
+        InterruptGuard
+    }
+
+    #[cfg(not(unix))]
+    pub fn install() -> Self {
+        InterruptGuard
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        INTERRUPTED.store(false, std::sync::atomic::Ordering::Relaxed);
+        // SAFETY: restores the default disposition of SIGINT.
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(std::sync::atomic::Ordering::Relaxed)
+}
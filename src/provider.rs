@@ -0,0 +1,92 @@
+//! `setup --provider`: the ssh hostname and key type an account on a hosted
+//! git provider needs, with the Host alias following from the hostname, so
+//! only the account's own details have to be typed. `--hostname`, `--host`
+//! and `--key-type` still win, e.g. for a self-hosted GitLab.
+
+use crate::account::PROVIDER_HOST;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Provider {
+    Github,
+    Gitlab,
+    Bitbucket,
+    /// Azure DevOps Services.
+    Azure,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 4] = [Provider::Github, Provider::Gitlab, Provider::Bitbucket, Provider::Azure];
+
+    /// The hosted provider at `hostname`, `None` for a self-hosted server.
+    pub fn from_hostname(hostname: &str) -> Option<Provider> {
+        Provider::ALL.into_iter().find(|provider| provider.hostname().eq_ignore_ascii_case(hostname))
+    }
+
+    /// Where ssh connects. All of them take the `git` user the remotes
+    /// and Host blocks use; Azure DevOps too since it moved to
+    /// ssh.dev.azure.com.
+    pub fn hostname(self) -> &'static str {
+        match self {
+            Provider::Github => PROVIDER_HOST,
+            Provider::Gitlab => "gitlab.com",
+            Provider::Bitbucket => "bitbucket.org",
+            Provider::Azure => "ssh.dev.azure.com",
+        }
+    }
+
+    /// Key type to generate when `--key-type` is left out, for providers
+    /// that don't take the default ed25519. Azure DevOps only accepts RSA.
+    pub fn key_type(self) -> Option<&'static str> {
+        match self {
+            Provider::Azure => Some("rsa"),
+            _ => None,
+        }
+    }
+
+    /// Whether RSA keys only authenticate with SHA-2 signatures, GitHub
+    /// having refused SHA-1 `ssh-rsa` since March 2022.
+    pub fn refuses_sha1_rsa(self) -> bool {
+        self == Provider::Github
+    }
+}
+
+/// The page where the provider at `hostname` takes a new ssh key, or `None`
+/// for one it isn't known where that page is, such as a self-hosted one.
+pub fn ssh_keys_url(hostname: &str) -> Option<&'static str> {
+    let provider = Provider::from_hostname(hostname)?;
+    Some(match provider {
+        Provider::Github => "https://github.com/settings/keys",
+        Provider::Gitlab => "https://gitlab.com/-/user_settings/ssh_keys",
+        Provider::Bitbucket => "https://bitbucket.org/account/settings/ssh-keys/",
+        Provider::Azure => "https://dev.azure.com/_usersSettings/keys",
+    })
+}
+
+/// Whether a repository's https URL on `hostname` has the path of its ssh
+/// one, so an insteadOf can send it through the alias. Azure DevOps serves
+/// https from dev.azure.com with `_git` in the path.
+pub fn https_matches_ssh(hostname: &str) -> bool {
+    !hostname.eq_ignore_ascii_case(Provider::Azure.hostname())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_provider_has_its_ssh_keys_page() {
+        let pages: Vec<Option<&str>> = Provider::ALL.iter().map(|provider| ssh_keys_url(provider.hostname())).collect();
+        assert_eq!(
+            pages,
+            [
+                Some("https://github.com/settings/keys"),
+                Some("https://gitlab.com/-/user_settings/ssh_keys"),
+                Some("https://bitbucket.org/account/settings/ssh-keys/"),
+                Some("https://dev.azure.com/_usersSettings/keys"),
+            ]
+        );
+        assert_eq!(ssh_keys_url("GitHub.com"), Some("https://github.com/settings/keys"));
+        assert_eq!(ssh_keys_url("gitlab.example.com"), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! `prune --remotes`: repositories whose remotes still go through the Host
+//! alias of an account that no longer exists, which ssh resolves as a plain
+//! (and unknown) hostname.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::account::GitAccountManager;
+use crate::discover::{self, Cancel, RemoteUrl, Stop};
+use crate::git_url;
+use crate::keys::prompt;
+use crate::platform::InterruptGuard;
+use crate::ssh_config;
+use crate::store;
+
+struct StaleRemote {
+    repo: PathBuf,
+    remote: RemoteUrl,
+}
+
+impl GitAccountManager {
+    /// Hosts a remote can use without being stale: the providers, every
+    /// account's alias (disabled ones included) and anything a Host block
+    /// other than a catch-all applies to.
+    fn host_is_known(&self, host: &str, hostnames: &[String], ssh_blocks: &[ssh_config::HostBlock]) -> bool {
+        hostnames.iter().any(|hostname| hostname.eq_ignore_ascii_case(host))
+            || self.accounts.values().any(|account| account.host.eq_ignore_ascii_case(host))
+            || ssh_blocks
+                .iter()
+                .filter(|block| block.patterns.iter().any(|p| p != "*"))
+                .any(|block| block.matches(host))
+    }
+
+    fn ssh_host_blocks(&self) -> Vec<ssh_config::HostBlock> {
+        let mut blocks = Vec::new();
+        for path in [self.home_dir.join(".ssh/config"), store::managed_ssh_config_path(&self.home_dir)] {
+            if let Ok(content) = fs::read_to_string(&path) {
+                let lines: Vec<&str> = content.lines().collect();
+                blocks.extend(ssh_config::host_blocks(&lines));
+            }
+        }
+        blocks
+    }
+
+    /// The provider hostname `host` is a `<hostname>-<name>` alias of, the
+    /// longest when several prefix it.
+    fn alias_hostname<'a>(host: &str, hostnames: &'a [String]) -> Option<&'a str> {
+        hostnames
+            .iter()
+            .filter(|hostname| host.strip_prefix(hostname.as_str()).is_some_and(|rest| rest.len() > 1 && rest.starts_with('-')))
+            .max_by_key(|hostname| hostname.len())
+            .map(String::as_str)
+    }
+
+    /// The stale remotes found before `cancel` stopped the search, if it
+    /// did, and why.
+    fn stale_remotes(&self, extra_roots: &[PathBuf], cancel: Cancel) -> (Vec<StaleRemote>, Option<Stop>) {
+        let ssh_blocks = self.ssh_host_blocks();
+        let hostnames = self.provider_hostnames();
+        let mut roots: Vec<PathBuf> = self.sorted_accounts(true).iter().map(|account| account.codebase_dir_path.clone()).collect();
+        roots.extend(extra_roots.iter().cloned());
+
+        let mut seen = HashSet::new();
+        let mut stale = Vec::new();
+        for root in roots {
+            let walked = discover::walk_until(&root, cancel, &mut |repo| {
+                if !seen.insert(repo.path.clone()) {
+                    return;
+                }
+                for remote in discover::remote_urls(&repo.path) {
+                    let Some(url) = git_url::parse(&remote.url) else {
+                        continue;
+                    };
+                    let host = url.host.to_ascii_lowercase();
+                    if Self::alias_hostname(&host, &hostnames).is_some() && !self.host_is_known(&host, &hostnames, &ssh_blocks) {
+                        stale.push(StaleRemote { repo: repo.path.clone(), remote });
+                    }
+                }
+            });
+            if let Err(stop) = walked {
+                return (stale, Some(stop));
+            }
+        }
+        (stale, None)
+    }
+
+    /// Lists remotes that use the Host alias of a removed account and offers
+    /// to point each at its provider directly or at another account's alias.
+    /// `to` (`canonical` or an account name) answers for every remote.
+    /// A search cut short by `timeout` goes on with the remotes it found;
+    /// one stopped with Ctrl-C only lists them.
+    pub fn prune_remotes(&self, extra_roots: &[PathBuf], to: Option<&str>, timeout: Option<Duration>) -> Result<bool> {
+        if let Some(to) = to.filter(|to| *to != "canonical") {
+            if self.accounts.get(to).is_none_or(|account| account.disabled) {
+                return Err(Error::new(ErrorKind::NotFound, format!("--to must be 'canonical' or an enabled account, not '{}'", to)));
+            }
+        }
+
+        let (stale, stopped) = {
+            let _interrupt = InterruptGuard::install();
+            self.stale_remotes(extra_roots, Cancel::after(timeout))
+        };
+        match stopped {
+            Some(Stop::Interrupted) => {
+                for StaleRemote { repo, remote } in &stale {
+                    println!("{}: {}.{} = {}", repo.display(), remote.remote, remote.key, remote.url);
+                }
+                println!("Search interrupted after {} stale remote(s); nothing was rewritten", stale.len());
+                return Ok(false);
+            }
+            Some(Stop::TimedOut) => println!("Search timed out; only the {} stale remote(s) found so far are listed", stale.len()),
+            None if stale.is_empty() => {
+                println!("No remotes use the alias of a removed account.");
+                return Ok(true);
+            }
+            None => {}
+        }
+
+        let accounts = self.sorted_accounts(false);
+        let hostnames = self.provider_hostnames();
+        let mut ok = true;
+        let mut rewritten = 0;
+        for StaleRemote { repo, remote } in &stale {
+            let url = git_url::parse(&remote.url).expect("stale remotes are parsed ssh urls");
+            println!("{}: {}.{} = {}", repo.display(), remote.remote, remote.key, remote.url);
+
+            let hostname = Self::alias_hostname(&url.host.to_ascii_lowercase(), &hostnames).expect("stale remotes use an alias").to_string();
+            let mut choices = vec![("canonical".to_string(), url.with_host(&hostname))];
+            choices.extend(accounts.iter().map(|account| (account.name.clone(), url.with_host(&account.host))));
+
+            let choice = match to {
+                Some(to) => choices.iter().find(|(name, _)| name == to),
+                None => {
+                    let covering = self.account_for_path(repo).map(|account| account.name.as_str());
+                    for (i, (name, new_url)) in choices.iter().enumerate() {
+                        let note = if Some(name.as_str()) == covering { " (covers this repository)" } else { "" };
+                        println!("  {}) {}{}", i + 1, new_url, note);
+                    }
+                    let answer = prompt("Rewrite to (number, Enter to skip)")?;
+                    if answer.is_empty() {
+                        None
+                    } else {
+                        match answer.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| choices.get(i)) {
+                            Some(choice) => Some(choice),
+                            None => {
+                                eprintln!("warning: '{}' is not one of the choices; skipping", answer);
+                                None
+                            }
+                        }
+                    }
+                }
+            };
+            let Some((_, new_url)) = choice else {
+                continue;
+            };
+
+            match discover::set_remote_url(repo, remote, new_url) {
+                Ok(()) => {
+                    println!("  now {}", new_url);
+                    rewritten += 1;
+                }
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ok = false;
+                }
+            }
+        }
+        println!("{} of {} remote(s) rewritten", rewritten, stale.len());
+        Ok(ok && stopped.is_none())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::account::SetupOptions;
+    use crate::testutil::{self, TempHome};
+    use crate::worktree::git;
+
+    fn repo_with_origin(home: &TempHome, repo: &str, url: &str) -> std::path::PathBuf {
+        let repo = home.join(repo);
+        fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]).unwrap();
+        git(&repo, &["remote", "add", "origin", url]).unwrap();
+        repo
+    }
+
+    fn origin(repo: &std::path::Path) -> String {
+        git(repo, &["remote", "get-url", "origin"]).unwrap().unwrap()
+    }
+
+    #[test]
+    fn stale_aliases_of_any_provider_go_back_to_their_own_host() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let options = SetupOptions { hostname: Some("gitlab.com".to_string()), ..SetupOptions::default() };
+        manager.setup_account("lab", "lab@example.com", "code/lab", "gitlab.com-lab", options).unwrap();
+
+        let gone_github = repo_with_origin(&home, "code/work/a", "git@github.com-gone:org/a.git");
+        let gone_gitlab = repo_with_origin(&home, "code/lab/b", "git@gitlab.com-old:org/b.git");
+        let current = repo_with_origin(&home, "code/lab/c", "git@gitlab.com-lab:org/c.git");
+        let canonical = repo_with_origin(&home, "code/lab/d", "git@gitlab.com:org/d.git");
+
+        assert!(manager.prune_remotes(&[], Some("canonical"), None).unwrap());
+        assert_eq!(origin(&gone_github), "git@github.com:org/a.git");
+        assert_eq!(origin(&gone_gitlab), "git@gitlab.com:org/b.git");
+        assert_eq!(origin(&current), "git@gitlab.com-lab:org/c.git");
+        assert_eq!(origin(&canonical), "git@gitlab.com:org/d.git");
+    }
+}
@@ -0,0 +1,101 @@
+//! `reinit`: rebuilds one account's Host block, includeIf and include file
+//! from its stored record, for when they're broken beyond what `sync --fix`
+//! or `doctor --repair` put right. Whatever is in its marked regions is
+//! replaced, edited by hand or not; other accounts' regions aren't touched,
+//! and running it again changes nothing. `--new-key` rotates the key first,
+//! as `key rotate` does.
+
+use std::io::{Error, ErrorKind, Result};
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::managed;
+use crate::ssh_config;
+use crate::state::State;
+use crate::tamper::{self, Resolution, UserSection};
+
+impl GitAccountManager {
+    /// `reinit` for `account_name`, with a newly generated key when
+    /// `new_key`.
+    pub fn reinit(&mut self, account_name: &str, new_key: bool) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{} is disabled; enable it before reinitializing it", account_name)));
+        }
+        let ssh_config_path = self.ssh_config_path();
+        let content = match fsutil::read_to_string(&ssh_config_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(fsutil::blocked(e, "read", &ssh_config_path)),
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        if tamper::block_body(&content, account_name).is_none() && ssh_config::find_host(&ssh_config::host_blocks(&lines), &account.host).is_some() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} has a Host {} block multigit didn't write; remove it first", ssh_config_path.display(), account.host),
+            ));
+        }
+
+        // Undoing edits to the regions is what reinit is for.
+        self.tamper = Some(Resolution::Theirs);
+        self.user_section = UserSection::Overwrite;
+        if new_key {
+            self.rotate_key(account_name, None, None, false)?;
+            println!();
+        }
+
+        let account = &self.accounts[account_name];
+        if self.settings.managed_ssh_config {
+            self.ensure_ssh_include()?;
+        }
+        if let Some(parent) = ssh_config_path.parent() {
+            fsutil::create_dir_all(parent)?;
+        }
+        let block = self.host_block(account)?;
+        fsutil::modify(&ssh_config_path, |content| {
+            let merged = managed::merge(content, account_name, &block);
+            Ok((merged != content).then_some(merged))
+        })?;
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &ssh_config_path);
+        state.save(&self.home_dir)?;
+        println!("Wrote {}'s Host block in {}", account_name, ssh_config_path.display());
+
+        self.restore_include_file(account_name)?;
+        self.rewrite_include_ifs(account_name)?;
+        if self.include_if_order()?.is_some() {
+            self.fix_include_if_order()?;
+        }
+        println!("Reinitialized {}", account_name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn reinit_restores_only_the_named_account() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work", "home"]);
+        let (config, gitconfig, include) = (home.read(".ssh/config"), home.read(".gitconfig"), home.read("code/work/.gitconfig"));
+        let (work_key, home_key) = (manager.accounts["work"].ssh_key.clone(), manager.accounts["home"].ssh_key.clone());
+
+        let corrupted = config.replace(&work_key, "~/.ssh/broken").replace(&home_key, "~/.ssh/mine");
+        home.write(".ssh/config", &corrupted);
+        home.write(".gitconfig", &gitconfig.replace("code/work/.gitconfig", "nowhere/.gitconfig"));
+        home.write("code/work/.gitconfig", &include.replace("work@example.com", "someone@example.com"));
+
+        manager.reinit("work", false).unwrap();
+
+        assert_eq!(home.read(".ssh/config"), config.replace(&home_key, "~/.ssh/mine"));
+        assert_eq!(home.read(".gitconfig"), gitconfig);
+        assert_eq!(home.read("code/work/.gitconfig"), include);
+
+        manager.reinit("work", false).unwrap();
+        assert_eq!(home.read(".ssh/config"), config.replace(&home_key, "~/.ssh/mine"));
+    }
+}
@@ -0,0 +1,161 @@
+//! `relocate`: points an account at a codebase dir that has moved, or with
+//! `--move` moves it there first. The stored path and everything under the
+//! old dir follow, the include file is written in the new dir, and the
+//! account's includeIf region is written again, which drops the entries
+//! for the old location.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::migrate::rebase_in_place;
+use crate::remove::remove_include_file;
+use crate::state::State;
+
+/// The absolute, symlink-free form of `dir`. A dir that doesn't exist yet
+/// is resolved through its parent.
+fn canonical_dir(dir: &Path) -> Result<PathBuf> {
+    if dir.exists() {
+        return fs::canonicalize(dir);
+    }
+    match (dir.parent(), dir.file_name()) {
+        (Some(parent), Some(name)) => Ok(canonical_dir(parent)?.join(name)),
+        _ => Ok(dir.to_path_buf()),
+    }
+}
+
+impl GitAccountManager {
+    /// `relocate`: moves `account_name`'s codebase dir to `new_dir`, on disk
+    /// too with `move_dir`, and rewrites its include file and includeIf.
+    pub fn relocate(&mut self, account_name: &str, new_dir: &Path, move_dir: bool) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{} is disabled; enable it before relocating it", account_name)));
+        }
+        if account.dir_pattern.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} covers a glob, {}; set it up again for another one", account_name, account.dir_display()),
+            ));
+        }
+        let old_dir = account.codebase_dir_path.clone();
+        let new_dir = canonical_dir(new_dir)?;
+        fsutil::utf8(&new_dir)?;
+        if new_dir == old_dir {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{} already uses {}", account_name, new_dir.display())));
+        }
+        if let Some(other) = self.accounts.values().find(|other| other.name != account_name && other.codebase_dir_path == new_dir) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("{} is {}'s codebase dir", new_dir.display(), other.name)));
+        }
+        if move_dir {
+            if new_dir.exists() {
+                return Err(Error::new(ErrorKind::AlreadyExists, format!("{} already exists; --move needs a new path", new_dir.display())));
+            }
+            if new_dir.starts_with(&old_dir) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("{} is inside {}", new_dir.display(), old_dir.display())));
+            }
+            if let Some(parent) = new_dir.parent() {
+                fsutil::create_dir_all(parent)?;
+            }
+            // A rename can't cross filesystems; copying a tree of
+            // repositories is left to the user.
+            fs::rename(&old_dir, &new_dir).map_err(|e| fsutil::blocked(e, "move", &old_dir))?;
+            println!("Moved {} to {}", old_dir.display(), new_dir.display());
+        } else if !new_dir.is_dir() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{} is not a directory; move {} there first, or pass --move", new_dir.display(), old_dir.display()),
+            ));
+        }
+
+        let old_account = account.clone();
+        let account = self.accounts.get_mut(account_name).expect("account looked up above");
+        account.codebase_dir_path = new_dir.clone();
+        for path in [&mut account.hooks_path, &mut account.template_dir].into_iter().flatten() {
+            rebase_in_place(path, &old_dir, &new_dir);
+        }
+        for exclusion in &mut account.exclusions {
+            rebase_in_place(&mut exclusion.dir, &old_dir, &new_dir);
+        }
+        self.save()?;
+
+        let mut state = State::load(&self.home_dir)?;
+        for record in &mut state.written {
+            rebase_in_place(&mut record.path, &old_dir, &new_dir);
+        }
+        state.save(&self.home_dir)?;
+
+        // Left behind when the dir was copied rather than moved.
+        if !move_dir {
+            remove_include_file(&old_account)?;
+        }
+        let account = &self.accounts[account_name];
+        self.setup_local_gitconfig(account)?;
+        let include_path = new_dir.join(".gitconfig");
+        let mut state = State::load(&self.home_dir)?;
+        state.record_write(account_name, &self.include_file_path(account));
+        state.save(&self.home_dir)?;
+        self.setup_global_gitconfig(account_name, fsutil::utf8(&new_dir)?, fsutil::utf8(&include_path)?)?;
+        // Other accounts' exclusions name this account's include file.
+        let excluding: Vec<String> = self
+            .sorted_accounts(false)
+            .iter()
+            .filter(|other| other.exclusions.iter().any(|exclusion| exclusion.account == account_name))
+            .map(|other| other.name.clone())
+            .collect();
+        for name in excluding {
+            let other = &self.accounts[&name];
+            let (dir, path) = (other.codebase_dir_path.to_string_lossy().into_owned(), other.codebase_dir_path.join(".gitconfig"));
+            self.setup_global_gitconfig(&name, &dir, &path.to_string_lossy())?;
+        }
+        self.fix_include_if_order()?;
+        println!("{} now uses {}", account_name, new_dir.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn include_if_and_include_file_follow_the_moved_dir() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        home.write("code/work/repo/README", "hello\n");
+        let include = home.read("code/work/.gitconfig");
+        let new_dir = home.join("projects/work");
+
+        manager.relocate("work", &new_dir, true).unwrap();
+
+        assert!(!home.join("code/work").exists());
+        assert_eq!(home.read("projects/work/repo/README"), "hello\n");
+        assert_eq!(home.read("projects/work/.gitconfig"), include);
+        let gitconfig = home.read(".gitconfig");
+        assert!(gitconfig.contains(&format!(":{}/\"]", new_dir.display())), "{}", gitconfig);
+        assert!(gitconfig.contains(&format!("path = {}", new_dir.join(".gitconfig").display())), "{}", gitconfig);
+        assert!(!gitconfig.contains("code/work"), "{}", gitconfig);
+        let reloaded = GitAccountManager::load(home.path.clone()).unwrap();
+        assert_eq!(reloaded.accounts["work"].codebase_dir_path, new_dir);
+    }
+
+    #[test]
+    fn without_move_the_old_include_file_is_removed() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        fs::create_dir_all(home.join("projects/work")).unwrap();
+
+        manager.relocate("work", &home.join("projects/work"), false).unwrap();
+
+        assert!(!home.join("code/work/.gitconfig").exists());
+        assert!(home.read("projects/work/.gitconfig").contains("email = work@example.com"));
+        let error = manager.relocate("work", &home.join("elsewhere"), false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+}
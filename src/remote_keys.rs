@@ -0,0 +1,184 @@
+//! `key remote-list`: the keys registered on the provider account, matched
+//! against the keys multigit knows locally.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result, Write};
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::github;
+use crate::keys::{self, confirm};
+use crate::state::State;
+
+#[derive(Deserialize)]
+struct RemoteKey {
+    id: u64,
+    key: String,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    created_at: String,
+    /// Not returned by every API version.
+    #[serde(default)]
+    last_used: Option<String>,
+}
+
+enum Origin {
+    /// One of this account's keys.
+    Managed(String),
+    /// Another multigit account's key, or a key elsewhere in `~/.ssh`.
+    KnownOther(String),
+    /// A key multigit set up for this account whose file has since been
+    /// deleted.
+    Deleted(String),
+    Unknown,
+}
+
+/// `SHA256:...` fingerprint of a public key line.
+pub fn fingerprint_of(public_key: &str) -> Option<String> {
+    let mut child = Command::new("ssh-keygen")
+        .args(["-l", "-f", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    writeln!(child.stdin.take()?, "{}", public_key).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).split_whitespace().nth(1).map(str::to_string)
+}
+
+/// Date part of an ISO 8601 timestamp.
+fn date(timestamp: &str) -> &str {
+    timestamp.split('T').next().unwrap_or(timestamp)
+}
+
+impl GitAccountManager {
+    fn origin(&self, account_name: &str, fingerprint: Option<&str>, state: &State) -> Origin {
+        let Some(fingerprint) = fingerprint else {
+            return Origin::Unknown;
+        };
+
+        for account in self.sorted_accounts(true) {
+            for key in account.identity_files() {
+                if keys::fingerprint_hash(key).as_deref() == Some(fingerprint) {
+                    return if account.name == account_name {
+                        Origin::Managed(key.to_string())
+                    } else {
+                        Origin::KnownOther(format!("account {}", account.name))
+                    };
+                }
+            }
+        }
+
+        let deleted = state
+            .keys
+            .iter()
+            .find(|record| record.account == account_name && record.fingerprint == fingerprint && !fs::exists(&record.path).unwrap_or(true));
+        if let Some(record) = deleted {
+            return Origin::Deleted(record.path.clone());
+        }
+
+        let other = fs::read_dir(self.home_dir.join(".ssh"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pub"))
+            .find(|path| fs::read_to_string(path).ok().and_then(|line| fingerprint_of(line.trim())).as_deref() == Some(fingerprint));
+        match other {
+            Some(path) => Origin::KnownOther(path.display().to_string()),
+            None => Origin::Unknown,
+        }
+    }
+
+    /// Lists the account's keys on the provider and what each one is
+    /// locally. With `prune`, deletes the remote keys whose local file
+    /// multigit created and which has since been deleted.
+    pub fn remote_list_keys(&self, account_name: &str, prune: bool, yes: bool) -> Result<bool> {
+        if !self.accounts.contains_key(account_name) {
+            return Err(account_not_found(account_name));
+        }
+        if self.offline {
+            return Err(Error::other("key remote-list needs the network, and this run is offline"));
+        }
+        let (token, _) = self.api_token(Some(account_name)).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "key remote-list needs a token with the read:public_key scope; run multigit token set {} or set {}",
+                    account_name,
+                    github::account_token_var(account_name)
+                ),
+            )
+        })?;
+
+        self.remember_keys(account_name)?;
+        let state = State::load(&self.home_dir)?;
+        let remote: Vec<RemoteKey> = github::get_all("/user/keys", Some(&token), "Listing the account's ssh keys")?;
+        if remote.is_empty() {
+            println!("No ssh keys on the provider account.");
+            return Ok(true);
+        }
+
+        let rows: Vec<(&RemoteKey, Option<String>, Origin)> = remote
+            .iter()
+            .map(|key| {
+                let fingerprint = fingerprint_of(&key.key);
+                let origin = self.origin(account_name, fingerprint.as_deref(), &state);
+                (key, fingerprint, origin)
+            })
+            .collect();
+
+        for (key, fingerprint, origin) in &rows {
+            let status = match origin {
+                Origin::Managed(path) => format!("managed by multigit ({})", path),
+                Origin::KnownOther(what) => format!("known, other ({})", what),
+                Origin::Deleted(path) => format!("managed by multigit, deleted locally ({})", path),
+                Origin::Unknown => "unknown".to_string(),
+            };
+            println!("{}", if key.title.is_empty() { "(untitled)" } else { &key.title });
+            println!("  fingerprint: {}", fingerprint.as_deref().unwrap_or("(unreadable)"));
+            println!("  created:     {}", date(&key.created_at));
+            println!("  last used:   {}", key.last_used.as_deref().map(date).unwrap_or("unknown"));
+            println!("  status:      {}", status);
+        }
+
+        let stale: Vec<&RemoteKey> = rows
+            .iter()
+            .filter(|(_, _, origin)| matches!(origin, Origin::Deleted(_)))
+            .map(|(key, _, _)| *key)
+            .collect();
+        if !prune {
+            if !stale.is_empty() {
+                println!("{} key(s) were deleted locally; `--prune-remote` removes them from the provider.", stale.len());
+            }
+            return Ok(true);
+        }
+        if stale.is_empty() {
+            println!("No remote keys to prune.");
+            return Ok(true);
+        }
+
+        let mut ok = true;
+        for key in stale {
+            if !yes && !confirm(&format!("Delete '{}' from the provider?", key.title))? {
+                continue;
+            }
+            // Deleting needs the admin:public_key scope, which the error explains.
+            match github::delete(&format!("/user/keys/{}", key.id), Some(&token), &format!("Deleting '{}'", key.title)) {
+                Ok(()) => println!("Deleted '{}'", key.title),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    ok = false;
+                }
+            }
+        }
+        Ok(ok)
+    }
+}
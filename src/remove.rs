@@ -0,0 +1,323 @@
+//! Removing accounts: `remove_account`, behind `remove`, takes one
+//! account's managed blocks out of every file multigit writes, and
+//! `uninstall` does that for every
+//! account and then deletes multigit's own files. A setup that fails part
+//! way is rolled back with `roll_back_setup`, which only touches what setup
+//! got to write.
+
+use std::fs;
+use std::io::Result;
+use std::path::{Path, PathBuf};
+
+use crate::account::{GitAccount, GitAccountManager, AGENT_STEP};
+use crate::disable;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::keys::{self, confirm};
+use crate::managed;
+use crate::multiplex;
+use crate::snapshot::snapshots_dir;
+use crate::ssh_config;
+use crate::state::State;
+use crate::store;
+
+/// `content` without `account`'s marked regions and without lines `disable`
+/// commented out for it.
+fn strip_account(content: &str, account: &str) -> String {
+    let stripped = managed::strip(content, |owner| owner == account);
+    let mut out = String::new();
+    for line in stripped.lines() {
+        if disable::disabled_line(line).is_some_and(|(owner, _)| owner == account) {
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// Takes `account`'s region out of its include file, deleting the file, and
+/// the symlink to it, when nothing else is left.
+pub fn remove_include_file(account: &GitAccount) -> Result<()> {
+    let include_path = account.codebase_dir_path.join(".gitconfig");
+    let target = fs::read_link(&include_path).map(|target| include_path.parent().unwrap_or(Path::new("")).join(target));
+    let file = target.as_ref().unwrap_or(&include_path);
+    if let Ok(content) = fs::read_to_string(file) {
+        let stripped = strip_account(&content, &account.name);
+        if stripped.trim().is_empty() {
+            fs::remove_file(file).map_err(|e| fsutil::blocked(e, "remove", file))?;
+            if target.is_ok() {
+                fs::remove_file(&include_path).map_err(|e| fsutil::blocked(e, "remove", &include_path))?;
+            }
+        } else if stripped != content {
+            fsutil::write_atomic(file, stripped.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+impl GitAccountManager {
+    /// Removes `account_name`'s Host block, includeIf, include file and
+    /// records, and the exclusions other accounts hand to it. Keys are
+    /// deleted only with `remove_keys`.
+    pub fn remove_account(&mut self, account_name: &str, remove_keys: bool) -> Result<()> {
+        let account = self.accounts.get(account_name).cloned().ok_or_else(|| account_not_found(account_name))?;
+
+        for path in [self.ssh_config_path(), self.global_gitconfig_path()] {
+            fsutil::modify(&path, |content| {
+                let stripped = strip_account(content, account_name);
+                Ok((stripped != content).then_some(stripped))
+            })?;
+        }
+
+        remove_include_file(&account)?;
+
+        if !self.skips_agent(&account) {
+            for key in account.identity_files() {
+                let removal = keys::remove_from_agent(key);
+                match (removal.from_agent, removal.from_keychain) {
+                    (true, true) => println!("Removed {} from the ssh agent and its passphrase from the macOS keychain", key),
+                    (true, false) => println!("Removed {} from the ssh agent", key),
+                    (false, true) => println!("Removed the passphrase of {} from the macOS keychain", key),
+                    (false, false) => {}
+                }
+            }
+        }
+        multiplex::remove_sockets(&self.home_dir, &account.host);
+        if let Some(path) = account.known_hosts_file.as_ref().filter(|_| account.known_hosts_created) {
+            let shared = self.accounts.values().any(|other| other.name != account_name && other.known_hosts_file.as_ref() == Some(path));
+            if !shared && path.exists() {
+                fs::remove_file(path).map_err(|e| fsutil::blocked(e, "remove", path))?;
+                println!("Removed {}, which setup created for {}", path.display(), account_name);
+            }
+        }
+        if remove_keys {
+            for key in account.identity_files() {
+                for path in [PathBuf::from(key), PathBuf::from(format!("{}.pub", key))] {
+                    if path.exists() {
+                        fs::remove_file(&path).map_err(|e| fsutil::blocked(e, "remove", &path))?;
+                    }
+                }
+            }
+        }
+
+        self.accounts.remove(account_name);
+        let handed_over: Vec<String> = self
+            .accounts
+            .values_mut()
+            .filter(|other| other.exclusions.iter().any(|exclusion| exclusion.account == account_name))
+            .map(|other| {
+                other.exclusions.retain(|exclusion| exclusion.account != account_name);
+                other.name.clone()
+            })
+            .collect();
+        self.save()?;
+        for other in handed_over {
+            let other = &self.accounts[&other];
+            if !other.disabled {
+                let include_path = other.codebase_dir_path.join(".gitconfig");
+                self.setup_global_gitconfig(&other.name, &other.codebase_dir_path.to_string_lossy(), &include_path.to_string_lossy())?;
+            }
+        }
+
+        let mut state = State::load(&self.home_dir)?;
+        state.forget(account_name);
+        state.save(&self.home_dir)?;
+        println!("Removed account {}", account_name);
+        Ok(())
+    }
+
+    /// Undoes a setup of `account_name` that failed part way: the steps in
+    /// `done` that wrote something, the key when `remove_key`, and the
+    /// account's records. Each undo is tried even when an earlier one
+    /// fails; the first error is returned.
+    pub fn roll_back_setup(&mut self, account_name: &str, done: &[&str], remove_key: bool) -> Result<()> {
+        let account = self.accounts.get(account_name).cloned().ok_or_else(|| account_not_found(account_name))?;
+        let mut results = Vec::new();
+        for (step, path) in [("ssh config", self.ssh_config_path()), ("includeIf", self.global_gitconfig_path())] {
+            if done.contains(&step) {
+                results.push(fsutil::modify(&path, |content| {
+                    let stripped = strip_account(content, account_name);
+                    Ok((stripped != content).then_some(stripped))
+                }));
+            }
+        }
+        if done.contains(&"include file") {
+            results.push(remove_include_file(&account));
+        }
+        if done.contains(&AGENT_STEP) {
+            keys::remove_from_agent(&account.ssh_key);
+        }
+        if let Some(path) = account.known_hosts_file.as_ref().filter(|path| account.known_hosts_created && path.exists()) {
+            results.push(fs::remove_file(path).map_err(|e| fsutil::blocked(e, "remove", path)));
+        }
+        if remove_key {
+            for path in [PathBuf::from(&account.ssh_key), PathBuf::from(format!("{}.pub", account.ssh_key))] {
+                if path.exists() {
+                    results.push(fs::remove_file(&path).map_err(|e| fsutil::blocked(e, "remove", &path)));
+                }
+            }
+        }
+
+        self.accounts.remove(account_name);
+        results.push(self.save());
+        results.push(State::load(&self.home_dir).and_then(|mut state| {
+            state.forget(account_name);
+            state.save(&self.home_dir)
+        }));
+        results.into_iter().collect()
+    }
+
+    /// What uninstall deletes that a snapshot doesn't already cover: the
+    /// known_hosts files multigit created.
+    fn uninstall_extra_files(&self) -> Vec<PathBuf> {
+        self.sorted_accounts(true)
+            .iter()
+            .filter(|account| account.known_hosts_created)
+            .filter_map(|account| account.known_hosts_file.clone())
+            .collect()
+    }
+
+    /// Removes every account, the `Include` of the managed ssh config,
+    /// multigit's config directory and its ssh sockets, after taking a
+    /// snapshot of everything it touches that `snapshot restore` puts back.
+    /// The snapshot stays in the data dir.
+    pub fn uninstall(&mut self, remove_keys: bool, yes: bool) -> Result<bool> {
+        let accounts: Vec<String> = self.sorted_accounts(true).iter().map(|account| account.name.clone()).collect();
+        let what = if remove_keys { "accounts and their keys" } else { "accounts" };
+        if !yes && !confirm(&format!("Remove {} {} and multigit's configuration?", accounts.len(), what))? {
+            return Ok(false);
+        }
+
+        let snapshot = self.take_snapshot("uninstall", remove_keys, self.uninstall_extra_files())?;
+
+        for account in &accounts {
+            self.remove_account(account, remove_keys)?;
+        }
+
+        // Blocks of accounts no longer in accounts.toml, and the Include
+        // ahead of the first Host block in managed mode.
+        let managed_ssh_config = store::managed_ssh_config_path(&self.home_dir);
+        let main_ssh_config = self.home_dir.join(".ssh/config");
+        for path in [main_ssh_config.clone(), self.global_gitconfig_path()] {
+            fsutil::modify(&path, |content| {
+                let mut stripped = managed::strip(content, |_| true);
+                if path == main_ssh_config {
+                    let mut lines = stripped.lines().peekable();
+                    let mut out = String::new();
+                    while let Some(line) = lines.next() {
+                        let includes_managed = matches!(ssh_config::directive(line), Some((keyword, value)) if keyword == "include"
+                            && value.split_whitespace().any(|v| ssh_config::expand_tilde(v, &self.home_dir) == managed_ssh_config.to_string_lossy()));
+                        if includes_managed {
+                            if lines.peek().is_some_and(|next| next.trim().is_empty()) {
+                                lines.next();
+                            }
+                            continue;
+                        }
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                    stripped = out;
+                }
+                Ok((stripped != content).then_some(stripped))
+            })?;
+        }
+
+        let config_dir = store::config_dir(&self.home_dir);
+        if config_dir.exists() {
+            fs::remove_dir_all(&config_dir).map_err(|e| fsutil::blocked(e, "remove", &config_dir))?;
+        }
+        println!("Removed {}", config_dir.display());
+        let socket_dir = multiplex::socket_dir(&self.home_dir);
+        if socket_dir.exists() {
+            fs::remove_dir_all(&socket_dir).map_err(|e| fsutil::blocked(e, "remove", &socket_dir))?;
+            println!("Removed {}", socket_dir.display());
+        }
+        self.mark_uninstalled(&snapshot)?;
+        println!(
+            "Kept {} with snapshot {}; undo with `multigit snapshot restore {}`, or delete the directory",
+            snapshots_dir(&self.home_dir).parent().unwrap_or(&self.home_dir).display(),
+            snapshot,
+            snapshot
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::account::{GitAccountManager, SetupOptions};
+    use crate::multiplex;
+    use crate::snapshot::snapshots_dir;
+    use crate::store;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn uninstall_leaves_no_managed_markers() {
+        let home = TempHome::new();
+        let ssh_config = "Host personal\n    HostName example.com\n";
+        let gitconfig = "[user]\n    name = Me\n";
+        home.write(".ssh/config", ssh_config);
+        home.write(".gitconfig", gitconfig);
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        manager.settings.managed_ssh_config = true;
+        testutil::set_up(&mut manager, &["home"]);
+
+        assert!(manager.uninstall(false, true).unwrap());
+
+        assert_eq!(home.read(".ssh/config"), ssh_config);
+        assert_eq!(home.read(".gitconfig"), gitconfig);
+        for file in [".ssh/config", ".gitconfig"] {
+            assert!(!home.read(file).contains("multigit"), "{}", file);
+        }
+        assert!(!store::config_dir(&home.path).exists());
+        assert!(home.join(".ssh/id_ed25519_work").exists(), "keys stay without --remove-keys");
+    }
+
+    #[test]
+    fn uninstall_keeps_a_snapshot_that_restore_puts_back() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work", "home"]);
+        home.write(".ssh/multigit-sockets/git@github.com-work-22", "");
+        let before: Vec<_> = testutil::tree(&home.path).into_iter().filter(|(path, _)| !path.starts_with(home.join(".ssh/multigit-sockets"))).collect();
+
+        assert!(manager.uninstall(true, true).unwrap());
+
+        assert!(!multiplex::socket_dir(&home.path).exists());
+        assert!(!home.join(".ssh/id_ed25519_work").exists());
+        let ids: Vec<_> = fs::read_dir(snapshots_dir(&home.path)).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        assert_eq!(ids.len(), 1);
+        let left: Vec<_> = fs::read_dir(&home.path).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert!(!left.iter().any(|name| name.to_string_lossy().starts_with("multigit-backup-")), "{:?}", left);
+
+        assert!(home.manager().restore_snapshot(&ids[0], false).unwrap());
+        let after: Vec<_> = testutil::tree(&home.path).into_iter().filter(|(path, _)| !path.starts_with(snapshots_dir(&home.path))).collect();
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn remove_takes_out_only_that_account() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        for name in ["work", "home"] {
+            manager.setup_account(name, &format!("{}@example.com", name), &format!("code/{}", name), &format!("github.com-{}", name), SetupOptions::default()).unwrap();
+        }
+        let key = manager.accounts["work"].ssh_key.clone();
+
+        manager.remove_account("work", true).unwrap();
+
+        let ssh_config = home.read(".ssh/config");
+        assert!(!ssh_config.contains("github.com-work"));
+        assert!(ssh_config.contains("github.com-home"));
+        assert!(!home.read(".gitconfig").contains("code/work"));
+        assert!(!home.join("code/work/.gitconfig").exists());
+        assert!(!std::path::Path::new(&key).exists());
+        let loaded = GitAccountManager::load(home.path.clone()).unwrap();
+        assert!(!loaded.accounts.contains_key("work"));
+        assert!(loaded.accounts.contains_key("home"));
+    }
+}
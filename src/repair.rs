@@ -0,0 +1,187 @@
+//! `doctor --repair`: writing back whatever managed config went missing,
+//! from the stored accounts alone, such as after a dotfile sync replaced
+//! `~/.gitconfig` or a reinstall kept only `~/.config/multigit` and the
+//! keys. Nothing is generated or asked for; blocks that are still there
+//! are left as they are, edited or not, so running it again changes
+//! nothing.
+
+use std::fmt;
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::fsutil;
+use crate::keys;
+use crate::managed;
+use crate::ssh_config;
+use crate::state::State;
+use crate::tamper;
+
+enum Outcome {
+    Created,
+    Present,
+    Skipped(String),
+    Failed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Created => write!(f, "created"),
+            Outcome::Present => write!(f, "already present"),
+            Outcome::Skipped(reason) => write!(f, "skipped ({})", reason),
+            Outcome::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    match fsutil::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(fsutil::blocked(e, "read", path)),
+    }
+}
+
+fn has_block(path: &Path, account: &str) -> Result<bool> {
+    Ok(tamper::block_body(&read(path)?, account).is_some())
+}
+
+/// `Created` from a write that went through, `Failed` otherwise.
+fn written(result: Result<()>) -> Outcome {
+    match result {
+        Ok(()) => Outcome::Created,
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+fn outcome(result: Result<Outcome>) -> Outcome {
+    result.unwrap_or_else(|e| Outcome::Failed(e.to_string()))
+}
+
+impl GitAccountManager {
+    fn repair_host_block(&self, account: &GitAccount) -> Result<Outcome> {
+        let path = self.ssh_config_path();
+        let content = read(&path)?;
+        if tamper::block_body(&content, &account.name).is_some() {
+            return Ok(Outcome::Present);
+        }
+        let lines: Vec<&str> = content.lines().collect();
+        if ssh_config::find_host(&ssh_config::host_blocks(&lines), &account.host).is_some() {
+            return Ok(Outcome::Skipped(format!("{} has a Host {} block multigit didn't write", path.display(), account.host)));
+        }
+        let block = self.host_block(account)?;
+        if let Some(parent) = path.parent() {
+            fsutil::create_dir_all(parent)?;
+        }
+        let result = fsutil::modify(&path, |content| Ok(Some(managed::merge(content, &account.name, &block))));
+        if result.is_ok() {
+            let mut state = State::load(&self.home_dir)?;
+            state.record_write(&account.name, &path);
+            state.save(&self.home_dir)?;
+        }
+        Ok(written(result))
+    }
+
+    fn repair_include_file(&self, account: &GitAccount) -> Result<Outcome> {
+        let path = self.include_file_path(account);
+        let linked = self.settings.gitconfig_export_dir.is_none() || account.codebase_dir_path.join(".gitconfig").exists();
+        if linked && has_block(&path, &account.name)? {
+            return Ok(Outcome::Present);
+        }
+        let result = self.setup_local_gitconfig(account);
+        if result.is_ok() {
+            let mut state = State::load(&self.home_dir)?;
+            state.record_write(&account.name, &path);
+            state.save(&self.home_dir)?;
+        }
+        Ok(written(result))
+    }
+
+    fn repair_include_if(&self, account: &GitAccount) -> Result<Outcome> {
+        if self.settings.no_global_edit {
+            return Ok(Outcome::Skipped("--no-global-edit".to_string()));
+        }
+        if has_block(&self.global_gitconfig_path(), &account.name)? {
+            return Ok(Outcome::Present);
+        }
+        let include_path = account.codebase_dir_path.join(".gitconfig");
+        let codebase_dir = fsutil::utf8(&account.codebase_dir_path)?;
+        let result = self.setup_global_gitconfig(&account.name, codebase_dir, fsutil::utf8(&include_path)?);
+        Ok(written(result))
+    }
+
+    /// Loads whichever of the account's keys the agent doesn't have. The
+    /// keys themselves are never regenerated.
+    fn repair_agent(&self, account: &GitAccount) -> Result<Outcome> {
+        if self.skips_agent(account) {
+            return Ok(Outcome::Skipped("--no-agent".to_string()));
+        }
+        let keys = account.identity_files();
+        if let Some(missing) = keys.iter().find(|key| !Path::new(key).exists()) {
+            return Ok(Outcome::Failed(format!("{} is missing; restore it from a backup", missing)));
+        }
+        let Some(loaded) = keys::agent_fingerprints() else {
+            return Ok(Outcome::Failed("no ssh-agent reachable".to_string()));
+        };
+        let unloaded: Vec<&str> = keys
+            .into_iter()
+            .filter(|key| keys::fingerprint_hash(key).is_none_or(|fingerprint| !loaded.contains(&fingerprint)))
+            .collect();
+        if unloaded.is_empty() {
+            return Ok(Outcome::Present);
+        }
+        for key in unloaded {
+            self.add_ssh_agent(key, account.keychain())?;
+        }
+        Ok(Outcome::Created)
+    }
+
+    /// Writes back every missing Host block, includeIf and include file of
+    /// the enabled accounts and loads their keys, printing what was done
+    /// for each. Returns false when anything failed.
+    pub fn repair(&self) -> Result<bool> {
+        let (mut created, mut present, mut failed) = (0, 0, 0);
+        let mut include_ifs_created = false;
+        let mut tally = |artifact: &str, outcome: Outcome| {
+            match outcome {
+                Outcome::Created => created += 1,
+                Outcome::Present => present += 1,
+                Outcome::Failed(_) => failed += 1,
+                Outcome::Skipped(_) => {}
+            }
+            println!("  {:<14}{}", artifact, outcome);
+        };
+
+        if self.settings.managed_ssh_config {
+            let path = self.home_dir.join(".ssh/config");
+            println!("ssh config:");
+            let include = match read(&path) {
+                Ok(content) if self.ssh_include_update(&content).is_none() => Outcome::Present,
+                Ok(_) => written(self.ensure_ssh_include()),
+                Err(e) => Outcome::Failed(e.to_string()),
+            };
+            tally("Include", include);
+        }
+        for account in self.sorted_accounts(true) {
+            println!("{}:", account.name);
+            if account.disabled {
+                println!("  skipped: disabled");
+                continue;
+            }
+            tally("Host block", outcome(self.repair_host_block(account)));
+            tally("include file", outcome(self.repair_include_file(account)));
+            let include_if = outcome(self.repair_include_if(account));
+            include_ifs_created |= matches!(include_if, Outcome::Created);
+            tally("includeIf", include_if);
+            tally("ssh-agent", outcome(self.repair_agent(account)));
+        }
+
+        if include_ifs_created && self.include_if_order()?.is_some() {
+            self.fix_include_if_order()?;
+            println!("Moved the includeIf entries so the most specific directory comes last");
+        }
+        println!("Repair: {} created, {} already present, {} failed", created, present, failed);
+        Ok(failed == 0)
+    }
+}
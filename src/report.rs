@@ -0,0 +1,209 @@
+use std::fmt;
+use std::io::{Error, ErrorKind, Result, Write};
+
+use serde::Serialize;
+#[cfg(feature = "json")]
+use serde_json::{json, Value};
+
+use crate::account::{AGENT_STEP, CONNECTIVITY_STEP, LOGIN_STEP};
+#[cfg(feature = "json")]
+use crate::events;
+
+/// `--format` for commands with machine-readable output.
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// What JSON output fails with in a build without the `json` feature.
+pub fn no_json() -> Error {
+    Error::new(ErrorKind::Unsupported, "JSON output needs multigit built with the json feature")
+}
+
+/// Prints `value` as indented JSON, for `--format json`.
+pub fn print_json<T: Serialize + ?Sized>(value: &T) -> Result<()> {
+    #[cfg(feature = "json")]
+    {
+        println!("{}", serde_json::to_string_pretty(value).map_err(Error::other)?);
+        Ok(())
+    }
+    #[cfg(not(feature = "json"))]
+    {
+        let _ = value;
+        Err(no_json())
+    }
+}
+
+/// Writes `value` as one line of JSON, for JSON Lines output.
+pub fn write_json_line<T: Serialize + ?Sized>(out: &mut impl Write, value: &T) -> Result<()> {
+    #[cfg(feature = "json")]
+    {
+        serde_json::to_writer(&mut *out, value).map_err(Error::other)?;
+        writeln!(out)
+    }
+    #[cfg(not(feature = "json"))]
+    {
+        let _ = (out, value);
+        Err(no_json())
+    }
+}
+
+/// Prints `rows` as columns padded to their widest field, two spaces
+/// apart; the first row is usually the header.
+pub fn print_table<const N: usize>(rows: &[[String; N]]) {
+    write_table(&mut std::io::stdout().lock(), rows).expect("failed printing to stdout");
+}
+
+/// [`print_table`] to `out`.
+pub fn write_table<const N: usize>(out: &mut impl Write, rows: &[[String; N]]) -> Result<()> {
+    let mut widths = [0; N];
+    for row in rows {
+        for (width, field) in widths.iter_mut().zip(row) {
+            *width = (*width).max(field.chars().count());
+        }
+    }
+    for row in rows {
+        let line: Vec<String> = row.iter().zip(widths).map(|(field, width)| format!("{:<width$}", field, width = width)).collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+pub enum StepStatus {
+    Ok,
+    Skipped(String),
+    /// Postponed until a later run, e.g. a network step while offline.
+    Deferred(String),
+    Failed(String),
+}
+
+impl StepStatus {
+    fn label(&self) -> &'static str {
+        match self {
+            StepStatus::Ok => "ok",
+            StepStatus::Skipped(_) => "skipped",
+            StepStatus::Deferred(_) => "deferred",
+            StepStatus::Failed(_) => "failed",
+        }
+    }
+
+    #[cfg(feature = "json")]
+    fn reason(&self) -> Option<&str> {
+        match self {
+            StepStatus::Ok => None,
+            StepStatus::Skipped(reason) | StepStatus::Deferred(reason) | StepStatus::Failed(reason) => Some(reason),
+        }
+    }
+}
+
+impl fmt::Display for StepStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepStatus::Ok => write!(f, "ok"),
+            StepStatus::Skipped(reason) => write!(f, "skipped ({})", reason),
+            StepStatus::Deferred(reason) => write!(f, "deferred ({})", reason),
+            StepStatus::Failed(reason) => write!(f, "FAILED: {}", reason),
+        }
+    }
+}
+
+/// What happened at each step of setting up one account.
+pub struct SetupReport {
+    pub account: String,
+    pub steps: Vec<(&'static str, StepStatus)>,
+    pub warnings: Vec<String>,
+}
+
+impl SetupReport {
+    pub fn new(account: &str) -> Self {
+        SetupReport {
+            account: account.to_string(),
+            steps: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, step: &'static str, status: StepStatus) {
+        #[cfg(feature = "json")]
+        events::emit("step", Some(&self.account), json!({ "step": step, "status": status.label(), "reason": status.reason() }));
+        self.steps.push((step, status));
+    }
+
+    pub fn warn(&mut self, warning: String) {
+        #[cfg(feature = "json")]
+        events::warning(Some(&self.account), &warning);
+        self.warnings.push(warning);
+    }
+
+    /// What `--strict` fails on: the warnings, and the steps that failed
+    /// without keeping the config from being written.
+    pub fn strict_problems(&self) -> Vec<String> {
+        let failed = self.steps.iter().filter_map(|(step, status)| match status {
+            StepStatus::Failed(reason) => Some(format!("{} failed: {}", step, reason)),
+            _ => None,
+        });
+        self.warnings.iter().cloned().chain(failed).collect()
+    }
+
+    pub fn succeeded(&self, step: &str) -> bool {
+        self.steps.iter().any(|(name, status)| *name == step && matches!(status, StepStatus::Ok))
+    }
+
+    /// Whether every step that writes config succeeded. A failed
+    /// connectivity or login check only means the credentials need fixing on
+    /// the provider's side, and a key missing from the agent can be added
+    /// later.
+    pub fn config_written(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|(step, status)| [CONNECTIVITY_STEP, LOGIN_STEP, AGENT_STEP].contains(step) || !matches!(status, StepStatus::Failed(_)))
+    }
+
+    /// Every step on one line, e.g. "generate key ok, ssh config ok,
+    /// includeIf FAILED: <reason>", for a setup that stopped short.
+    pub fn summary(&self) -> String {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(step, status)| match status {
+                StepStatus::Failed(_) => format!("{} {}", step, status),
+                _ => format!("{} {}", step, status.label()),
+            })
+            .collect();
+        steps.join(", ")
+    }
+
+    /// The first step that failed, with why.
+    pub fn first_failure(&self) -> Option<(&'static str, &str)> {
+        self.steps.iter().find_map(|(step, status)| match status {
+            StepStatus::Failed(reason) => Some((*step, reason.as_str())),
+            _ => None,
+        })
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let steps: Vec<Value> = self
+            .steps
+            .iter()
+            .map(|(step, status)| json!({ "step": step, "status": status.label(), "reason": status.reason() }))
+            .collect();
+        json!({ "account": self.account, "steps": steps, "warnings": self.warnings })
+    }
+
+    pub fn print(&self) {
+        println!("Setup report for {}:", self.account);
+        for (step, status) in &self.steps {
+            println!("  {:<16} {}", step, status);
+        }
+        for warning in &self.warnings {
+            println!("warning: {}", warning);
+        }
+        if !self.config_written() {
+            println!("Setup of {} did not finish: {}", self.account, self.summary());
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! `resolve`: what git and ssh would do with a remote URL from a given
+//! directory, without connecting anywhere. The insteadOf rewrites come from
+//! the global gitconfig and the include file git would pick for a
+//! repository there; the ssh side is `ssh -G`, which evaluates the config
+//! the way a real connection would and stops before connecting.
+//!
+//! `which-key` goes one step further: the keys ssh would try, in order,
+//! and with `--probe` a real `ssh -v` through the alias, telling which
+//! keys it offered and which the server took. Without `IdentitiesOnly`
+//! ssh offers agent keys first, and the provider may take the wrong one.
+//!
+//! Both print a JSON object instead with `--format json`, for editor
+//! integrations asking which key a repository will use.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde::Serialize;
+
+use crate::account::GitAccountManager;
+use crate::git_url::{self, SshUrl};
+use crate::gitconfig;
+use crate::report::{print_json, OutputFormat};
+use crate::ssh_config;
+use crate::store;
+
+/// How a URL resolves, as `--format json` prints it. The ssh fields are
+/// `null` for a URL that isn't ssh.
+#[derive(Serialize)]
+struct Resolution {
+    input_url: String,
+    rewritten_url: String,
+    /// The host ssh is asked for, usually an account's Host alias.
+    alias: Option<String>,
+    hostname: Option<String>,
+    user: Option<String>,
+    /// The first IdentityFile, the one ssh tries first.
+    identity_file: Option<String>,
+    matched_account: Option<String>,
+}
+
+/// `which-key --format json`: the resolution and the keys ssh tries, and
+/// after `--probe` the ones it offered and the one the server accepted.
+#[derive(Serialize)]
+struct KeyReport {
+    #[serde(flatten)]
+    resolution: Resolution,
+    identity_files: Vec<String>,
+    identities_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offered: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    accepted: Option<Option<String>>,
+}
+
+/// An `url.<base>.insteadOf` rule and the file it's in.
+struct Rewrite {
+    base: String,
+    prefix: String,
+    file: PathBuf,
+}
+
+/// The `url.<base>.insteadOf` rules of a git config file.
+fn rewrites_in(path: &Path) -> Vec<Rewrite> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let mut rewrites = Vec::new();
+    for section in gitconfig::sections(&lines) {
+        let Some(base) = section.header.strip_prefix("url").map(|rest| rest.trim().trim_matches('"')) else {
+            continue;
+        };
+        for prefix in gitconfig::values(&lines, &section, "insteadof") {
+            rewrites.push(Rewrite { base: base.to_string(), prefix: prefix.trim_matches('"').to_string(), file: path.to_path_buf() });
+        }
+    }
+    rewrites
+}
+
+/// The git dir of the repository containing `dir`, or the one `dir` would
+/// have if it were a repository.
+fn gitdir_for(dir: &Path) -> PathBuf {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .unwrap_or(dir)
+        .join(".git")
+}
+
+/// The keys of an `ssh -v` log: those offered, in order, and the one the
+/// server accepted.
+#[derive(Default)]
+struct Probe {
+    offered: Vec<String>,
+    accepted: Option<String>,
+}
+
+/// The key of an "Offering public key:" or "Server accepts key:" line:
+/// its path, or for a key only in the agent its comment. OpenSSH 8 and
+/// later log the path first; older versions log the type and fingerprint
+/// before it.
+fn logged_key(rest: &str) -> String {
+    let words: Vec<&str> = rest.split_whitespace().collect();
+    words
+        .iter()
+        .find(|word| word.starts_with('/') || word.starts_with('~'))
+        .or(words.first())
+        .map_or_else(String::new, |word| word.to_string())
+}
+
+fn parse_probe(log: &str) -> Probe {
+    let mut probe = Probe::default();
+    for line in log.lines() {
+        let line = line.trim_start_matches("debug1: ").trim();
+        if let Some(rest) = line.strip_prefix("Offering public key:") {
+            probe.offered.push(logged_key(rest));
+        } else if let Some(rest) = line.strip_prefix("Server accepts key:") {
+            probe.accepted = Some(logged_key(rest));
+        }
+    }
+    probe
+}
+
+impl GitAccountManager {
+    /// `url` after the insteadOf rewrite git would apply for a repository
+    /// in `dir`, and the rule that did it.
+    fn rewritten(&self, url: &str, dir: &Path) -> (String, Option<Rewrite>) {
+        let global = self.global_gitconfig_path();
+        let mut rewrites = rewrites_in(&global);
+        let gitdir = gitdir_for(dir);
+        if let Ok(content) = fs::read_to_string(&global) {
+            let lines: Vec<&str> = content.lines().collect();
+            if let Some(include) = gitconfig::effective_include_if(&lines, &gitdir, &self.home_dir) {
+                rewrites.extend(rewrites_in(Path::new(&ssh_config::expand_tilde(include, &self.home_dir))));
+            }
+        }
+        // git uses the longest matching prefix.
+        match rewrites.into_iter().filter(|rewrite| url.starts_with(&rewrite.prefix)).max_by_key(|rewrite| rewrite.prefix.len()) {
+            Some(rewrite) => (format!("{}{}", rewrite.base, &url[rewrite.prefix.len()..]), Some(rewrite)),
+            None => (url.to_string(), None),
+        }
+    }
+
+    /// ssh with the user's config and the URL's user and port, ready for
+    /// options and the host.
+    fn ssh_command(&self, ssh: &SshUrl) -> Command {
+        let ssh_config_path = self.home_dir.join(".ssh/config");
+        let mut command = Command::new("ssh");
+        if ssh_config_path.exists() {
+            command.arg("-F").arg(&ssh_config_path);
+        }
+        if let Some(user) = ssh.user {
+            command.args(["-l", user]);
+        }
+        if let Some(port) = ssh.port {
+            command.args(["-p", port]);
+        }
+        command
+    }
+
+    /// The values of each keyword in `ssh -G` for the URL's host.
+    fn effective_ssh_config(&self, ssh: &SshUrl) -> Result<Vec<(String, String)>> {
+        let output = self
+            .ssh_command(ssh)
+            .arg("-G")
+            .arg(ssh.host)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| Error::new(ErrorKind::NotFound, format!("Failed to run ssh -G: {}", e)))?;
+        if !output.status.success() {
+            return Err(Error::other(format!("ssh -G {} failed: {}", ssh.host, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once(' ').map(|(key, value)| (key.to_string(), value.to_string())))
+            .collect())
+    }
+
+    fn resolution(&self, url: &str, dir: &Path) -> Result<Resolution> {
+        let (rewritten_url, _) = self.rewritten(url, dir);
+        let mut resolution = Resolution {
+            input_url: url.to_string(),
+            rewritten_url: rewritten_url.clone(),
+            alias: None,
+            hostname: None,
+            user: None,
+            identity_file: None,
+            matched_account: None,
+        };
+        if let Some(ssh) = git_url::parse(&rewritten_url) {
+            let effective = self.effective_ssh_config(&ssh)?;
+            let first = |keyword: &str| effective.iter().find(|(key, _)| key == keyword).map(|(_, value)| value.clone());
+            resolution.alias = Some(ssh.host.to_string());
+            resolution.hostname = first("hostname");
+            resolution.user = first("user");
+            resolution.identity_file = first("identityfile").map(|path| ssh_config::expand_tilde(&path, &self.home_dir));
+            resolution.matched_account = self.accounts.values().find(|account| account.host.eq_ignore_ascii_case(ssh.host)).map(|account| account.name.clone());
+        }
+        Ok(resolution)
+    }
+
+    /// Prints how `url` resolves for a repository in `dir`: the rewritten
+    /// URL, the Host block ssh uses for it, and the HostName, User and
+    /// IdentityFile that block gives.
+    pub fn resolve_url(&self, url: &str, dir: &Path, format: OutputFormat) -> Result<bool> {
+        if let OutputFormat::Json = format {
+            print_json(&self.resolution(url, dir)?)?;
+            return Ok(true);
+        }
+        println!("URL:          {}", url);
+        let (resolved, rewrite) = self.rewritten(url, dir);
+        match rewrite {
+            Some(rewrite) => println!("Rewritten:    {} (url.\"{}\".insteadOf in {})", resolved, rewrite.base, rewrite.file.display()),
+            None => println!("Rewritten:    (no insteadOf rule applies in {})", dir.display()),
+        }
+
+        let Some(ssh) = git_url::parse(&resolved) else {
+            println!("Transport:    not ssh; no ssh key is used");
+            return Ok(true);
+        };
+
+        let ssh_config_path = self.home_dir.join(".ssh/config");
+        let mut block_source = None;
+        for path in [ssh_config_path, store::managed_ssh_config_path(&self.home_dir)] {
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+            let lines: Vec<&str> = content.lines().collect();
+            let blocks = ssh_config::host_blocks(&lines);
+            if let Some(block) = blocks.iter().find(|block| block.patterns.iter().any(|p| p != "*") && block.matches(ssh.host)) {
+                block_source = Some(format!("Host {} ({}:{})", block.patterns.join(" "), path.display(), block.start + 1));
+                break;
+            }
+        }
+        println!("Host block:   {}", block_source.as_deref().unwrap_or("(none; ssh uses the defaults)"));
+
+        let effective = self.effective_ssh_config(&ssh)?;
+        let values = |keyword: &str| -> Vec<String> {
+            effective.iter().filter(|(key, _)| key == keyword).map(|(_, value)| value.clone()).collect()
+        };
+        println!("HostName:     {}", values("hostname").join(" "));
+        println!("User:         {}", values("user").join(" "));
+        for (i, identity) in values("identityfile").iter().enumerate() {
+            let label = if i == 0 { "IdentityFile:" } else { "" };
+            println!("{:<13} {}", label, ssh_config::expand_tilde(identity, &self.home_dir));
+        }
+        for certificate in values("certificatefile") {
+            println!("Certificate:  {}", ssh_config::expand_tilde(&certificate, &self.home_dir));
+        }
+
+        let by_alias = self.accounts.values().find(|account| account.host.eq_ignore_ascii_case(ssh.host));
+        match by_alias {
+            Some(account) => println!("Account:      {}", account.name),
+            None => println!("Account:      (none; {} isn't an account's Host alias)", ssh.host),
+        }
+        if let (Some(by_alias), Some(owner)) = (by_alias, self.account_for_path(dir)) {
+            if by_alias.name != owner.name {
+                println!("warning: {} belongs to {}, but this URL goes through {}'s alias", dir.display(), owner.name, by_alias.name);
+            }
+        }
+        Ok(true)
+    }
+
+    /// `which-key`: the keys ssh tries for `url` from `dir`, in order, and
+    /// with `probe` the ones a real connection offered and the one the
+    /// server accepted. Fails when it accepted none, or another key than
+    /// the Host block's.
+    pub fn which_key(&self, url: &str, dir: &Path, probe: bool, format: OutputFormat) -> Result<bool> {
+        if let OutputFormat::Json = format {
+            return self.which_key_json(url, dir, probe);
+        }
+        let (resolved, rewrite) = self.rewritten(url, dir);
+        println!("URL:          {}", url);
+        if rewrite.is_some() {
+            println!("Rewritten:    {}", resolved);
+        }
+        let Some(ssh) = git_url::parse(&resolved) else {
+            println!("Transport:    not ssh; no ssh key is used");
+            return Ok(true);
+        };
+        let by_alias = self.accounts.values().find(|account| account.host.eq_ignore_ascii_case(ssh.host));
+        match by_alias {
+            Some(account) => println!("Host:         {} (account {})", ssh.host, account.name),
+            None => println!("Host:         {} (no account's alias)", ssh.host),
+        }
+
+        let effective = self.effective_ssh_config(&ssh)?;
+        let identities: Vec<String> = effective
+            .iter()
+            .filter(|(key, _)| key == "identityfile")
+            .map(|(_, value)| ssh_config::expand_tilde(value, &self.home_dir))
+            .collect();
+        let identities_only = effective.iter().any(|(key, value)| key == "identitiesonly" && value == "yes");
+        println!("Keys ssh tries, in order:");
+        for (i, identity) in identities.iter().enumerate() {
+            println!("  {}. {}", i + 1, identity);
+        }
+        println!("IdentitiesOnly: {}", if identities_only { "yes" } else { "no" });
+        if !probe {
+            if !identities_only {
+                println!("note: without IdentitiesOnly every key in the agent is offered first; --probe shows which one the server takes");
+            }
+            return Ok(true);
+        }
+        if self.offline {
+            println!("note: offline; not probing {}", ssh.host);
+            return Ok(true);
+        }
+
+        let probe = self.probe(&ssh)?;
+        println!("Offered:");
+        if probe.offered.is_empty() {
+            println!("  (no key)");
+        }
+        for (i, key) in probe.offered.iter().enumerate() {
+            let verdict = if probe.accepted.as_ref() == Some(key) { "accepted" } else { "rejected" };
+            println!("  {}. {} ({})", i + 1, key, verdict);
+        }
+        let Some(accepted) = &probe.accepted else {
+            println!("Accepted:     none; the server took none of the offered keys");
+            return Ok(false);
+        };
+        println!("Accepted:     {}", accepted);
+        match identities.first() {
+            Some(expected) if expected != accepted => {
+                println!(
+                    "warning: the server took {}, not the Host block's IdentityFile {}; {}",
+                    accepted,
+                    expected,
+                    if identities_only { "that key decides the identity on the provider" } else { "set IdentitiesOnly yes in the Host block" }
+                );
+                Ok(false)
+            }
+            _ => {
+                let before = probe.offered.iter().take_while(|key| *key != accepted).count();
+                if before > 0 && !identities_only {
+                    println!(
+                        "warning: ssh offered {} other key(s) first; if the provider knew one of them, you'd be authenticated as its account. Set IdentitiesOnly yes in the Host block",
+                        before
+                    );
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Connects through the URL's host with `ssh -v` to see which keys it
+    /// offers and which one the server takes.
+    fn probe(&self, ssh: &SshUrl) -> Result<Probe> {
+        let output = self
+            .ssh_command(ssh)
+            .args(["-v", "-T", "-o", "BatchMode=yes", "-o", "ConnectTimeout=10"])
+            .arg(ssh.host)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| Error::new(ErrorKind::NotFound, format!("Failed to run ssh: {}", e)))?;
+        Ok(parse_probe(&String::from_utf8_lossy(&output.stderr)))
+    }
+
+    /// `which-key --format json`: the resolution with every IdentityFile,
+    /// and with `probe` the keys offered and the one accepted. Fails like
+    /// the text form.
+    fn which_key_json(&self, url: &str, dir: &Path, probe: bool) -> Result<bool> {
+        let resolution = self.resolution(url, dir)?;
+        let rewritten_url = resolution.rewritten_url.clone();
+        let mut report = KeyReport { resolution, identity_files: Vec::new(), identities_only: false, offered: None, accepted: None };
+        let mut ok = true;
+        if let Some(ssh) = git_url::parse(&rewritten_url) {
+            let effective = self.effective_ssh_config(&ssh)?;
+            report.identity_files = effective
+                .iter()
+                .filter(|(key, _)| key == "identityfile")
+                .map(|(_, value)| ssh_config::expand_tilde(value, &self.home_dir))
+                .collect();
+            report.identities_only = effective.iter().any(|(key, value)| key == "identitiesonly" && value == "yes");
+            if probe && !self.offline {
+                let probe = self.probe(&ssh)?;
+                ok = probe.accepted.is_some() && (report.identity_files.is_empty() || probe.accepted.as_ref() == report.identity_files.first());
+                report.offered = Some(probe.offered);
+                report.accepted = Some(probe.accepted);
+            }
+        }
+        print_json(&report)?;
+        Ok(ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn ssh_url_resolves_to_the_accounts_key() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work", "home"]);
+
+        let resolution = manager.resolution("git@github.com:org/repo.git", &home.join("code/work/repo")).unwrap();
+        assert_eq!(resolution.rewritten_url, "git@github.com-work:org/repo.git");
+        assert_eq!(resolution.alias.as_deref(), Some("github.com-work"));
+        assert_eq!(resolution.hostname.as_deref(), Some("github.com"));
+        assert_eq!(resolution.identity_file.as_deref(), Some(manager.accounts["work"].ssh_key.as_str()));
+        assert_eq!(resolution.matched_account.as_deref(), Some("work"));
+
+        let elsewhere = manager.resolution("git@github.com:org/repo.git", &home.join("elsewhere")).unwrap();
+        assert_eq!(elsewhere.rewritten_url, "git@github.com:org/repo.git");
+        assert_eq!(elsewhere.matched_account, None);
+    }
+
+    #[test]
+    fn https_and_git_urls_resolve_to_the_accounts_key() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let repo = home.join("code/work/repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+
+        for url in ["https://github.com/org/repo.git", "git://github.com/org/repo.git"] {
+            let resolution = manager.resolution(url, &repo).unwrap();
+            assert_eq!(resolution.rewritten_url, "git@github.com-work:org/repo.git", "{}", url);
+            assert_eq!(resolution.identity_file.as_deref(), Some(manager.accounts["work"].ssh_key.as_str()));
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn resolution_json_has_every_field() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+
+        let resolution = manager.resolution("git@github.com:org/repo.git", &home.join("code/work/repo")).unwrap();
+        assert_eq!(
+            serde_json::to_value(&resolution).unwrap(),
+            serde_json::json!({
+                "input_url": "git@github.com:org/repo.git",
+                "rewritten_url": "git@github.com-work:org/repo.git",
+                "alias": "github.com-work",
+                "hostname": "github.com",
+                "user": "git",
+                "identity_file": manager.accounts["work"].ssh_key,
+                "matched_account": "work",
+            })
+        );
+    }
+
+    #[test]
+    fn probe_reports_the_keys_offered_and_the_one_accepted() {
+        let log = "OpenSSH_9.6p1, OpenSSL 3.0.13 30 Jan 2024\n\
+                   debug1: Will attempt key: me@laptop ED25519 SHA256:aaaa agent\n\
+                   debug1: Offering public key: me@laptop ED25519 SHA256:aaaa agent\n\
+                   debug1: Authentications that can continue: publickey\n\
+                   debug1: Offering public key: /home/me/.ssh/id_ed25519_work ED25519 SHA256:bbbb explicit\n\
+                   debug1: Server accepts key: /home/me/.ssh/id_ed25519_work ED25519 SHA256:bbbb explicit\n\
+                   Authenticated to github.com ([140.82.121.4]:22) using \"publickey\".\n";
+        let probe = super::parse_probe(log);
+        assert_eq!(probe.offered, ["me@laptop", "/home/me/.ssh/id_ed25519_work"]);
+        assert_eq!(probe.accepted.as_deref(), Some("/home/me/.ssh/id_ed25519_work"));
+
+        // OpenSSH 7 logs the type and fingerprint before the path.
+        let old = "debug1: Offering public key: ED25519 SHA256:bbbb /home/me/.ssh/id_ed25519_work\n\
+                   debug1: Authentications that can continue: publickey\n";
+        let probe = super::parse_probe(old);
+        assert_eq!(probe.offered, ["/home/me/.ssh/id_ed25519_work"]);
+        assert_eq!(probe.accepted, None);
+    }
+}
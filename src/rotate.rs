@@ -0,0 +1,381 @@
+//! `key rotate`: replaces an account's primary key, or with `--label` one
+//! of its secondary keys, with a newly generated one. The new key gets a
+//! file of its own and the Host block is pointed at it; the old key leaves
+//! ssh-agent, but its file stays until the new public key is on the
+//! provider and the old one has been removed there. `--dry-run` shows all of this
+//! without generating or editing anything. `--all --older-than` rotates
+//! every key past an age.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::account::{run_ssh_keygen, GitAccount, GitAccountManager, DEFAULT_KEY_TYPE};
+use crate::doctor::describe_duration;
+use crate::errors::account_not_found;
+use crate::fsutil;
+use crate::key_audit::key_created;
+use crate::keys::{self, fingerprint, key_comment, AUTO_KEY_TYPE};
+use crate::managed;
+use crate::ssh_config;
+use crate::state::State;
+use crate::tamper;
+
+/// Parses an `--older-than` age, a number of days followed by `d`, weeks
+/// by `w` or years by `y`, into seconds. A bare number is days.
+pub fn parse_age(age: &str) -> std::result::Result<u64, String> {
+    let (number, unit) = match age.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => age.split_at(split),
+        None => (age, "d"),
+    };
+    let days = match unit {
+        "d" => 1,
+        "w" => 7,
+        "y" => 365,
+        _ => return Err(format!("expected an age such as 90d, 12w or 1y, got '{}'", age)),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("expected an age such as 90d, 12w or 1y, got '{}'", age))?;
+    Ok(number * days * 86400)
+}
+
+/// What rotating a key does.
+struct Rotation {
+    /// The secondary key's label, `None` for the primary key.
+    label: Option<String>,
+    old_key: String,
+    new_key: PathBuf,
+    key_type: String,
+    /// The Host block's lines that change, as they are and as they'll be.
+    removed: Vec<String>,
+    added: Vec<String>,
+}
+
+/// `path`, or when that's taken `path-2`, `path-3` and so on, with neither
+/// the key nor its `.pub` there yet.
+fn free_key_path(path: PathBuf) -> PathBuf {
+    let taken = |path: &Path| path.exists() || Path::new(&format!("{}.pub", path.display())).exists();
+    if !taken(&path) {
+        return path;
+    }
+    (2..)
+        .map(|n| PathBuf::from(format!("{}-{}", path.display(), n)))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+/// `account` with the key labelled `label`, or the primary key, replaced
+/// by `new_key`.
+fn rotated(account: &GitAccount, label: Option<&str>, new_key: &Path, key_type: &str) -> Result<GitAccount> {
+    let mut rotated = account.clone();
+    let new_key = fsutil::utf8(new_key)?.to_string();
+    match rotated.secondary_keys.iter_mut().find(|key| Some(key.label.as_str()) == label) {
+        Some(key) => {
+            key.path = new_key;
+            key.key_type = key_type.to_string();
+        }
+        None => rotated.ssh_key = new_key,
+    }
+    Ok(rotated)
+}
+
+impl GitAccountManager {
+    /// The account's Host block with every key it has, secondary ones
+    /// included, as `key add` leaves it.
+    fn full_host_block(&self, account: &GitAccount) -> Result<String> {
+        let block = self.host_block(account)?;
+        let lines: Vec<&str> = block.lines().collect();
+        let blocks = ssh_config::host_blocks(&lines);
+        let Some(host) = ssh_config::find_host(&blocks, &account.host) else {
+            return Ok(block);
+        };
+        let keys: Vec<String> = account.identity_files().into_iter().map(str::to_string).collect();
+        let mut block = ssh_config::replace_identity_files(&lines, host, &keys).join("\n");
+        block.push('\n');
+        Ok(block)
+    }
+
+    fn plan_rotation(&self, account: &GitAccount, label: Option<&str>, key_type: Option<&str>) -> Result<Rotation> {
+        let secondary = match label {
+            Some(label) => Some(account.secondary_keys.iter().position(|key| key.label == label).ok_or_else(|| {
+                Error::new(ErrorKind::NotFound, format!("Account '{}' has no key labelled '{}'", account.name, label))
+            })?),
+            None => None,
+        };
+        if account.pkcs11_provider.is_some() && secondary.is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{}'s keys are on a PKCS#11 token; rotate them with the token's own tools", account.name),
+            ));
+        }
+        let key_type = match key_type {
+            Some(AUTO_KEY_TYPE) => {
+                let (key_type, reason) = keys::negotiate_key_type(self.offline, account.hostname());
+                println!("Key type: {} ({})", key_type, reason);
+                key_type.to_string()
+            }
+            Some(key_type) => key_type.to_string(),
+            None => match secondary {
+                Some(index) => account.secondary_keys[index].key_type.clone(),
+                None => keys::key_strength(&account.ssh_key).map_or_else(|| DEFAULT_KEY_TYPE.to_string(), |(_, key_type)| key_type),
+            },
+        };
+        keys::validate_key_type(&key_type)?;
+        let old_key = match secondary {
+            Some(index) => account.secondary_keys[index].path.clone(),
+            None => account.ssh_key.clone(),
+        };
+        let new_key = match label {
+            // Named as `key add` names them.
+            Some(label) => self.home_dir.join(".ssh").join(format!("id_{}_{}_{}", key_type.replace('-', "_"), account.name, label)),
+            None => self.default_key_path(&account.name, &account.host, &key_type)?,
+        };
+        let new_key = free_key_path(new_key);
+
+        let rotated = rotated(account, label, &new_key, &key_type)?;
+        let before = self.full_host_block(account)?;
+        let after = self.full_host_block(&rotated)?;
+        let removed = before.lines().filter(|line| !after.lines().any(|other| other == *line)).map(str::to_string).collect();
+        let added = after.lines().filter(|line| !before.lines().any(|other| other == *line)).map(str::to_string).collect();
+        Ok(Rotation { label: label.map(str::to_string), old_key, new_key, key_type, removed, added })
+    }
+
+    /// How long ago the account's key was made: when multigit first saw
+    /// it or the file's date, whichever is earlier, else when the account
+    /// was added.
+    fn key_age(&self, account: &GitAccount, state: &State, now: u64) -> Option<u64> {
+        let fingerprint = keys::fingerprint_hash(&account.ssh_key);
+        let created = key_created(&account.ssh_key, fingerprint.as_deref(), state).or(account.created_at)?;
+        Some(now.saturating_sub(created))
+    }
+
+    /// `key rotate` for `account_name`, or with `None` every enabled
+    /// account, skipping keys younger than `older_than` seconds. `label`
+    /// picks one of the account's secondary keys instead of its primary
+    /// one. Lists which were rotated and which skipped; fails if any
+    /// rotation did.
+    pub fn rotate_keys(&mut self, account_name: Option<&str>, label: Option<&str>, older_than: Option<u64>, key_type: Option<&str>, dry_run: bool) -> Result<bool> {
+        let names: Vec<String> = match account_name {
+            Some(name) => vec![name.to_string()],
+            None => self.sorted_accounts(false).iter().filter(|account| account.pkcs11_provider.is_none()).map(|account| account.name.clone()).collect(),
+        };
+        let state = State::load(&self.home_dir)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        let (mut rotated, mut recent, mut failed) = (Vec::new(), Vec::new(), Vec::new());
+        for name in names {
+            let account = self.accounts.get(&name).ok_or_else(|| account_not_found(&name))?;
+            let age = match label.and_then(|label| account.secondary_keys.iter().find(|key| key.label == label)) {
+                Some(key) => key_created(&key.path, keys::fingerprint_hash(&key.path).as_deref(), &state).map(|created| now.saturating_sub(created)),
+                None => self.key_age(account, &state, now),
+            };
+            if let Some(older_than) = older_than {
+                match age {
+                    Some(age) if age < older_than => {
+                        recent.push(format!("{} ({} old)", name, describe_duration(age)));
+                        continue;
+                    }
+                    Some(_) => {}
+                    None => {
+                        println!("warning: {}: can't tell how old the key is; skipping it", name);
+                        failed.push(name);
+                        continue;
+                    }
+                }
+            }
+            match self.rotate_key(&name, label, key_type, dry_run) {
+                Ok(()) => rotated.push(name),
+                Err(e) => {
+                    println!("error: {}: {}", name, e);
+                    failed.push(name);
+                }
+            }
+            println!();
+        }
+
+        let list = |names: &[String]| if names.is_empty() { "none".to_string() } else { names.join(", ") };
+        println!("{}: {}", if dry_run { "Would rotate" } else { "Rotated" }, list(&rotated));
+        if older_than.is_some() {
+            println!("Skipped as recent: {}", list(&recent));
+        }
+        if !failed.is_empty() {
+            println!("Failed: {}", list(&failed));
+        }
+        Ok(failed.is_empty())
+    }
+
+    /// `key rotate`. With `dry_run` only prints what would happen.
+    pub fn rotate_key(&mut self, account_name: &str, label: Option<&str>, key_type: Option<&str>, dry_run: bool) -> Result<()> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        let rotation = self.plan_rotation(account, label, key_type)?;
+        let hostname = account.hostname().to_string();
+        let has_certificate = account.certificate_file.is_some() && rotation.label.is_none();
+
+        let verb = if dry_run { "Would replace" } else { "Replacing" };
+        match &rotation.label {
+            Some(label) => println!("{} the key '{}' of {}:", verb, label, account_name),
+            None => println!("{} the key of {}:", verb, account_name),
+        }
+        println!("  current: {}", rotation.old_key);
+        println!("    {}", fingerprint(&rotation.old_key));
+        println!("  new:     {} ({})", rotation.new_key.display(), rotation.key_type);
+        println!("{} in {}:", if dry_run { "Would change" } else { "Changing" }, self.ssh_config_path().display());
+        for line in &rotation.removed {
+            println!("  - {}", line.trim());
+        }
+        for line in &rotation.added {
+            println!("  + {}", line.trim());
+        }
+        let reminder = format!(
+            "add {}.pub to your {} account, check with multigit test {}, then remove the old key from {} and delete {}",
+            rotation.new_key.display(),
+            hostname,
+            account_name,
+            hostname,
+            rotation.old_key
+        );
+        if has_certificate {
+            println!("warning: the certificate is for the old key; have the new one signed");
+        }
+        if dry_run {
+            println!("Then: {}", reminder);
+            println!("Dry run: no key was generated and no file was changed.");
+            return Ok(());
+        }
+
+        // Asked before the key is made, so keeping an edited block leaves
+        // nothing half done.
+        let rotated = rotated(account, label, &rotation.new_key, &rotation.key_type)?;
+        let ssh_config_path = self.ssh_config_path();
+        let block = self.full_host_block(&rotated)?;
+        if !self.resolve_tamper(account_name, &ssh_config_path, &block)? {
+            return Err(Error::new(ErrorKind::Interrupted, format!("{}'s key wasn't rotated; its Host block still has your edit", account_name)));
+        }
+
+        let comment = key_comment(self.settings.comment_prefix.as_deref(), &account.email);
+        run_ssh_keygen(&rotation.key_type, &comment, &rotation.new_key, self.key_passphrase.as_deref().unwrap_or(""))?;
+        self.accounts.insert(account_name.to_string(), rotated);
+        let managed_block = fsutil::read_to_string(&ssh_config_path).ok().and_then(|content| tamper::block_body(&content, account_name)).is_some();
+        if managed_block {
+            fsutil::modify(&ssh_config_path, |content| {
+                let merged = managed::merge(content, account_name, &block);
+                Ok((merged != content).then_some(merged))
+            })?;
+            let mut state = State::load(&self.home_dir)?;
+            state.record_write(account_name, &ssh_config_path);
+            state.save(&self.home_dir)?;
+        } else {
+            // A Host block from before multigit marked its regions; only
+            // its IdentityFile lines change.
+            let drift: Vec<_> = self.identity_file_drift()?.into_iter().filter(|item| item.account == account_name).collect();
+            self.fix_identity_file_drift(&drift)?;
+        }
+        self.save()?;
+        self.remember_keys(account_name)?;
+        let account = &self.accounts[account_name];
+        if !self.skips_agent(account) {
+            let removal = keys::remove_from_agent(&rotation.old_key);
+            match (removal.from_agent, removal.from_keychain) {
+                (true, true) => println!("Removed {} from the ssh agent and its passphrase from the macOS keychain", rotation.old_key),
+                (true, false) => println!("Removed {} from the ssh agent", rotation.old_key),
+                (false, true) => println!("Removed the passphrase of {} from the macOS keychain", rotation.old_key),
+                (false, false) => {}
+            }
+            if let Err(e) = self.add_ssh_agent(fsutil::utf8(&rotation.new_key)?, account.keychain()) {
+                eprintln!("warning: {}", e);
+            }
+        }
+        println!("Next: {}", reminder);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    fn set_up(home: &TempHome) -> GitAccountManager {
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work", "home"]);
+        manager
+    }
+
+    #[test]
+    fn rotating_leaves_other_accounts_blocks_alone() {
+        let home = TempHome::new();
+        let mut manager = set_up(&home);
+        let config = home.read(".ssh/config");
+        let edited = config.replace(&format!("{}/.ssh/id_ed25519_home", home.path.display()), "~/.ssh/mine");
+        assert_ne!(edited, config);
+        home.write(".ssh/config", &edited);
+
+        manager.rotate_key("work", None, None, false).unwrap();
+
+        let config = home.read(".ssh/config");
+        assert!(config.contains("~/.ssh/mine"));
+        assert!(config.contains(&manager.accounts["work"].ssh_key));
+        assert!(manager.accounts["work"].ssh_key.ends_with("-2"));
+    }
+
+    #[test]
+    fn label_rotates_a_secondary_key() {
+        let home = TempHome::new();
+        let mut manager = set_up(&home);
+        let old = manager.add_secondary_key("work", "deploy", "ed25519", None).unwrap();
+        let primary = manager.accounts["work"].ssh_key.clone();
+
+        manager.rotate_key("work", Some("deploy"), None, false).unwrap();
+
+        let account = &manager.accounts["work"];
+        assert_eq!(account.ssh_key, primary);
+        let new = &account.secondary_keys[0].path;
+        assert_eq!(*new, format!("{}-2", old));
+        assert!(Path::new(new).exists());
+        let config = home.read(".ssh/config");
+        assert!(config.contains(new.as_str()));
+        assert!(!config.contains(&format!("{}\n", old)));
+
+        let new = new.clone();
+        manager.rotate_key("work", None, None, false).unwrap();
+        assert!(home.read(".ssh/config").contains(&new), "the secondary key stays in the Host block");
+    }
+
+    #[test]
+    fn unknown_label_is_an_error() {
+        let home = TempHome::new();
+        let mut manager = set_up(&home);
+        let error = manager.rotate_key("work", Some("nope"), None, true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn older_than_rotates_only_the_old_key() {
+        let home = TempHome::new();
+        let mut manager = set_up(&home);
+        let old_key = manager.accounts["home"].ssh_key.clone();
+        let recent_key = manager.accounts["work"].ssh_key.clone();
+        let long_ago = SystemTime::now() - std::time::Duration::from_secs(400 * 24 * 60 * 60);
+        std::fs::File::options().write(true).open(&old_key).unwrap().set_modified(long_ago).unwrap();
+
+        assert!(manager.rotate_keys(None, None, Some(365 * 24 * 60 * 60), None, false).unwrap());
+
+        assert_eq!(manager.accounts["home"].ssh_key, format!("{}-2", old_key));
+        assert_eq!(manager.accounts["work"].ssh_key, recent_key);
+        assert!(!Path::new(&format!("{}-2", recent_key)).exists());
+    }
+
+    #[test]
+    fn dry_run_changes_no_file_and_shows_the_identity_file_change() {
+        let home = TempHome::new();
+        let mut manager = set_up(&home);
+        let before = testutil::tree(&home.path);
+
+        manager.rotate_key("work", None, None, true).unwrap();
+
+        assert_eq!(testutil::tree(&home.path), before);
+        let old = manager.accounts["work"].ssh_key.clone();
+        assert!(!old.ends_with("-2"));
+        let rotation = manager.plan_rotation(&manager.accounts["work"], None, None).unwrap();
+        assert_eq!(rotation.removed, [format!("    IdentityFile {}", old)]);
+        assert_eq!(rotation.added, [format!("    IdentityFile {}-2", old)]);
+    }
+}
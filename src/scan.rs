@@ -0,0 +1,115 @@
+use std::io::{self, Result, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::account::GitAccountManager;
+use crate::discover::{self, Cancel};
+use crate::platform::InterruptGuard;
+use crate::report::write_json_line;
+
+#[derive(Serialize)]
+struct ScanRecord<'a> {
+    path: &'a Path,
+    origin: Option<&'a str>,
+    account: Option<&'a str>,
+}
+
+/// Lists the repositories under `root` and the account governing each,
+/// printing every repository as soon as it's found. Past `timeout`, or on
+/// Ctrl-C, stops and fails after what was found so far.
+pub fn run_scan(manager: &GitAccountManager, root: &Path, json_lines: bool, timeout: Option<Duration>) -> Result<bool> {
+    let _interrupt = InterruptGuard::install();
+    scan(manager, root, json_lines, timeout, &mut io::stdout().lock())
+}
+
+fn scan(manager: &GitAccountManager, root: &Path, json_lines: bool, timeout: Option<Duration>, out: &mut impl Write) -> Result<bool> {
+    let mut result = Ok(());
+    let mut count = 0;
+
+    let walked = discover::walk_until(root, Cancel::after(timeout), &mut |repo| {
+        if result.is_err() {
+            return;
+        }
+        let account = manager.account_for_path(&repo.path).map(|account| account.name.as_str());
+        let record = ScanRecord {
+            path: &repo.path,
+            origin: repo.origin.as_deref(),
+            account,
+        };
+
+        result = if json_lines {
+            write_json_line(out, &record)
+        } else {
+            writeln!(
+                out,
+                "{}\t{}\t{}",
+                record.path.display(),
+                record.account.unwrap_or("-"),
+                record.origin.unwrap_or("-")
+            )
+        }
+        .and_then(|_| out.flush());
+        count += 1;
+    });
+    result?;
+
+    if let Err(stop) = walked {
+        eprintln!("Scan {}; {} repositories found under {} so far, the rest not searched", stop.describe(), count, root.display());
+        return Ok(false);
+    }
+    if !json_lines {
+        eprintln!("{} repositories found under {}", count, root.display());
+    }
+    Ok(true)
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    use crate::testutil::{self, TempHome};
+
+    /// Keeps what had been written at each flush.
+    #[derive(Default)]
+    struct Flushes {
+        buffer: Vec<u8>,
+        flushed: Vec<usize>,
+    }
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flushed.push(self.buffer.len());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines_writes_one_record_per_line_as_it_goes() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        for repo in ["code/work/api", "code/work/web", "elsewhere/tool"] {
+            let status = Command::new("git").args(["init", "-q"]).arg(home.join(repo)).status().unwrap();
+            assert!(status.success());
+        }
+
+        let mut out = Flushes::default();
+        assert!(scan(&manager, &home.path, true, None, &mut out).unwrap());
+
+        let text = String::from_utf8(out.buffer).unwrap();
+        let records: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 3);
+        let work = records.iter().filter(|record| record["account"] == "work").count();
+        assert_eq!(work, 2);
+        let ends: Vec<usize> = text.match_indices('\n').map(|(i, _)| i + 1).collect();
+        assert_eq!(out.flushed, ends, "each record is flushed on its own");
+    }
+}
@@ -0,0 +1,249 @@
+//! Per-account `git send-email` settings (`setup --smtp-server` and
+//! friends), written to the include file as a `[sendemail]` section. The
+//! SMTP password is never stored: send-email asks git's credential helpers
+//! for it, and `--smtp-credential-helper` names the one to use.
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// `sendemail.smtpEncryption`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpEncryption {
+    /// STARTTLS, usually on port 587.
+    Tls,
+    /// TLS from the start, usually on port 465.
+    Ssl,
+    None,
+}
+
+impl SmtpEncryption {
+    fn as_str(self) -> &'static str {
+        match self {
+            SmtpEncryption::Tls => "tls",
+            SmtpEncryption::Ssl => "ssl",
+            SmtpEncryption::None => "none",
+        }
+    }
+
+    /// The port send-email uses when smtpServerPort isn't set.
+    fn default_port(encryption: Option<Self>) -> u16 {
+        match encryption {
+            Some(SmtpEncryption::Tls) => 587,
+            Some(SmtpEncryption::Ssl) => 465,
+            Some(SmtpEncryption::None) | None => 25,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SendEmail {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_encryption: Option<SmtpEncryption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smtp_server_port: Option<u16>,
+    /// `sendemail.from`, e.g. `Jane Doe <jane@example.com>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Credential helper send-email gets the SMTP password from, configured
+    /// for `smtp://<server>` only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential_helper: Option<String>,
+}
+
+impl SendEmail {
+    pub fn is_empty(&self) -> bool {
+        *self == SendEmail::default()
+    }
+
+    pub fn validate(&self, account_name: &str) -> Result<()> {
+        let invalid = |message: String| Err(Error::new(ErrorKind::InvalidInput, format!("Account '{}': {}", account_name, message)));
+        for (flag, value) in [("--smtp-server", &self.smtp_server), ("--smtp-user", &self.smtp_user)] {
+            if value.as_ref().is_some_and(|value| value.is_empty() || value.contains(char::is_whitespace)) {
+                return invalid(format!("{} can't be empty or contain spaces", flag));
+            }
+        }
+        if let Some(from) = &self.from {
+            let address = from.rsplit_once('<').map(|(_, rest)| rest.trim_end_matches('>')).unwrap_or(from);
+            if !address.contains('@') || address.contains(char::is_whitespace) || from.contains(['\n', '#', ';', '"']) {
+                return invalid(format!("--sendemail-from {} is not an email address or Name <address>", from));
+            }
+        }
+        match (self.smtp_server_port, self.smtp_encryption) {
+            (Some(0), _) => return invalid("--smtp-server-port must be between 1 and 65535".to_string()),
+            (Some(_), _) if self.smtp_server.is_none() => return invalid("--smtp-server-port needs --smtp-server".to_string()),
+            // Port 465 is TLS from the first byte and 587 starts in plain
+            // text, so the other encryption can never connect.
+            (Some(465), Some(SmtpEncryption::Tls)) => return invalid("port 465 needs --smtp-encryption ssl".to_string()),
+            (Some(587), Some(SmtpEncryption::Ssl)) => return invalid("port 587 needs --smtp-encryption tls".to_string()),
+            _ => {}
+        }
+        if self.credential_helper.is_some() && self.smtp_server.is_none() {
+            return invalid("--smtp-credential-helper needs --smtp-server".to_string());
+        }
+        if self.credential_helper.as_ref().is_some_and(|helper| helper.trim().is_empty() || helper.contains('\n')) {
+            return invalid("--smtp-credential-helper can't be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// The `[sendemail]` section, and the credential helper for the server.
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let mut out = String::new();
+        let port = self.smtp_server_port.map(|port| port.to_string());
+        let entries = [
+            ("smtpServer", self.smtp_server.as_deref()),
+            ("smtpUser", self.smtp_user.as_deref()),
+            ("smtpEncryption", self.smtp_encryption.map(SmtpEncryption::as_str)),
+            ("smtpServerPort", port.as_deref()),
+            ("from", self.from.as_deref()),
+        ];
+        if entries.iter().any(|(_, value)| value.is_some()) {
+            out.push_str("[sendemail]\n");
+            for (key, value) in entries {
+                if let Some(value) = value {
+                    out.push_str(&format!("    {} = {}\n", key, value));
+                }
+            }
+        }
+        if let (Some(server), Some(helper)) = (&self.smtp_server, &self.credential_helper) {
+            // Quoted, since helpers run through the shell often have `;` in them.
+            let helper = helper.replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("[credential \"smtp://{}\"]\n    helper = \"{}\"\n", server, helper));
+        }
+        out
+    }
+
+    /// One line for `key show`, e.g. `jane@smtp.example.com:587 (tls)`.
+    pub fn describe(&self) -> Option<String> {
+        let server = self.smtp_server.as_ref()?;
+        let user = self.smtp_user.as_ref().map(|user| format!("{}@", user)).unwrap_or_default();
+        let mut out = format!("{}{}:{}", user, server, self.port());
+        if let Some(encryption) = self.smtp_encryption {
+            out.push_str(&format!(" ({})", encryption.as_str()));
+        }
+        if let Some(from) = &self.from {
+            out.push_str(&format!(", from {}", from));
+        }
+        Some(out)
+    }
+
+    fn port(&self) -> u16 {
+        self.smtp_server_port.unwrap_or_else(|| SmtpEncryption::default_port(self.smtp_encryption))
+    }
+
+    /// Connects to the SMTP server within `timeout`, returning what it
+    /// answered. A server that starts in plain text has to greet with 220;
+    /// one speaking TLS from the start only has to accept the connection,
+    /// the handshake being beyond a plain socket. No login is attempted.
+    pub fn test_connection(&self, timeout: Duration) -> Result<String> {
+        let Some(server) = &self.smtp_server else {
+            return Err(Error::new(ErrorKind::InvalidInput, "no SMTP server set"));
+        };
+        let port = self.port();
+        let address = (server.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("{} does not resolve", server)))?;
+        let stream = TcpStream::connect_timeout(&address, timeout)?;
+        if self.smtp_encryption == Some(SmtpEncryption::Ssl) {
+            return Ok(format!("{}:{} accepts connections", server, port));
+        }
+        stream.set_read_timeout(Some(timeout))?;
+        let mut greeting = String::new();
+        BufReader::new(stream).read_line(&mut greeting)?;
+        let greeting = greeting.trim_end();
+        if !greeting.starts_with("220") {
+            let answer = if greeting.is_empty() { "nothing" } else { greeting };
+            return Err(Error::other(format!("{}:{} answered {} instead of an SMTP greeting", server, port, answer)));
+        }
+        Ok(greeting.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::account::SetupOptions;
+    use crate::store;
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn sendemail_section_without_a_password() {
+        let home = TempHome::new();
+        let sendemail = SendEmail {
+            smtp_server: Some("smtp.example.com".to_string()),
+            smtp_user: Some("jane".to_string()),
+            smtp_encryption: Some(SmtpEncryption::Tls),
+            from: Some("Jane Doe <jane@example.com>".to_string()),
+            credential_helper: Some("store".to_string()),
+            ..SendEmail::default()
+        };
+        let options = SetupOptions { sendemail, ..SetupOptions::default() };
+        home.manager().setup_account("work", "jane@example.com", "code/work", "github.com-work", options).unwrap();
+
+        let content = home.read("code/work/.gitconfig");
+        assert!(content.contains(
+            "[sendemail]\n    smtpServer = smtp.example.com\n    smtpUser = jane\n    smtpEncryption = tls\n    from = Jane Doe <jane@example.com>\n"
+        ));
+        assert!(content.contains("[credential \"smtp://smtp.example.com\"]\n    helper = \"store\"\n"));
+        assert!(!content.to_ascii_lowercase().contains("pass"));
+        let stored = fs::read_to_string(store::accounts_path(&home.path)).unwrap();
+        assert!(stored.contains("smtp.example.com") && !stored.to_ascii_lowercase().contains("pass"));
+    }
+
+    #[test]
+    fn encryption_is_tls_ssl_or_none() {
+        assert!(toml::from_str::<SendEmail>("smtp_encryption = \"starttls\"").is_err());
+        assert_eq!(toml::from_str::<SendEmail>("smtp_encryption = \"ssl\"").unwrap().smtp_encryption, Some(SmtpEncryption::Ssl));
+        let mismatched = SendEmail { smtp_server: Some("smtp.example.com".to_string()), smtp_server_port: Some(465), smtp_encryption: Some(SmtpEncryption::Tls), ..SendEmail::default() };
+        assert!(mismatched.validate("work").is_err());
+    }
+
+    #[test]
+    fn port_is_validated_and_shown() {
+        let server = |port, encryption| SendEmail {
+            smtp_server: Some("smtp.example.com".to_string()),
+            smtp_user: Some("jane".to_string()),
+            smtp_server_port: port,
+            smtp_encryption: encryption,
+            ..SendEmail::default()
+        };
+        assert!(server(Some(0), None).validate("work").is_err());
+        assert!(server(Some(587), Some(SmtpEncryption::Ssl)).validate("work").is_err());
+        assert!(SendEmail { smtp_server_port: Some(25), ..SendEmail::default() }.validate("work").is_err());
+        assert!(server(Some(2525), Some(SmtpEncryption::Tls)).validate("work").is_ok());
+
+        assert_eq!(server(None, Some(SmtpEncryption::Ssl)).describe().as_deref(), Some("jane@smtp.example.com:465 (ssl)"));
+        assert_eq!(server(Some(2525), None).describe().as_deref(), Some("jane@smtp.example.com:2525"));
+        assert!(server(Some(2525), None).render().contains("    smtpServerPort = 2525\n"));
+    }
+
+    #[test]
+    fn connection_test_wants_an_smtp_greeting() {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        for (greeting, ok) in ["220 smtp.example.com ESMTP\r\n", "HTTP/1.1 400 Bad Request\r\n"].into_iter().zip([true, false]) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = std::thread::spawn(move || listener.accept().unwrap().0.write_all(greeting.as_bytes()).unwrap());
+            let sendemail = SendEmail { smtp_server: Some("127.0.0.1".to_string()), smtp_server_port: Some(port), ..SendEmail::default() };
+            assert_eq!(sendemail.test_connection(Duration::from_secs(5)).is_ok(), ok, "{}", greeting);
+            server.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! `shell-hook`: a function for the shell's startup file that runs
+//! `status --quiet --warn-on-mismatch` whenever the working directory
+//! changes, so stepping into a repository that would commit under the
+//! wrong identity prints a warning. Loaded with
+//! `eval "$(multigit shell-hook <shell>)"`, or for fish
+//! `multigit shell-hook fish | source`.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// bash has no directory change hook, so PROMPT_COMMAND compares with the
+/// directory of the last prompt. The exit status of the user's command is
+/// passed on for prompts that show it.
+const BASH: &str = r#"# multigit: warn when a repository's identity isn't its account's.
+__multigit_identity_check() {
+    local status=$?
+    if [ "$PWD" != "${__multigit_last_dir-}" ]; then
+        __multigit_last_dir=$PWD
+        command multigit status --quiet --warn-on-mismatch
+    fi
+    return $status
+}
+case ";${PROMPT_COMMAND-};" in
+    *";__multigit_identity_check;"*) ;;
+    *) PROMPT_COMMAND="__multigit_identity_check${PROMPT_COMMAND:+;$PROMPT_COMMAND}" ;;
+esac
+"#;
+
+const ZSH: &str = r#"# multigit: warn when a repository's identity isn't its account's.
+__multigit_identity_check() {
+    command multigit status --quiet --warn-on-mismatch
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook chpwd __multigit_identity_check
+__multigit_identity_check
+"#;
+
+const FISH: &str = r#"# multigit: warn when a repository's identity isn't its account's.
+function __multigit_identity_check --on-variable PWD
+    command multigit status --quiet --warn-on-mismatch
+end
+__multigit_identity_check
+"#;
+
+pub fn hook(shell: Shell) -> &'static str {
+    match shell {
+        Shell::Bash => BASH,
+        Shell::Zsh => ZSH,
+        Shell::Fish => FISH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    use super::*;
+
+    #[test]
+    fn each_shell_gets_its_own_hook_for_the_same_command() {
+        let (bash, zsh, fish) = (hook(Shell::Bash), hook(Shell::Zsh), hook(Shell::Fish));
+        assert_ne!(bash, zsh);
+        assert_ne!(zsh, fish);
+        for hook in [bash, zsh, fish] {
+            assert!(hook.contains("command multigit status --quiet --warn-on-mismatch\n"));
+        }
+        assert!(bash.contains("PROMPT_COMMAND=") && !bash.contains("chpwd"));
+        assert!(zsh.contains("add-zsh-hook chpwd __multigit_identity_check\n") && !zsh.contains("PROMPT_COMMAND"));
+        assert!(fish.contains("--on-variable PWD"));
+    }
+
+    #[test]
+    fn bash_hook_parses() {
+        let Ok(mut child) = Command::new("bash").arg("-n").stdin(Stdio::piped()).spawn() else {
+            return;
+        };
+        child.stdin.take().unwrap().write_all(hook(Shell::Bash).as_bytes()).unwrap();
+        assert!(child.wait().unwrap().success());
+    }
+}
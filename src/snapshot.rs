@@ -0,0 +1,318 @@
+//! Snapshots of every file multigit touches, taken before a big change so
+//! it can be undone: `snapshot create`, `snapshot list` and `snapshot
+//! restore`. They live in `~/.local/share/multigit/snapshots`, outside the
+//! config dir so `uninstall` doesn't take them with it.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::GitAccountManager;
+use crate::fsutil;
+use crate::report::print_table;
+use crate::state::State;
+use crate::store;
+
+/// One file as it was when the snapshot was taken.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    path: PathBuf,
+    /// Name of the copy inside the snapshot's `files` dir; `None` when the
+    /// file didn't exist, so restoring removes it.
+    stored: Option<String>,
+    /// Permission bits of the file (of the link's target for a symlink).
+    #[serde(default)]
+    mode: u32,
+    /// Where `path` pointed when it was a symlink, such as an include file
+    /// kept in the export dir.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    label: String,
+    /// Seconds since the Unix epoch.
+    at: u64,
+    #[serde(default)]
+    keys: bool,
+    /// When `uninstall` finished, for the snapshot it took first: what it
+    /// wrote up to then isn't an edit made outside multigit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uninstalled_at: Option<u64>,
+    files: Vec<SnapshotFile>,
+}
+
+pub fn snapshots_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join(".local/share/multigit/snapshots")
+}
+
+/// `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, minute) = (secs % 86400 / 3600, secs % 3600 / 60);
+    // Howard Hinnant's days-to-civil.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, hour, minute)
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_of(_metadata: &fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Files under `dir`, recursively, leaving out multigit's lock and temp
+/// files.
+fn files_under(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    paths.sort();
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.ends_with(".multigit-lock") || name.ends_with(".multigit-tmp") {
+            continue;
+        }
+        if path.is_dir() {
+            files_under(&path, out);
+        } else {
+            out.push(path);
+        }
+    }
+}
+
+fn load_manifest(dir: &Path) -> Result<Manifest> {
+    let path = dir.join("manifest.toml");
+    let content = fs::read_to_string(&path)?;
+    toml::from_str(&content).map_err(|e| Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", path.display(), e)))
+}
+
+impl GitAccountManager {
+    /// Every file a snapshot covers: the global gitconfig, the ssh config,
+    /// the include files, multigit's config dir and, with `include_keys`,
+    /// the accounts' keys.
+    fn snapshot_paths(&self, include_keys: bool) -> Vec<PathBuf> {
+        let mut paths = vec![self.global_gitconfig_path(), self.home_dir.join(".ssh/config")];
+        for account in self.sorted_accounts(true) {
+            paths.push(account.codebase_dir_path.join(".gitconfig"));
+            if include_keys {
+                for key in account.identity_files() {
+                    paths.push(PathBuf::from(key));
+                    paths.push(PathBuf::from(format!("{}.pub", key)));
+                }
+            }
+        }
+        files_under(&store::config_dir(&self.home_dir), &mut paths);
+        let mut seen = HashSet::new();
+        paths.retain(|path| seen.insert(path.clone()));
+        paths
+    }
+
+    /// Copies every multigit-touched file into a new snapshot and returns its
+    /// id. Private keys are only copied with `include_keys`.
+    pub fn create_snapshot(&self, label: &str, include_keys: bool) -> Result<String> {
+        self.take_snapshot(label, include_keys, Vec::new())
+    }
+
+    /// `create_snapshot`, also covering `extra`.
+    pub(crate) fn take_snapshot(&self, label: &str, include_keys: bool, extra: Vec<PathBuf>) -> Result<String> {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let root = snapshots_dir(&self.home_dir);
+        let mut id = at.to_string();
+        let mut n = 1;
+        while root.join(&id).exists() {
+            n += 1;
+            id = format!("{}-{}", at, n);
+        }
+        let dir = root.join(&id);
+        fsutil::create_dir_all(&dir.join("files"))?;
+        // Keys and tokens may be in here.
+        set_mode(&root, 0o700)?;
+        set_mode(&dir, 0o700)?;
+
+        let mut paths = self.snapshot_paths(include_keys);
+        for path in extra {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        let mut files = Vec::new();
+        for (i, path) in paths.into_iter().enumerate() {
+            let link = fs::read_link(&path).ok();
+            let Ok(metadata) = fs::metadata(&path) else {
+                files.push(SnapshotFile { path, stored: None, mode: 0, link });
+                continue;
+            };
+            let stored = i.to_string();
+            fs::copy(&path, dir.join("files").join(&stored)).map_err(|e| fsutil::blocked(e, "read", &path))?;
+            files.push(SnapshotFile { path, stored: Some(stored), mode: mode_of(&metadata), link });
+        }
+
+        let manifest = Manifest { label: label.to_string(), at, keys: include_keys, uninstalled_at: None, files };
+        let content = toml::to_string_pretty(&manifest).map_err(Error::other)?;
+        fsutil::write_atomic(&dir.join("manifest.toml"), content.as_bytes())?;
+        println!("Created snapshot {} ({} files)", id, manifest.files.iter().filter(|file| file.stored.is_some()).count());
+        Ok(id)
+    }
+
+    /// Records on snapshot `id` that `uninstall` is done with the files.
+    pub(crate) fn mark_uninstalled(&self, id: &str) -> Result<()> {
+        let dir = snapshots_dir(&self.home_dir).join(id);
+        let mut manifest = load_manifest(&dir)?;
+        manifest.uninstalled_at = Some(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+        let content = toml::to_string_pretty(&manifest).map_err(Error::other)?;
+        fsutil::write_atomic(&dir.join("manifest.toml"), content.as_bytes())
+    }
+
+    pub fn list_snapshots(&self) -> Result<()> {
+        let root = snapshots_dir(&self.home_dir);
+        let mut ids: Vec<String> = match fs::read_dir(&root) {
+            Ok(entries) => entries.filter_map(|entry| Some(entry.ok()?.file_name().to_string_lossy().into_owned())).collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        if ids.is_empty() {
+            println!("No snapshots in {}", root.display());
+            return Ok(());
+        }
+        ids.sort();
+
+        let mut rows = vec![["ID".to_string(), "TAKEN (UTC)".to_string(), "FILES".to_string(), "LABEL".to_string()]];
+        for id in ids {
+            match load_manifest(&root.join(&id)) {
+                Ok(manifest) => {
+                    let count = manifest.files.iter().filter(|file| file.stored.is_some()).count();
+                    let files = if manifest.keys { format!("{} +keys", count) } else { count.to_string() };
+                    rows.push([id, format_time(manifest.at), files, manifest.label]);
+                }
+                Err(e) => eprintln!("warning: {}: {}", id, e),
+            }
+        }
+        print_table(&rows);
+        Ok(())
+    }
+
+    /// Files that differ from the snapshot because of something other than
+    /// multigit: changed after the snapshot and after multigit last wrote
+    /// them. multigit's own config dir is left out, as only multigit
+    /// writes there.
+    fn changed_since(&self, dir: &Path, manifest: &Manifest) -> Result<Vec<PathBuf>> {
+        let state = State::load(&self.home_dir)?;
+        let config_dir = store::config_dir(&self.home_dir);
+        let mut changed = Vec::new();
+        for file in &manifest.files {
+            if file.path.starts_with(&config_dir) {
+                continue;
+            }
+            let current = fs::read(&file.path).ok();
+            let snapshot = match &file.stored {
+                Some(stored) => Some(fs::read(dir.join("files").join(stored))?),
+                None => None,
+            };
+            if current == snapshot {
+                continue;
+            }
+            let modified = fs::metadata(&file.path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let by_multigit = match (modified, state.last_write_to(&file.path)) {
+                // uninstall took the state file with it.
+                (Some(modified), _) if manifest.uninstalled_at.is_some_and(|done| modified <= done) => true,
+                (Some(modified), Some(written)) => written >= manifest.at && modified <= written,
+                // Removed since; multigit's only removals are whole accounts.
+                (None, _) => true,
+                (Some(_), None) => false,
+            };
+            if !by_multigit {
+                changed.push(file.path.clone());
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Puts every file of snapshot `id` back as it was, writing through
+    /// symlinks and restoring permissions. Files that didn't exist then are
+    /// removed. Refuses when a file was changed outside multigit since,
+    /// unless `force`.
+    pub fn restore_snapshot(&self, id: &str, force: bool) -> Result<bool> {
+        let dir = snapshots_dir(&self.home_dir).join(id);
+        if id.contains('/') || !dir.join("manifest.toml").exists() {
+            return Err(Error::new(ErrorKind::NotFound, format!("No snapshot '{}'; see `multigit snapshot list`", id)));
+        }
+        let manifest = load_manifest(&dir)?;
+
+        let changed = self.changed_since(&dir, &manifest)?;
+        if !changed.is_empty() && !force {
+            for path in &changed {
+                println!("changed: {} was edited outside multigit since the snapshot", path.display());
+            }
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "Not restoring over changes made outside multigit; rerun with --force to discard them",
+            ));
+        }
+
+        let mut restored = 0;
+        for file in &manifest.files {
+            let Some(stored) = &file.stored else {
+                if fs::symlink_metadata(&file.path).is_ok() {
+                    fs::remove_file(&file.path).map_err(|e| fsutil::blocked(e, "remove", &file.path))?;
+                    println!("Removed {}", file.path.display());
+                }
+                continue;
+            };
+            if let Some(link) = &file.link {
+                if fs::read_link(&file.path).ok().as_ref() != Some(link) {
+                    if fs::symlink_metadata(&file.path).is_ok() {
+                        fs::remove_file(&file.path).map_err(|e| fsutil::blocked(e, "remove", &file.path))?;
+                    }
+                    #[cfg(unix)]
+                    std::os::unix::fs::symlink(link, &file.path)?;
+                }
+            }
+            let target = fsutil::write_target(&file.path);
+            let content = fs::read(dir.join("files").join(stored))?;
+            if fs::read(&target).ok().as_ref() != Some(&content) {
+                fsutil::write_atomic(&target, &content)?;
+                restored += 1;
+            }
+            if file.mode != 0 {
+                set_mode(&target, file.mode)?;
+            }
+        }
+        println!("Restored snapshot {} ({}): {} file(s) changed back", id, manifest.label, restored);
+        Ok(true)
+    }
+}
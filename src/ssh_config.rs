@@ -0,0 +1,142 @@
+//! Minimal line-oriented view of an OpenSSH client config file.
+
+use std::path::Path;
+
+use crate::managed;
+
+/// A `Host` block, as the range of lines it spans in the file.
+pub struct HostBlock {
+    pub patterns: Vec<String>,
+    /// Index of the `Host` line.
+    pub start: usize,
+    /// Index one past the last line belonging to the block.
+    pub end: usize,
+}
+
+impl HostBlock {
+    /// Whether ssh applies this block to `host`, honouring `!` negations.
+    pub fn matches(&self, host: &str) -> bool {
+        let negated = self.patterns.iter().filter_map(|p| p.strip_prefix('!')).any(|p| pattern_matches(p, host));
+        !negated && self.patterns.iter().any(|p| !p.starts_with('!') && pattern_matches(p, host))
+    }
+}
+
+/// Splits a config line into its lowercased keyword and value, skipping
+/// blank lines and comments. Accepts both `Keyword value` and `Keyword=value`.
+pub fn directive(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let split = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(split);
+    let value = rest.trim_start().strip_prefix('=').unwrap_or(rest).trim();
+    Some((keyword.to_ascii_lowercase(), value))
+}
+
+/// ssh `Host` pattern matching with `*` and `?`, ignoring case.
+pub fn pattern_matches(pattern: &str, host: &str) -> bool {
+    fn matches(pattern: &[u8], host: &[u8]) -> bool {
+        match (pattern.first(), host.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => matches(&pattern[1..], host) || (!host.is_empty() && matches(pattern, &host[1..])),
+            (Some(b'?'), Some(_)) => matches(&pattern[1..], &host[1..]),
+            (Some(p), Some(h)) if p.eq_ignore_ascii_case(h) => matches(&pattern[1..], &host[1..]),
+            _ => false,
+        }
+    }
+    matches(pattern.as_bytes(), host.as_bytes())
+}
+
+pub fn host_blocks(lines: &[&str]) -> Vec<HostBlock> {
+    let mut blocks: Vec<HostBlock> = Vec::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        // A managed-block marker closes the block before it.
+        if managed::is_marker(line) {
+            if let Some(last) = blocks.last_mut().filter(|last| last.end == lines.len()) {
+                last.end = i;
+            }
+            continue;
+        }
+        let Some((keyword, value)) = directive(line) else {
+            continue;
+        };
+        if keyword != "host" && keyword != "match" {
+            continue;
+        }
+
+        if let Some(last) = blocks.last_mut().filter(|last| last.end == lines.len()) {
+            last.end = i;
+        }
+        if keyword == "host" {
+            blocks.push(HostBlock {
+                patterns: value.split_whitespace().map(str::to_string).collect(),
+                start: i,
+                end: lines.len(),
+            });
+        }
+    }
+
+    blocks
+}
+
+pub fn find_host<'a>(blocks: &'a [HostBlock], alias: &str) -> Option<&'a HostBlock> {
+    blocks.iter().find(|block| block.patterns.iter().any(|p| p == alias))
+}
+
+/// Returns the line index and value of every `IdentityFile` in the block.
+pub fn identity_files(lines: &[&str], block: &HostBlock) -> Vec<(usize, String)> {
+    (block.start + 1..block.end)
+        .filter_map(|i| match directive(lines[i]) {
+            Some((keyword, value)) if keyword == "identityfile" => Some((i, value.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Expands a leading `~/` the way ssh does for IdentityFile paths.
+pub fn expand_tilde(value: &str, home_dir: &Path) -> String {
+    match value.strip_prefix("~/") {
+        Some(rest) => home_dir.join(rest).to_string_lossy().into_owned(),
+        None => value.to_string(),
+    }
+}
+
+/// The reverse of `expand_tilde`: `value` as `~/...` when it's inside the
+/// home dir.
+pub fn contract_tilde(value: &str, home_dir: &Path) -> String {
+    match Path::new(value).strip_prefix(home_dir) {
+        Ok(rest) if !rest.as_os_str().is_empty() => format!("~/{}{}", rest.display(), if value.ends_with('/') { "/" } else { "" }),
+        _ => value.to_string(),
+    }
+}
+
+/// Rewrites the block so that `identity_files` are its only IdentityFile
+/// lines, in order, at the position and indentation of the first existing one.
+pub fn replace_identity_files(lines: &[&str], block: &HostBlock, identity_files: &[String]) -> Vec<String> {
+    let existing = self::identity_files(lines, block);
+    let mut out = Vec::with_capacity(lines.len() + identity_files.len());
+
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(pos) = existing.iter().position(|(idx, _)| *idx == i) {
+            if pos == 0 {
+                let indent: String = line.chars().take_while(|c| c.is_whitespace()).collect();
+                for identity_file in identity_files {
+                    out.push(format!("{}IdentityFile {}", indent, identity_file));
+                }
+            }
+            continue;
+        }
+
+        out.push(line.to_string());
+        if i == block.start && existing.is_empty() {
+            for identity_file in identity_files {
+                out.push(format!("    IdentityFile {}", identity_file));
+            }
+        }
+    }
+
+    out
+}
@@ -0,0 +1,155 @@
+//! Bookkeeping multigit keeps about past runs, separate from the accounts
+//! the user configured.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fsutil;
+use crate::store;
+use crate::tamper;
+
+/// A network step that was skipped while offline and still has to run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeferredStep {
+    pub account: String,
+    pub step: String,
+}
+
+/// When multigit last wrote a file on behalf of an account.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WriteRecord {
+    pub account: String,
+    pub path: PathBuf,
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+    /// `tamper::hash` of the account's marked block as written, when the
+    /// file has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+/// A key multigit created or registered, remembered so the key can still
+/// be recognised on the provider after the file is deleted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub account: String,
+    pub path: String,
+    /// `SHA256:...`
+    pub fingerprint: String,
+    /// When multigit first recorded the key, in seconds since the Unix
+    /// epoch; unset for keys recorded before this was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct State {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deferred: Vec<DeferredStep>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub written: Vec<WriteRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<KeyRecord>,
+}
+
+pub fn state_path(home_dir: &Path) -> PathBuf {
+    store::config_dir(home_dir).join("state.toml")
+}
+
+impl State {
+    pub fn load(home_dir: &Path) -> Result<State> {
+        let path = state_path(home_dir);
+        if !path.exists() {
+            return Ok(State::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        toml::from_str(&content).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", path.display(), e))
+        })
+    }
+
+    pub fn save(&self, home_dir: &Path) -> Result<()> {
+        let content = toml::to_string_pretty(self).map_err(Error::other)?;
+        fsutil::write_atomic(&state_path(home_dir), content.as_bytes())
+    }
+
+    pub fn defer(&mut self, account: &str, step: &str) {
+        let item = DeferredStep {
+            account: account.to_string(),
+            step: step.to_string(),
+        };
+        if !self.deferred.contains(&item) {
+            self.deferred.push(item);
+        }
+    }
+
+    /// Records a write to `path` that just happened, with the hash of the
+    /// account's block as it now is on disk.
+    pub fn record_write(&mut self, account: &str, path: &Path) {
+        let at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let hash = fsutil::read_to_string(path)
+            .ok()
+            .and_then(|content| tamper::block_body(&content, account))
+            .map(|body| tamper::hash(&body));
+        self.written.retain(|record| record.account != account || record.path != path);
+        self.written.push(WriteRecord {
+            account: account.to_string(),
+            path: path.to_path_buf(),
+            at,
+            hash,
+        });
+    }
+
+    /// Drops everything recorded for `account`.
+    pub fn forget(&mut self, account: &str) {
+        self.deferred.retain(|step| step.account != account);
+        self.written.retain(|record| record.account != account);
+        self.keys.retain(|record| record.account != account);
+    }
+
+    pub fn record_key(&mut self, account: &str, path: &str, fingerprint: &str) {
+        let first_seen = match self.keys.iter().find(|record| record.fingerprint == fingerprint) {
+            Some(record) => record.first_seen,
+            None => SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|since| since.as_secs()),
+        };
+        self.keys.retain(|record| record.fingerprint != fingerprint);
+        self.keys.push(KeyRecord {
+            account: account.to_string(),
+            path: path.to_string(),
+            fingerprint: fingerprint.to_string(),
+            first_seen,
+        });
+    }
+
+    /// Latest write to `path` for `account`, comparing resolved paths so a
+    /// symlinked include file matches its target.
+    fn latest_write(&self, account: &str, path: &Path) -> Option<&WriteRecord> {
+        let resolve = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let path = resolve(path);
+        self.written
+            .iter()
+            .filter(|record| record.account == account && resolve(&record.path) == path)
+            .max_by_key(|record| record.at)
+    }
+
+    pub fn last_written(&self, account: &str, path: &Path) -> Option<u64> {
+        self.latest_write(account, path).map(|record| record.at)
+    }
+
+    /// Latest write to `path` for any account.
+    pub fn last_write_to(&self, path: &Path) -> Option<u64> {
+        let resolve = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let path = resolve(path);
+        self.written.iter().filter(|record| resolve(&record.path) == path).map(|record| record.at).max()
+    }
+
+    /// Hash of `account`'s block in `path` as multigit last left it.
+    pub fn block_hash(&self, account: &str, path: &Path) -> Option<&str> {
+        self.latest_write(account, path)?.hash.as_deref()
+    }
+}
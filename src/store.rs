@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::GitAccount;
+use crate::fsutil;
+use crate::include_if::Placement;
+
+/// Options that apply to every account rather than one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Settings {
+    /// Keep Host blocks in `~/.config/multigit/ssh_config`, included from
+    /// `~/.ssh/config`, instead of editing `~/.ssh/config` directly.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub managed_ssh_config: bool,
+    /// Write each account's per-directory gitconfig here (e.g. a dotfiles
+    /// repo) and symlink `<codebase>/.gitconfig` to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gitconfig_export_dir: Option<PathBuf>,
+    /// Name for the keys setup generates, with `{account}`, `{host}` and
+    /// `{type}` placeholders; `keys::DEFAULT_KEY_FILENAME` when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_filename: Option<String>,
+    /// Put in front of the email in the comment of every key multigit
+    /// generates, e.g. `[multigit]`, to tell them apart on the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment_prefix: Option<String>,
+    /// Leave the global gitconfig to the user: print the includeIf entries
+    /// to add instead of writing them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_global_edit: bool,
+    /// Keep the managed includeIf entries at the top or bottom of the
+    /// global gitconfig; where they were added when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_placement: Option<Placement>,
+    /// Email domains accounts must use, by ssh hostname, with `*` for
+    /// every hostname (`--require-email-domain`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub email_domains: BTreeMap<String, Vec<String>>,
+    /// Only warn about emails outside `email_domains`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub email_domain_warn_only: bool,
+    /// Write includeIf conditions and paths inside the home dir with `~/`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub home_relative_paths: bool,
+}
+
+/// The accounts file format this binary reads and writes. Files from before
+/// the format was versioned have no `version` and are version 1.
+pub const ACCOUNTS_VERSION: u32 = 2;
+
+/// `MIGRATIONS[n]` upgrades a version `n + 1` file to version `n + 2`.
+const MIGRATIONS: [fn(&mut toml::Table); 1] = [explicit_keychain];
+
+/// Version 1 left `keychain` out for accounts set up before `--keychain`,
+/// which always got the keychain directives; version 2 records them as
+/// `store`.
+fn explicit_keychain(file: &mut toml::Table) {
+    let Some(toml::Value::Array(accounts)) = file.get_mut("accounts") else {
+        return;
+    };
+    for account in accounts.iter_mut().filter_map(toml::Value::as_table_mut) {
+        account.entry("keychain").or_insert_with(|| toml::Value::String("store".to_string()));
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccountsFile {
+    version: u32,
+    #[serde(default)]
+    settings: Settings,
+    #[serde(default)]
+    accounts: Vec<GitAccount>,
+}
+
+/// Directory holding multigit's own files, `~/.config/multigit`.
+pub fn config_dir(home_dir: &Path) -> PathBuf {
+    home_dir.join(".config").join("multigit")
+}
+
+pub fn accounts_path(home_dir: &Path) -> PathBuf {
+    config_dir(home_dir).join("accounts.toml")
+}
+
+/// The ssh config file multigit writes its Host blocks to in managed mode.
+pub fn managed_ssh_config_path(home_dir: &Path) -> PathBuf {
+    config_dir(home_dir).join("ssh_config")
+}
+
+pub fn load_accounts(home_dir: &Path) -> Result<(Settings, Vec<GitAccount>)> {
+    let path = accounts_path(home_dir);
+    if !path.exists() {
+        return Ok((Settings::default(), Vec::new()));
+    }
+
+    let content = fs::read_to_string(&path)?;
+    let invalid = |e: &dyn std::fmt::Display| Error::new(ErrorKind::InvalidData, format!("Failed to parse {}: {}", path.display(), e));
+    let mut table: toml::Table = toml::from_str(&content).map_err(|e| invalid(&e))?;
+    let version = match table.get("version") {
+        None => 1,
+        Some(toml::Value::Integer(version)) if *version >= 1 => u32::try_from(*version).unwrap_or(u32::MAX),
+        Some(version) => return Err(invalid(&format!("version = {} is not a format version", version))),
+    };
+    if version > ACCOUNTS_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} is format version {}, written by a newer multigit; this one only understands up to version {}. Upgrade multigit to use it",
+                path.display(),
+                version,
+                ACCOUNTS_VERSION
+            ),
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(&mut table);
+    }
+    table.insert("version".to_string(), toml::Value::Integer(ACCOUNTS_VERSION.into()));
+    let file: AccountsFile = table.try_into().map_err(|e| invalid(&e))?;
+
+    if version < ACCOUNTS_VERSION {
+        // A file that can't be written back is still migrated on every load.
+        match save_accounts(home_dir, &file.settings, file.accounts.clone()) {
+            Ok(()) => eprintln!("note: upgraded {} from format version {} to {}", path.display(), version, ACCOUNTS_VERSION),
+            Err(e) => eprintln!("warning: couldn't write the upgraded {}: {}", path.display(), e),
+        }
+    }
+    Ok((file.settings, file.accounts))
+}
+
+pub fn save_accounts(home_dir: &Path, settings: &Settings, accounts: Vec<GitAccount>) -> Result<()> {
+    let file = AccountsFile { version: ACCOUNTS_VERSION, settings: settings.clone(), accounts };
+    let content = toml::to_string_pretty(&file).map_err(Error::other)?;
+    fsutil::write_atomic(&accounts_path(home_dir), content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::Keychain;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn version_1_file_is_migrated_on_load() {
+        let home = TempHome::new();
+        save_accounts(&home.path, &Settings::default(), vec![testutil::account(&home, "work")]).unwrap();
+        let path = accounts_path(&home.path);
+        let v2 = fs::read_to_string(&path).unwrap();
+        assert!(v2.starts_with("version = 2\n") && !v2.contains("keychain"));
+        fs::write(&path, v2.replace("version = 2\n", "")).unwrap();
+
+        let (_, accounts) = load_accounts(&home.path).unwrap();
+        assert_eq!(accounts[0].keychain, Some(Keychain::Store));
+        let migrated = fs::read_to_string(&path).unwrap();
+        assert!(migrated.starts_with("version = 2\n"));
+        assert!(migrated.contains("keychain = \"store\""));
+        assert_eq!(load_accounts(&home.path).unwrap().1[0].keychain, Some(Keychain::Store));
+    }
+
+    #[test]
+    fn newer_file_is_refused() {
+        let home = TempHome::new();
+        let path = accounts_path(&home.path);
+        fsutil::write_atomic(&path, b"version = 3\n").unwrap();
+        let error = load_accounts(&home.path).unwrap_err();
+        assert!(error.to_string().contains("written by a newer multigit"), "{}", error);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "version = 3\n");
+    }
+}
@@ -0,0 +1,252 @@
+//! Managed blocks the user edited by hand since multigit last wrote them.
+//! Every write records a hash of the block it left behind, so a block that
+//! no longer matches its hash was changed by someone else, and rewriting it
+//! would throw that change away.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::account::GitAccountManager;
+use crate::diff;
+use crate::fsutil;
+use crate::gitconfig;
+use crate::keys::prompt;
+use crate::managed;
+use crate::state::State;
+
+/// Width of each column when the two versions are shown side by side.
+const COLUMN: usize = 38;
+
+/// What to do with a block edited by hand, chosen up front with
+/// `--force-ours` or `--force-theirs`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resolution {
+    /// Keep the block as it is on disk.
+    Ours,
+    /// Replace it with what multigit would write.
+    Theirs,
+}
+
+/// What rewriting an include file does with a `[user]` section edited by
+/// hand inside multigit's region (`--user-section`).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum UserSection {
+    /// Put the account's name and email back, without asking when
+    /// nothing else in the region was edited.
+    #[default]
+    Overwrite,
+    /// Leave the edited section as it is and warn that it drifted.
+    Keep,
+}
+
+/// `body` with its `[user]` section, header to the next section, replaced
+/// by the one in `from`, or dropped when `from` has none.
+fn with_user_section(body: &str, from: &str) -> String {
+    let user_lines = |text: &str| -> (Vec<String>, Option<(usize, usize)>) {
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let refs: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let range = gitconfig::sections(&refs).into_iter().find(|section| section.header.eq_ignore_ascii_case("user")).map(|section| (section.start, section.end));
+        (lines, range)
+    };
+    let (mut lines, range) = user_lines(body);
+    let (from_lines, from_range) = user_lines(from);
+    let replacement = from_range.map(|(start, end)| from_lines[start..end].to_vec()).unwrap_or_default();
+    match range {
+        Some((start, end)) => {
+            lines.splice(start..end, replacement);
+        }
+        None => lines.extend(replacement),
+    }
+    let mut out = lines.join("\n");
+    out.push('\n');
+    out
+}
+
+/// The lines between the markers of `account`'s first region in `content`.
+pub fn block_body(content: &str, account: &str) -> Option<String> {
+    let lines: Vec<&str> = content.lines().collect();
+    let block = managed::blocks(&lines).into_iter().find(|block| block.account == account)?;
+    let mut body = lines[block.start + 1..block.end - 1].join("\n");
+    body.push('\n');
+    Some(body)
+}
+
+/// FNV-1a of the body without trailing blank lines, which stays the same
+/// from one build to the next, unlike std's hasher.
+pub fn hash(body: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in body.trim_end().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+impl GitAccountManager {
+    /// Applies `--user-section` to the include file `path` about to get
+    /// `intended` for `account`. Returns what to write, and whether it can
+    /// be written without `resolve_tamper` because `[user]` is the only
+    /// part of the region edited by hand.
+    pub fn user_section_edits(&self, account: &str, path: &Path, intended: String) -> Result<(String, bool)> {
+        let Some(current) = fsutil::read_to_string(path).ok().and_then(|content| block_body(&content, account)) else {
+            return Ok((intended, false));
+        };
+        if with_user_section(&intended, &current).trim_end() == intended.trim_end() {
+            return Ok((intended, false));
+        }
+        // Taken to be what multigit last wrote when its [user] hasn't
+        // changed since, which the recorded hash confirms.
+        let recorded = State::load(&self.home_dir)?.block_hash(account, path).map(str::to_string);
+        let user_only = recorded.as_deref() == Some(hash(&with_user_section(&current, &intended)).as_str());
+        let other_edits = !user_only && recorded.is_some_and(|recorded| recorded != hash(&current));
+        match self.user_section {
+            UserSection::Keep => {
+                println!("warning: {}: keeping the [user] you edited in {}'s block; it differs from the account's name and email", path.display(), account);
+                Ok((with_user_section(&intended, &current), user_only))
+            }
+            UserSection::Overwrite => {
+                if !other_edits {
+                    println!("Replacing the hand-edited [user] in {}'s block in {}", account, path.display());
+                }
+                Ok((intended, user_only))
+            }
+        }
+    }
+
+    /// Whether `account`'s region in `path` may be replaced by `intended`.
+    /// A region that still hashes to what multigit last wrote, or that has
+    /// no recorded hash, may. One edited since is shown next to `intended`
+    /// and the user picks which to keep; `--force-ours` and
+    /// `--force-theirs` pick without asking. The hash is left as it was
+    /// when the user's version is kept, so the next rewrite asks again.
+    pub fn resolve_tamper(&self, account: &str, path: &Path, intended: &str) -> Result<bool> {
+        let Ok(content) = fsutil::read_to_string(path) else {
+            return Ok(true);
+        };
+        let Some(current) = block_body(&content, account) else {
+            return Ok(true);
+        };
+        if current.trim_end() == intended.trim_end() {
+            return Ok(true);
+        }
+        let state = State::load(&self.home_dir)?;
+        match state.block_hash(account, path) {
+            Some(recorded) if recorded != hash(&current) => {}
+            _ => return Ok(true),
+        }
+
+        let resolution = match self.tamper {
+            Some(resolution) => resolution,
+            None => {
+                println!("{}: {}'s block was edited since multigit wrote it.", path.display(), account);
+                println!("{:<width$}   multigit's version", "yours", width = COLUMN);
+                print!("{}", diff::side_by_side(&current, intended, COLUMN));
+                let answer = prompt("[k]eep yours, [t]ake multigit's, or [a]bort to merge by hand")?;
+                match answer.to_ascii_lowercase().as_str() {
+                    "k" | "keep" => Resolution::Ours,
+                    "t" | "take" => Resolution::Theirs,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::Interrupted,
+                            format!(
+                                "Left {} alone; merge the block by hand, then rerun with --force-ours to keep the result",
+                                path.display()
+                            ),
+                        ))
+                    }
+                }
+            }
+        };
+
+        if resolution == Resolution::Ours {
+            println!("Kept your edit of {}'s block in {}; multigit's changes to it were not written", account, path.display());
+        }
+        Ok(resolution == Resolution::Theirs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    /// Sets up `work` and hand-edits an option inside its include file's
+    /// region, as a user fixing it would.
+    fn hand_fixed(home: &TempHome) -> (GitAccountManager, String) {
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let written = home.read("code/work/.gitconfig");
+        let fixed = written.replace("insteadOf = git@github.com:", "insteadOf = git@github.com:acme/");
+        assert_ne!(fixed, written);
+        home.write("code/work/.gitconfig", &fixed);
+        (manager, fixed)
+    }
+
+    #[test]
+    fn a_hand_fix_is_kept_with_force_ours() {
+        let home = TempHome::new();
+        let (mut manager, fixed) = hand_fixed(&home);
+        manager.tamper = Some(Resolution::Ours);
+
+        manager.setup_local_gitconfig(&manager.accounts["work"]).unwrap();
+        assert_eq!(home.read("code/work/.gitconfig"), fixed);
+        // The hash stays at multigit's version, so the next rewrite asks again.
+        let intended = manager.include_file_body(&manager.accounts["work"]).unwrap();
+        assert!(!manager.resolve_tamper("work", &home.join("code/work/.gitconfig"), &intended).unwrap());
+    }
+
+    #[test]
+    fn a_hand_fix_is_replaced_only_with_force_theirs() {
+        let home = TempHome::new();
+        let (mut manager, fixed) = hand_fixed(&home);
+        manager.tamper = Some(Resolution::Theirs);
+
+        manager.setup_local_gitconfig(&manager.accounts["work"]).unwrap();
+        let content = home.read("code/work/.gitconfig");
+        assert_ne!(content, fixed);
+        assert!(content.contains("insteadOf = git@github.com:\n"));
+    }
+
+    #[test]
+    fn an_untouched_block_is_rewritten_without_asking() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        // Nothing was edited, so there is nothing to protect.
+        manager.tamper = Some(Resolution::Ours);
+        let path = home.join("code/work/.gitconfig");
+        assert!(manager.resolve_tamper("work", &path, "[user]\n    email = new@example.com\n").unwrap());
+    }
+
+    /// Sets up `work` and changes the email inside its include file's region.
+    fn hand_edited_email(home: &TempHome) -> GitAccountManager {
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let written = home.read("code/work/.gitconfig");
+        home.write("code/work/.gitconfig", &written.replace("email = work@example.com", "email = me@elsewhere.com"));
+        manager
+    }
+
+    #[test]
+    fn an_edited_email_is_put_back_by_default() {
+        let home = TempHome::new();
+        let manager = hand_edited_email(&home);
+        assert_eq!(manager.user_section, UserSection::Overwrite);
+
+        manager.setup_local_gitconfig(&manager.accounts["work"]).unwrap();
+        let content = home.read("code/work/.gitconfig");
+        assert!(content.contains("email = work@example.com") && !content.contains("me@elsewhere.com"), "{}", content);
+    }
+
+    #[test]
+    fn an_edited_email_is_kept_with_user_section_keep() {
+        let home = TempHome::new();
+        let mut manager = hand_edited_email(&home);
+        manager.user_section = UserSection::Keep;
+
+        manager.setup_local_gitconfig(&manager.accounts["work"]).unwrap();
+        let content = home.read("code/work/.gitconfig");
+        assert!(content.contains("email = me@elsewhere.com") && !content.contains("work@example.com"), "{}", content);
+    }
+}
@@ -0,0 +1,156 @@
+//! Text templates for the blocks multigit writes. Users can replace the
+//! built-in ones by creating files under `~/.config/multigit/templates/`.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::store;
+
+#[derive(Clone, Copy)]
+pub enum TemplateKind {
+    SshHost,
+    /// Host block for `setup --minimal`, which also never gets the keychain
+    /// directives.
+    SshHostMinimal,
+    GitconfigInclude,
+}
+
+pub const ALL: &[TemplateKind] = &[TemplateKind::SshHost, TemplateKind::SshHostMinimal, TemplateKind::GitconfigInclude];
+
+/// Placeholders every template may use.
+pub const PLACEHOLDERS: &[&str] = &["name", "email", "alias", "hostname", "identity_file", "codebase_dir"];
+
+const SSH_HOST: &str = "Host {alias}
+    HostName {hostname}
+    User git
+    IdentityFile {identity_file}
+";
+
+const SSH_HOST_MINIMAL: &str = "Host {alias}
+    HostName {hostname}
+    User git
+    IdentityFile {identity_file}
+    IdentitiesOnly yes
+";
+
+const GITCONFIG_INCLUDE: &str = "[url \"git@{alias}:\"]
+    insteadOf = git@{hostname}:
+[user]
+    name = {name}
+    email = {email}
+";
+
+impl TemplateKind {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            TemplateKind::SshHost => "ssh_host.tmpl",
+            TemplateKind::SshHostMinimal => "ssh_host_minimal.tmpl",
+            TemplateKind::GitconfigInclude => "gitconfig_include.tmpl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TemplateKind> {
+        let name = name.strip_suffix(".tmpl").unwrap_or(name);
+        ALL.iter().copied().find(|kind| kind.file_name().strip_suffix(".tmpl") == Some(name))
+    }
+
+    pub fn builtin(self) -> &'static str {
+        match self {
+            TemplateKind::SshHost => SSH_HOST,
+            TemplateKind::SshHostMinimal => SSH_HOST_MINIMAL,
+            TemplateKind::GitconfigInclude => GITCONFIG_INCLUDE,
+        }
+    }
+}
+
+pub fn templates_dir(home_dir: &Path) -> PathBuf {
+    store::config_dir(home_dir).join("templates")
+}
+
+/// The user's template if they created one, otherwise the built-in.
+pub fn load(home_dir: &Path, kind: TemplateKind) -> Result<String> {
+    let path = templates_dir(home_dir).join(kind.file_name());
+    if path.exists() {
+        fs::read_to_string(path)
+    } else {
+        Ok(kind.builtin().to_string())
+    }
+}
+
+/// Substitutes `{placeholder}`s from `vars`; `{{` and `}}` produce literal
+/// braces. Placeholders that aren't in `vars` are an error rather than being
+/// left in the generated config.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> Result<String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        if let Some(after) = tail.strip_prefix("{{") {
+            out.push('{');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("}}") {
+            out.push('}');
+            rest = after;
+        } else if tail.starts_with('}') {
+            return Err(Error::new(ErrorKind::InvalidData, "Unmatched '}' in template; use '}}' for a literal brace"));
+        } else {
+            let close = tail.find('}').ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "Unclosed '{' in template; use '{{' for a literal brace")
+            })?;
+            let name = &tail[1..close];
+            let value = vars.iter().find(|(key, _)| *key == name).map(|(_, value)| *value).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Unknown placeholder {{{}}} in template; known: {}",
+                        name,
+                        vars.iter().map(|(key, _)| *key).collect::<Vec<_>>().join(", ")
+                    ),
+                )
+            })?;
+            out.push_str(value);
+            rest = &tail[close + 1..];
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Renders every template with dummy values so broken user templates are
+/// reported before setup changes anything.
+pub fn validate(home_dir: &Path) -> Result<()> {
+    let vars: Vec<(&str, &str)> = PLACEHOLDERS.iter().map(|name| (*name, "")).collect();
+    for kind in ALL {
+        render(&load(home_dir, *kind)?, &vars).map_err(|e| {
+            Error::new(e.kind(), format!("{}: {}", templates_dir(home_dir).join(kind.file_name()).display(), e))
+        })?;
+    }
+    Ok(())
+}
+
+/// Prints one built-in template verbatim, or all of them with a header
+/// saying where an override would go.
+pub fn show(home_dir: &Path, name: Option<&str>) -> Result<()> {
+    if let Some(name) = name {
+        let kind = TemplateKind::from_name(name).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("Unknown template '{}'; expected ssh_host, ssh_host_minimal or gitconfig_include", name))
+        })?;
+        print!("{}", kind.builtin());
+        return Ok(());
+    }
+
+    println!("# Placeholders: {}", PLACEHOLDERS.iter().map(|name| format!("{{{}}}", name)).collect::<Vec<_>>().join(" "));
+    for kind in ALL {
+        let path = templates_dir(home_dir).join(kind.file_name());
+        let status = if path.exists() { "overridden" } else { "not overridden" };
+        println!();
+        println!("# {} ({})", path.display(), status);
+        print!("{}", kind.builtin());
+    }
+    Ok(())
+}
@@ -0,0 +1,286 @@
+//! `test`: trying to authenticate through each account's Host alias, several
+//! at a time, and summing up which keys the provider accepts. A key whose
+//! type the two sides can't agree on is told apart from one that isn't on
+//! the account, and gets a rotate to a type that works suggested, or with
+//! `--auto-fix` done. `--web` opens the provider's ssh keys page for each
+//! key it doesn't know, with the public key printed to paste there.
+
+use std::io::{Error, Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::connectivity::{self, Connectivity, Rejection};
+use crate::errors::account_not_found;
+use crate::keys;
+use crate::platform;
+use crate::provider;
+use crate::report::write_table;
+
+/// The type to rotate to when the account's key type was rejected: the
+/// one `--key-type auto` would pick, unless that's the type it has.
+fn fallback_key_type(account: &GitAccount) -> Option<&'static str> {
+    let current = keys::key_strength(&account.ssh_key).map(|(_, key_type)| key_type);
+    let server = connectivity::server_sig_algs(&account.host);
+    let (key_type, _) = keys::choose_key_type(keys::local_key_types().as_deref(), server.as_deref(), account.hostname());
+    (current.as_deref() != Some(key_type)).then_some(key_type)
+}
+
+/// The RESULT and DETAIL columns for one account, `rejection` being why a
+/// denied key was denied and `fallback` the key type that would do.
+fn describe(account: &GitAccount, result: &Connectivity, rejection: Option<&Rejection>, fallback: Option<&str>, timeout: Duration) -> (&'static str, String) {
+    match result {
+        Connectivity::Authenticated(greeting) => match (connectivity::greeting_login(greeting), &account.login) {
+            (Some(actual), Some(expected)) if !actual.eq_ignore_ascii_case(expected) => {
+                ("wrong user", format!("the key authenticates as {}, not {}", actual, expected))
+            }
+            _ => ("ok", greeting.clone()),
+        },
+        Connectivity::Denied => match (rejection, fallback) {
+            (Some(Rejection::Algorithm(reason)), Some(key_type)) => (
+                "key type",
+                format!("{}; rerun with --auto-fix, or run multigit key rotate {} --type {}", reason, account.name, key_type),
+            ),
+            (Some(Rejection::Algorithm(reason)), None) => ("key type", format!("{}; no other key type is known to work", reason)),
+            _ => ("denied", format!("add {}.pub to your {} account", account.ssh_key, account.hostname())),
+        },
+        Connectivity::TimedOut => ("timeout", format!("no answer within {}s", timeout.as_secs())),
+        Connectivity::Error(e) => ("error", e.clone()),
+    }
+}
+
+/// One account's `ssh -T`: the result, why a denied key was denied, and
+/// the key type that would do instead.
+struct Outcome<'a> {
+    account: &'a GitAccount,
+    result: Connectivity,
+    rejection: Option<Rejection>,
+    fallback: Option<&'static str>,
+}
+
+/// Writes the table of `outcomes` and the "N passed, M failed" line to
+/// `out`, and returns whether none failed.
+fn write_summary(out: &mut impl Write, outcomes: &[Outcome], timeout: Duration) -> Result<bool> {
+    let mut rows = vec![["ACCOUNT".to_string(), "HOST".to_string(), "RESULT".to_string(), "DETAIL".to_string()]];
+    let (mut passed, mut failed) = (0, 0);
+    for outcome in outcomes {
+        let (label, detail) = describe(outcome.account, &outcome.result, outcome.rejection.as_ref(), outcome.fallback, timeout);
+        if label == "ok" {
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+        rows.push([outcome.account.name.clone(), outcome.account.host.clone(), label.to_string(), detail]);
+    }
+    write_table(out, &rows)?;
+    writeln!(out, "{} passed, {} failed", passed, failed)?;
+    Ok(failed == 0)
+}
+
+/// `--web` for a denied account: its public key, then the page to add it
+/// on. A browser that won't start only costs a warning; the URL is printed
+/// either way.
+fn open_ssh_keys_page(account: &GitAccount) {
+    println!();
+    match std::fs::read_to_string(format!("{}.pub", account.ssh_key)) {
+        Ok(public) => print!("{}'s public key ({}.pub):\n{}", account.name, account.ssh_key, public),
+        Err(e) => println!("warning: {}: couldn't read {}.pub: {}", account.name, account.ssh_key, e),
+    }
+    let Some(url) = provider::ssh_keys_url(account.hostname()) else {
+        println!("note: {}: no known ssh keys page on {}; add the key in its user settings", account.name, account.hostname());
+        return;
+    };
+    println!("Add it at {}", url);
+    if let Err(e) = platform::open_url(url) {
+        println!("warning: {}", e);
+    }
+}
+
+/// `test(item)` for every item, `jobs` at a time, in the items' order.
+fn run_pool<T: Sync, R: Send>(items: &[T], jobs: usize, test: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                let result = test(item);
+                results.lock().expect("no test thread panics while holding the lock")[i] = Some(result);
+            });
+        }
+    });
+    results.into_inner().expect("test threads have finished").into_iter().map(|result| result.expect("every item is tested")).collect()
+}
+
+impl GitAccountManager {
+    /// Runs `ssh -T` through the Host alias of `account_name`, or of every
+    /// enabled account when it's `None`, `jobs` at a time with each given
+    /// `timeout`. Prints a table and a count; fails if any account did.
+    /// With `auto_fix`, accounts whose key type was rejected have their key
+    /// rotated to a type that works; with `web`, denied keys are printed and
+    /// the provider's page for adding them opened.
+    pub fn test_accounts(&mut self, account_name: Option<&str>, jobs: usize, timeout: Duration, auto_fix: bool, web: bool) -> Result<bool> {
+        let accounts: Vec<&GitAccount> = match account_name {
+            Some(name) => vec![self.accounts.get(name).ok_or_else(|| account_not_found(name))?],
+            None => self.sorted_accounts(false),
+        };
+        if self.offline {
+            return Err(Error::other("test needs the network, and this run is offline"));
+        }
+        if accounts.is_empty() {
+            println!("No accounts to test.");
+            return Ok(true);
+        }
+
+        let results = run_pool(&accounts, jobs, |account| connectivity::test_connection_within(&account.host, timeout));
+        let outcomes: Vec<Outcome> = accounts
+            .iter()
+            .zip(results)
+            .map(|(account, result)| {
+                // Only denied keys are tried again, one at a time, with -v.
+                let rejection = matches!(result, Connectivity::Denied).then(|| connectivity::rejection(&account.host, timeout));
+                let fallback = match &rejection {
+                    Some(Rejection::Algorithm(_)) => fallback_key_type(account),
+                    _ => None,
+                };
+                Outcome { account, result, rejection, fallback }
+            })
+            .collect();
+        let passed = write_summary(&mut std::io::stdout().lock(), &outcomes, timeout)?;
+
+        if web {
+            for outcome in &outcomes {
+                if matches!(outcome.result, Connectivity::Denied) && !matches!(outcome.rejection, Some(Rejection::Algorithm(_))) {
+                    open_ssh_keys_page(outcome.account);
+                }
+            }
+        }
+        let fixes: Vec<(String, &str)> = outcomes.iter().filter_map(|outcome| Some((outcome.account.name.clone(), outcome.fallback?))).collect();
+
+        if auto_fix {
+            self.rotate_rejected_keys(&fixes);
+        }
+        Ok(passed)
+    }
+
+    /// `--auto-fix`: rotates each account's primary key to the type paired
+    /// with it. The new keys still have to be added on the provider, so
+    /// the accounts keep failing until then, as the rotation reminds.
+    fn rotate_rejected_keys(&mut self, fixes: &[(String, &str)]) {
+        for (name, key_type) in fixes {
+            if let Err(e) = self.rotate_key(name, None, Some(key_type), false) {
+                println!("error: couldn't rotate {} to a {} key: {}", name, key_type, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn pool_runs_at_most_jobs_at_once_and_keeps_the_order() {
+        let running = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+        let items: Vec<usize> = (0..8).collect();
+        let results = run_pool(&items, 3, |item| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            item * 10
+        });
+        assert_eq!(results, [0, 10, 20, 30, 40, 50, 60, 70]);
+        assert!(most.load(Ordering::SeqCst) <= 3);
+        assert!(run_pool(&[] as &[usize], 4, |item| *item).is_empty());
+    }
+
+    #[test]
+    fn summary_tells_each_outcome_apart() {
+        let home = TempHome::new();
+        let account = testutil::account(&home, "work");
+        let timeout = Duration::from_secs(5);
+        let label = |result: Connectivity| describe(&account, &result, None, None, timeout);
+
+        assert_eq!(label(Connectivity::Authenticated("Hi work! You've successfully authenticated".to_string())).0, "ok");
+        let (denied, detail) = label(Connectivity::Denied);
+        assert_eq!(denied, "denied");
+        assert!(detail.contains(&format!("add {}.pub", account.ssh_key)));
+        assert_eq!(label(Connectivity::TimedOut), ("timeout", "no answer within 5s".to_string()));
+        assert_eq!(label(Connectivity::Error("ssh: not found".to_string())).0, "error");
+
+        let expecting = GitAccount { login: Some("someone-else".to_string()), ..account.clone() };
+        let result = Connectivity::Authenticated("Hi work! You've successfully authenticated".to_string());
+        assert_eq!(describe(&expecting, &result, None, None, timeout).0, "wrong user");
+    }
+
+    #[test]
+    fn summary_counts_the_passed_and_failed_and_fails_on_any_failure() {
+        let home = TempHome::new();
+        let (work, side) = (testutil::account(&home, "work"), testutil::account(&home, "side"));
+        let outcome = |account, result| Outcome { account, result, rejection: None, fallback: None };
+        let timeout = Duration::from_secs(5);
+
+        let mut out = Vec::new();
+        let outcomes = [outcome(&work, Connectivity::Authenticated("Hi work!".to_string())), outcome(&side, Connectivity::Denied)];
+        assert!(!write_summary(&mut out, &outcomes, timeout).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", out);
+        assert!(lines[0].starts_with("ACCOUNT  HOST             RESULT  DETAIL"), "{}", out);
+        assert!(lines[1].starts_with("work     github.com-work  ok      Hi work!"), "{}", out);
+        assert!(lines[2].starts_with("side     github.com-side  denied  add "), "{}", out);
+        assert_eq!(lines[3], "1 passed, 1 failed");
+
+        let mut out = Vec::new();
+        assert!(write_summary(&mut out, &[outcome(&work, Connectivity::Authenticated("Hi work!".to_string()))], timeout).unwrap());
+        assert!(String::from_utf8(out).unwrap().ends_with("\n1 passed, 0 failed\n"));
+    }
+
+    #[test]
+    fn only_a_rejected_key_type_gets_a_rotate_suggested() {
+        let home = TempHome::new();
+        let account = testutil::account(&home, "work");
+        let timeout = Duration::from_secs(5);
+
+        let algorithm = "debug1: Offering public key: /home/me/.ssh/id_ed25519_work ED25519\n\
+                         debug1: send_pubkey_test: no mutual signature algorithm\n\
+                         git@github.com: Permission denied (publickey).\n";
+        let rejection = connectivity::classify_rejection(algorithm);
+        assert_eq!(rejection, Rejection::Algorithm("send_pubkey_test: no mutual signature algorithm".to_string()));
+        let (label, detail) = describe(&account, &Connectivity::Denied, Some(&rejection), Some("rsa"), timeout);
+        assert_eq!(label, "key type");
+        assert!(detail.contains("rerun with --auto-fix, or run multigit key rotate work --type rsa"), "{}", detail);
+
+        let denied = "debug1: Offering public key: /home/me/.ssh/id_ed25519_work ED25519\n\
+                      debug1: Authentications that can continue: publickey\n\
+                      git@github.com: Permission denied (publickey).\n";
+        let rejection = connectivity::classify_rejection(denied);
+        assert_eq!(rejection, Rejection::Key);
+        let (label, detail) = describe(&account, &Connectivity::Denied, Some(&rejection), None, timeout);
+        assert_eq!(label, "denied");
+        assert!(!detail.contains("--auto-fix") && !detail.contains("key rotate"), "{}", detail);
+    }
+
+    #[test]
+    fn auto_fix_rotates_the_primary_key_to_the_fallback_type() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["work"]);
+        let old_key = manager.accounts["work"].ssh_key.clone();
+
+        manager.rotate_rejected_keys(&[("work".to_string(), "rsa")]);
+        let account = &manager.accounts["work"];
+        assert_ne!(account.ssh_key, old_key);
+        assert_eq!(keys::key_strength(&account.ssh_key).map(|(_, key_type)| key_type).as_deref(), Some("rsa"));
+        assert!(account.secondary_keys.is_empty());
+        assert!(home.read(".ssh/config").contains(&format!("IdentityFile {}", account.ssh_key)));
+    }
+}
@@ -0,0 +1,215 @@
+//! `test-push`: whether the provider would accept a push from an account,
+//! which `test` can't tell: the key may authenticate while the login lacks
+//! write access or hasn't authorized SAML SSO for the organization. A
+//! shallow clone of the repository goes to a temporary directory, and
+//! `git push --dry-run` through the account's Host alias asks the server
+//! for its receive-pack without sending anything.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use crate::account::{GitAccount, GitAccountManager, PROVIDER_HOST};
+use crate::errors::account_not_found;
+use crate::git_url;
+
+/// `org/repo` of a repository given as `org/repo`, or as an ssh or https
+/// URL on the provider or an account's alias.
+pub fn repo_path(repo: &str) -> Option<String> {
+    let path = match git_url::parse(repo).map(|url| url.path).or_else(|| git_url::parse_https(repo).map(|(_, path)| path)) {
+        Some(path) => path,
+        None if !repo.contains(':') => repo,
+        None => return None,
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let mut parts = path.split('/');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(owner), Some(name), None) if !owner.is_empty() && !name.is_empty() && !path.contains(char::is_whitespace) => Some(path.to_string()),
+        _ => None,
+    }
+}
+
+pub fn validate_repo(repo: &str) -> Result<()> {
+    if repo_path(repo).is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("--canary-repo {}: expected owner/repo or a {} URL of one", repo, PROVIDER_HOST),
+        ));
+    }
+    Ok(())
+}
+
+/// How the server answered.
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Accepted,
+    /// The key wasn't accepted at all.
+    Authentication,
+    /// The organization enforces SAML SSO and the key isn't authorized for it.
+    Sso,
+    /// The login can read the repository but not write to it.
+    Permission,
+    NotFound,
+    /// A protected branch rule. Servers apply these to the refs received,
+    /// so a dry run mostly doesn't get that far.
+    ProtectedBranch,
+    Other(String),
+}
+
+/// Sorts git's stderr by the messages GitHub sends for each case.
+fn classify(stderr: &str) -> Verdict {
+    let lower = stderr.to_ascii_lowercase();
+    if lower.contains("saml") || lower.contains("sso") {
+        Verdict::Sso
+    } else if lower.contains("protected branch") || lower.contains("gh006") {
+        Verdict::ProtectedBranch
+    } else if lower.contains("permission denied (publickey") || lower.contains("authentication failed") {
+        Verdict::Authentication
+    } else if lower.contains("permission to") && lower.contains("denied") || lower.contains("write access to repository not granted") {
+        Verdict::Permission
+    } else if lower.contains("repository not found") || lower.contains("does not appear to be a git repository") {
+        Verdict::NotFound
+    } else {
+        // git's own closing "failed to push some refs" says nothing new.
+        let reason = stderr
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.contains("failed to push some refs"))
+            .unwrap_or("git failed");
+        Verdict::Other(reason.to_string())
+    }
+}
+
+/// A directory under the system temp dir, removed when dropped, so the
+/// clone goes away whichever way the test ends.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Result<Self> {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|since| since.subsec_nanos()).unwrap_or(0);
+        let path = std::env::temp_dir().join(format!("multigit-test-push-{}-{}", std::process::id(), nanos));
+        fs::create_dir(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<Output> {
+    Command::new("git")
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .map_err(|e| Error::other(format!("Failed to run git: {}", e)))
+}
+
+impl GitAccountManager {
+    fn push_verdict(&self, account: &GitAccount, path: &str) -> Result<Verdict> {
+        let url = format!("git@{}:{}.git", account.host, path);
+        let temp = TempDir::new()?;
+        let clone = git(&temp.0, &["clone", "--quiet", "--depth", "1", "--single-branch", "--no-tags", "--no-checkout", &url, "repo"])?;
+        if !clone.status.success() {
+            return Ok(classify(&String::from_utf8_lossy(&clone.stderr)));
+        }
+        let repo = temp.0.join("repo");
+        let branch = git(&repo, &["symbolic-ref", "--short", "HEAD"])?;
+        let branch = String::from_utf8_lossy(&branch.stdout).trim().to_string();
+        if branch.is_empty() {
+            return Ok(Verdict::Other("the repository has no branch to push to".to_string()));
+        }
+        // --dry-run stops before any object or ref update is sent, and no
+        // hooks of the clone run.
+        let refspec = format!("HEAD:refs/heads/{}", branch);
+        let push = git(&repo, &["-c", "core.hooksPath=/dev/null", "push", "--dry-run", "--porcelain", "--no-verify", "origin", &refspec])?;
+        if push.status.success() {
+            return Ok(Verdict::Accepted);
+        }
+        let mut output = String::from_utf8_lossy(&push.stderr).into_owned();
+        output.push_str(&String::from_utf8_lossy(&push.stdout));
+        Ok(classify(&output))
+    }
+
+    /// `test-push`: the repository is `repo` or the account's canary repo.
+    pub fn test_push(&self, account_name: &str, repo: Option<&str>) -> Result<bool> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        let repo = repo.or(account.canary_repo.as_deref()).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("no repository to test with; pass --repo, or record one with setup --canary-repo for {}", account_name),
+            )
+        })?;
+        let path = repo_path(repo).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{}: expected owner/repo or a {} URL of one", repo, PROVIDER_HOST)))?;
+        if self.offline {
+            return Err(Error::other("test-push needs the network, and this run is offline"));
+        }
+
+        println!("Testing a push to {} as {} through {} (dry run, nothing is pushed)", path, account_name, account.host);
+        let verdict = self.push_verdict(account, &path)?;
+        match &verdict {
+            Verdict::Accepted => println!("ok: the server would accept a push to {}", path),
+            Verdict::Authentication => println!("authentication failed: {} doesn't accept the key; add {}.pub to the {} account", PROVIDER_HOST, account.ssh_key, account_name),
+            Verdict::Sso => println!(
+                "SSO not authorized: the organization of {} enforces SAML SSO; authorize the key for it under SSH keys in the {} settings",
+                path, PROVIDER_HOST
+            ),
+            Verdict::Permission => println!("no write access: the login of {} can read {} but not push to it", account_name, path),
+            Verdict::NotFound => println!("not found: {} doesn't exist or the login of {} can't see it", path, account_name),
+            Verdict::ProtectedBranch => println!("protected branch: the server refuses pushes to the default branch of {}", path),
+            Verdict::Other(reason) => println!("failed: {}", reason),
+        }
+        if verdict == Verdict::Accepted {
+            println!("note: branch protection rules are only applied to a real push");
+        }
+        Ok(verdict == Verdict::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn github_answers_are_told_apart() {
+        let cases = [
+            ("git@github.com: Permission denied (publickey).\nfatal: Could not read from remote repository.", Verdict::Authentication),
+            (
+                "ERROR: The 'acme' organization has enabled or enforced SAML SSO. To access this repository, you must use the HTTPS remote with a personal access token or SSH with an SSH key and passphrase that has been authorized for this organization.",
+                Verdict::Sso,
+            ),
+            ("ERROR: Permission to acme/app.git denied to jane.\nfatal: Could not read from remote repository.", Verdict::Permission),
+            ("remote: error: GH006: Protected branch update failed for refs/heads/main.", Verdict::ProtectedBranch),
+            ("ERROR: Repository not found.\nfatal: Could not read from remote repository.", Verdict::NotFound),
+            ("\nssh: connect to host github.com port 22: Connection timed out\nerror: failed to push some refs", Verdict::Other("ssh: connect to host github.com port 22: Connection timed out".to_string())),
+        ];
+        for (stderr, verdict) in cases {
+            assert_eq!(classify(stderr), verdict, "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn repo_is_owner_and_name() {
+        assert_eq!(repo_path("acme/app").as_deref(), Some("acme/app"));
+        assert_eq!(repo_path("git@github.com-work:acme/app.git").as_deref(), Some("acme/app"));
+        assert_eq!(repo_path("https://github.com/acme/app").as_deref(), Some("acme/app"));
+        assert_eq!(repo_path("acme"), None);
+        assert_eq!(repo_path("acme/app/extra"), None);
+        assert!(validate_repo("acme app/x").is_err());
+    }
+
+    #[test]
+    fn clone_dir_is_removed_when_dropped() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.0.clone();
+        fs::write(path.join("file"), "x").unwrap();
+        drop(temp);
+        assert!(!path.exists());
+    }
+}
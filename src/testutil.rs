@@ -0,0 +1,93 @@
+//! Helpers for the unit tests: a throwaway home directory per test, so
+//! nothing reads or writes the real `$HOME`. Not every test uses every
+//! helper.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::account::{GitAccount, GitAccountManager, SetupOptions};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temp dir, removed when dropped.
+pub struct TempHome {
+    pub path: PathBuf,
+}
+
+impl TempHome {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("multigit-test-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create temp home");
+        TempHome { path: fs::canonicalize(&path).expect("canonicalize temp home") }
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// A manager for this home, offline and without ssh-agent.
+    pub fn manager(&self) -> GitAccountManager {
+        let mut manager = GitAccountManager::new(self.path.clone());
+        manager.offline = true;
+        manager.no_agent = true;
+        manager
+    }
+
+    /// Writes `contents` to `path` inside the home, creating its parents.
+    pub fn write(&self, path: impl AsRef<Path>, contents: &str) -> PathBuf {
+        let path = self.join(path);
+        fs::create_dir_all(path.parent().expect("path has a parent")).expect("create parent");
+        fs::write(&path, contents).expect("write file");
+        path
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> String {
+        fs::read_to_string(self.join(path)).unwrap_or_default()
+    }
+}
+
+impl Drop for TempHome {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Every file under `dir` with its contents, to tell that nothing changed.
+pub fn tree(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir).expect("read dir") {
+        let path = entry.expect("dir entry").path();
+        if path.is_dir() {
+            files.extend(tree(&path));
+        } else {
+            let contents = fs::read(&path).expect("read file");
+            files.insert(path, contents);
+        }
+    }
+    files
+}
+
+/// Runs `setup` for each of `names`, with the defaults `account` uses.
+pub fn set_up(manager: &mut GitAccountManager, names: &[&str]) {
+    for name in names {
+        manager
+            .setup_account(name, &format!("{}@example.com", name), &format!("code/{}", name), &format!("github.com-{}", name), SetupOptions::default())
+            .expect("setup");
+    }
+}
+
+/// An account named `name` with alias `github.com-<name>`, key
+/// `~/.ssh/id_ed25519_<name>` and codebase dir `~/code/<name>`.
+pub fn account(home: &TempHome, name: &str) -> GitAccount {
+    GitAccount::new(
+        name,
+        &format!("{}@example.com", name),
+        &home.join(format!(".ssh/id_ed25519_{}", name)).to_string_lossy(),
+        home.join("code").join(name),
+        &format!("github.com-{}", name),
+    )
+}
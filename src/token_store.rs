@@ -0,0 +1,243 @@
+//! `token`: provider API tokens kept in the OS secret store instead of
+//! environment variables, through the store's own command line tool:
+//! `security` for the macOS keychain, `secret-tool` for the Secret Service
+//! on Linux and PowerShell's `PasswordVault` for the Windows Credential
+//! Manager. Tokens are never written to multigit's files, printed or put
+//! on a command line, where `ps` would show them; the tools read them from
+//! stdin. `token status` only says where one would come from.
+
+use std::fmt;
+use std::io::{self, BufRead, Error, ErrorKind, IsTerminal, Result, Write};
+use std::process::{Command, Stdio};
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::github;
+use crate::platform;
+use crate::report::print_table;
+
+/// The service the tokens are stored under.
+const SERVICE: &str = "multigit";
+
+/// The account the Windows PowerShell scripts work on, passed in the
+/// environment so it needs no quoting.
+const ACCOUNT_VAR: &str = "MULTIGIT_TOKEN_ACCOUNT";
+
+/// Loads the WinRT `PasswordVault`, the Windows Credential Manager's store
+/// for web credentials, into `$vault`.
+const VAULT: &str = "[void][Windows.Security.Credentials.PasswordVault, Windows.Security.Credentials, ContentType = WindowsRuntime]; \
+                     $vault = New-Object Windows.Security.Credentials.PasswordVault;";
+
+/// PowerShell running `script` after [`VAULT`] for `account`; `None` off
+/// Windows.
+fn vault_command(account: &str, script: &str) -> Option<Command> {
+    if !cfg!(windows) {
+        return None;
+    }
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", &format!("{} {}", VAULT, script)]).env(ACCOUNT_VAR, account);
+    Some(command)
+}
+
+/// Where a token came from, for `token status`.
+pub enum TokenSource {
+    Flag,
+    Env(String),
+    Keyring,
+    Gh,
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenSource::Flag => write!(f, "--token"),
+            TokenSource::Env(name) => write!(f, "${}", name),
+            TokenSource::Keyring => write!(f, "{}", keyring_name()),
+            TokenSource::Gh => write!(f, "gh auth token"),
+        }
+    }
+}
+
+fn keyring_name() -> &'static str {
+    if platform::is_macos() {
+        "macOS keychain"
+    } else if cfg!(windows) {
+        "Windows Credential Manager"
+    } else {
+        "Secret Service keyring"
+    }
+}
+
+fn unsupported() -> Error {
+    Error::new(ErrorKind::Unsupported, "Storing tokens needs the macOS keychain, secret-tool (libsecret) on Linux or PowerShell on Windows")
+}
+
+/// Runs `command` with `input` on its stdin.
+fn output_with_stdin(command: &mut Command, input: &str) -> Result<std::process::Output> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    child.stdin.take().expect("stdin is piped").write_all(input.as_bytes())?;
+    child.wait_with_output()
+}
+
+/// The stored token of `account`, if the secret store has one.
+pub fn keyring_get(account: &str) -> Option<String> {
+    let mut command = if platform::is_macos() {
+        let mut command = Command::new("security");
+        command.args(["find-generic-password", "-s", SERVICE, "-a", account, "-w"]);
+        command
+    } else if cfg!(unix) {
+        let mut command = Command::new("secret-tool");
+        command.args(["lookup", "service", SERVICE, "account", account]);
+        command
+    } else {
+        vault_command(account, &format!("$credential = $vault.Retrieve('{}', $env:{}); $credential.RetrievePassword(); $credential.Password", SERVICE, ACCOUNT_VAR))?
+    };
+    let output = command.stdin(Stdio::null()).stderr(Stdio::null()).output().ok()?;
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !token.is_empty()).then_some(token)
+}
+
+fn keyring_set(account: &str, token: &str) -> Result<()> {
+    let output = if platform::is_macos() {
+        // -U replaces a token stored before. -w last, without a value,
+        // makes security ask for the token and then for it again.
+        let mut command = Command::new("security");
+        command.args(["add-generic-password", "-U", "-s", SERVICE, "-a", account, "-w"]);
+        output_with_stdin(&mut command, &format!("{}\n{}\n", token, token))
+    } else if cfg!(unix) {
+        let label = format!("multigit token for {}", account);
+        let mut command = Command::new("secret-tool");
+        command.args(["store", "--label", &label, "service", SERVICE, "account", account]);
+        output_with_stdin(&mut command, token)
+    } else {
+        // Adding a credential the vault has replaces it.
+        let script = format!("$vault.Add((New-Object Windows.Security.Credentials.PasswordCredential('{}', $env:{}, [Console]::In.ReadLine())))", SERVICE, ACCOUNT_VAR);
+        output_with_stdin(&mut vault_command(account, &script).ok_or_else(unsupported)?, token)
+    };
+    let output = output.map_err(|e| match e.kind() {
+        ErrorKind::NotFound => unsupported(),
+        _ => e,
+    })?;
+    if !output.status.success() {
+        return Err(Error::other(format!("Failed to store the token: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// Removes the stored token of `account`, returning whether there was one.
+fn keyring_remove(account: &str) -> Result<bool> {
+    if keyring_get(account).is_none() {
+        return Ok(false);
+    }
+    let mut command = if platform::is_macos() {
+        let mut command = Command::new("security");
+        command.args(["delete-generic-password", "-s", SERVICE, "-a", account]);
+        command
+    } else if cfg!(unix) {
+        let mut command = Command::new("secret-tool");
+        command.args(["clear", "service", SERVICE, "account", account]);
+        command
+    } else {
+        vault_command(account, &format!("$vault.Remove($vault.Retrieve('{}', $env:{}))", SERVICE, ACCOUNT_VAR)).ok_or_else(unsupported)?
+    };
+    let output = command.stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("Failed to remove the token: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(true)
+}
+
+/// The first line of stdin, without echoing it when stdin is a terminal.
+fn read_token() -> Result<String> {
+    let terminal = io::stdin().is_terminal();
+    if terminal {
+        print!("Token (input is hidden): ");
+        io::stdout().flush()?;
+    }
+    let stty = |arg: &str| {
+        if terminal && cfg!(unix) {
+            let _ = Command::new("stty").arg(arg).stdin(Stdio::inherit()).status();
+        }
+    };
+    stty("-echo");
+    let mut line = String::new();
+    let read = io::stdin().lock().read_line(&mut line);
+    stty("echo");
+    if terminal {
+        println!();
+    }
+    read?;
+    let token = line.trim().to_string();
+    if token.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No token given"));
+    }
+    Ok(token)
+}
+
+impl GitAccountManager {
+    /// The API token for `account` and where it came from: `--token`, the
+    /// environment, the secret store, then the gh CLI.
+    pub fn api_token(&self, account: Option<&str>) -> Option<(String, TokenSource)> {
+        if let Some(token) = &self.token {
+            return Some((token.clone(), TokenSource::Flag));
+        }
+        if let Some((name, token)) = github::env_token(account) {
+            return Some((token, TokenSource::Env(name)));
+        }
+        if let Some(token) = account.and_then(keyring_get) {
+            return Some((token, TokenSource::Keyring));
+        }
+        github::gh_token().map(|token| (token, TokenSource::Gh))
+    }
+
+    fn check_token_account(&self, account_name: &str) -> Result<()> {
+        if !self.accounts.contains_key(account_name) {
+            return Err(account_not_found(account_name));
+        }
+        Ok(())
+    }
+
+    /// Reads a token from stdin and stores it for `account_name`.
+    pub fn set_token(&self, account_name: &str) -> Result<bool> {
+        self.check_token_account(account_name)?;
+        let token = read_token()?;
+        keyring_set(account_name, &token)?;
+        println!("Stored {}'s token in the {}", account_name, keyring_name());
+        Ok(true)
+    }
+
+    pub fn remove_token(&self, account_name: &str) -> Result<bool> {
+        self.check_token_account(account_name)?;
+        if keyring_remove(account_name)? {
+            println!("Removed {}'s token from the {}", account_name, keyring_name());
+        } else {
+            println!("No token stored for {}", account_name);
+        }
+        Ok(true)
+    }
+
+    /// Where each account's token would come from, without printing it.
+    pub fn token_status(&self, account_name: Option<&str>) -> Result<bool> {
+        let names: Vec<String> = match account_name {
+            Some(name) => {
+                self.check_token_account(name)?;
+                vec![name.to_string()]
+            }
+            None => self.sorted_accounts(true).into_iter().map(|account| account.name.clone()).collect(),
+        };
+        if names.is_empty() {
+            println!("No accounts configured.");
+            return Ok(true);
+        }
+        let mut rows = vec![["ACCOUNT".to_string(), "TOKEN FROM".to_string()]];
+        for name in &names {
+            let source = match self.api_token(Some(name)) {
+                Some((_, source)) => source.to_string(),
+                None => format!("none; run multigit token set {} or set {}", name, github::account_token_var(name)),
+            };
+            rows.push([name.clone(), source]);
+        }
+        print_table(&rows);
+        Ok(true)
+    }
+}
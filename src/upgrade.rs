@@ -0,0 +1,480 @@
+//! Bringing setups written by older multigit versions up to date. Those
+//! versions appended unmarked blocks, repeated them on every run and wrote a
+//! stray quote after the email. This finds that content, deduplicates it,
+//! fixes the quote, wraps it in managed markers and registers the accounts
+//! it belongs to. What can't be worked out from the files is asked for, or
+//! with `--non-interactive` reported and left alone.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use crate::account::{GitAccount, GitAccountManager, DEFAULT_KEY_TYPE};
+use crate::fsutil;
+use crate::gitconfig;
+use crate::keys::prompt_from;
+use crate::managed;
+use crate::ssh_config;
+
+/// An account reconstructed from an old includeIf and its include file.
+struct Candidate {
+    name: String,
+    email: Option<String>,
+    alias: Option<String>,
+    dir: PathBuf,
+    key: Option<String>,
+}
+
+struct FilePlan {
+    path: PathBuf,
+    original: String,
+    updated: String,
+    notes: Vec<String>,
+    /// An unusable email line in `updated` and the account whose email
+    /// replaces it once the user has been asked.
+    missing_email: Option<(String, String)>,
+}
+
+enum Edit {
+    Wrap(String),
+    Remove,
+}
+
+pub struct UpgradePlan {
+    files: Vec<FilePlan>,
+    register: Vec<Candidate>,
+    /// Codebase dirs of old includeIfs nothing names an account for, to ask
+    /// about.
+    unnamed: Vec<PathBuf>,
+    /// Entries left as they are, and why.
+    skipped: Vec<String>,
+}
+
+/// What an old include file says about its account.
+struct OldInclude {
+    alias: Option<String>,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+fn is_plausible_email(email: &str) -> bool {
+    email.contains('@') && !email.contains(|c: char| c.is_whitespace() || c == '"')
+}
+
+/// Drops the quote old versions wrote after the email.
+fn fix_email(value: &str) -> &str {
+    match value.strip_suffix('"') {
+        Some(fixed) if !fixed.starts_with('"') => fixed,
+        _ => value,
+    }
+}
+
+fn parse_include(content: &str) -> OldInclude {
+    let lines: Vec<&str> = content.lines().collect();
+    let sections = gitconfig::sections(&lines);
+    let mut include = OldInclude { alias: None, name: None, email: None };
+
+    for section in &sections {
+        if let Some(url) = section.header.strip_prefix("url \"git@").and_then(|rest| rest.strip_suffix(":\"")) {
+            include.alias.get_or_insert_with(|| url.to_string());
+        }
+        if section.header == "user" {
+            if let Some(name) = gitconfig::values(&lines, section, "name").first() {
+                include.name.get_or_insert_with(|| name.to_string());
+            }
+            if let Some(email) = gitconfig::values(&lines, section, "email").first() {
+                include.email.get_or_insert_with(|| fix_email(email).to_string());
+            }
+        }
+    }
+    include
+}
+
+/// Applies whole-range edits: `Wrap` puts markers around the range's
+/// non-blank lines, `Remove` drops the range.
+fn apply_edits(lines: &[&str], edits: &[(usize, usize, Edit)]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some((start, end, edit)) = edits.iter().find(|(start, _, _)| *start == i) else {
+            out.push_str(lines[i]);
+            out.push('\n');
+            i += 1;
+            continue;
+        };
+        if let Edit::Wrap(account) = edit {
+            let last = (*start..*end).rev().find(|&j| !lines[j].trim().is_empty()).unwrap_or(*start);
+            let body: String = lines[*start..=last].iter().map(|line| format!("{}\n", line)).collect();
+            out.push_str(&managed::wrap(account, &body));
+            for line in &lines[last + 1..*end] {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+        i = *end;
+    }
+    out
+}
+
+fn read_if_exists(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e),
+    }
+}
+
+impl GitAccountManager {
+    /// Old includeIf entries in the global gitconfig, deduplicated and
+    /// wrapped, plus the accounts they point at. `names` holds the account
+    /// names the user gave for dirs the files don't name one for.
+    fn plan_global_gitconfig(&self, plan: &mut UpgradePlan, interactive: bool, names: &HashMap<PathBuf, String>) -> Result<Vec<(Candidate, PathBuf)>> {
+        let path = self.global_gitconfig_path();
+        let original = read_if_exists(&path)?;
+        let lines: Vec<&str> = original.lines().collect();
+        let sections = gitconfig::sections(&lines);
+        let marked = managed::blocks(&lines);
+
+        let mut candidates: Vec<(Candidate, PathBuf)> = Vec::new();
+        let mut seen: HashSet<(String, String)> = HashSet::new();
+        let mut edits = Vec::new();
+        let mut notes = Vec::new();
+
+        for section in &sections {
+            let Some(condition) = section.header.strip_prefix("includeIf \"").and_then(|rest| rest.strip_suffix('"')) else {
+                continue;
+            };
+            let Some(dir) = condition.strip_prefix("gitdir/i:").or_else(|| condition.strip_prefix("gitdir:")) else {
+                continue;
+            };
+            let Some(include_path) = gitconfig::values(&lines, section, "path").first().map(|p| p.to_string()) else {
+                continue;
+            };
+            let dir = PathBuf::from(dir.trim_end_matches('/'));
+            // Old versions always included `<dir>/.gitconfig`.
+            if Path::new(&include_path) != dir.join(".gitconfig") {
+                continue;
+            }
+
+            let line = section.start + 1;
+            if !seen.insert((condition.to_string(), include_path.clone())) {
+                edits.push((section.start, section.end, Edit::Remove));
+                notes.push(format!("remove duplicate includeIf for {} (line {})", dir.display(), line));
+                continue;
+            }
+            if managed::enclosing(&marked, section.start, section.end).is_some() {
+                continue;
+            }
+
+            let include = parse_include(&read_if_exists(Path::new(&include_path))?);
+            let registered = self.accounts.values().find(|account| account.codebase_dir_path == dir);
+            let name = registered.map(|account| account.name.clone()).or(include.name.clone()).or_else(|| names.get(&dir).cloned());
+            let Some(name) = name else {
+                if interactive {
+                    plan.unnamed.push(dir);
+                } else {
+                    plan.skipped.push(format!("can't tell which account the includeIf for {} (line {}) belongs to", dir.display(), line));
+                }
+                continue;
+            };
+            if !interactive && registered.is_none() && !include.email.as_deref().is_some_and(is_plausible_email) {
+                plan.skipped.push(format!("{} has no usable email for account {}", include_path, name));
+                continue;
+            }
+
+            edits.push((section.start, section.end, Edit::Wrap(name.clone())));
+            notes.push(format!("wrap the includeIf for {} (line {}) as account {}", dir.display(), line, name));
+            candidates.push((
+                Candidate {
+                    name,
+                    email: include.email,
+                    alias: include.alias,
+                    dir,
+                    key: None,
+                },
+                PathBuf::from(include_path),
+            ));
+        }
+
+        if !edits.is_empty() {
+            let updated = apply_edits(&lines, &edits);
+            plan.files.push(FilePlan { path, original, updated, notes, missing_email: None });
+        }
+        Ok(candidates)
+    }
+
+    /// An old include file, with repeated copies dropped, the email quote
+    /// fixed and the result wrapped.
+    fn plan_include(&self, plan: &mut UpgradePlan, name: &str, include_path: &Path) -> Result<()> {
+        // Write through a symlink from --export-gitconfig-dir, not over it.
+        let path = fs::canonicalize(include_path).unwrap_or_else(|_| include_path.to_path_buf());
+        let original = read_if_exists(&path)?;
+        let lines: Vec<&str> = original.lines().collect();
+        if lines.is_empty() || !managed::blocks(&lines).is_empty() {
+            return Ok(());
+        }
+
+        let mut notes = Vec::new();
+        let mut body = String::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        let sections = gitconfig::sections(&lines);
+        let first = sections.first().map_or(lines.len(), |section| section.start);
+        for line in &lines[..first] {
+            body.push_str(line);
+            body.push('\n');
+        }
+        let mut duplicates = 0;
+        let mut missing_email = None;
+        for section in &sections {
+            let text: String = lines[section.start..section.end].iter().map(|line| format!("{}\n", line)).collect();
+            if !seen.insert(text.trim_end().to_string()) {
+                duplicates += 1;
+                continue;
+            }
+            for line in &lines[section.start..section.end] {
+                match gitconfig::entry(line) {
+                    Some((key, value)) if key == "email" && !is_plausible_email(fix_email(value)) => {
+                        notes.push(format!("replace the unusable email line '{}' with one you enter", line.trim()));
+                        missing_email.get_or_insert_with(|| (line.to_string(), name.to_string()));
+                        body.push_str(line);
+                    }
+                    Some((key, value)) if key == "email" && fix_email(value) != value => {
+                        notes.push(format!("drop the stray quote after {}", fix_email(value)));
+                        body.push_str(&line.replacen(value, fix_email(value), 1));
+                    }
+                    _ => body.push_str(line),
+                }
+                body.push('\n');
+            }
+        }
+        if duplicates > 0 {
+            notes.push(format!("remove {} repeated section(s) from earlier runs", duplicates));
+        }
+        notes.push(format!("wrap as account {}", name));
+
+        let updated = managed::wrap(name, body.trim_end_matches('\n'));
+        plan.files.push(FilePlan { path, original, updated, notes, missing_email });
+        Ok(())
+    }
+
+    /// Old Host blocks for the candidates' aliases, deduplicated and wrapped.
+    /// Fills in each candidate's key from its block.
+    fn plan_ssh_config(&self, plan: &mut UpgradePlan, candidates: &mut [Candidate]) -> Result<()> {
+        let path = self.ssh_config_path();
+        let original = read_if_exists(&path)?;
+        let lines: Vec<&str> = original.lines().collect();
+        let marked = managed::blocks(&lines);
+
+        let mut edits = Vec::new();
+        let mut notes = Vec::new();
+        let mut seen: HashSet<String> = HashSet::new();
+        for block in ssh_config::host_blocks(&lines) {
+            let [alias] = block.patterns.as_slice() else {
+                continue;
+            };
+            let Some(candidate) = candidates.iter_mut().find(|c| c.alias.as_ref() == Some(alias)) else {
+                continue;
+            };
+            if !seen.insert(alias.clone()) {
+                edits.push((block.start, block.end, Edit::Remove));
+                notes.push(format!("remove duplicate Host {} (line {})", alias, block.start + 1));
+                continue;
+            }
+            if let Some((_, key)) = ssh_config::identity_files(&lines, &block).into_iter().next() {
+                candidate.key = Some(ssh_config::expand_tilde(&key, &self.home_dir));
+            }
+            if managed::enclosing(&marked, block.start, block.end).is_none() {
+                edits.push((block.start, block.end, Edit::Wrap(candidate.name.clone())));
+                notes.push(format!("wrap Host {} (line {}) as account {}", alias, block.start + 1, candidate.name));
+            }
+        }
+
+        if !edits.is_empty() {
+            let updated = apply_edits(&lines, &edits);
+            plan.files.push(FilePlan { path, original, updated, notes, missing_email: None });
+        }
+        Ok(())
+    }
+
+    pub fn plan_upgrade(&self, interactive: bool, names: &HashMap<PathBuf, String>) -> Result<UpgradePlan> {
+        let mut plan = UpgradePlan { files: Vec::new(), register: Vec::new(), unnamed: Vec::new(), skipped: Vec::new() };
+
+        let found = self.plan_global_gitconfig(&mut plan, interactive, names)?;
+        for (candidate, include_path) in &found {
+            self.plan_include(&mut plan, &candidate.name, include_path)?;
+        }
+        let mut candidates: Vec<Candidate> = found.into_iter().map(|(candidate, _)| candidate).collect();
+        self.plan_ssh_config(&mut plan, &mut candidates)?;
+
+        plan.register = candidates
+            .into_iter()
+            .filter(|candidate| !self.accounts.contains_key(&candidate.name))
+            .collect();
+        Ok(plan)
+    }
+
+    /// Asks for any email the old files don't have, reading the answers
+    /// from `input`, then backs up and rewrites the planned files and
+    /// registers the accounts.
+    fn apply_upgrade(&mut self, mut plan: UpgradePlan, input: &mut impl BufRead) -> Result<()> {
+        let mut emails = Vec::new();
+        for candidate in &plan.register {
+            let email = match candidate.email.as_ref().filter(|email| is_plausible_email(email)) {
+                Some(email) => email.clone(),
+                None => prompt_from(input, &format!("Email for account {}", candidate.name))?,
+            };
+            if !is_plausible_email(&email) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{}' is not a usable email for {}; nothing was changed", email, candidate.name),
+                ));
+            }
+            emails.push(email);
+        }
+
+        for file in &mut plan.files {
+            let Some((line, account)) = &file.missing_email else {
+                continue;
+            };
+            let email = match plan.register.iter().position(|candidate| &candidate.name == account) {
+                Some(i) => emails[i].clone(),
+                None => self.accounts.get(account).map(|a| a.email.clone()).unwrap_or_default(),
+            };
+            let indent = &line[..line.len() - line.trim_start().len()];
+            file.updated = file.updated.replacen(line.as_str(), &format!("{}email = {}", indent, email), 1);
+        }
+
+        for file in &plan.files {
+            let backup = fsutil::backup(&file.path)?;
+            fsutil::modify(&file.path, |current| {
+                if current != file.original {
+                    return Err(Error::other(format!(
+                        "{} changed while upgrading; nothing was written to it. Run upgrade-setup again",
+                        file.path.display()
+                    )));
+                }
+                Ok(Some(file.updated.clone()))
+            })?;
+            println!("Updated {} (original saved as {})", file.path.display(), backup.display());
+        }
+
+        for (candidate, email) in plan.register.into_iter().zip(emails) {
+            let host = candidate.alias.unwrap_or_else(|| format!("github.com-{}", candidate.name));
+            let key = match candidate.key {
+                Some(key) => key,
+                None => self.default_key_path(&candidate.name, &host, DEFAULT_KEY_TYPE)?.to_string_lossy().into_owned(),
+            };
+            let account = GitAccount::new(&candidate.name, &email, &key, candidate.dir, &host);
+            self.accounts.insert(candidate.name.clone(), account);
+            println!("Registered account {}", candidate.name);
+        }
+        self.save()
+    }
+}
+
+/// Asks for the account name of each of `dirs`, returning the ones given.
+/// An empty answer leaves that dir's includeIf alone.
+fn ask_names(manager: &GitAccountManager, dirs: &[PathBuf], input: &mut impl BufRead) -> Result<HashMap<PathBuf, String>> {
+    let mut names: HashMap<PathBuf, String> = HashMap::new();
+    for dir in dirs {
+        loop {
+            let name = prompt_from(input, &format!("Account name for the includeIf for {} (empty to skip)", dir.display()))?;
+            if name.is_empty() {
+                break;
+            }
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                println!("Use letters, digits, '-' and '_' only.");
+            } else if manager.accounts.contains_key(&name) || names.values().any(|taken| *taken == name) {
+                println!("There's already an account {}.", name);
+            } else {
+                names.insert(dir.clone(), name);
+                break;
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Prints what would change, and with `apply` makes the changes. Names and
+/// emails the old files don't have are asked for while applying; with
+/// `non_interactive` their entries are reported and skipped instead.
+pub fn run_upgrade(manager: &mut GitAccountManager, apply: bool, non_interactive: bool) -> Result<bool> {
+    upgrade(manager, apply, non_interactive, &mut io::stdin().lock())
+}
+
+fn upgrade(manager: &mut GitAccountManager, apply: bool, non_interactive: bool, input: &mut impl BufRead) -> Result<bool> {
+    let mut plan = manager.plan_upgrade(!non_interactive, &HashMap::new())?;
+    if apply && !plan.unnamed.is_empty() {
+        let names = ask_names(manager, &plan.unnamed, input)?;
+        let unnamed = plan.unnamed.clone();
+        plan = manager.plan_upgrade(true, &names)?;
+        plan.unnamed = unnamed.into_iter().filter(|dir| !names.contains_key(dir)).collect();
+    }
+    for dir in &plan.unnamed {
+        let verb = if apply { "left as is" } else { "will ask for its account name" };
+        println!("includeIf for {}: no account name in the old files; {}", dir.display(), verb);
+    }
+    for reason in &plan.skipped {
+        println!("skipped: {} (--non-interactive)", reason);
+    }
+    if plan.files.is_empty() && plan.register.is_empty() {
+        println!("Nothing to upgrade.");
+        return Ok(true);
+    }
+
+    for file in &plan.files {
+        println!("{}:", file.path.display());
+        for note in &file.notes {
+            println!("  {}", note);
+        }
+    }
+    for candidate in &plan.register {
+        let email = match &candidate.email {
+            Some(email) if is_plausible_email(email) => email.clone(),
+            _ => "unknown, will ask".to_string(),
+        };
+        println!(
+            "register account {}: email {}, dir {}, host {}",
+            candidate.name,
+            email,
+            candidate.dir.display(),
+            candidate.alias.as_deref().unwrap_or("(default)")
+        );
+    }
+
+    if !apply {
+        println!();
+        println!("Dry run; nothing was changed. Re-run with --apply to upgrade (originals are backed up first).");
+        return Ok(true);
+    }
+    manager.apply_upgrade(plan, input)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn interactive_import_asks_for_the_name_and_email() {
+        let home = TempHome::new();
+        let dir = home.join("code/work");
+        home.write(".gitconfig", &format!("[includeIf \"gitdir:{}/\"]\n    path = {}/.gitconfig\n", dir.display(), dir.display()));
+        home.write("code/work/.gitconfig", "[user]\n    email = \"\n[url \"git@github.com-work:\"]\n    insteadOf = git@github.com:\n");
+        let mut manager = home.manager();
+
+        upgrade(&mut manager, true, false, &mut Cursor::new("work\nme@example.com\n")).unwrap();
+
+        let account = &manager.accounts["work"];
+        assert_eq!(account.email, "me@example.com");
+        assert_eq!(account.host, "github.com-work");
+        assert_eq!(account.codebase_dir_path, dir);
+        assert!(managed::blocks(&home.read(".gitconfig").lines().collect::<Vec<_>>()).iter().any(|block| block.account == "work"));
+        assert!(home.read("code/work/.gitconfig").contains("email = me@example.com"));
+        assert!(GitAccountManager::load(home.path.clone()).unwrap().accounts.contains_key("work"));
+    }
+}
@@ -0,0 +1,219 @@
+//! `validate`: checks a plan file the way `apply` would, without setting
+//! anything up, and reports every problem instead of stopping at the first.
+//! Accounts the plan shares with the current setup are checked against
+//! the other accounts like new ones.
+
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+
+use crate::account::{split_dir_glob, GitAccount, GitAccountBuilder, GitAccountManager, DEFAULT_KEY_TYPE, PROVIDER_HOST};
+use crate::keys;
+use crate::plan::{self, PlanAccount};
+
+/// A problem `validate` found, with the account it is about.
+struct Problem {
+    account: Option<String>,
+    message: String,
+}
+
+/// What's wrong with an account name, which ends up in file names and
+/// block markers.
+pub fn name_problem(name: &str) -> Option<&'static str> {
+    if name.is_empty() {
+        Some("the name is empty")
+    } else if name.contains(|c: char| c.is_whitespace() || c.is_control() || c == '/' || c == '\\') {
+        Some("the name can't contain whitespace or slashes")
+    } else if name.starts_with('.') || name.starts_with('-') {
+        Some("the name can't start with . or -")
+    } else {
+        None
+    }
+}
+
+fn email_problem(email: &str) -> Option<&'static str> {
+    match email.split_once('@') {
+        _ if email.contains(|c: char| c.is_whitespace() || c.is_control() || c == '<' || c == '>') => Some("the email can't contain whitespace or angle brackets"),
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() && !domain.contains('@') => None,
+        _ => Some("the email is not of the form user@domain"),
+    }
+}
+
+/// Whether which of the two accounts a repository gets depends on the
+/// order of the includeIfs: they cover each other's directories without
+/// one being more specific.
+fn ambiguous(a: &GitAccount, b: &GitAccount) -> bool {
+    if !a.scope.is_dir() || !b.scope.is_dir() {
+        return false;
+    }
+    let a_covers_b = a.covers(&b.probe_dir());
+    let b_covers_a = b.covers(&a.probe_dir());
+    (a_covers_b || b_covers_a) && !(b_covers_a && b.specificity() < a.specificity()) && !(a_covers_b && a.specificity() < b.specificity())
+}
+
+impl GitAccountManager {
+    /// The account as `apply` would register it, or why it can't be.
+    fn validate_plan_account(&self, account: &PlanAccount) -> std::result::Result<GitAccount, Vec<String>> {
+        let mut problems = Vec::new();
+        if let Some(problem) = name_problem(&account.name) {
+            problems.push(problem.to_string());
+        }
+        if let Some(problem) = email_problem(&account.email) {
+            problems.push(format!("{}: {}", problem, account.email));
+        }
+        let options = account.options(&self.home_dir);
+        let hostname = options.hostname.as_deref().unwrap_or(PROVIDER_HOST);
+        for email in std::iter::once(account.email.as_str()).chain(options.committer_email.as_deref()) {
+            match self.check_email_domain(email, hostname) {
+                Ok(Some(warning)) => eprintln!("warning: {}: {}", account.name, warning),
+                Ok(None) => {}
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+        let glob = split_dir_glob(&self.home_dir, &account.codebase_dir);
+        let checked = self.validate_setup(&account.name, &account.codebase_dir, &account.host, &options);
+        let (keychain, hooks_path, glob) = match (checked, glob) {
+            (Ok((keychain, hooks_path)), Ok(glob)) => (keychain, hooks_path, glob),
+            (checked, glob) => {
+                problems.extend(checked.err().map(|e| e.to_string()));
+                problems.extend(glob.err().map(|e| e.to_string()));
+                return Err(problems);
+            }
+        };
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        let codebase_dir = match &glob {
+            Some((base, _)) => base.clone(),
+            None => self.home_dir.join(&account.codebase_dir),
+        };
+        let key = match (&options.existing_key, &options.pkcs11_provider) {
+            (_, Some(_)) => String::new(),
+            (Some(path), None) => path.to_string_lossy().into_owned(),
+            (None, None) => {
+                let key_type = options.key_type.as_deref().unwrap_or(DEFAULT_KEY_TYPE);
+                self.default_key_path(&account.name, &account.host, key_type).map_err(|e| vec![e.to_string()])?.to_string_lossy().into_owned()
+            }
+        };
+        GitAccountBuilder::new(&account.name, &account.email, &key, codebase_dir, &account.host)
+            .options(options, keychain, hooks_path)
+            .dir_pattern(glob.map(|(_, pattern)| pattern))
+            .build()
+            .map_err(|e| vec![e.to_string()])
+    }
+
+    fn plan_problems(&self, path: &Path) -> Vec<Problem> {
+        let problem = |account: Option<&str>, message: String| Problem { account: account.map(str::to_string), message };
+        let plan = match plan::load(path) {
+            Ok(plan) => plan,
+            Err(e) if e.kind() == ErrorKind::NotFound => return vec![problem(None, format!("{} does not exist", path.display()))],
+            Err(e) => return vec![problem(None, e.to_string())],
+        };
+        let mut problems = Vec::new();
+        if let Some(template) = &plan.settings.key_filename {
+            if let Err(e) = keys::validate_key_filename(template) {
+                problems.push(problem(None, e.to_string()));
+            }
+        }
+
+        // Accounts of the current setup the plan doesn't mention, then the
+        // plan's own, each checked against all the ones before it.
+        let mut registered: Vec<GitAccount> = self
+            .sorted_accounts(true)
+            .into_iter()
+            .filter(|existing| !plan.accounts.iter().any(|account| account.name == existing.name))
+            .cloned()
+            .collect();
+        let mut identities: Vec<(String, String, String)> = registered.iter().map(|other| (other.name.clone(), other.email.clone(), other.host.clone())).collect();
+        let mut names: Vec<&str> = Vec::new();
+        for account in &plan.accounts {
+            let name = Some(account.name.as_str());
+            if names.contains(&account.name.as_str()) {
+                problems.push(problem(name, "an earlier account of the plan has the same name".to_string()));
+                continue;
+            }
+            names.push(&account.name);
+            let built = self.validate_plan_account(account);
+            if let Err(messages) = &built {
+                problems.extend(messages.iter().map(|message| problem(name, message.clone())));
+            }
+            for (other, other_email, other_host) in &identities {
+                if other_email.eq_ignore_ascii_case(&account.email) {
+                    problems.push(problem(name, format!("{} also uses {}", other, account.email)));
+                }
+                if other_host.eq_ignore_ascii_case(&account.host) {
+                    problems.push(problem(name, format!("{} also uses the Host alias {}", other, account.host)));
+                }
+            }
+            identities.push((account.name.clone(), account.email.clone(), account.host.clone()));
+            let Ok(built) = built else {
+                continue;
+            };
+            for other in &registered {
+                if built.scope.is_dir() && other.scope.is_dir() && other.dir_display() == built.dir_display() {
+                    problems.push(problem(name, format!("{} also uses {}", other.name, built.dir_display())));
+                } else if ambiguous(other, &built) {
+                    problems.push(problem(
+                        name,
+                        format!("{} overlaps {}'s {} without either being more specific", built.dir_display(), other.name, other.dir_display()),
+                    ));
+                }
+            }
+            registered.push(built);
+        }
+
+        for account in &plan.accounts {
+            for exclusion in &account.exclusions {
+                if !registered.iter().any(|other| other.name == exclusion.account) {
+                    problems.push(problem(Some(&account.name), format!("{} is handed to {}, which is not an account", exclusion.dir, exclusion.account)));
+                }
+            }
+        }
+        problems
+    }
+
+    /// `validate <file>`. Fails when there is any problem.
+    pub fn validate_plan(&self, path: &Path) -> Result<bool> {
+        let problems = self.plan_problems(path);
+        for problem in &problems {
+            match &problem.account {
+                Some(account) => println!("error: {}: {}", account, problem.message),
+                None => println!("error: {}", problem.message),
+            }
+        }
+        if problems.is_empty() {
+            println!("{}: no problems found", path.display());
+        } else {
+            println!("{}: {} problem(s) found", path.display(), problems.len());
+        }
+        Ok(problems.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testutil::TempHome;
+
+    #[test]
+    fn every_problem_of_a_plan_is_reported() {
+        let home = TempHome::new();
+        let plan = home.write(
+            "plan.toml",
+            "[[accounts]]\nname = \"work\"\nemail = \"me@example.com\"\nhost = \"github.com-work\"\ncodebase_dir = \"code/work\"\n\n\
+             [[accounts]]\nname = \"bad name\"\nemail = \"home@example.com\"\nhost = \"github.com-home\"\ncodebase_dir = \"code/home\"\n\n\
+             [[accounts]]\nname = \"oss\"\nemail = \"ME@example.com\"\nhost = \"github.com-work\"\ncodebase_dir = \"code/oss\"\n\n\
+             [[accounts]]\nname = \"side\"\nemail = \"not-an-email\"\nhost = \"github.com-side\"\ncodebase_dir = \"code/work\"\nkey_type = \"dsa\"\n",
+        );
+        let manager = home.manager();
+
+        let problems: Vec<(Option<String>, String)> = manager.plan_problems(&plan).into_iter().map(|problem| (problem.account, problem.message)).collect();
+        let about = |account: &str| problems.iter().filter(|(name, _)| name.as_deref() == Some(account)).map(|(_, message)| message.as_str()).collect::<Vec<_>>();
+        assert!(about("work").is_empty(), "{:?}", problems);
+        assert!(!about("bad name").is_empty(), "{:?}", problems);
+        let oss = about("oss");
+        assert!(oss.contains(&"work also uses ME@example.com") && oss.contains(&"work also uses the Host alias github.com-work"), "{:?}", oss);
+        let side = about("side");
+        assert_eq!(side.len(), 2, "{:?}", side);
+        assert!(side[0].contains("not-an-email") && side[1].contains("'dsa'"), "{:?}", side);
+        assert!(!manager.validate_plan(&plan).unwrap());
+    }
+}
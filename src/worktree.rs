@@ -0,0 +1,197 @@
+//! `switch`: giving one repository, or one worktree of it, an account's
+//! identity regardless of the directory it's in. includeIf gitdir: follows
+//! a worktree's common git dir, so a worktree checked out under another
+//! account's codebase dir still gets the identity of its main repository;
+//! `--scope worktree` writes to the worktree's own config instead, turning
+//! on `extensions.worktreeConfig` for the repository the first time.
+
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::account::GitAccountManager;
+use crate::errors::account_not_found;
+use crate::fix_remotes::{self, Verdict};
+use crate::keys::confirm;
+use crate::platform;
+
+/// First git with `extensions.worktreeConfig` and `git config --worktree`.
+const WORKTREE_CONFIG_GIT: (u32, u32) = (2, 20);
+
+/// The settings git only applies to the main worktree while they're in the
+/// common config, and stops special-casing once worktreeConfig is on.
+const MAIN_WORKTREE_SETTINGS: &[&str] = &["core.bare", "core.worktree"];
+
+/// The git config file `switch` writes to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConfigScope {
+    /// The repository's config, shared by all its worktrees.
+    Local,
+    /// The config of the worktree in the directory only.
+    Worktree,
+}
+
+impl ConfigScope {
+    fn flag(self) -> &'static str {
+        match self {
+            ConfigScope::Local => "--local",
+            ConfigScope::Worktree => "--worktree",
+        }
+    }
+}
+
+/// Runs git in `dir`, returning its trimmed output; `None` when git exits
+/// with 1, as `git config --get` does for unset keys.
+pub fn git(dir: &Path, args: &[&str]) -> Result<Option<String>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| Error::new(e.kind(), format!("Failed to run git: {}", e)))?;
+    match output.status.code() {
+        Some(0) => Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string())),
+        Some(1) if output.stderr.is_empty() => Ok(None),
+        _ => Err(Error::other(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))),
+    }
+}
+
+fn set(dir: &Path, scope: &str, key: &str, value: &str) -> Result<()> {
+    git(dir, &["config", scope, key, value]).map(|_| ())
+}
+
+/// The common git dir of the repository `dir` is in, shared by all its
+/// worktrees.
+fn common_dir(dir: &Path) -> Result<PathBuf> {
+    match git(dir, &["rev-parse", "--git-common-dir"]) {
+        Ok(Some(common)) => Ok(dir.join(common)),
+        _ => Err(Error::new(ErrorKind::NotFound, format!("{} is not inside a git repository", dir.display()))),
+    }
+}
+
+/// Turns on `extensions.worktreeConfig` unless it already is, after asking
+/// unless `yes`. First moves core.bare and core.worktree into the main
+/// worktree's own config as git asks, so the other worktrees don't pick
+/// them up. Returns false when the user said no.
+fn enable_worktree_config(dir: &Path, common: &Path, yes: bool) -> Result<bool> {
+    match platform::git_version() {
+        Some(version) if version >= WORKTREE_CONFIG_GIT => {}
+        version => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} has no per-worktree config, which needs git {}.{} or later; upgrade git, or use --scope local for the whole repository",
+                    version.map(|(major, minor)| format!("git {}.{}", major, minor)).unwrap_or_else(|| "this git".to_string()),
+                    WORKTREE_CONFIG_GIT.0,
+                    WORKTREE_CONFIG_GIT.1
+                ),
+            ))
+        }
+    }
+    if git(dir, &["config", "--local", "--bool", "--get", "extensions.worktreeConfig"])?.as_deref() == Some("true") {
+        return Ok(true);
+    }
+    if !yes && !confirm(&format!("--scope worktree needs extensions.worktreeConfig, which {} doesn't have yet. Turn it on?", common.display()))? {
+        println!("Nothing changed; use --scope local to set the identity for the whole repository");
+        return Ok(false);
+    }
+
+    let common_config = common.join("config");
+    let main_config = common.join("config.worktree");
+    let (common_config, main_config) = (common_config.to_string_lossy(), main_config.to_string_lossy());
+    for key in MAIN_WORKTREE_SETTINGS {
+        let Some(value) = git(dir, &["config", "--file", &common_config, "--get", key])? else {
+            continue;
+        };
+        git(dir, &["config", "--file", &main_config, key, &value])?;
+        git(dir, &["config", "--file", &common_config, "--unset", key])?;
+        println!("Moved {} to {}", key, main_config);
+    }
+    set(dir, "--local", "extensions.worktreeConfig", "true")?;
+    println!("Enabled extensions.worktreeConfig in {}", common_config);
+    Ok(true)
+}
+
+impl GitAccountManager {
+    /// Writes the account's user.name and user.email into the `scope` config
+    /// of the repository in `dir`, and with `push_url` points origin's
+    /// pushurl at the account's Host alias.
+    pub fn switch_identity(&self, account_name: &str, dir: &Path, scope: ConfigScope, push_url: bool, yes: bool) -> Result<bool> {
+        let account = self.accounts.get(account_name).ok_or_else(|| account_not_found(account_name))?;
+        if account.disabled {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Account '{}' is disabled; run multigit enable {} first", account_name, account_name),
+            ));
+        }
+        let common = common_dir(dir)?;
+        let origin = git(dir, &["config", "--get", "remote.origin.url"]).ok().flatten();
+        if let Some(inferred) = origin.and_then(|url| self.infer_account(&url, dir).filter(|inferred| inferred.account.name != account_name)) {
+            let other = &inferred.account.name;
+            println!("note: origin looks like {}'s ({}); run multigit use {} if that's the one", other, inferred.reason, other);
+        }
+        if scope == ConfigScope::Worktree && !enable_worktree_config(dir, &common, yes)? {
+            return Ok(false);
+        }
+        let target = if scope == ConfigScope::Worktree { "worktree" } else { "repository" };
+        let scope = scope.flag();
+
+        set(dir, scope, "user.name", &account.name)?;
+        set(dir, scope, "user.email", &account.email)?;
+        println!("This {} now commits as {} <{}>", target, account.name, account.email);
+
+        if push_url {
+            match git(dir, &["config", "--get", "remote.origin.url"])? {
+                None => println!("warning: no origin remote; pushurl not set"),
+                Some(url) => match fix_remotes::verdict(account, &url) {
+                    Verdict::Rewrite(proposed) => {
+                        set(dir, scope, "remote.origin.pushurl", &proposed)?;
+                        println!("Pushes to origin go to {}", proposed);
+                    }
+                    Verdict::AlreadyRight => println!("origin already uses {}'s {}", account_name, account.host),
+                    Verdict::Foreign => println!("warning: origin ({}) isn't on the provider; pushurl not set", url),
+                },
+            }
+        }
+
+        // A later config that also sets user.email still wins over this one.
+        if let Ok(Some(origin)) = git(dir, &["config", "--show-origin", "--show-scope", "--get", "user.email"]) {
+            let mut fields = origin.split('\t');
+            if let (Some(scope), Some(file), Some(email)) = (fields.next(), fields.next(), fields.next()) {
+                if email != account.email {
+                    println!("warning: git still reads user.email {} from {} ({} config)", email, file.trim_start_matches("file:"), scope);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, TempHome};
+
+    #[test]
+    fn identity_lands_in_the_chosen_scope() {
+        let home = TempHome::new();
+        let mut manager = home.manager();
+        testutil::set_up(&mut manager, &["home", "work"]);
+        let (repo, worktree) = (home.join("repo"), home.join("feature"));
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]).unwrap();
+        git(&repo, &["-c", "user.name=t", "-c", "user.email=t@example.com", "commit", "-q", "--allow-empty", "-m", "init"]).unwrap();
+        git(&repo, &["worktree", "add", "-q", &worktree.to_string_lossy()]).unwrap();
+
+        assert!(manager.switch_identity("work", &repo, ConfigScope::Local, false, true).unwrap());
+        assert_eq!(git(&repo, &["config", "--local", "user.email"]).unwrap().as_deref(), Some("work@example.com"));
+
+        assert!(manager.switch_identity("home", &worktree, ConfigScope::Worktree, false, true).unwrap());
+        assert_eq!(git(&repo, &["config", "--local", "--bool", "extensions.worktreeConfig"]).unwrap().as_deref(), Some("true"));
+        assert_eq!(git(&worktree, &["config", "--worktree", "user.email"]).unwrap().as_deref(), Some("home@example.com"));
+        assert_eq!(git(&worktree, &["config", "user.email"]).unwrap().as_deref(), Some("home@example.com"));
+        assert_eq!(git(&repo, &["config", "user.email"]).unwrap().as_deref(), Some("work@example.com"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Codebase dirs on a Windows drive under WSL, which Windows-side git sees
+//! as `C:/...` rather than `/mnt/c/...`.
+
+use std::fs;
+use std::path::Path;
+
+use crate::account::{GitAccount, GitAccountManager};
+use crate::gitconfig;
+
+/// Whether multigit runs inside WSL.
+pub fn is_wsl() -> bool {
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || fs::read_to_string("/proc/sys/kernel/osrelease").is_ok_and(|release| release.to_ascii_lowercase().contains("microsoft"))
+}
+
+/// The Windows form of a path on a mounted drive, with forward slashes as
+/// git for Windows writes them: `/mnt/d/My Code` becomes `D:/My Code`.
+/// `None` for paths that aren't on a drive mount.
+pub fn windows_path(path: &Path) -> Option<String> {
+    let rest = path.to_str()?.strip_prefix("/mnt/")?;
+    let (drive, rest) = rest.split_once('/').unwrap_or((rest, ""));
+    let mut letters = drive.chars();
+    let letter = letters.next().filter(char::is_ascii_alphabetic)?;
+    if letters.next().is_some() {
+        return None;
+    }
+    Some(format!("{}:/{}", letter.to_ascii_uppercase(), rest.trim_end_matches('/')))
+}
+
+impl GitAccountManager {
+    /// One line per account whose codebase dir is on a Windows drive, saying
+    /// whether Windows git picks up the same identity. The bool is false
+    /// when a `--also-windows` includeIf has gone missing.
+    pub fn wsl_status(&self, include_disabled: bool) -> Vec<(String, bool)> {
+        let content = fs::read_to_string(self.global_gitconfig_path()).unwrap_or_default();
+        let lines: Vec<&str> = content.lines().collect();
+        let sections = gitconfig::sections(&lines);
+
+        self.sorted_accounts(include_disabled)
+            .into_iter()
+            .filter_map(|account: &GitAccount| {
+                let windows_dir = windows_path(&account.codebase_dir_path)?;
+                let status = if !account.also_windows {
+                    (
+                        format!(
+                            "{}: {} is on a Windows drive but Windows git won't use this identity there (setup --also-windows)",
+                            account.name, account.codebase_dir_path.display()
+                        ),
+                        true,
+                    )
+                } else if account
+                    .windows_include_path()
+                    .is_some_and(|path| gitconfig::include_ifs_for(&lines, &sections, &path, &self.home_dir).is_empty())
+                {
+                    (format!("{}: the includeIf for {} is missing from {}", account.name, windows_dir, self.global_gitconfig_path().display()), false)
+                } else {
+                    (
+                        format!(
+                            "{}: Windows git uses this identity under {}; the ssh keys and agent stay Linux-only",
+                            account.name, windows_dir
+                        ),
+                        true,
+                    )
+                };
+                Some(status)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_path_translates_drive_mounts() {
+        let windows = |path: &str| windows_path(Path::new(path));
+        assert_eq!(windows("/mnt/c/Users/me/code").as_deref(), Some("C:/Users/me/code"));
+        assert_eq!(windows("/mnt/d/My Code/work/").as_deref(), Some("D:/My Code/work"));
+        assert_eq!(windows("/mnt/z").as_deref(), Some("Z:/"));
+        assert_eq!(windows("/mnt/wsl/instance"), None);
+        assert_eq!(windows("/mnt/1/code"), None);
+        assert_eq!(windows("/home/me/code"), None);
+    }
+}
@@ -0,0 +1,32 @@
+//! `init-agent-service` on Linux: the systemd user unit it writes and
+//! removes again.
+#![cfg(all(feature = "cli", target_os = "linux"))]
+
+mod common;
+
+use std::fs;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn systemd_unit_runs_this_binary_to_reload_the_agent() {
+    let temp = TempHome::new("agent-service");
+    let home = &temp.path;
+
+    let output = multigit(home, &["init-agent-service"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let unit_path = home.join(".config/systemd/user/multigit-agent.service");
+    let unit = fs::read_to_string(&unit_path).unwrap();
+    let binary = fs::canonicalize(env!("CARGO_BIN_EXE_multigit")).unwrap();
+    assert!(unit.contains(&format!("\nExecStart={} --home {} reload-agent --managed-only --yes\n", binary.display(), home.display())), "{}", unit);
+    assert!(unit.contains("\n[Install]\nWantedBy=default.target\n"), "{}", unit);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("systemctl --user enable --now multigit-agent.service"));
+
+    assert!(multigit(home, &["init-agent-service", "--uninstall"]).status.success());
+    assert!(!unit_path.exists());
+
+    // A unit the user wrote is left alone.
+    fs::write(&unit_path, "[Service]\nExecStart=/bin/true\n").unwrap();
+    assert!(!multigit(home, &["init-agent-service"]).status.success());
+    assert_eq!(fs::read_to_string(&unit_path).unwrap(), "[Service]\nExecStart=/bin/true\n");
+}
@@ -0,0 +1,57 @@
+//! Helpers for the integration tests: a throwaway home directory per test
+//! and the binary run against it. Not every test uses every helper.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "cli")]
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temp dir, removed when dropped, so a
+/// failed assert doesn't leave it behind.
+pub struct TempHome {
+    pub path: PathBuf,
+}
+
+impl TempHome {
+    /// `name` tells the test's directory apart from the others in the
+    /// temp dir.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("multigit-{}-{}-{}", name, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("create temp home");
+        TempHome { path: fs::canonicalize(&path).expect("canonicalize temp home") }
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.path.join(path)
+    }
+
+    pub fn read(&self, path: impl AsRef<Path>) -> String {
+        fs::read_to_string(self.join(path)).unwrap_or_default()
+    }
+}
+
+impl Drop for TempHome {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// The binary, for `home`, offline, without ssh-agent and allowed to run
+/// as root; ready for more arguments or its environment.
+#[cfg(feature = "cli")]
+pub fn command(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_multigit"));
+    command.arg("--home").arg(home).args(["--offline", "--no-agent", "--allow-root"]);
+    command
+}
+
+/// Runs the binary with `args` as [`command`] sets it up.
+#[cfg(feature = "cli")]
+pub fn multigit(home: &Path, args: &[&str]) -> Output {
+    command(home).args(args).output().unwrap()
+}
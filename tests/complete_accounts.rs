@@ -0,0 +1,35 @@
+//! `__complete-accounts`, which the completion scripts run on every tab
+//! press.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::fs;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn lists_exactly_the_account_names_and_is_silent_on_errors() {
+    let temp = TempHome::new("complete");
+    let home = &temp.path;
+
+    let empty = multigit(home, &["__complete-accounts"]);
+    assert!(empty.status.success());
+    assert!(empty.stdout.is_empty());
+
+    for name in ["work", "home"] {
+        let email = format!("{}@example.com", name);
+        let dir = format!("code/{}", name);
+        let output = multigit(home, &["setup", "--name", name, "--email", &email, "--codebase-dir", &dir]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    let listed = multigit(home, &["__complete-accounts"]);
+    assert!(listed.status.success());
+    assert_eq!(String::from_utf8(listed.stdout).unwrap(), "home\nwork\n");
+    assert!(listed.stderr.is_empty());
+
+    fs::write(home.join(".config/multigit/accounts.toml"), "not [valid toml").unwrap();
+    let broken = multigit(home, &["__complete-accounts"]);
+    assert!(broken.status.success());
+    assert!(broken.stdout.is_empty() && broken.stderr.is_empty());
+}
@@ -0,0 +1,35 @@
+//! A hand-edited IdentityFile, as `sync` and the check before every
+//! command that writes report it.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::fs;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn edited_identity_file_is_reported_with_sync_fix() {
+    let temp = TempHome::new("consistency");
+    let home = &temp.path;
+    let output = multigit(home, &["setup", "--name", "work", "--email", "work@example.com", "--codebase-dir", "code/work"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let config_path = home.join(".ssh").join("config");
+    let config = fs::read_to_string(&config_path).unwrap();
+    let key = home.join(".ssh").join("id_ed25519_work");
+    fs::write(&config_path, config.replace(&format!("IdentityFile {}", key.display()), "IdentityFile ~/.ssh/id_rsa")).unwrap();
+
+    let sync = multigit(home, &["sync"]);
+    assert!(!sync.status.success());
+    assert!(String::from_utf8_lossy(&sync.stdout).contains("Run `multigit sync --fix`"));
+
+    let output = multigit(home, &["setup", "--name", "home", "--email", "home@example.com", "--codebase-dir", "code/home"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("warning: work: Host github.com-work has IdentityFile ~/.ssh/id_rsa instead of {}", key.display())), "{}", stderr);
+    assert!(stderr.contains("run `multigit sync --fix`"), "{}", stderr);
+
+    assert!(multigit(home, &["sync", "--fix"]).status.success());
+    let sync = multigit(home, &["sync"]);
+    assert!(sync.status.success());
+    assert!(String::from_utf8_lossy(&sync.stdout).contains("Everything is in sync."));
+}
@@ -0,0 +1,25 @@
+//! `--events` as a frontend sees it: the binary's stdout, one JSON object
+//! per line.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use serde_json::Value;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn setup_emits_its_events_in_order() {
+    let home = TempHome::new("events");
+    let output = multigit(&home.path, &["--events", "setup", "--name", "work", "--email", "work@example.com", "--codebase-dir", "code/work"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let events: Vec<Value> = String::from_utf8(output.stdout).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    let kinds: Vec<&str> = events.iter().filter(|event| event["event"] != "step").map(|event| event["event"].as_str().unwrap()).collect();
+    assert_eq!(kinds, ["keygen_start", "keygen_done", "ssh_config_written", "done"]);
+    assert!(events.iter().filter(|event| event["event"] != "done").all(|event| event["account"] == "work"));
+    assert_eq!(events.last().unwrap()["ok"], true);
+    let steps: Vec<&str> = events.iter().filter(|event| event["event"] == "step").map(|event| event["step"].as_str().unwrap()).collect();
+    assert_eq!(steps.first(), Some(&"generate key"));
+    assert!(steps.contains(&"ssh config"));
+}
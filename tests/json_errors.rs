@@ -0,0 +1,21 @@
+//! `--json` failures as a wrapper sees them: an error envelope on stderr.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use serde_json::Value;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn unknown_account_fails_with_an_account_not_found_envelope() {
+    let home = TempHome::new("json-errors");
+    let output = multigit(&home.path, &["--json", "key", "show", "nope"]);
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let envelope: Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(envelope["error"]["kind"], "AccountNotFound");
+    assert_eq!(envelope["error"]["account"], "nope");
+    assert_eq!(envelope["error"]["message"], "Account 'nope' not found");
+}
@@ -0,0 +1,45 @@
+//! `setup --enable-lfs` with no git-lfs on the PATH: the filter is still
+//! written, and the missing binary is only a warning.
+#![cfg(all(feature = "cli", unix))]
+
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use common::{command, TempHome};
+
+/// Where `tool` is on this process's PATH.
+fn find(tool: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?).map(|dir| dir.join(tool)).find(|path| path.is_file())
+}
+
+/// A directory with `tools` linked into it and nothing else, to run the
+/// binary with as its PATH.
+fn bin_dir(root: &Path, tools: &[&str]) -> PathBuf {
+    let dir = root.join("bin");
+    fs::create_dir_all(&dir).unwrap();
+    for tool in tools {
+        std::os::unix::fs::symlink(find(tool).unwrap(), dir.join(tool)).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn lfs_filter_is_written_and_a_missing_git_lfs_only_warns() {
+    let root = TempHome::new("lfs");
+    let home = root.join("home");
+    fs::create_dir_all(&home).unwrap();
+    let path = bin_dir(&root.path, &["ssh-keygen", "ssh", "git"]);
+
+    let output = command(&home)
+        .env("PATH", &path)
+        .args(["setup", "--enable-lfs", "--name", "work", "--email", "work@example.com", "--codebase-dir", "code/work"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("warning: git-lfs isn't installed"));
+
+    let include = fs::read_to_string(home.join("code/work/.gitconfig")).unwrap();
+    assert!(include.contains("[filter \"lfs\"]\n    clean = git-lfs clean -- %f\n    smudge = git-lfs smudge -- %f\n    process = git-lfs filter-process\n    required = true\n"), "{}", include);
+}
@@ -0,0 +1,23 @@
+//! The library on its own, as a program without the `cli` feature uses it.
+
+mod common;
+
+use multigit::account::{GitAccountManager, SetupOptions};
+
+use common::TempHome;
+
+#[test]
+fn sets_up_and_reloads_an_account() {
+    let home = TempHome::new("library");
+    let mut manager = GitAccountManager::new(home.path.clone());
+    manager.offline = true;
+    manager.no_agent = true;
+    let report = manager.setup_account("work", "me@example.com", "code/work", "github.com-work", SetupOptions::default()).unwrap();
+    assert!(report.config_written());
+
+    let loaded = GitAccountManager::load(home.path.clone()).unwrap();
+    let account = &loaded.accounts["work"];
+    assert_eq!(account.email, "me@example.com");
+    assert_eq!(account.host, "github.com-work");
+    assert!(home.read(".ssh/config").contains("Host github.com-work"));
+}
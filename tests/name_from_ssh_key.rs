@@ -0,0 +1,30 @@
+//! `setup --name-from-ssh-key`, naming an imported key's account after the
+//! comment of its `.pub`.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn account_is_named_after_the_key_comment() {
+    let temp = TempHome::new("name-from-key");
+    let home = &temp.path;
+    fs::create_dir_all(home.join(".ssh")).unwrap();
+    let key = home.join(".ssh").join("id_ed25519_laptop");
+    let generated = Command::new("ssh-keygen").args(["-q", "-t", "ed25519", "-N", "", "-C", "work-ed25519", "-f"]).arg(&key).status().unwrap();
+    assert!(generated.success());
+
+    let key = key.to_str().unwrap();
+    let output = multigit(home, &["setup", "--name-from-ssh-key", "--existing-key", key, "--email", "work@example.com", "--codebase-dir", "code/work"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("naming the account work after the key comment 'work-ed25519'"));
+    assert!(multigit(home, &["key", "show", "work"]).status.success());
+
+    let output = multigit(home, &["setup", "--name-from-ssh-key", "{name}@laptop", "--existing-key", key, "--email", "other@example.com", "--codebase-dir", "code/other"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("doesn't match {name}@laptop"));
+}
@@ -0,0 +1,35 @@
+//! `setup --provider` presets as the binary applies them to the Host block.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::fs;
+
+use common::{multigit, TempHome};
+
+/// The Host block and key files `setup --provider <provider>` writes in a
+/// fresh home.
+fn set_up_with(provider: &str) -> (String, Vec<String>) {
+    let home = TempHome::new("provider");
+    let output = multigit(&home.path, &["setup", "--provider", provider, "--name", "work", "--email", "work@example.com", "--codebase-dir", "code/work"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let config = home.read(".ssh/config");
+    let mut keys: Vec<String> = fs::read_dir(home.join(".ssh")).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).filter(|name| name.starts_with("id_")).collect();
+    keys.sort();
+    (config, keys)
+}
+
+#[test]
+fn azure_preset_connects_to_azure_devops_with_an_rsa_key() {
+    let (config, keys) = set_up_with("azure");
+    assert!(config.contains("Host ssh.dev.azure.com-work\n    HostName ssh.dev.azure.com\n    User git\n"), "{}", config);
+    assert_eq!(keys, ["id_rsa_work", "id_rsa_work.pub"]);
+}
+
+#[test]
+fn github_preset_is_git_at_github_com() {
+    let (config, keys) = set_up_with("github");
+    assert!(config.contains("Host github.com-work\n    HostName github.com\n    User git\n"), "{}", config);
+    assert_eq!(keys, ["id_ed25519_work", "id_ed25519_work.pub"]);
+}
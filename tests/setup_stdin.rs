@@ -0,0 +1,41 @@
+//! `add --stdin`: account records from a script, set up as `setup` would
+//! with flags.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{command, TempHome};
+
+#[test]
+fn add_sets_up_each_json_line_and_reports_the_invalid_one() {
+    let home = TempHome::new("setup-stdin");
+    let records = concat!(
+        r#"{"name": "work", "email": "work@example.com", "codebase_dir": "code/work"}"#,
+        "\n",
+        r#"{"name": "broken", "email": "broken@example.com"}"#,
+        "\n",
+        r#"{"name": "lab", "email": "lab@example.com", "codebase_dir": "code/lab", "hostname": "gitlab.com"}"#,
+        "\n",
+    );
+    let mut child = command(&home.path)
+        .args(["add", "--stdin", "--format", "json-lines"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(records.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("record 2 (broken)"), "{}", stdout);
+    assert!(stdout.contains("Rejected: 1 record(s)"), "{}", stdout);
+
+    let config = home.read(".ssh/config");
+    assert!(config.contains("Host github.com-work\n"), "{}", config);
+    assert!(config.contains("Host gitlab.com-lab\n    HostName gitlab.com\n"), "{}", config);
+    assert!(!home.join(".ssh/id_ed25519_broken").exists());
+}
@@ -0,0 +1,29 @@
+//! `--strict`: a setup that warns, here about a directory inside another
+//! account's, fails the run, and nothing of the account is left behind.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use std::fs;
+
+use common::{multigit, TempHome};
+
+#[test]
+fn overlapping_dir_fails_the_run_under_strict() {
+    let temp = TempHome::new("strict");
+    let home = &temp.path;
+    let output = multigit(home, &["setup", "--name", "work", "--email", "work@example.com", "--codebase-dir", "code/work"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = multigit(home, &["--strict", "setup", "--name", "side", "--email", "side@example.com", "--codebase-dir", "code/work/side"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let inner = home.join("code/work/side");
+    assert!(stderr.contains(&format!("error: --strict: 1 warning(s): {} is inside work's {}", inner.display(), home.join("code/work").display())), "{}", stderr);
+    assert!(!home.join(".ssh/id_ed25519_side").exists());
+    assert!(!fs::read_to_string(home.join(".config/multigit/accounts.toml")).unwrap().contains("side"));
+
+    // Without it the same setup only warns.
+    let output = multigit(home, &["setup", "--name", "side", "--email", "side@example.com", "--codebase-dir", "code/work/side"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}